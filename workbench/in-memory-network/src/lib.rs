@@ -11,9 +11,39 @@ mod util;
 #[cfg(feature = "schc-observer")]
 pub mod schc_observer;
 
+#[cfg(feature = "schc-observer")]
+pub mod schc_residue;
+
+#[cfg(feature = "schc-observer")]
+pub mod schc_rule_synthesis;
+
+#[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+mod schc_frame;
+
+#[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+pub mod quic_header_protection;
+
+#[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+pub mod schc_stats;
+
+#[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+pub mod schc_node_roles;
+
 #[cfg(feature = "schc-compressor")]
 pub mod schc_compressor;
 
+#[cfg(feature = "schc-compressor")]
+pub mod schc_fragmentation;
+
+#[cfg(feature = "schc-compressor")]
+pub mod schc_provisioning;
+
+#[cfg(feature = "schc-compressor")]
+pub mod schc_desync;
+
+#[cfg(feature = "schc-compressor")]
+pub mod schc_pcap;
+
 use crate::network::node::UdpEndpoint;
 use std::sync::Arc;
 use transmit::OwnedTransmit;
@@ -28,6 +58,65 @@ pub struct InTransitData {
     source_id: Arc<str>,
     transmit: OwnedTransmit,
     number: u64,
+    /// Set on SCHC fragments and ACKs travelling between SCHC F/R endpoints
+    #[cfg(feature = "schc-compressor")]
+    schc_fragment: Option<schc_fragmentation::FragmentMeta>,
+    /// Set while `transmit.contents` holds SCHC-compressed bytes
+    #[cfg(feature = "schc-compressor")]
+    schc_compressed: Option<schc_compressor::SchcCompressionState>,
+    /// Set on the chunks of SCHC rule updates travelling to the node that installs them
+    #[cfg(feature = "schc-compressor")]
+    schc_rule_update: Option<schc_provisioning::RuleUpdateChunkMeta>,
+}
+
+impl InTransitData {
+    /// The size of the packet when transmitted, in bytes
+    ///
+    /// SCHC packets (compressed packets, fragments and ACKs) replace the IP and UDP headers, so
+    /// they are as big as their contents
+    pub(crate) fn packet_size(&self) -> usize {
+        if self.is_schc_packet() {
+            return self.transmit.contents.len();
+        }
+
+        self.transmit.packet_size()
+    }
+
+    /// Whether the packet travels as a SCHC packet (compressed, as a fragment or ACK, or as a
+    /// rule update chunk) instead of an IP datagram
+    pub(crate) fn is_schc_packet(&self) -> bool {
+        #[cfg(feature = "schc-compressor")]
+        if self.schc_compressed.is_some()
+            || self.schc_fragment.is_some()
+            || self.schc_rule_update.is_some()
+        {
+            return true;
+        }
+
+        false
+    }
+
+    /// Whether the packet is a SCHC fragment or ACK
+    #[cfg(feature = "schc-observer")]
+    pub(crate) fn is_schc_fragment(&self) -> bool {
+        #[cfg(feature = "schc-compressor")]
+        if self.schc_fragment.is_some() {
+            return true;
+        }
+
+        false
+    }
+
+    /// Whether the packet is a chunk of a SCHC rule update
+    #[cfg(feature = "schc-observer")]
+    pub(crate) fn is_schc_rule_update(&self) -> bool {
+        #[cfg(feature = "schc-compressor")]
+        if self.schc_rule_update.is_some() {
+            return true;
+        }
+
+        false
+    }
 }
 
 #[cfg(test)]
@@ -37,12 +126,12 @@ mod test {
     use crate::network::event::{
         NetworkEvent, NetworkEventPayload, NetworkEvents, UpdateLinkStatus,
     };
-    use crate::network::ip::Ipv4Cidr;
+    use crate::network::ip::IpCidr;
     use crate::network::route::{IpRange, Route};
     use crate::network::spec::{
-        NetworkInterface, NetworkLinkSpec, NetworkNodeSpec, NetworkSpec, NodeKind,
+        NetworkInterface, NetworkLinkSpec, NetworkNodeSpec, NetworkSpec, NodeKind, SchcRole,
     };
-    use crate::pcap_exporter::NoOpPcapExporterFactory;
+    use crate::pcap_exporter::{NoOpPcapExporterFactory, PcapExporterFactory};
     use crate::quinn_interop::BufsAndMeta;
     use crate::tracing::tracer::SimulationStepTracer;
     use bon::builder;
@@ -55,10 +144,10 @@ mod test {
     use std::sync::Arc;
     use std::time::Duration;
 
-    const SERVER_ADDR: Ipv4Cidr = Ipv4Cidr::from_ipv4(Ipv4Addr::new(88, 88, 88, 88), 24);
-    const ROUTER1_ADDR: Ipv4Cidr = Ipv4Cidr::from_ipv4(Ipv4Addr::new(200, 200, 200, 1), 24);
-    const ROUTER2_ADDR: Ipv4Cidr = Ipv4Cidr::from_ipv4(Ipv4Addr::new(200, 200, 200, 2), 24);
-    const CLIENT_ADDR: Ipv4Cidr = Ipv4Cidr::from_ipv4(Ipv4Addr::new(1, 1, 1, 1), 24);
+    const SERVER_ADDR: IpCidr = IpCidr::from_ipv4(Ipv4Addr::new(88, 88, 88, 88), 24);
+    const ROUTER1_ADDR: IpCidr = IpCidr::from_ipv4(Ipv4Addr::new(200, 200, 200, 1), 24);
+    const ROUTER2_ADDR: IpCidr = IpCidr::from_ipv4(Ipv4Addr::new(200, 200, 200, 2), 24);
+    const CLIENT_ADDR: IpCidr = IpCidr::from_ipv4(Ipv4Addr::new(1, 1, 1, 1), 24);
     const BANDWIDTH_100_MBPS: u64 = 1000 * 1000 * 100;
     const BANDWIDTH_8_KBPS: u64 = 1000 * 8;
 
@@ -66,6 +155,13 @@ mod test {
     fn default_network(
         bandwidth_bps: Option<u64>,
         events: Option<Vec<NetworkEvent>>,
+        pcap_exporter_factory: Option<Arc<dyn PcapExporterFactory>>,
+        /// Links over which packets should travel SCHC-compressed (the client is then a SCHC
+        /// device, and the server a SCHC application)
+        schc_constrained_links: Option<Vec<&'static str>>,
+        /// Ratio of the packets marked with CE on the SCHC-constrained links
+        schc_congestion_event_ratio: Option<f64>,
+        framing_overhead_bytes: Option<usize>,
     ) -> Arc<InMemoryNetwork> {
         let bandwidth_bps = bandwidth_bps.unwrap_or(BANDWIDTH_100_MBPS);
 
@@ -75,7 +171,7 @@ mod test {
 
         // SERVER_ADDR -> ROUTER1_ADDR
 
-        let network_spec = NetworkSpec {
+        let mut network_spec = NetworkSpec {
             nodes: vec![
                 NetworkNodeSpec {
                     id: "server".to_string(),
@@ -91,6 +187,7 @@ mod test {
                     buffer_size_bytes: u64::MAX,
                     packet_loss_ratio: 0.0,
                     packet_duplication_ratio: 0.0,
+                    schc_role: None,
                 },
                 NetworkNodeSpec {
                     id: "client".to_string(),
@@ -106,6 +203,7 @@ mod test {
                     buffer_size_bytes: u64::MAX,
                     packet_loss_ratio: 0.0,
                     packet_duplication_ratio: 0.0,
+                    schc_role: None,
                 },
                 NetworkNodeSpec {
                     id: "router1".to_string(),
@@ -128,6 +226,7 @@ mod test {
                     buffer_size_bytes: u64::MAX,
                     packet_loss_ratio: 0.0,
                     packet_duplication_ratio: 0.0,
+                    schc_role: None,
                 },
                 NetworkNodeSpec {
                     id: "router2".to_string(),
@@ -150,6 +249,7 @@ mod test {
                     buffer_size_bytes: u64::MAX,
                     packet_loss_ratio: 0.0,
                     packet_duplication_ratio: 0.0,
+                    schc_role: None,
                 },
             ],
             links: vec![
@@ -162,6 +262,8 @@ mod test {
                     congestion_event_ratio: 0.0,
                     extra_delay: Default::default(),
                     extra_delay_ratio: 0.0,
+                    schc_constrained: false,
+                    framing_overhead_bytes: 0,
                 },
                 NetworkLinkSpec {
                     id: "router1-router2".to_string().into_boxed_str().into(),
@@ -172,6 +274,8 @@ mod test {
                     congestion_event_ratio: 0.0,
                     extra_delay: Default::default(),
                     extra_delay_ratio: 0.0,
+                    schc_constrained: false,
+                    framing_overhead_bytes: 0,
                 },
                 NetworkLinkSpec {
                     id: "router2-client".to_string().into_boxed_str().into(),
@@ -182,6 +286,8 @@ mod test {
                     congestion_event_ratio: 0.0,
                     extra_delay: Default::default(),
                     extra_delay_ratio: 0.0,
+                    schc_constrained: false,
+                    framing_overhead_bytes: 0,
                 },
                 NetworkLinkSpec {
                     id: "router1-server".to_string().into_boxed_str().into(),
@@ -192,6 +298,8 @@ mod test {
                     congestion_event_ratio: 0.0,
                     extra_delay: Default::default(),
                     extra_delay_ratio: 0.0,
+                    schc_constrained: false,
+                    framing_overhead_bytes: 0,
                 },
                 NetworkLinkSpec {
                     id: "router2-router1".to_string().into_boxed_str().into(),
@@ -202,6 +310,8 @@ mod test {
                     congestion_event_ratio: 0.0,
                     extra_delay: Default::default(),
                     extra_delay_ratio: 0.0,
+                    schc_constrained: false,
+                    framing_overhead_bytes: 0,
                 },
                 NetworkLinkSpec {
                     id: "client-router2".to_string().into_boxed_str().into(),
@@ -212,21 +322,245 @@ mod test {
                     congestion_event_ratio: 0.0,
                     extra_delay: Default::default(),
                     extra_delay_ratio: 0.0,
+                    schc_constrained: false,
+                    framing_overhead_bytes: 0,
                 },
             ],
         };
 
+        for link in &mut network_spec.links {
+            link.framing_overhead_bytes = framing_overhead_bytes.unwrap_or(0);
+        }
+
+        if let Some(schc_constrained_links) = schc_constrained_links {
+            for node in &mut network_spec.nodes {
+                node.schc_role = match node.id.as_str() {
+                    "client" => Some(SchcRole::Device),
+                    "server" => Some(SchcRole::Application),
+                    _ => None,
+                };
+            }
+
+            for link in &mut network_spec.links {
+                if schc_constrained_links.contains(&link.id.as_ref()) {
+                    link.schc_constrained = true;
+                    link.congestion_event_ratio = schc_congestion_event_ratio.unwrap_or(0.0);
+                }
+            }
+        }
+
         InMemoryNetwork::initialize(
             network_spec.clone(),
             NetworkEvents::new(events.unwrap_or_default(), &network_spec.links),
             Arc::new(SimulationStepTracer::new(network_spec)),
-            Arc::new(NoOpPcapExporterFactory),
+            pcap_exporter_factory.unwrap_or_else(|| Arc::new(NoOpPcapExporterFactory)),
             Rng::with_seed(42),
             async_rt::time::Instant::now(),
         )
         .unwrap()
     }
 
+    /// Captures a single link in memory
+    #[cfg(feature = "schc-compressor")]
+    struct LinkCaptureFactory {
+        link_id: &'static str,
+        capture: Arc<parking_lot::Mutex<Vec<u8>>>,
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    impl PcapExporterFactory for LinkCaptureFactory {
+        fn create_pcap_exporter_for_node(
+            &self,
+            _: &str,
+        ) -> anyhow::Result<crate::pcap_exporter::PcapExporter> {
+            Ok(crate::pcap_exporter::PcapExporter::noop())
+        }
+
+        fn create_pcap_exporter_for_link(
+            &self,
+            link_id: &str,
+        ) -> anyhow::Result<Option<crate::pcap_exporter::PcapExporter>> {
+            if link_id != self.link_id {
+                return Ok(None);
+            }
+
+            let writer = SharedWriter(self.capture.clone());
+            Ok(Some(crate::pcap_exporter::PcapExporter::new(writer)))
+        }
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    struct SharedWriter(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+    #[cfg(feature = "schc-compressor")]
+    impl std::io::Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A compressor without compression rules, sending every packet with the no-compression rule
+    /// (so the packets keep their IP/UDP headers, ECN codepoint included, behind the rule ID)
+    #[cfg(feature = "schc-compressor")]
+    fn no_compression_compressor() -> crate::schc_compressor::SharedSchcCompressor {
+        let rules =
+            r#"{ "rules": [], "no_compression_rule": { "rule_id": 0, "rule_id_length": 8 } }"#;
+
+        // Tests run in parallel, each in its own thread
+        let file_name = format!("schc-network-{:?}.json", std::thread::current().id());
+        let path = std::env::temp_dir().join(file_name);
+        std::fs::write(&path, rules).unwrap();
+        let compressor =
+            crate::schc_compressor::SchcCompressor::from_files(path.to_str().unwrap(), "", false);
+        std::fs::remove_file(&path).unwrap();
+        Arc::new(compressor.unwrap())
+    }
+
+    /// Installs a compressor at the provided nodes
+    #[cfg(feature = "schc-compressor")]
+    fn enable_schc_compression(
+        network: &InMemoryNetwork,
+        compressor_node_ids: &[&str],
+    ) -> crate::schc_compressor::SharedSchcCompressor {
+        use crate::schc_node_roles::SchcNodeRole;
+
+        let compressor = no_compression_compressor();
+        *network.schc_compressor.write() = Some(compressor.clone());
+        for node_id in compressor_node_ids {
+            network
+                .schc_node_roles
+                .write()
+                .add(node_id, SchcNodeRole::Compressor);
+        }
+
+        compressor
+    }
+
+    /// The SCHC steps of the simulation, e.g. "compressed at router2 (Up)"
+    #[cfg(feature = "schc-compressor")]
+    fn schc_steps(network: &InMemoryNetwork) -> Vec<String> {
+        use crate::tracing::simulation_step::SimulationStepKind;
+
+        let steps = network.tracer.stepper().steps();
+        steps
+            .into_iter()
+            .filter_map(|step| match step.kind {
+                SimulationStepKind::SchcPacketCompressed(s) => {
+                    Some(format!("compressed at {} ({:?})", s.node_id, s.direction))
+                }
+                SimulationStepKind::SchcPacketDecompressed(s) => {
+                    Some(format!("decompressed at {} ({:?})", s.node_id, s.direction))
+                }
+                SimulationStepKind::SchcProcessingFailed(s) => {
+                    Some(format!("failed at {} ({:?})", s.node_id, s.direction))
+                }
+                SimulationStepKind::SchcCompressedPacketDropped(s) => {
+                    Some(format!("dropped at {}", s.node_id))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// A datagram from the client to the server, already SCHC-compressed by the client
+    #[cfg(feature = "schc-compressor")]
+    fn compressed_by_client(
+        network: &Arc<InMemoryNetwork>,
+        compressor: &crate::schc_compressor::SchcCompressor,
+        contents: &[u8],
+    ) -> InTransitData {
+        use crate::schc_compressor::SchcCompressionState;
+
+        let server_node = network.host(SERVER_ADDR.as_ip_addr());
+        let client_node = network.host(CLIENT_ADDR.as_ip_addr());
+        let result = compressor.compress(
+            contents,
+            client_node.quic_addr(),
+            server_node.quic_addr(),
+            None,
+            true,
+            "client",
+        );
+        assert!(result.success);
+
+        let mut data = network.in_transit_data(
+            client_node,
+            OwnedTransmit {
+                destination: server_node.quic_addr(),
+                ecn: None,
+                contents: result.compressed_packet,
+                segment_size: None,
+            },
+        );
+        data.schc_compressed = Some(SchcCompressionState {
+            rule_id: result.rule_id,
+            rule_id_length: compressor.rule_id_length(result.rule_id),
+            compressor_id: "client".into(),
+            context_id: None,
+            context_version: 0,
+            header_size: result.compressed_header_size,
+            original_size: contents.len(),
+            original_datagram: None,
+        });
+        data
+    }
+
+    /// The state of a packet compressed by router2, with a header of `header_size` bytes in front
+    /// of `original_size` bytes of payload
+    #[cfg(feature = "schc-compressor")]
+    fn compressed_by_router2(
+        header_size: usize,
+        original_size: usize,
+    ) -> crate::schc_compressor::SchcCompressionState {
+        crate::schc_compressor::SchcCompressionState {
+            rule_id: 5,
+            rule_id_length: Some(8),
+            compressor_id: "router2".into(),
+            context_id: None,
+            context_version: 0,
+            header_size,
+            original_size,
+            original_datagram: None,
+        }
+    }
+
+    /// Sends a datagram between the hosts, returning the contents and ECN codepoint it arrived
+    /// with
+    #[cfg(feature = "schc-compressor")]
+    async fn send_between_hosts(
+        network: &Arc<InMemoryNetwork>,
+        from: IpCidr,
+        to: IpCidr,
+        contents: &[u8],
+        ecn: Option<quinn::udp::EcnCodepoint>,
+    ) -> (Vec<u8>, Option<quinn::udp::EcnCodepoint>) {
+        let source_node = network.host(from.as_ip_addr());
+        let target_node = network.host(to.as_ip_addr());
+        let data = network.in_transit_data(
+            source_node,
+            OwnedTransmit {
+                destination: target_node.quic_addr(),
+                ecn,
+                contents: contents.to_vec(),
+                segment_size: None,
+            },
+        );
+        network.forward(source_node.clone(), data);
+
+        let mut recv_result = BufsAndMeta::new(1200, 10);
+        let target_socket = network.udp_socket_for_node(target_node.clone());
+        let received = target_socket.receive_raw(&mut recv_result).await.unwrap();
+        assert_eq!(received, 1);
+
+        let meta = &recv_result.meta[0];
+        (recv_result.bufs[0][..meta.len].to_vec(), meta.ecn)
+    }
+
     fn default_server_config() -> (&'static str, CertificateDer<'static>, ServerConfig) {
         let server_name = "server-name";
         let cert = rcgen::generate_simple_self_signed(vec![server_name.into()]).unwrap();
@@ -516,4 +850,428 @@ mod test {
             assert_eq!(duration, expected_duration, "{node:?}");
         }
     }
+
+    #[cfg(feature = "schc-compressor")]
+    #[cfg_attr(feature = "rt-custom", sittard::test)]
+    #[cfg_attr(feature = "rt-tokio", tokio::test(start_paused = true))]
+    async fn test_schc_link_capture() {
+        use crate::schc_compressor::SchcCompressionState;
+        use pcap_file::DataLink;
+        use pcap_file::pcapng::blocks::enhanced_packet::EnhancedPacketOption;
+        use pcap_file::pcapng::{Block, PcapNgReader};
+
+        let capture = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let network = default_network()
+            .pcap_exporter_factory(Arc::new(LinkCaptureFactory {
+                link_id: "router2-router1",
+                capture: capture.clone(),
+            }))
+            .call();
+        let server_node = network.host(SERVER_ADDR.as_ip_addr());
+        let client_node = network.host(CLIENT_ADDR.as_ip_addr());
+        let transmit = |contents: &[u8]| OwnedTransmit {
+            destination: server_node.quic_addr(),
+            ecn: None,
+            contents: contents.to_vec(),
+            segment_size: None,
+        };
+
+        // A SCHC packet (dropped when it reaches the server, since nobody decompresses it),
+        // followed by a plain datagram
+        let mut data = network.in_transit_data(client_node, transmit(&[0x05, 0xaa, 0xbb]));
+        data.schc_compressed = Some(SchcCompressionState {
+            rule_id: 5,
+            rule_id_length: Some(8),
+            compressor_id: "router2".into(),
+            context_id: None,
+            context_version: 0,
+            header_size: 1,
+            original_size: 60,
+            original_datagram: None,
+        });
+        network.forward(client_node.clone(), data);
+        let data = network.in_transit_data(client_node, transmit(b"hello"));
+        network.forward(client_node.clone(), data);
+
+        let mut recv_result = BufsAndMeta::new(1200, 10);
+        let server_socket = network.udp_socket_for_node(server_node.clone());
+        let received = server_socket.receive_raw(&mut recv_result).await.unwrap();
+        assert_eq!(received, 1);
+        network.flush_pcap_exporters().unwrap();
+
+        // Read the packets back from the capture
+        let capture = capture.lock().clone();
+        let mut reader = PcapNgReader::new(capture.as_slice()).unwrap();
+        let mut packets = Vec::new();
+        while let Some(block) = reader.next_block() {
+            if let Block::EnhancedPacket(packet) = block.unwrap() {
+                let comment = packet.options.iter().find_map(|option| match option {
+                    EnhancedPacketOption::Comment(comment) => Some(comment.to_string()),
+                    _ => None,
+                });
+                packets.push((packet.interface_id, packet.data.to_vec(), comment));
+            }
+        }
+        let linktype = |interface_id: u32| reader.interfaces()[interface_id as usize].linktype;
+        assert_eq!(packets.len(), 2);
+
+        // The SCHC packet is preceded by its pseudo-header (unknown direction, rule 5/8)
+        let (interface_id, frame, comment) = &packets[0];
+        assert_eq!(linktype(*interface_id), DataLink::USER0);
+        assert_eq!(frame, &[1, 0, 0, 8, 0, 0, 0, 5, 0x05, 0xaa, 0xbb]);
+        assert_eq!(
+            comment.as_deref(),
+            Some(
+                "SCHC packet #0 (rule 5/8, unknown direction, compressed by router2 with context v0, 60 bytes before compression)"
+            )
+        );
+
+        // The plain datagram is an IPv4 packet
+        let (interface_id, packet, comment) = &packets[1];
+        assert_eq!(linktype(*interface_id), DataLink::IPV4);
+        assert_eq!(packet.len(), 20 + 8 + 5);
+        assert_eq!(&packet[28..], b"hello");
+        assert_eq!(comment.as_deref(), Some("Packet #1"));
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    #[cfg_attr(feature = "rt-custom", sittard::test)]
+    #[cfg_attr(feature = "rt-tokio", tokio::test(start_paused = true))]
+    async fn test_schc_constrained_link_between_compressors() {
+        // The hosts are outside the 192.168.40.0/24 and 192.168.41.0/24 networks of the example
+        // SCHC topologies, so the direction can only come from their SCHC roles
+        let network = default_network()
+            .schc_constrained_links(vec!["router2-router1", "router1-router2"])
+            .call();
+        enable_schc_compression(&network, &["router2", "router1"]);
+
+        // The packets are compressed when entering the constrained link, and decompressed when
+        // leaving it
+        let (contents, _) =
+            send_between_hosts(&network, CLIENT_ADDR, SERVER_ADDR, b"hello", None).await;
+        assert_eq!(contents, b"hello");
+        let (contents, _) =
+            send_between_hosts(&network, SERVER_ADDR, CLIENT_ADDR, b"world", None).await;
+        assert_eq!(contents, b"world");
+
+        assert_eq!(
+            schc_steps(&network),
+            [
+                "compressed at router2 (Up)",
+                "decompressed at router1 (Up)",
+                "compressed at router1 (Down)",
+                "decompressed at router2 (Down)",
+            ]
+        );
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    #[cfg_attr(feature = "rt-custom", sittard::test)]
+    #[cfg_attr(feature = "rt-tokio", tokio::test(start_paused = true))]
+    async fn test_schc_node_in_constrained_segment_forwards_as_is() {
+        let network = default_network()
+            .schc_constrained_links(vec!["client-router2", "router2-router1"])
+            .call();
+        enable_schc_compression(&network, &["client", "router2", "router1"]);
+
+        let (contents, _) =
+            send_between_hosts(&network, CLIENT_ADDR, SERVER_ADDR, b"hello", None).await;
+        assert_eq!(contents, b"hello");
+
+        // Router2 is between two constrained links, so it leaves the packet alone
+        assert_eq!(
+            schc_steps(&network),
+            ["compressed at client (Up)", "decompressed at router1 (Up)"]
+        );
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    #[cfg_attr(feature = "rt-custom", sittard::test)]
+    #[cfg_attr(feature = "rt-tokio", tokio::test(start_paused = true))]
+    async fn test_schc_compressor_without_constrained_neighbour() {
+        let network = default_network()
+            .schc_constrained_links(vec!["router2-router1"])
+            .call();
+        enable_schc_compression(&network, &["client", "server"]);
+
+        let (contents, _) =
+            send_between_hosts(&network, CLIENT_ADDR, SERVER_ADDR, b"hello", None).await;
+        assert_eq!(contents, b"hello");
+        assert!(schc_steps(&network).is_empty());
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    #[cfg_attr(feature = "rt-custom", sittard::test)]
+    #[cfg_attr(feature = "rt-tokio", tokio::test(start_paused = true))]
+    async fn test_schc_compressed_packet_is_not_compressed_again() {
+        let network = default_network()
+            .schc_constrained_links(vec!["router2-router1"])
+            .call();
+        let compressor = enable_schc_compression(&network, &["router2", "router1"]);
+        let server_node = network.host(SERVER_ADDR.as_ip_addr());
+        let client_node = network.host(CLIENT_ADDR.as_ip_addr());
+
+        // Router2 would compress the packet, but it is already compressed
+        let data = compressed_by_client(&network, &compressor, b"hello");
+        network.forward(client_node.clone(), data);
+
+        let mut recv_result = BufsAndMeta::new(1200, 10);
+        let server_socket = network.udp_socket_for_node(server_node.clone());
+        let received = server_socket.receive_raw(&mut recv_result).await.unwrap();
+        assert_eq!(received, 1);
+        assert_eq!(&recv_result.bufs[0][..recv_result.meta[0].len], b"hello");
+
+        assert_eq!(schc_steps(&network), ["decompressed at router1 (Up)"]);
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    #[cfg_attr(feature = "rt-custom", sittard::test)]
+    #[cfg_attr(feature = "rt-tokio", tokio::test(start_paused = true))]
+    async fn test_schc_uncompressed_packet_is_not_decompressed() {
+        let network = default_network()
+            .schc_constrained_links(vec!["router2-router1"])
+            .call();
+        enable_schc_compression(&network, &["router1"]);
+
+        // Router1 would decompress the packet, but nobody compressed it
+        let (contents, _) =
+            send_between_hosts(&network, CLIENT_ADDR, SERVER_ADDR, b"hello", None).await;
+        assert_eq!(contents, b"hello");
+        assert!(schc_steps(&network).is_empty());
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    #[cfg_attr(feature = "rt-custom", sittard::test)]
+    #[cfg_attr(feature = "rt-tokio", tokio::test(start_paused = true))]
+    async fn test_schc_compressed_packet_is_dropped_at_host() {
+        use crate::tracing::simulation_step::SimulationStepKind;
+
+        let network = default_network().call();
+        let compressor = no_compression_compressor();
+        let client_node = network.host(CLIENT_ADDR.as_ip_addr());
+
+        // Nobody decompresses the packet, so it never reaches the server's socket (the plain
+        // datagram sent after it does)
+        let data = compressed_by_client(&network, &compressor, b"compressed");
+        let packet_id = data.id;
+        network.forward(client_node.clone(), data);
+        let (contents, _) =
+            send_between_hosts(&network, CLIENT_ADDR, SERVER_ADDR, b"plain", None).await;
+        assert_eq!(contents, b"plain");
+
+        let steps = network.tracer.stepper().steps();
+        let dropped: Vec<_> = steps
+            .into_iter()
+            .filter_map(|step| match step.kind {
+                SimulationStepKind::SchcCompressedPacketDropped(dropped) => Some(dropped),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].packet_id, packet_id);
+        assert_eq!(&*dropped[0].node_id, "server");
+        assert_eq!(&*dropped[0].compressor_node_id, "client");
+        assert_eq!(dropped[0].rule_id, 0);
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    #[cfg_attr(feature = "rt-custom", sittard::test)]
+    #[cfg_attr(feature = "rt-tokio", tokio::test(start_paused = true))]
+    async fn test_schc_packet_size() {
+        use crate::tracing::simulation_step::SimulationStepKind;
+
+        let network = default_network().framing_overhead_bytes(4).call();
+        let server_node = network.host(SERVER_ADDR.as_ip_addr());
+        let client_node = network.host(CLIENT_ADDR.as_ip_addr());
+        let transmit = |contents: Vec<u8>| OwnedTransmit {
+            destination: server_node.quic_addr(),
+            ecn: None,
+            contents,
+            segment_size: None,
+        };
+
+        // IP datagrams carry IP and UDP headers, SCHC packets are as big as their contents
+        let plain = network.in_transit_data(client_node, transmit(vec![42; 100]));
+        assert_eq!(plain.packet_size(), 20 + 8 + 100);
+        let mut compressed = network.in_transit_data(client_node, transmit(vec![42; 10]));
+        compressed.schc_compressed = Some(compressed_by_router2(1, 9));
+        assert_eq!(compressed.packet_size(), 10);
+
+        // Both pay the framing overhead of every link they cross (the compressed packet is
+        // dropped when it reaches the server)
+        let plain_id = plain.id;
+        let compressed_id = compressed.id;
+        network.forward(client_node.clone(), compressed);
+        network.forward(client_node.clone(), plain);
+
+        let mut recv_result = BufsAndMeta::new(1200, 10);
+        let server_socket = network.udp_socket_for_node(server_node.clone());
+        let received = server_socket.receive_raw(&mut recv_result).await.unwrap();
+        assert_eq!(received, 1);
+
+        let steps = network.tracer.stepper().steps();
+        let frame_sizes = |packet_id| {
+            steps
+                .iter()
+                .filter_map(|step| match &step.kind {
+                    SimulationStepKind::PacketInTransit(transit)
+                        if transit.packet_id == packet_id =>
+                    {
+                        transit.frame_size_bytes
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(frame_sizes(plain_id), [132, 132, 132]);
+        assert_eq!(frame_sizes(compressed_id), [14, 14, 14]);
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    #[cfg_attr(feature = "rt-custom", sittard::test)]
+    #[cfg_attr(feature = "rt-tokio", tokio::test(start_paused = true))]
+    async fn test_schc_packet_is_delayed_by_its_compressed_size() {
+        // 1200 bytes of payload, as an IP datagram and behind a 3-byte SCHC header
+        let packets = [
+            (None, Duration::from_secs_f64(1.228)),
+            (
+                Some(compressed_by_router2(3, 1200)),
+                Duration::from_secs_f64(1.203),
+            ),
+        ];
+        let mut delays = Vec::new();
+        for (schc_compressed, expected_delay) in packets {
+            let network = default_network().bandwidth_bps(BANDWIDTH_8_KBPS).call();
+            let server_node = network.host(SERVER_ADDR.as_ip_addr());
+            let client_node = network.host(CLIENT_ADDR.as_ip_addr());
+            let contents_len = if schc_compressed.is_some() {
+                1203
+            } else {
+                1200
+            };
+
+            let mut packet_ids = Vec::new();
+            for _ in 0..2 {
+                let mut data = network.in_transit_data(
+                    client_node,
+                    OwnedTransmit {
+                        destination: server_node.quic_addr(),
+                        ecn: None,
+                        contents: vec![42; contents_len],
+                        segment_size: None,
+                    },
+                );
+                data.schc_compressed = schc_compressed.clone();
+
+                packet_ids.push(data.id);
+                network.forward(client_node.clone(), data);
+            }
+
+            // The second packet waits for the first one to be sent over the 8 kbps link between
+            // the routers
+            async_rt::time::sleep(Duration::from_secs(5)).await;
+            let stepper = network.tracer.stepper();
+            let arrival_times: Vec<_> = packet_ids
+                .into_iter()
+                .map(|packet_id| stepper.get_packet_arrived_at(packet_id, "router1").unwrap())
+                .collect();
+
+            let delay = arrival_times[1] - arrival_times[0];
+            assert_eq!(delay, expected_delay);
+            delays.push(delay);
+        }
+
+        assert!(delays[1] < delays[0]);
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    #[cfg_attr(feature = "rt-custom", sittard::test)]
+    #[cfg_attr(feature = "rt-tokio", tokio::test(start_paused = true))]
+    async fn test_schc_context_selection() {
+        use crate::schc_compressor::{SchcContext, SchcContextBinding, SharedSchcCompressor};
+        use std::collections::HashSet;
+        use std::sync::atomic::Ordering;
+
+        let bindings = [
+            (
+                SchcContextBinding::NodePair(["router1".into(), "router2".into()]),
+                true,
+            ),
+            (
+                SchcContextBinding::Links(HashSet::from(["router2-router1".into()])),
+                true,
+            ),
+            // Router2 compresses packets for router1, not for the client
+            (
+                SchcContextBinding::NodePair(["client".into(), "router2".into()]),
+                false,
+            ),
+        ];
+        for (binding, context_applies) in bindings {
+            let network = default_network()
+                .schc_constrained_links(vec!["router2-router1"])
+                .call();
+            let default_compressor = enable_schc_compression(&network, &["router2", "router1"]);
+            let context_compressor = no_compression_compressor();
+            network.schc_contexts.write().push(SchcContext {
+                id: "context".into(),
+                binding,
+                compressor: context_compressor.clone(),
+            });
+
+            let (contents, _) =
+                send_between_hosts(&network, CLIENT_ADDR, SERVER_ADDR, b"hello", None).await;
+            assert_eq!(contents, b"hello");
+
+            // The packet is compressed and decompressed with the context's compressor, falling
+            // back to the default one when the context doesn't apply
+            let (used, unused) = if context_applies {
+                (context_compressor, default_compressor)
+            } else {
+                (default_compressor, context_compressor)
+            };
+            let counts = |compressor: &SharedSchcCompressor| {
+                let stats = compressor.stats();
+                (
+                    stats.no_compression_fallbacks.load(Ordering::Relaxed),
+                    stats.no_compression_restored.load(Ordering::Relaxed),
+                )
+            };
+            assert_eq!(counts(&used), (1, 1));
+            assert_eq!(counts(&unused), (0, 0));
+        }
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    #[cfg_attr(feature = "rt-custom", sittard::test)]
+    #[cfg_attr(feature = "rt-tokio", tokio::test(start_paused = true))]
+    async fn test_schc_ecn_roundtrip() {
+        use quinn::udp::EcnCodepoint;
+
+        // ECT(0) survives compression and decompression, unless the packet is marked with CE
+        // while it crosses the constrained link
+        let congestion_event_ratios = [(0.0, EcnCodepoint::Ect0), (1.0, EcnCodepoint::Ce)];
+        for (congestion_event_ratio, expected_ecn) in congestion_event_ratios {
+            let network = default_network()
+                .schc_constrained_links(vec!["router2-router1"])
+                .schc_congestion_event_ratio(congestion_event_ratio)
+                .call();
+            enable_schc_compression(&network, &["router2", "router1"]);
+
+            let (contents, ecn) = send_between_hosts(
+                &network,
+                CLIENT_ADDR,
+                SERVER_ADDR,
+                b"hello",
+                Some(EcnCodepoint::Ect0),
+            )
+            .await;
+            assert_eq!(contents, b"hello");
+            assert_eq!(ecn, Some(expected_ecn));
+            assert_eq!(
+                schc_steps(&network),
+                ["compressed at router2 (Up)", "decompressed at router1 (Up)"]
+            );
+        }
+    }
 }
@@ -1,35 +1,35 @@
 use anyhow::{Context, anyhow, bail};
 use std::fmt::{Display, Formatter};
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::IpAddr;
 use std::str::FromStr;
 
 #[derive(Clone)]
-pub struct Ipv4Cidr {
-    pub(crate) address: Ipv4Addr,
+pub struct IpCidr {
+    pub(crate) address: IpAddr,
     pub(crate) network_prefix: u8,
 }
 
-impl Ipv4Cidr {
+impl IpCidr {
     #[cfg(test)]
-    pub const fn from_ipv4(address: Ipv4Addr, network_prefix: u8) -> Self {
+    pub const fn from_ipv4(address: std::net::Ipv4Addr, network_prefix: u8) -> Self {
         Self {
-            address,
+            address: IpAddr::V4(address),
             network_prefix,
         }
     }
 
     pub(crate) fn as_ip_addr(&self) -> IpAddr {
-        IpAddr::V4(self.address)
+        self.address
     }
 }
 
-impl Display for Ipv4Cidr {
+impl Display for IpCidr {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}/{}", self.address, self.network_prefix)
     }
 }
 
-impl FromStr for Ipv4Cidr {
+impl FromStr for IpCidr {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -40,21 +40,20 @@ impl FromStr for Ipv4Cidr {
             .parse()
             .context("invalid ip address in ip range")?;
 
-        let IpAddr::V4(base_ip) = base_ip else {
-            bail!("only IPv4 supported at the moment");
+        // A missing network prefix is interpreted as /32 for IPv4 and /128 for IPv6 (i.e. singleton
+        // ip range)
+        let max_network_prefix = if base_ip.is_ipv4() { 32 } else { 128 };
+        let network_prefix: u8 = match parts.next() {
+            Some(prefix) => prefix
+                .parse()
+                .context("the provided network prefix is not a valid unsigned integer")?,
+            None => max_network_prefix,
         };
-
-        // A missing network prefix is interpreted as /32 (i.e. singleton ip range)
-        let network_prefix: u8 = parts
-            .next()
-            .unwrap_or("32")
-            .parse()
-            .context("the provided network prefix is not a valid unsigned integer")?;
         if network_prefix == 0 {
             bail!("network prefix cannot be 0");
         }
-        if network_prefix > 32 {
-            bail!("network prefix cannot be higher than 32");
+        if network_prefix > max_network_prefix {
+            bail!("network prefix cannot be higher than {max_network_prefix}");
         }
 
         if parts.next().is_some() {
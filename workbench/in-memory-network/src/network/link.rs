@@ -31,6 +31,12 @@ pub struct NetworkLink {
     pub(crate) congestion_event_ratio: f64,
     pub(crate) extra_delay: Duration,
     pub(crate) extra_delay_ratio: f64,
+    #[cfg_attr(
+        not(feature = "schc-compressor"),
+        expect(dead_code, reason = "only used for SCHC compression")
+    )]
+    pub(crate) schc_constrained: bool,
+    pub(crate) framing_overhead_bytes: usize,
 }
 
 pub(crate) enum LinkStatus {
@@ -82,6 +88,8 @@ impl NetworkLink {
             congestion_event_ratio: l.congestion_event_ratio,
             extra_delay: l.extra_delay,
             extra_delay_ratio: l.extra_delay_ratio,
+            schc_constrained: l.schc_constrained,
+            framing_overhead_bytes: l.framing_overhead_bytes,
         }
     }
 
@@ -136,10 +144,15 @@ impl NetworkLink {
         // Send
         self.pacer
             .lock()
-            .track_send(Instant::now(), data.transmit.packet_size());
+            .track_send(Instant::now(), self.frame_size(&data));
         self.in_transit.lock().send(data, self.delay + extra_delay);
     }
 
+    /// The number of bytes the packet occupies on the link, including link-layer framing
+    pub(crate) fn frame_size(&self, data: &InTransitData) -> usize {
+        data.packet_size() + self.framing_overhead_bytes
+    }
+
     pub(crate) fn sleep_until_ready_to_send(
         this: Arc<Mutex<Self>>,
         cancellation_token: EventListener,
@@ -17,9 +17,19 @@ use crate::async_rt::time::Instant;
 use crate::network::event::{NetworkEventPayload, NetworkEvents};
 use crate::network::inbound_queue::InboundQueue;
 use crate::network::node::Node;
+#[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+use crate::network::spec::SchcRole;
 use crate::network::spec::{NetworkSpec, NodeKind};
-use crate::pcap_exporter::PcapExporterFactory;
+use crate::pcap_exporter::{PacketDirection, PcapExporter, PcapExporterFactory};
 use crate::quinn_interop::InMemoryUdpSocket;
+#[cfg(feature = "schc-compressor")]
+use crate::schc_compressor::SchcCompressionState;
+#[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+use crate::schc_node_roles::SchcNodeRole;
+#[cfg(feature = "schc-compressor")]
+use crate::schc_provisioning::INITIAL_CONTEXT_VERSION;
+#[cfg(feature = "schc-compressor")]
+use crate::tracing::simulation_step::SchcOperation;
 use crate::tracing::tracer::SimulationStepTracer;
 use crate::transmit::OwnedTransmit;
 use anyhow::{anyhow, bail};
@@ -32,6 +42,7 @@ use route::Route;
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
@@ -57,18 +68,36 @@ pub struct InMemoryNetwork {
     rng: Mutex<Rng>,
     next_transmit_number: AtomicU64,
     pcap_exporter_factory: Arc<dyn PcapExporterFactory>,
+    /// Captures of the sockets of the hosts
+    host_pcap_exporters: Mutex<Vec<Arc<PcapExporter>>>,
+    /// Captures of the packets arriving at, leaving and dropped by the selected nodes, by node id
+    node_pcap_exporters: HashMap<Arc<str>, PcapExporter>,
+    /// Captures of the packets sent over and lost by the selected links, by link id
+    link_pcap_exporters: HashMap<Arc<str>, PcapExporter>,
     /// SCHC observer for compression analysis (optional)
     #[cfg(feature = "schc-observer")]
     pub schc_observer: parking_lot::RwLock<Option<crate::schc_observer::SharedSchcObserver>>,
-    /// Node IDs where SCHC observer is active (None = all routers)
-    #[cfg(feature = "schc-observer")]
-    pub schc_enabled_nodes: parking_lot::RwLock<Option<std::collections::HashSet<Arc<str>>>>,
-    /// SCHC compressor for actual packet compression (optional)
+    /// SCHC compressor for actual packet compression (optional), used for the compressed segments
+    /// that no SCHC context applies to
     #[cfg(feature = "schc-compressor")]
     pub schc_compressor: parking_lot::RwLock<Option<crate::schc_compressor::SharedSchcCompressor>>,
-    /// Node IDs where SCHC compression is active
+    /// SCHC contexts bound to node pairs or links, each with its own compressor
+    #[cfg(feature = "schc-compressor")]
+    pub schc_contexts: parking_lot::RwLock<Vec<crate::schc_compressor::SchcContext>>,
+    /// Nodes where the SCHC observer and compressor are active
+    #[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+    pub schc_node_roles: parking_lot::RwLock<crate::schc_node_roles::SchcNodeRoles>,
+    /// SCHC fragmenter for packets exceeding the fragment MTU at compression nodes (optional)
+    #[cfg(feature = "schc-compressor")]
+    pub schc_fragmenter:
+        parking_lot::RwLock<Option<crate::schc_fragmentation::SharedSchcFragmenter>>,
+    /// In-band SCHC rule provisioning, sending rule updates over the network (optional)
     #[cfg(feature = "schc-compressor")]
-    pub schc_compress_nodes: parking_lot::RwLock<Option<std::collections::HashSet<Arc<str>>>>,
+    pub schc_provisioning:
+        parking_lot::RwLock<Option<crate::schc_provisioning::SharedSchcProvisioning>>,
+    /// Faults desynchronizing the SCHC decompressors from the compressors (optional)
+    #[cfg(feature = "schc-compressor")]
+    pub schc_desync: parking_lot::RwLock<Option<crate::schc_desync::SharedSchcDesync>>,
 }
 
 impl InMemoryNetwork {
@@ -125,6 +154,19 @@ impl InMemoryNetwork {
                 );
             }
 
+            // Dual-stack hosts are reachable through their other addresses too (e.g. by links)
+            for address in h.addresses().filter(|&a| a != endpoint.addr.ip()) {
+                let address_taken = nodes_by_addr.insert(address, h.clone());
+                if let Some(conflicting_node) = address_taken {
+                    bail!(
+                        "nodes {} and {} share the same address: {}",
+                        h.id,
+                        conflicting_node.id,
+                        address
+                    );
+                }
+            }
+
             nodes_and_outbound_rx.push((h, outbound_rx));
         }
 
@@ -179,6 +221,22 @@ impl InMemoryNetwork {
             nodes_and_outbound_rx.push((router, outbound_rx));
         }
 
+        let mut node_pcap_exporters = HashMap::new();
+        for (node, _) in &nodes_and_outbound_rx {
+            if let Some(exporter) =
+                pcap_exporter_factory.create_pcap_exporter_for_network_node(&node.id)?
+            {
+                node_pcap_exporters.insert(node.id.clone(), exporter);
+            }
+        }
+
+        let mut link_pcap_exporters = HashMap::new();
+        for link_id in links_by_id.keys() {
+            if let Some(exporter) = pcap_exporter_factory.create_pcap_exporter_for_link(link_id)? {
+                link_pcap_exporters.insert(link_id.clone(), exporter);
+            }
+        }
+
         let network = Arc::new(Self {
             nodes_by_addr: Arc::new(nodes_by_addr),
             routes_by_addr: Arc::new(routes_by_addr),
@@ -188,14 +246,23 @@ impl InMemoryNetwork {
             rng: Mutex::new(rng),
             next_transmit_number: Default::default(),
             pcap_exporter_factory,
+            host_pcap_exporters: Mutex::new(Vec::new()),
+            node_pcap_exporters,
+            link_pcap_exporters,
             #[cfg(feature = "schc-observer")]
             schc_observer: parking_lot::RwLock::new(None),
-            #[cfg(feature = "schc-observer")]
-            schc_enabled_nodes: parking_lot::RwLock::new(None),
             #[cfg(feature = "schc-compressor")]
             schc_compressor: parking_lot::RwLock::new(None),
             #[cfg(feature = "schc-compressor")]
-            schc_compress_nodes: parking_lot::RwLock::new(None),
+            schc_contexts: parking_lot::RwLock::new(Vec::new()),
+            #[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+            schc_node_roles: parking_lot::RwLock::new(Default::default()),
+            #[cfg(feature = "schc-compressor")]
+            schc_fragmenter: parking_lot::RwLock::new(None),
+            #[cfg(feature = "schc-compressor")]
+            schc_provisioning: parking_lot::RwLock::new(None),
+            #[cfg(feature = "schc-compressor")]
+            schc_desync: parking_lot::RwLock::new(None),
         });
 
         // Process node buffers in the background
@@ -311,9 +378,64 @@ impl InMemoryNetwork {
             .pcap_exporter_factory
             .create_pcap_exporter_for_node(&node.id)
             .unwrap();
+        let pcap_exporter = Arc::new(pcap_exporter);
+        self.host_pcap_exporters.lock().push(pcap_exporter.clone());
         InMemoryUdpSocket::from_node(self.clone(), node, pcap_exporter)
     }
 
+    /// Embeds the TLS key log in every capture (see [`PcapExporter::embed_tls_key_log`])
+    pub fn embed_tls_key_log(&self, key_log: &[u8]) -> anyhow::Result<()> {
+        for exporter in self.host_pcap_exporters.lock().iter() {
+            exporter.embed_tls_key_log(key_log)?;
+        }
+
+        for exporter in self
+            .node_pcap_exporters
+            .values()
+            .chain(self.link_pcap_exporters.values())
+        {
+            exporter.embed_tls_key_log(key_log)?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the captures of the nodes and links to their files, returning their paths
+    pub fn flush_pcap_exporters(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for exporter in self
+            .node_pcap_exporters
+            .values()
+            .chain(self.link_pcap_exporters.values())
+        {
+            exporter.flush()?;
+            paths.extend(exporter.path().map(Path::to_path_buf));
+        }
+
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Records the packet in the capture of the node (if the node is captured)
+    pub(crate) fn capture_at_node(
+        &self,
+        node: &Node,
+        data: &InTransitData,
+        direction: PacketDirection,
+        drop_reason: Option<&str>,
+    ) {
+        if let Some(exporter) = self.node_pcap_exporters.get(&node.id) {
+            exporter.track_in_network(self, data, Some(direction), drop_reason);
+        }
+    }
+
+    /// Records the packet in the capture of the link (if the link is captured)
+    fn capture_at_link(&self, link_id: &str, data: &InTransitData, drop_reason: Option<&str>) {
+        if let Some(exporter) = self.link_pcap_exporters.get(link_id) {
+            exporter.track_in_network(self, data, None, drop_reason);
+        }
+    }
+
     /// Returns the host bound to the provided address
     pub fn host(self: &InMemoryNetwork, ip: IpAddr) -> &Arc<Node> {
         let node = &self.nodes_by_addr[&ip];
@@ -321,6 +443,40 @@ impl InMemoryNetwork {
         node
     }
 
+    /// Returns the node with the provided id, if any
+    #[cfg(feature = "schc-compressor")]
+    pub(crate) fn node_by_id(&self, node_id: &str) -> Option<&Arc<Node>> {
+        self.nodes_by_addr
+            .values()
+            .find(|node| node.id.as_ref() == node_id)
+    }
+
+    /// Returns true if packets sent from the node to the destination reach it, following the links
+    /// that are chosen first at each hop
+    #[cfg(feature = "schc-compressor")]
+    pub(crate) fn has_route(&self, node: &Node, destination: IpAddr) -> bool {
+        let next_hop = |node: &Node| {
+            self.walk_links(node, destination, |link| {
+                ControlFlow::Break(link.lock().target)
+            })
+        };
+
+        // Bounded by the number of addresses, in case the routes contain a loop
+        let mut next_hop_addr = next_hop(node);
+        for _ in 0..self.nodes_by_addr.len() {
+            let Some(hop) = next_hop_addr.and_then(|addr| self.nodes_by_addr.get(&addr)) else {
+                return false;
+            };
+            if hop.addresses.contains(&destination) {
+                return true;
+            }
+
+            next_hop_addr = next_hop(hop);
+        }
+
+        false
+    }
+
     pub async fn assert_connectivity_between_hosts(
         self: &Arc<Self>,
         host_a: &Arc<Node>,
@@ -436,6 +592,10 @@ impl InMemoryNetwork {
         None
     }
 
+    pub(crate) fn next_packet_number(&self) -> u64 {
+        self.next_transmit_number.fetch_add(1, Ordering::Relaxed)
+    }
+
     pub(crate) fn in_transit_data(&self, source: &Node, transmit: OwnedTransmit) -> InTransitData {
         InTransitData {
             id: self.new_packet_id(),
@@ -443,130 +603,470 @@ impl InMemoryNetwork {
             source_id: source.id.clone(),
             source_endpoint: source.udp_endpoint.as_ref().unwrap().clone(),
             transmit,
-            number: self.next_transmit_number.fetch_add(1, Ordering::Relaxed),
+            number: self.next_packet_number(),
+            #[cfg(feature = "schc-compressor")]
+            schc_fragment: None,
+            #[cfg(feature = "schc-compressor")]
+            schc_compressed: None,
+            #[cfg(feature = "schc-compressor")]
+            schc_rule_update: None,
         }
     }
 
+    /// Sends a packet created by a SCHC F/R endpoint (a fragment or an ACK), or a rule update
+    /// chunk, from the provided node
+    #[cfg(feature = "schc-compressor")]
+    pub(crate) fn emit_schc_packet(self: &Arc<InMemoryNetwork>, node: &Node, data: InTransitData) {
+        self.tracer.track_schc_packet_created(node, &data);
+        self.tracer.track_packet_in_node(node, &data);
+        self.send_from_node(node, data);
+    }
+
     /// Forwards an [`InTransitData`] to the next node in the network.
     ///
     /// Resolves the link through which the packet should be sent and attempts to send it right
     /// away. If the link is temporarily unavailable or saturated, stores the packet in the node's
     /// buffer for later sending (or drops it when the buffer is full).
+    ///
+    /// Before that, the SCHC roles of the node are applied in a fixed order: first the observer
+    /// sees the packet as it arrived, then the compressor compresses or decompresses it.
     pub(crate) fn forward(
         self: &Arc<InMemoryNetwork>,
         current_node: Arc<Node>,
         data: InTransitData,
+    ) {
+        self.forward_from_link(current_node, data, None);
+    }
+
+    /// Like [`InMemoryNetwork::forward`], for packets that have just arrived to the node through a
+    /// link
+    fn forward_from_link(
+        self: &Arc<InMemoryNetwork>,
+        current_node: Arc<Node>,
+        data: InTransitData,
+        arrived_over_link: Option<&Arc<Mutex<NetworkLink>>>,
     ) {
         self.tracer.track_packet_in_node(&current_node, &data);
+        if arrived_over_link.is_some() {
+            self.capture_at_node(&current_node, &data, PacketDirection::Inbound, None);
+        }
+
+        // SCHC rule updates are installed by their target node, everywhere else they are forwarded
+        // as-is
+        #[cfg(feature = "schc-compressor")]
+        if data
+            .schc_rule_update
+            .as_ref()
+            .is_some_and(|update| update.is_for(&current_node.id))
+        {
+            if let Some(ref provisioning) = *self.schc_provisioning.read() {
+                provisioning.handle_chunk(self, &current_node, data);
+            }
+            return;
+        }
 
-        // SCHC Observer: analyze compression potential at router nodes
+        // SCHC Observer: analyze compression potential at the observer nodes, before the node's
+        // compressor (if any) touches the packet
         #[cfg(feature = "schc-observer")]
         if let Some(ref observer) = *self.schc_observer.read() {
-            let should_observe = match &*self.schc_enabled_nodes.read() {
-                Some(nodes) => nodes.contains(&current_node.id),
-                None => current_node.udp_endpoint.is_none(), // Default: all routers
-            };
+            let is_router = current_node.udp_endpoint.is_none();
+            let should_observe = self.schc_node_roles.read().has_role(
+                &current_node.id,
+                is_router,
+                SchcNodeRole::Observer,
+            );
             if should_observe {
-                // Direction based on IP addresses:
-                // Earth (192.168.40.x) → Moon (192.168.41.x) = OUT (Up)
-                // Moon (192.168.41.x) → Earth (192.168.40.x) = IN (Down)
-                let src_ip = data.source_endpoint.addr.ip();
-                let dst_ip = data.transmit.destination.ip();
-                let is_outgoing = match (src_ip, dst_ip) {
-                    (std::net::IpAddr::V4(src), std::net::IpAddr::V4(dst)) => {
-                        // Compare second octet: 40 = Earth, 41 = Moon
-                        src.octets()[2] < dst.octets()[2]
-                    }
-                    _ => true, // Default to outgoing for IPv6 or mixed
-                };
-                observer.observe(
-                    &data.transmit.contents,
-                    data.source_endpoint.addr,
-                    data.transmit.destination,
-                    is_outgoing,
-                );
+                if data.is_schc_rule_update() {
+                    // Rule updates carry no headers to compress
+                } else if data.is_schc_packet() {
+                    // Compressed upstream, so there are no headers left to match rules against
+                    observer.observe_schc_packet(
+                        data.packet_size(),
+                        data.is_schc_fragment(),
+                        &current_node.id,
+                    );
+                } else if let Some(is_outgoing) = self.schc_is_uplink(&data) {
+                    observer.observe(
+                        &data.transmit.contents,
+                        data.source_endpoint.addr,
+                        data.transmit.destination,
+                        data.transmit.ecn,
+                        is_outgoing,
+                        &current_node.id,
+                    );
+                } else {
+                    // The rules can't be matched without knowing the direction, so the packet is
+                    // counted and reported instead
+                    observer.skip_without_direction(&current_node.id);
+                }
             }
         }
 
-        // SCHC Compressor: actually compress/decompress packets at designated nodes
+        // SCHC Compressor: actually compress/decompress packets at the compressor nodes
         #[cfg(feature = "schc-compressor")]
         let mut data = data; // Make data mutable for compression
         #[cfg(feature = "schc-compressor")]
-        if let Some(ref compressor) = *self.schc_compressor.read() {
-            let should_compress = match &*self.schc_compress_nodes.read() {
-                Some(nodes) => nodes.contains(&current_node.id),
-                None => false, // Compression must be explicitly enabled per-node
-            };
+        if self.schc_compressor.read().is_some() || !self.schc_contexts.read().is_empty() {
+            // Compression must be explicitly enabled per-node
+            let should_compress = self.schc_node_roles.read().has_role(
+                &current_node.id,
+                false,
+                SchcNodeRole::Compressor,
+            );
+            let fragmenter = self.schc_fragmenter.read().clone();
             if should_compress {
-                // Determine direction based on IP addresses
-                let src_ip = data.source_endpoint.addr.ip();
-                let dst_ip = data.transmit.destination.ip();
-                let is_outgoing = match (src_ip, dst_ip) {
-                    (std::net::IpAddr::V4(src), std::net::IpAddr::V4(dst)) => {
-                        src.octets()[2] < dst.octets()[2]
-                    }
-                    _ => true,
-                };
-
-                // Determine if this node is "near source" or "near destination"
-                // by checking if any of the node's interfaces are on the source subnet
-                let src_subnet = match src_ip {
-                    std::net::IpAddr::V4(ip) => ip.octets()[2],
-                    _ => 0,
-                };
-                let dst_subnet = match dst_ip {
-                    std::net::IpAddr::V4(ip) => ip.octets()[2],
-                    _ => 0,
-                };
-                
-                // Check if this node has an interface on source subnet (near source)
-                let is_near_source = current_node.addresses.iter().any(|addr| {
-                    if let std::net::IpAddr::V4(ip) = addr {
-                        ip.octets()[2] == src_subnet
-                    } else {
-                        false
+                let is_outgoing = self.schc_is_uplink(&data);
+
+                // Compression/decompression logic based on the node's position relative to the
+                // SCHC-constrained links:
+                // - Next hop crosses a constrained link → COMPRESS
+                // - Packet just arrived over a constrained link → DECOMPRESS
+                // - Both (in the middle of a constrained segment) or neither → forward as-is
+                let arrived_over_schc_constrained_link =
+                    arrived_over_link.is_some_and(|link| link.lock().schc_constrained);
+                let next_hop_is_schc_constrained =
+                    self.next_hop_is_schc_constrained(&current_node, &data);
+                let should_do_compression =
+                    next_hop_is_schc_constrained && !arrived_over_schc_constrained_link;
+                let should_do_decompression =
+                    arrived_over_schc_constrained_link && !next_hop_is_schc_constrained;
+
+                // SCHC F/R messages are only handled by the F/R endpoints, everywhere else they
+                // are forwarded as-is
+                if let Some(fragment) = data.schc_fragment.clone() {
+                    match &fragmenter {
+                        Some(fragmenter) if fragment.is_ack_for(&current_node.id) => {
+                            fragmenter.handle_ack(self, &current_node, data);
+                            return;
+                        }
+                        Some(fragmenter) if fragment.is_fragment() && should_do_decompression => {
+                            // Reassemble before decompressing
+                            match fragmenter.handle_fragment(self, &current_node, data) {
+                                Some(reassembled) => data = reassembled,
+                                None => return,
+                            }
+                        }
+                        _ => {}
                     }
-                });
-                
-                // Check if this node has an interface on destination subnet (near destination)
-                let is_near_dest = current_node.addresses.iter().any(|addr| {
-                    if let std::net::IpAddr::V4(ip) = addr {
-                        ip.octets()[2] == dst_subnet
-                    } else {
-                        false
-                    }
-                });
-
-                // Compression/decompression logic based on node position:
-                // - Near packet SOURCE → COMPRESS (before constrained link)
-                // - Near packet DEST → DECOMPRESS (after constrained link)
-                // This works for both UP (Earth→Moon) and DOWN (Moon→Earth) directions
-                let should_do_compression = is_near_source && !is_near_dest;
-                let should_do_decompression = is_near_dest && !is_near_source;
-
-                if should_do_compression {
-                    // Compress packet for transmission
-                    let result = compressor.compress(
-                        &data.transmit.contents,
-                        data.source_endpoint.addr,
-                        data.transmit.destination,
-                        is_outgoing,
-                        current_node.id.as_ref(),
-                    );
-                    if result.success {
-                        data.transmit.contents = result.compressed_packet;
+                }
+
+                // Only uncompressed packets are compressed, and only compressed packets are
+                // decompressed
+                let is_compressed = data.schc_compressed.is_some();
+                match is_outgoing {
+                    // Not ours to compress or decompress
+                    _ if data.schc_fragment.is_some() || data.schc_rule_update.is_some() => {}
+                    Some(is_outgoing) if should_do_compression && !is_compressed => {
+                        // Compress packet for transmission (packets without a compressor are
+                        // forwarded uncompressed)
+                        let context_id = self.schc_context_for_compression(&current_node, &data);
+                        let compressor =
+                            self.schc_compressor_for_context(&current_node, context_id.as_ref());
+                        let size_before = data.packet_size();
+                        let result = compressor.as_ref().map(|(compressor, _)| {
+                            compressor.compress(
+                                &data.transmit.contents,
+                                data.source_endpoint.addr,
+                                data.transmit.destination,
+                                data.transmit.ecn,
+                                is_outgoing,
+                                current_node.id.as_ref(),
+                            )
+                        });
+                        match result {
+                            Some(result) if result.success => {
+                                let uncompressed = std::mem::replace(
+                                    &mut data.transmit.contents,
+                                    result.compressed_packet,
+                                );
+                                data.schc_compressed = Some(SchcCompressionState {
+                                    rule_id: result.rule_id,
+                                    rule_id_length: compressor.as_ref().and_then(
+                                        |(compressor, _)| compressor.rule_id_length(result.rule_id),
+                                    ),
+                                    compressor_id: current_node.id.clone(),
+                                    context_id,
+                                    context_version: compressor
+                                        .as_ref()
+                                        .map_or(INITIAL_CONTEXT_VERSION, |(_, version)| *version),
+                                    header_size: result.compressed_header_size,
+                                    original_size: uncompressed.len(),
+                                    original_datagram: result.original_datagram.map(Arc::from),
+                                });
+                                self.tracer.track_schc_packet_compressed(
+                                    &current_node,
+                                    &data,
+                                    size_before,
+                                    &uncompressed,
+                                    is_outgoing,
+                                );
+                            }
+                            Some(_) => self.tracer.track_schc_processing_failed(
+                                &current_node,
+                                &data,
+                                SchcOperation::Compress,
+                                is_outgoing,
+                            ),
+                            None => {}
+                        }
+
+                        // Fragment the SCHC packet if it doesn't fit in a single frame (packets that
+                        // could not be compressed are still IP datagrams, so they are left as-is)
+                        if let Some(fragmenter) = &fragmenter {
+                            if data.schc_compressed.is_none() {
+                                fragmenter.skip_uncompressed(&current_node, &data);
+                            } else {
+                                match fragmenter.fragment(self, &current_node, data) {
+                                    Some(unfragmented) => data = unfragmented,
+                                    None => return,
+                                }
+                            }
+                        }
                     }
-                } else if should_do_decompression {
-                    // Decompress received packet
-                    if let Ok(result) = compressor.decompress(&data.transmit.contents, is_outgoing, current_node.id.as_ref()) {
-                        data.transmit.contents = result.decompressed_packet;
+                    Some(is_outgoing) if should_do_decompression && is_compressed => {
+                        // Decompress received packet, with the context it was compressed with
+                        let context_id = data
+                            .schc_compressed
+                            .as_ref()
+                            .and_then(|state| state.context_id.as_ref());
+                        let compressor =
+                            self.schc_compressor_for_context(&current_node, context_id);
+                        // The decompressor may not have received the rule update the compressor
+                        // used (or the other way around)
+                        let version_mismatch = match (&compressor, &data.schc_compressed) {
+                            (Some((_, version)), Some(state))
+                                if *version != state.context_version =>
+                            {
+                                Some((state.context_version, *version))
+                            }
+                            _ => None,
+                        };
+                        let provisioning = self.schc_provisioning.read().clone();
+                        if let (Some((compressed_with, decompressed_with)), Some(provisioning)) =
+                            (version_mismatch, provisioning)
+                        {
+                            provisioning.version_mismatch(
+                                &current_node,
+                                compressed_with,
+                                decompressed_with,
+                            );
+                        }
+
+                        let context_version = compressor
+                            .as_ref()
+                            .map_or(INITIAL_CONTEXT_VERSION, |(_, version)| *version);
+
+                        // Desynchronization faults mutate the packet, or swap the node's rules
+                        let desync = self.schc_desync.read().clone();
+                        let applied_fault = desync
+                            .as_ref()
+                            .zip(data.schc_compressed.as_ref())
+                            .and_then(|(desync, state)| {
+                                desync.apply(
+                                    &current_node.id,
+                                    &mut data.transmit.contents,
+                                    state,
+                                    compressor.as_ref().map(|(compressor, _)| compressor),
+                                )
+                            });
+                        let compressor = match applied_fault
+                            .as_ref()
+                            .and_then(|applied| applied.compressor.clone())
+                        {
+                            Some(replacement) => Some(replacement),
+                            None => compressor.map(|(compressor, _)| compressor),
+                        };
+
+                        let size_before = data.packet_size();
+                        let result = compressor.map(|compressor| {
+                            compressor.decompress(
+                                &data.transmit.contents,
+                                data.schc_compressed
+                                    .as_ref()
+                                    .and_then(|state| state.original_datagram.as_deref()),
+                                is_outgoing,
+                                current_node.id.as_ref(),
+                            )
+                        });
+                        if let Some(desync) = &desync {
+                            let success = matches!(result, Some(Ok(_)));
+                            desync.decompressed(&current_node.id, applied_fault, success);
+                        }
+
+                        match result {
+                            Some(Ok(result)) => {
+                                // Restore the reconstructed ECN codepoint, keeping congestion marks
+                                // received over the compressed segment (like a tunnel egress would)
+                                let marked_in_transit = data.transmit.ecn.is_some_and(is_ce);
+                                data.transmit.ecn = match result.ecn {
+                                    Some(Some(ecn)) if marked_in_transit && is_ect(ecn) => {
+                                        EcnCodepoint::from_bits(0b11)
+                                    }
+                                    Some(ecn) => ecn,
+                                    None => data.transmit.ecn,
+                                };
+                                data.transmit.contents = result.decompressed_packet;
+                                data.schc_compressed = None;
+                                self.tracer.track_schc_packet_decompressed(
+                                    &current_node,
+                                    &data,
+                                    result.rule_id,
+                                    context_version,
+                                    size_before,
+                                    is_outgoing,
+                                );
+                            }
+                            Some(Err(_)) => self.tracer.track_schc_processing_failed(
+                                &current_node,
+                                &data,
+                                SchcOperation::Decompress,
+                                is_outgoing,
+                            ),
+                            None => {}
+                        }
                     }
+                    // Packets whose direction can't be derived from the SCHC roles are forwarded
+                    // as-is
+                    _ => {}
                 }
             }
         }
 
+        self.send_from_node(&current_node, data);
+    }
+
+    /// Derives the SCHC direction of a packet from the roles of its source and destination nodes
+    ///
+    /// Returns `Some(true)` for UP, `Some(false)` for DOWN, and `None` when neither endpoint has a
+    /// SCHC role
+    #[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+    pub(crate) fn schc_is_uplink(&self, data: &InTransitData) -> Option<bool> {
+        let role_of = |ip| self.nodes_by_addr.get(&ip).and_then(|n| n.schc_role);
+        let source_role = role_of(data.source_endpoint.addr.ip());
+        let destination_role = role_of(data.transmit.destination.ip());
+        match (source_role, destination_role) {
+            (Some(SchcRole::Device), _) | (_, Some(SchcRole::Application)) => Some(true),
+            (Some(SchcRole::Application), _) | (_, Some(SchcRole::Device)) => Some(false),
+            (None, None) => None,
+        }
+    }
+
+    /// Returns true if the link the node would use to send the packet towards its destination is
+    /// SCHC-constrained
+    #[cfg(feature = "schc-compressor")]
+    fn next_hop_is_schc_constrained(&self, node: &Node, data: &InTransitData) -> bool {
+        self.next_hop_link(node, data)
+            .is_some_and(|link| link.lock().schc_constrained)
+    }
+
+    /// The link the node would use to send the packet towards its destination, if any
+    #[cfg(feature = "schc-compressor")]
+    fn next_hop_link(&self, node: &Node, data: &InTransitData) -> Option<Arc<Mutex<NetworkLink>>> {
+        if let Some(udp_endpoint) = &node.udp_endpoint {
+            if udp_endpoint.addr == data.transmit.destination {
+                // The packet has arrived, there is no next hop
+                return None;
+            }
+        }
+
+        self.walk_links(node, data.transmit.destination.ip(), |link| {
+            ControlFlow::Break(link.clone())
+        })
+    }
+
+    /// The node that will decompress the packet once it leaves the SCHC-constrained segment it is
+    /// about to enter (i.e. the first node on its path whose next hop is not constrained)
+    #[cfg(feature = "schc-compressor")]
+    fn schc_segment_end(&self, node: &Node, data: &InTransitData) -> Option<Arc<Node>> {
+        let next_node = |node: &Node| {
+            let link = self.next_hop_link(node, data)?;
+            let target = link.lock().target;
+            self.nodes_by_addr.get(&target).cloned()
+        };
+
+        // Bounded by the number of addresses, in case the routes contain a loop
+        let mut current = next_node(node)?;
+        for _ in 0..self.nodes_by_addr.len() {
+            if !self.next_hop_is_schc_constrained(&current, data) {
+                return Some(current);
+            }
+
+            current = next_node(&current)?;
+        }
+
+        None
+    }
+
+    /// The SCHC context of a packet about to enter a SCHC-constrained segment: the first one that
+    /// applies to the segment, or `None` if the default rules apply
+    #[cfg(feature = "schc-compressor")]
+    fn schc_context_for_compression(&self, node: &Node, data: &InTransitData) -> Option<Arc<str>> {
+        let contexts = self.schc_contexts.read();
+        if contexts.is_empty() {
+            return None;
+        }
+
+        let link_id = self
+            .next_hop_link(node, data)
+            .map(|link| link.lock().id.clone());
+        let decompressor = self.schc_segment_end(node, data);
+        let context = contexts.iter().find(|context| {
+            context.applies_to(
+                link_id.as_deref(),
+                &node.id,
+                decompressor.as_ref().map(|n| n.id.as_ref()),
+            )
+        })?;
+        Some(context.id.clone())
+    }
+
+    /// The compressor the node currently uses for the SCHC context (or for the default rules, for
+    /// `None`), along with the context version: the last rule update it installed, or the
+    /// compressor loaded at startup
+    #[cfg(feature = "schc-compressor")]
+    fn schc_compressor_for_context(
+        &self,
+        node: &Node,
+        context_id: Option<&Arc<str>>,
+    ) -> Option<(crate::schc_compressor::SharedSchcCompressor, u32)> {
+        let provisioning = self.schc_provisioning.read();
+        let installed = provisioning
+            .as_ref()
+            .and_then(|provisioning| provisioning.installed_context(&node.id, context_id));
+
+        installed.or_else(|| {
+            let loaded = match context_id {
+                Some(context_id) => self
+                    .schc_contexts
+                    .read()
+                    .iter()
+                    .find(|context| context.id == *context_id)
+                    .map(|context| context.compressor.clone()),
+                None => self.schc_compressor.read().clone(),
+            };
+            Some((loaded?, INITIAL_CONTEXT_VERSION))
+        })
+    }
+
+    /// Delivers the packet to the node's application, or stores it in the node's outbound buffer
+    /// so it is sent to the next hop
+    fn send_from_node(self: &Arc<InMemoryNetwork>, current_node: &Node, data: InTransitData) {
         if let Some(udp_endpoint) = &current_node.udp_endpoint {
             if udp_endpoint.addr == data.transmit.destination {
+                // SCHC-compressed bytes are meaningless to the application
+                #[cfg(feature = "schc-compressor")]
+                if let Some(state) = &data.schc_compressed {
+                    self.tracer
+                        .track_schc_compressed_packet_dropped(current_node, &data, state);
+                    self.capture_at_node(
+                        current_node,
+                        &data,
+                        PacketDirection::Inbound,
+                        Some("SCHC-compressed packet at host"),
+                    );
+                    return;
+                }
+
                 // The packet has arrived to a quinn endpoint, so we forward it directly to the nodes's
                 // inbound queue (from where it will be automatically picked up by quinn)
                 udp_endpoint
@@ -596,7 +1096,13 @@ impl InMemoryNetwork {
         }
 
         if randomly_dropped {
-            self.tracer.track_dropped_randomly(&data, &current_node);
+            self.tracer.track_dropped_randomly(&data, current_node);
+            self.capture_at_node(
+                current_node,
+                &data,
+                PacketDirection::Outbound,
+                Some("injected packet loss"),
+            );
             return;
         }
 
@@ -614,7 +1120,7 @@ impl InMemoryNetwork {
                 true,
                 Duration::default(),
                 false,
-                &current_node,
+                current_node,
             );
 
             current_node.enqueue_outbound(self, duplicate);
@@ -657,11 +1163,17 @@ async fn process_buffer_for_node(
                     "Network error: missing link to {} ({path})",
                     data.transmit.destination
                 );
+                network.capture_at_node(
+                    &node,
+                    &data,
+                    PacketDirection::Outbound,
+                    Some("no route to destination"),
+                );
                 return;
             }
         };
 
-        node.outbound_buffer().release(data.transmit.packet_size());
+        node.outbound_buffer().release(data.packet_size());
         let congestion_experienced;
         let mut extra_delay = Duration::from_secs(0);
 
@@ -675,23 +1187,33 @@ async fn process_buffer_for_node(
             congestion_experienced = network.rng.lock().f64() < link.congestion_event_ratio;
         }
 
-        if congestion_experienced {
-            // The Quinn-provided transmit must indicate support for ECN
-            assert!(
-                data.transmit
-                    .ecn
-                    .is_some_and(|codepoint| codepoint as u8 == 0b10 || codepoint as u8 == 0b01)
-            );
-
+        // Only ECN-capable packets can be marked (the rules may reconstruct a Not-ECT header
+        // after decompression, even if Quinn sent the packet with ECT)
+        if congestion_experienced && data.transmit.ecn.is_some_and(is_ect) {
             // Set explicit congestion event codepoint
             data.transmit.ecn = Some(EcnCodepoint::from_bits(0b11).unwrap())
         }
 
+        network.capture_at_node(&node, &data, PacketDirection::Outbound, None);
+        let link_id = link.lock().id.clone();
+        network.capture_at_link(&link_id, &data, None);
+
         link.lock().send(&node, data, extra_delay);
         link.lock().notify_packet_sent.notify(usize::MAX);
     }
 }
 
+/// Returns true if the codepoint is ECT(0) or ECT(1)
+fn is_ect(codepoint: EcnCodepoint) -> bool {
+    codepoint as u8 == 0b10 || codepoint as u8 == 0b01
+}
+
+/// Returns true if the codepoint is CE
+#[cfg(feature = "schc-compressor")]
+fn is_ce(codepoint: EcnCodepoint) -> bool {
+    codepoint as u8 == 0b11
+}
+
 fn spawn_packet_forwarders(network: Arc<InMemoryNetwork>) {
     for link in network.links_by_id.values() {
         let network = network.clone();
@@ -719,12 +1241,13 @@ async fn forward_packets_for_link(network: Arc<InMemoryNetwork>, link: Arc<Mutex
                 let link = link.lock();
                 if link.was_down_after(transmit.sent) {
                     network.tracer.track_lost_in_transit(&transmit.data, &link);
+                    network.capture_at_link(&link.id, &transmit.data, Some("link went down"));
                     continue;
                 }
             }
 
-            let node = &network.nodes_by_addr[&link.lock().target];
-            network.forward(node.clone(), transmit.data);
+            let node = network.nodes_by_addr[&link.lock().target].clone();
+            network.forward_from_link(node, transmit.data, Some(&link));
         }
     }
 }
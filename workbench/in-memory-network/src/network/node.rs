@@ -2,7 +2,8 @@ use crate::network::InMemoryNetwork;
 use crate::network::inbound_queue::InboundQueue;
 use crate::network::link::NetworkLink;
 use crate::network::outbound_buffer::OutboundBuffer;
-use crate::network::spec::{NetworkNodeSpec, NodeKind};
+use crate::network::spec::{NetworkNodeSpec, NodeKind, SchcRole};
+use crate::pcap_exporter::PacketDirection;
 use crate::{HOST_PORT, InTransitData};
 use anyhow::bail;
 use event_listener::Event;
@@ -16,6 +17,7 @@ pub struct Node {
     pub(crate) id: Arc<str>,
     pub(crate) udp_endpoint: Option<Arc<UdpEndpoint>>,
     pub(crate) injected_failures: NodeInjectedFailures,
+    pub(crate) schc_role: Option<SchcRole>,
     outbound_buffer: Arc<OutboundBuffer>,
     outbound_tx: futures::channel::mpsc::UnboundedSender<InTransitData>,
 }
@@ -41,6 +43,8 @@ impl Node {
             bail!("Host {} has an interface without any address", node.id);
         }
 
+        // QUIC runs over the first address of the host (other addresses only make the host
+        // reachable, e.g. when it is dual-stack)
         let addresses = node.addresses();
         let quic_address = addresses[0];
         let quinn_endpoint = Arc::new(UdpEndpoint {
@@ -51,6 +55,7 @@ impl Node {
         let (tx, rx) = futures::channel::mpsc::unbounded();
         let host = Self {
             injected_failures: NodeInjectedFailures::from_spec(&node),
+            schc_role: node.schc_role,
             id: node.id.into(),
             addresses,
             outbound_buffer: Arc::new(OutboundBuffer::new(node.buffer_size_bytes as usize)),
@@ -74,6 +79,7 @@ impl Node {
         let (tx, rx) = futures::channel::mpsc::unbounded();
         let node = Node {
             injected_failures: NodeInjectedFailures::from_spec(&node),
+            schc_role: node.schc_role,
             id: node.id.into(),
             addresses,
             outbound_buffer: Arc::new(OutboundBuffer::new(node.buffer_size_bytes as usize)),
@@ -110,7 +116,7 @@ impl Node {
     pub(crate) fn enqueue_outbound(&self, network: &Arc<InMemoryNetwork>, data: InTransitData) {
         // Try to enqueue the data on the node's outbound buffer for later sending
         let outbound_buffer = self.outbound_buffer();
-        let data_len = data.transmit.packet_size();
+        let data_len = data.packet_size();
 
         if outbound_buffer.reserve(data_len) {
             // The buffer has capacity!
@@ -118,6 +124,12 @@ impl Node {
         } else {
             // The buffer is full and the packet is being dropped
             network.tracer.track_dropped_from_buffer(&data, self);
+            network.capture_at_node(
+                self,
+                &data,
+                PacketDirection::Outbound,
+                Some("outbound buffer full"),
+            );
         }
     }
 
@@ -133,6 +145,10 @@ impl Node {
         self.addresses.clone().into_iter()
     }
 
+    pub fn schc_role(&self) -> Option<SchcRole> {
+        self.schc_role
+    }
+
     pub fn outbound_buffer(&self) -> Arc<OutboundBuffer> {
         self.outbound_buffer.clone()
     }
@@ -1,5 +1,5 @@
-use crate::network::ip::Ipv4Cidr;
-use std::net::{IpAddr, Ipv4Addr};
+use crate::network::ip::IpCidr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
 
 #[derive(Clone)]
@@ -11,7 +11,9 @@ pub struct Route {
 
 impl Route {
     pub(crate) fn next_hop_towards_destination(&self, ip: IpAddr) -> Option<IpAddr> {
-        if (self.destination.start..=self.destination.end_inclusive).contains(&ip) {
+        // IPv4 routes only match IPv4 destinations, and IPv6 routes only match IPv6 destinations
+        let same_family = self.destination.start.is_ipv4() == ip.is_ipv4();
+        if same_family && (self.destination.start..=self.destination.end_inclusive).contains(&ip) {
             Some(self.next)
         } else {
             None
@@ -26,15 +28,30 @@ pub struct IpRange {
 }
 
 impl IpRange {
-    pub fn from_cidr(addr: Ipv4Cidr) -> Self {
-        let base_ip_bits = addr.address.to_bits();
-        let mask: u32 = u32::MAX << (32 - addr.network_prefix);
-        let start = Ipv4Addr::from_bits(base_ip_bits & mask);
-        let end_inclusive = Ipv4Addr::from_bits(base_ip_bits | (!mask));
+    pub fn from_cidr(addr: IpCidr) -> Self {
+        match addr.address {
+            IpAddr::V4(base_ip) => {
+                let base_ip_bits = base_ip.to_bits();
+                let mask: u32 = u32::MAX << (32 - addr.network_prefix);
+                let start = Ipv4Addr::from_bits(base_ip_bits & mask);
+                let end_inclusive = Ipv4Addr::from_bits(base_ip_bits | (!mask));
 
-        Self {
-            start: IpAddr::V4(start),
-            end_inclusive: IpAddr::V4(end_inclusive),
+                Self {
+                    start: IpAddr::V4(start),
+                    end_inclusive: IpAddr::V4(end_inclusive),
+                }
+            }
+            IpAddr::V6(base_ip) => {
+                let base_ip_bits = base_ip.to_bits();
+                let mask: u128 = u128::MAX << (128 - addr.network_prefix as u32);
+                let start = Ipv6Addr::from_bits(base_ip_bits & mask);
+                let end_inclusive = Ipv6Addr::from_bits(base_ip_bits | (!mask));
+
+                Self {
+                    start: IpAddr::V6(start),
+                    end_inclusive: IpAddr::V6(end_inclusive),
+                }
+            }
         }
     }
 }
@@ -42,9 +59,9 @@ impl IpRange {
 impl FromStr for IpRange {
     type Err = anyhow::Error;
 
-    // Parse ranges in CIDR syntax (e.g. 10.0.0.0/24)
+    // Parse ranges in CIDR syntax (e.g. 10.0.0.0/24 or 2001:db8::/32)
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let addr = Ipv4Cidr::from_str(s)?;
+        let addr = IpCidr::from_str(s)?;
         Ok(Self::from_cidr(addr))
     }
 }
@@ -57,6 +74,17 @@ fn test_ip_range() {
         ("10.0.0.0/8", "10.0.0.0", "10.255.255.255"),
         ("10.0.0.0/8", "10.0.0.0", "10.255.255.255"),
         ("20.0.0.0/12", "20.0.0.0", "20.15.255.255"),
+        (
+            "2001:db8::/32",
+            "2001:db8::",
+            "2001:db8:ffff:ffff:ffff:ffff:ffff:ffff",
+        ),
+        (
+            "2001:db8::1/64",
+            "2001:db8::",
+            "2001:db8::ffff:ffff:ffff:ffff",
+        ),
+        ("2001:db8::1", "2001:db8::1", "2001:db8::1"),
     ];
 
     for (input, range_start, range_end_inclusive) in cases {
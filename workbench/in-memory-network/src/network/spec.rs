@@ -1,4 +1,4 @@
-use crate::network::ip::Ipv4Cidr;
+use crate::network::ip::IpCidr;
 use crate::network::route::Route;
 use std::net::IpAddr;
 use std::sync::Arc;
@@ -18,6 +18,9 @@ pub struct NetworkNodeSpec {
     pub interfaces: Vec<NetworkInterface>,
     pub packet_loss_ratio: f64,
     pub packet_duplication_ratio: f64,
+    /// The SCHC role of the node, used to derive the direction (UP/DOWN) of packets sent to or
+    /// from it
+    pub schc_role: Option<SchcRole>,
 }
 
 impl NetworkNodeSpec {
@@ -40,9 +43,19 @@ pub enum NodeKind {
     Router,
 }
 
+/// The side of a SCHC deployment a node belongs to (RFC 8724, section 3)
+///
+/// Packets sent by a device (or to an application) travel UP, and packets sent by an application
+/// (or to a device) travel DOWN.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SchcRole {
+    Device,
+    Application,
+}
+
 #[derive(Clone)]
 pub struct NetworkInterface {
-    pub addresses: Vec<Ipv4Cidr>,
+    pub addresses: Vec<IpCidr>,
     pub routes: Vec<Route>,
 }
 
@@ -56,4 +69,9 @@ pub struct NetworkLinkSpec {
     pub congestion_event_ratio: f64,
     pub extra_delay: Duration,
     pub extra_delay_ratio: f64,
+    /// Whether the link is a SCHC-constrained link, i.e. packets crossing it should travel
+    /// compressed
+    pub schc_constrained: bool,
+    /// Link-layer framing overhead added to every packet sent over the link, in bytes
+    pub framing_overhead_bytes: usize,
 }
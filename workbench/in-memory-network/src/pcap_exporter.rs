@@ -1,24 +1,69 @@
+use crate::InTransitData;
 use crate::async_rt::time::Instant;
-use anyhow::Context;
+use crate::network::InMemoryNetwork;
+use anyhow::{Context, bail};
 use parking_lot::Mutex;
 use pcap_file::pcapng::PcapNgWriter;
-use pcap_file::pcapng::blocks::enhanced_packet::EnhancedPacketBlock;
+use pcap_file::pcapng::blocks::enhanced_packet::{EnhancedPacketBlock, EnhancedPacketOption};
 use pcap_file::pcapng::blocks::interface_description::InterfaceDescriptionBlock;
 use pcap_file::pcapng::blocks::section_header::SectionHeaderBlock;
 use pcap_file::{DataLink, Endianness};
 use pnet_packet::ip::IpNextHeaderProtocol;
 use pnet_packet::ipv4::MutableIpv4Packet;
+use pnet_packet::ipv6::MutableIpv6Packet;
 use pnet_packet::udp::MutableUdpPacket;
 use pnet_packet::{PacketSize, ipv4, udp};
 use quinn::udp::Transmit;
+use std::collections::HashSet;
 use std::fs;
 use std::io::{BufWriter, Write};
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
+/// Interface used for IPv4 packets in the pcapng file
+const IPV4_INTERFACE_ID: u32 = 0;
+/// Interface used for IPv6 packets in the pcapng file
+const IPV6_INTERFACE_ID: u32 = 1;
+/// Interface used for SCHC frames in the pcapng file (see [`schc_pcap`](crate::schc_pcap))
+#[cfg(feature = "schc-compressor")]
+const SCHC_INTERFACE_ID: u32 = 2;
+
+/// Block types of the pcapng section header and interface descriptions
+const SHB_BLOCK_TYPE: u32 = 0x0A0D_0D0A;
+const IDB_BLOCK_TYPE: u32 = 0x0000_0001;
+/// Block type of the pcapng Decryption Secrets Block
+const DSB_BLOCK_TYPE: u32 = 0x0000_000A;
+/// Secrets type of a TLS key log in a Decryption Secrets Block
+const DSB_SECRETS_TYPE_TLS_KEY_LOG: u32 = 0x544C_534B;
+
+/// Direction bits of the pcapng `epb_flags` option
+const EPB_FLAGS_INBOUND: u32 = 0b01;
+const EPB_FLAGS_OUTBOUND: u32 = 0b10;
+
 pub trait PcapExporterFactory: Send + Sync {
+    /// Creates the exporter for the socket of a host, capturing the datagrams it sends and
+    /// receives
     fn create_pcap_exporter_for_node(&self, node_id: &str) -> anyhow::Result<PcapExporter>;
+
+    /// Creates the exporter capturing the packets arriving at, leaving and dropped by a node
+    /// inside the network, or `None` if the node isn't captured
+    fn create_pcap_exporter_for_network_node(
+        &self,
+        _node_id: &str,
+    ) -> anyhow::Result<Option<PcapExporter>> {
+        Ok(None)
+    }
+
+    /// Creates the exporter capturing the packets sent over a link and those lost in transit, or
+    /// `None` if the link isn't captured
+    fn create_pcap_exporter_for_link(
+        &self,
+        _link_id: &str,
+    ) -> anyhow::Result<Option<PcapExporter>> {
+        Ok(None)
+    }
 }
 
 pub struct NoOpPcapExporterFactory;
@@ -28,19 +73,116 @@ impl PcapExporterFactory for NoOpPcapExporterFactory {
     }
 }
 
-pub struct FileBasedPcapExporterFactory;
+/// The nodes or links captured inside the network
+#[derive(Clone, Debug, Default)]
+pub enum PcapSelection {
+    #[default]
+    None,
+    All,
+    Only(HashSet<String>),
+}
+
+impl PcapSelection {
+    /// Parses a list of ids, where `all` selects everything
+    pub fn parse(ids: &[String]) -> Self {
+        let mut selection = Self::None;
+        for id in ids {
+            selection.add(id.trim());
+        }
+        selection
+    }
+
+    pub fn add(&mut self, id: &str) {
+        match self {
+            Self::All => {}
+            _ if id == "all" => *self = Self::All,
+            Self::None => *self = Self::Only(HashSet::from([id.to_string()])),
+            Self::Only(ids) => {
+                ids.insert(id.to_string());
+            }
+        }
+    }
+
+    pub fn includes(&self, id: &str) -> bool {
+        match self {
+            Self::None => false,
+            Self::All => true,
+            Self::Only(ids) => ids.contains(id),
+        }
+    }
+
+    /// Fails if the selection contains ids other than the provided ones
+    pub fn validate<'a>(
+        &self,
+        kind: &str,
+        ids: impl IntoIterator<Item = &'a str>,
+    ) -> anyhow::Result<()> {
+        let Self::Only(selected) = self else {
+            return Ok(());
+        };
+
+        let known: HashSet<_> = ids.into_iter().collect();
+        let mut unknown: Vec<_> = selected
+            .iter()
+            .filter(|id| !known.contains(id.as_str()))
+            .map(|id| id.as_str())
+            .collect();
+        if !unknown.is_empty() {
+            unknown.sort();
+            bail!("cannot capture unknown {kind}(s): {}", unknown.join(", "));
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the captures to pcapng files in `dir`: `<NODE>.pcap` for the sockets of the hosts,
+/// `<NODE>.node.pcap` for the selected nodes and `<LINK>.link.pcap` for the selected links
+#[derive(Default)]
+pub struct FileBasedPcapExporterFactory {
+    /// Directory where the captures are written (the current directory if empty)
+    pub dir: PathBuf,
+    pub nodes: PcapSelection,
+    pub links: PcapSelection,
+}
+
 impl PcapExporterFactory for FileBasedPcapExporterFactory {
     fn create_pcap_exporter_for_node(&self, node_id: &str) -> anyhow::Result<PcapExporter> {
-        let file_name = format!("{node_id}.pcap");
-        let pcap_file = fs::File::create(&file_name)
-            .with_context(|| format!("failed to open {file_name} for writing"))?;
-        Ok(PcapExporter::new(pcap_file))
+        PcapExporter::create(self.dir.join(format!("{node_id}.pcap")))
+    }
+
+    fn create_pcap_exporter_for_network_node(
+        &self,
+        node_id: &str,
+    ) -> anyhow::Result<Option<PcapExporter>> {
+        if !self.nodes.includes(node_id) {
+            return Ok(None);
+        }
+
+        PcapExporter::create(self.dir.join(format!("{node_id}.node.pcap"))).map(Some)
+    }
+
+    fn create_pcap_exporter_for_link(&self, link_id: &str) -> anyhow::Result<Option<PcapExporter>> {
+        if !self.links.includes(link_id) {
+            return Ok(None);
+        }
+
+        PcapExporter::create(self.dir.join(format!("{link_id}.link.pcap"))).map(Some)
     }
 }
 
+/// The direction of a packet captured at a node
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PacketDirection {
+    Inbound,
+    Outbound,
+}
+
 pub struct PcapExporter {
     capture_start: Instant,
     total_tracked_packets: AtomicU64,
+    /// The file the capture is written to (if any)
+    path: Option<PathBuf>,
     writer: Mutex<PcapNgWriter<BufWriter<Box<dyn Write + Send + Sync + 'static>>>>,
 }
 
@@ -59,21 +201,40 @@ impl PcapExporter {
         )
         .unwrap();
 
-        writer
-            .write_pcapng_block(InterfaceDescriptionBlock {
-                linktype: DataLink::IPV4,
-                snaplen: 65535,
-                options: vec![],
-            })
-            .unwrap();
+        // One interface per IP version and one for SCHC frames, in the order of their interface ids
+        for linktype in [DataLink::IPV4, DataLink::IPV6, DataLink::USER0] {
+            writer
+                .write_pcapng_block(InterfaceDescriptionBlock {
+                    linktype,
+                    snaplen: 65535,
+                    options: vec![],
+                })
+                .unwrap();
+        }
 
         Self {
             capture_start: Instant::now(),
             writer: Mutex::new(writer),
             total_tracked_packets: AtomicU64::new(0),
+            path: None,
         }
     }
 
+    /// Creates an exporter writing to the file at `path`
+    pub fn create(path: PathBuf) -> anyhow::Result<Self> {
+        let pcap_file = fs::File::create(&path)
+            .with_context(|| format!("failed to open {} for writing", path.display()))?;
+        Ok(Self {
+            path: Some(path),
+            ..Self::new(pcap_file)
+        })
+    }
+
+    /// The file the capture is written to (if any)
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
     pub fn noop() -> Self {
         Self::new(std::io::sink())
     }
@@ -86,58 +247,94 @@ impl PcapExporter {
             .context("failed to flush pcap writer")
     }
 
-    pub fn track_transmit(&self, source_addr: SocketAddr, transmit: &Transmit) {
-        let IpAddr::V4(source) = source_addr.ip() else {
-            unreachable!()
+    /// Embeds the TLS key log in the capture as a Decryption Secrets Block, so Wireshark decrypts
+    /// the QUIC traffic on its own
+    ///
+    /// Wireshark only applies secrets to the packets following them, so the file is rewritten
+    /// with the block before the first packet. Packets tracked afterwards are appended as usual.
+    pub fn embed_tls_key_log(&self, key_log: &[u8]) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
         };
+        if key_log.is_empty() {
+            return Ok(());
+        }
 
-        let IpAddr::V4(destination) = transmit.destination.ip() else {
-            unreachable!()
-        };
+        let mut writer = self.writer.lock();
+        writer
+            .get_mut()
+            .flush()
+            .with_context(|| format!("failed to flush {}", path.display()))?;
+
+        let capture =
+            fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let capture = embed_decryption_secrets(&capture, key_log)
+            .with_context(|| format!("invalid pcapng file at {}", path.display()))?;
+        fs::write(path, capture).with_context(|| format!("failed to write {}", path.display()))?;
+
+        let file = fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open {} for writing", path.display()))?;
+        *writer.get_mut() = BufWriter::new(Box::new(file));
+        Ok(())
+    }
 
-        let mut buffer = vec![0; 2000];
-
-        // Wrap the data in a UDP packet
-        let mut udp_writer = MutableUdpPacket::new(&mut buffer).unwrap();
-        let udp_packet_length = 8 + transmit.contents.len() as u16;
-        udp_writer.set_source(source_addr.port());
-        udp_writer.set_destination(transmit.destination.port());
-        udp_writer.set_length(udp_packet_length);
-        udp_writer.set_payload(transmit.contents);
-        let checksum = udp::ipv4_checksum(&udp_writer.to_immutable(), &source, &destination);
-        udp_writer.set_checksum(checksum);
-        drop(udp_writer);
-        let udp_packet = buffer[0..udp_packet_length as usize].to_vec();
-
-        // Wrap the UDP packet in an IP packet
-        let mut ip_writer = MutableIpv4Packet::new(&mut buffer).unwrap();
-        let ip_packet_length = 20 + udp_packet_length;
-        ip_writer.set_version(4);
-        ip_writer.set_header_length(5); // We don't use options
-        ip_writer.set_dscp(0); // Copied from a Wireshark dump
-        ip_writer.set_identification(0); // We never fragment
-        ip_writer.set_flags(0b010); // We never fragment
-        ip_writer.set_fragment_offset(0); // We never fragment
-        ip_writer.set_ttl(64);
-        ip_writer.set_next_level_protocol(IpNextHeaderProtocol::new(17)); // 17 = UDP
-        ip_writer.set_source(source);
-        ip_writer.set_destination(destination);
-        ip_writer.set_payload(&udp_packet);
-        ip_writer.set_total_length(ip_packet_length);
-        ip_writer.set_ecn(transmit.ecn.map(|codepoint| codepoint as u8).unwrap_or(0));
-        let checksum = ipv4::checksum(&ip_writer.to_immutable());
-        ip_writer.set_checksum(checksum);
-        let ip_packet_length = ip_writer.packet_size();
-        drop(ip_writer);
-
-        let ip_packet = buffer[0..ip_packet_length].to_vec();
+    /// Records a packet travelling through the network, as seen at a node (with its direction)
+    /// or at a link (without direction). Dropped packets carry the reason in their comment.
+    pub(crate) fn track_in_network(
+        &self,
+        network: &InMemoryNetwork,
+        data: &InTransitData,
+        direction: Option<PacketDirection>,
+        drop_reason: Option<&str>,
+    ) {
+        let (interface_id, packet, mut comment) = network_frame(network, data);
+        if data.duplicate {
+            comment.push_str(" (injected duplicate)");
+        }
+        if let Some(reason) = drop_reason {
+            comment = format!("DROPPED ({reason}): {comment}");
+        }
+
+        let mut options = vec![EnhancedPacketOption::Comment(comment.into())];
+        match direction {
+            Some(PacketDirection::Inbound) => {
+                options.push(EnhancedPacketOption::Flags(EPB_FLAGS_INBOUND))
+            }
+            Some(PacketDirection::Outbound) => {
+                options.push(EnhancedPacketOption::Flags(EPB_FLAGS_OUTBOUND))
+            }
+            None => {}
+        }
+
+        self.total_tracked_packets.fetch_add(1, Ordering::Relaxed);
+
+        let mut writer = self.writer.lock();
+        writer
+            .write_pcapng_block(EnhancedPacketBlock {
+                interface_id,
+                timestamp: correct_timestamp(self.capture_start.elapsed()),
+                original_len: packet.len() as u32,
+                data: packet.into(),
+                options,
+            })
+            .unwrap();
+    }
+
+    pub fn track_transmit(&self, source_addr: SocketAddr, transmit: &Transmit) {
+        let interface_id = match source_addr {
+            SocketAddr::V4(_) => IPV4_INTERFACE_ID,
+            SocketAddr::V6(_) => IPV6_INTERFACE_ID,
+        };
+        let ip_packet = ip_packet(source_addr, transmit);
 
         self.total_tracked_packets.fetch_add(1, Ordering::Relaxed);
 
         let mut writer = self.writer.lock();
         writer
             .write_pcapng_block(EnhancedPacketBlock {
-                interface_id: 0,
+                interface_id,
                 timestamp: correct_timestamp(self.capture_start.elapsed()),
                 original_len: ip_packet.len() as u32,
                 data: ip_packet.into(),
@@ -147,6 +344,163 @@ impl PcapExporter {
     }
 }
 
+/// Inserts a Decryption Secrets Block with the TLS key log before the first packet of the capture,
+/// or returns `None` if the capture is malformed
+///
+/// The capture is expected to be big endian, as written by [`PcapExporter`].
+fn embed_decryption_secrets(capture: &[u8], key_log: &[u8]) -> Option<Vec<u8>> {
+    // The packets start after the section header and interface descriptions
+    let mut packets_start = 0;
+    while packets_start < capture.len() {
+        let header = capture.get(packets_start..packets_start + 8)?;
+        let block_type = u32::from_be_bytes(header[0..4].try_into().unwrap());
+        let block_len = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+        if block_type != SHB_BLOCK_TYPE && block_type != IDB_BLOCK_TYPE {
+            break;
+        }
+
+        if block_len < 12 || packets_start + block_len > capture.len() {
+            return None;
+        }
+
+        packets_start += block_len;
+    }
+
+    let padded_len = key_log.len().next_multiple_of(4);
+    let block_len = (20 + padded_len) as u32;
+    let mut embedded = Vec::with_capacity(capture.len() + block_len as usize);
+    embedded.extend_from_slice(&capture[..packets_start]);
+    embedded.extend_from_slice(&DSB_BLOCK_TYPE.to_be_bytes());
+    embedded.extend_from_slice(&block_len.to_be_bytes());
+    embedded.extend_from_slice(&DSB_SECRETS_TYPE_TLS_KEY_LOG.to_be_bytes());
+    embedded.extend_from_slice(&(key_log.len() as u32).to_be_bytes());
+    embedded.extend_from_slice(key_log);
+    embedded.resize(embedded.len() + padded_len - key_log.len(), 0);
+    embedded.extend_from_slice(&block_len.to_be_bytes());
+    embedded.extend_from_slice(&capture[packets_start..]);
+    Some(embedded)
+}
+
+/// The interface, bytes and description of a packet travelling through the network: SCHC packets
+/// as SCHC frames, and the rest as IP packets
+#[cfg_attr(
+    not(feature = "schc-compressor"),
+    expect(unused_variables, reason = "only used for SCHC frames")
+)]
+fn network_frame(network: &InMemoryNetwork, data: &InTransitData) -> (u32, Vec<u8>, String) {
+    #[cfg(feature = "schc-compressor")]
+    if let Some((frame, comment)) = crate::schc_pcap::schc_frame(network, data) {
+        return (SCHC_INTERFACE_ID, frame, comment);
+    }
+
+    let source_addr = data.source_endpoint.addr;
+    let interface_id = match source_addr {
+        SocketAddr::V4(_) => IPV4_INTERFACE_ID,
+        SocketAddr::V6(_) => IPV6_INTERFACE_ID,
+    };
+    let packet = ip_packet(source_addr, &data.transmit.as_transmit());
+    (interface_id, packet, format!("Packet #{}", data.number))
+}
+
+/// Wraps the transmit's contents in a UDP datagram and an IPv4 or IPv6 packet (depending on the
+/// source and destination addresses)
+fn ip_packet(source_addr: SocketAddr, transmit: &Transmit) -> Vec<u8> {
+    match (source_addr.ip(), transmit.destination.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            ipv4_packet(source_addr, source, destination, transmit)
+        }
+        (IpAddr::V6(source), IpAddr::V6(destination)) => {
+            ipv6_packet(source_addr, source, destination, transmit)
+        }
+        _ => unreachable!("source and destination must use the same IP version"),
+    }
+}
+
+fn udp_packet(
+    source_addr: SocketAddr,
+    transmit: &Transmit,
+    checksum: impl FnOnce(&udp::UdpPacket) -> u16,
+) -> Vec<u8> {
+    let mut buffer = vec![0; 2000];
+
+    // Wrap the data in a UDP packet
+    let mut udp_writer = MutableUdpPacket::new(&mut buffer).unwrap();
+    let udp_packet_length = 8 + transmit.contents.len() as u16;
+    udp_writer.set_source(source_addr.port());
+    udp_writer.set_destination(transmit.destination.port());
+    udp_writer.set_length(udp_packet_length);
+    udp_writer.set_payload(transmit.contents);
+    let checksum = checksum(&udp_writer.to_immutable());
+    udp_writer.set_checksum(checksum);
+    drop(udp_writer);
+
+    buffer[0..udp_packet_length as usize].to_vec()
+}
+
+fn ipv4_packet(
+    source_addr: SocketAddr,
+    source: Ipv4Addr,
+    destination: Ipv4Addr,
+    transmit: &Transmit,
+) -> Vec<u8> {
+    let udp_packet = udp_packet(source_addr, transmit, |udp| {
+        udp::ipv4_checksum(udp, &source, &destination)
+    });
+    let mut buffer = vec![0; 2000];
+
+    // Wrap the UDP packet in an IP packet
+    let mut ip_writer = MutableIpv4Packet::new(&mut buffer).unwrap();
+    let ip_packet_length = 20 + udp_packet.len() as u16;
+    ip_writer.set_version(4);
+    ip_writer.set_header_length(5); // We don't use options
+    ip_writer.set_dscp(0); // Copied from a Wireshark dump
+    ip_writer.set_identification(0); // We never fragment
+    ip_writer.set_flags(0b010); // We never fragment
+    ip_writer.set_fragment_offset(0); // We never fragment
+    ip_writer.set_ttl(64);
+    ip_writer.set_next_level_protocol(IpNextHeaderProtocol::new(17)); // 17 = UDP
+    ip_writer.set_source(source);
+    ip_writer.set_destination(destination);
+    ip_writer.set_total_length(ip_packet_length);
+    ip_writer.set_payload(&udp_packet);
+    ip_writer.set_ecn(transmit.ecn.map(|codepoint| codepoint as u8).unwrap_or(0));
+    let checksum = ipv4::checksum(&ip_writer.to_immutable());
+    ip_writer.set_checksum(checksum);
+    let ip_packet_length = ip_writer.packet_size();
+    drop(ip_writer);
+
+    buffer[0..ip_packet_length].to_vec()
+}
+
+fn ipv6_packet(
+    source_addr: SocketAddr,
+    source: Ipv6Addr,
+    destination: Ipv6Addr,
+    transmit: &Transmit,
+) -> Vec<u8> {
+    let udp_packet = udp_packet(source_addr, transmit, |udp| {
+        udp::ipv6_checksum(udp, &source, &destination)
+    });
+    let mut buffer = vec![0; 2000];
+
+    // Wrap the UDP packet in an IP packet
+    let mut ip_writer = MutableIpv6Packet::new(&mut buffer).unwrap();
+    ip_writer.set_version(6);
+    // The ECN codepoint lives in the two low bits of the traffic class
+    ip_writer.set_traffic_class(transmit.ecn.map(|codepoint| codepoint as u8).unwrap_or(0));
+    ip_writer.set_flow_label(0);
+    ip_writer.set_payload_length(udp_packet.len() as u16);
+    ip_writer.set_next_header(IpNextHeaderProtocol::new(17)); // 17 = UDP
+    ip_writer.set_hop_limit(64);
+    ip_writer.set_source(source);
+    ip_writer.set_destination(destination);
+    ip_writer.set_payload(&udp_packet);
+    let ip_packet_length = ip_writer.packet_size();
+    drop(ip_writer);
+
+    buffer[0..ip_packet_length].to_vec()
+}
+
 fn correct_timestamp(d: Duration) -> Duration {
     // Round to the nearest millisecond
     let millis = (d.as_secs_f64() * 1000.0).round();
@@ -155,3 +509,57 @@ fn correct_timestamp(d: Duration) -> Duration {
     // using, which multiplies seconds by 1000)
     Duration::from_secs_f64(millis / 1_000_000.0)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pcap_selection() {
+        let selection = PcapSelection::parse(&["Router1".to_string(), " Router2".to_string()]);
+        assert!(selection.includes("Router2"));
+        assert!(!selection.includes("Router3"));
+        assert!(selection.validate("node", ["Router1", "Router2"]).is_ok());
+        assert!(selection.validate("node", ["Router1"]).is_err());
+
+        let mut selection = PcapSelection::default();
+        assert!(!selection.includes("Router1"));
+        selection.add("all");
+        selection.add("Router1");
+        assert!(selection.includes("Router3"));
+        assert!(selection.validate("node", []).is_ok());
+    }
+
+    #[test]
+    fn test_embed_decryption_secrets() {
+        let block = |block_type: u32, body: &[u8]| {
+            let block_len = (12 + body.len() as u32).to_be_bytes();
+            [&block_type.to_be_bytes(), &block_len, body, &block_len].concat()
+        };
+        let header = [
+            block(SHB_BLOCK_TYPE, &[0; 16]),
+            block(IDB_BLOCK_TYPE, &[0; 8]),
+        ]
+        .concat();
+        let packet = block(6, &[0; 24]);
+        let capture = [header.as_slice(), &packet].concat();
+
+        let key_log = b"CLIENT_TRAFFIC_SECRET_0 00 11\n";
+        let embedded = embed_decryption_secrets(&capture, key_log).unwrap();
+        let dsb = &embedded[header.len()..embedded.len() - packet.len()];
+        assert_eq!(embedded[..header.len()], header);
+        assert_eq!(embedded[embedded.len() - packet.len()..], packet);
+        assert_eq!(dsb.len(), 52);
+        assert_eq!(dsb[0..4], DSB_BLOCK_TYPE.to_be_bytes());
+        assert_eq!(dsb[4..8], 52u32.to_be_bytes());
+        assert_eq!(dsb[8..12], DSB_SECRETS_TYPE_TLS_KEY_LOG.to_be_bytes());
+        assert_eq!(dsb[12..16], (key_log.len() as u32).to_be_bytes());
+        assert_eq!(dsb[16..16 + key_log.len()], key_log[..]);
+        assert_eq!(dsb[48..52], 52u32.to_be_bytes());
+
+        // Captures without packets get the secrets at the end, and truncated ones are rejected
+        let embedded = embed_decryption_secrets(&header, key_log).unwrap();
+        assert_eq!(embedded.len(), header.len() + 52);
+        assert!(embed_decryption_secrets(&header[..header.len() - 1], key_log).is_none());
+    }
+}
@@ -0,0 +1,595 @@
+//! QUIC header protection removal for SCHC processing
+//!
+//! QUIC masks the first byte and the packet number of every packet (RFC 9001, section 5.4), so a
+//! SCHC gateway that doesn't know the connection's keys sees random values in those fields. The
+//! key log written by the client (see `SSLKEYLOGFILE`) holds the connection's traffic secrets,
+//! which we use to remove header protection before rule matching and to reapply it after
+//! decompression. Initial keys are derived from the connection IDs of Initial packets, like any
+//! on-path observer can.
+//!
+//! The key log doesn't tell which connection a secret belongs to, so the keys of a packet are
+//! found by trial decryption of its payload (the payload itself is never modified). Key updates
+//! are not followed, so packets protected with updated keys keep their header protection.
+
+use parking_lot::Mutex;
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::hkdf;
+use std::collections::{BTreeSet, HashSet};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/// Initial salt of QUIC v1 (RFC 9001, section 5.2)
+const INITIAL_SALT_V1: [u8; 20] = [
+    0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17, 0x9a, 0xe6, 0xa4, 0xc8, 0x0c, 0xad,
+    0xcc, 0xbb, 0x7f, 0x0a,
+];
+const SAMPLE_LEN: usize = 16;
+const MAX_PN_LEN: usize = 4;
+const IV_LEN: usize = 12;
+
+/// Removes and reapplies QUIC header protection, using the secrets of a TLS key log file
+pub struct QuicKeyLog {
+    path: PathBuf,
+    state: Mutex<KeyLogState>,
+}
+
+impl QuicKeyLog {
+    /// Creates a key log reading secrets from the provided file, starting after its first
+    /// `skip_bytes` bytes
+    ///
+    /// The file doesn't need to exist yet: it is (re)loaded whenever a packet can't be matched to
+    /// the keys we know of, since the secrets are logged as the handshakes progress. Key log files
+    /// are appended to, so the secrets of previous runs are skipped by passing the length the file
+    /// had before the run started.
+    pub fn new(path: impl Into<PathBuf>, skip_bytes: u64) -> Self {
+        Self {
+            path: path.into(),
+            state: Mutex::new(KeyLogState {
+                loaded_bytes: skip_bytes,
+                ..KeyLogState::default()
+            }),
+        }
+    }
+
+    /// Returns a copy of the datagram without header protection, or `None` if the keys of its
+    /// packets are unknown
+    ///
+    /// Coalesced packets whose keys are unknown keep their header protection.
+    pub fn unprotect(&self, datagram: &[u8]) -> Option<Vec<u8>> {
+        self.process(datagram, Operation::Unprotect)
+    }
+
+    /// Returns a copy of the datagram with header protection reapplied, or `None` if the keys of
+    /// its packets are unknown
+    ///
+    /// Packets are only protected if their header is unprotected (i.e. their payload
+    /// authenticates), so packets that kept their header protection are left as-is.
+    pub fn protect(&self, datagram: &[u8]) -> Option<Vec<u8>> {
+        self.process(datagram, Operation::Protect)
+    }
+
+    fn process(&self, datagram: &[u8], operation: Operation) -> Option<Vec<u8>> {
+        let mut state = self.state.lock();
+        let mut datagram = datagram.to_vec();
+        let mut processed_any = false;
+        let mut start = 0;
+        while start < datagram.len() {
+            let packet = &mut datagram[start..];
+            let Some(mut outcome) = state.process_packet(packet, operation) else {
+                // The rest of the datagram can't be parsed, so leave it untouched
+                break;
+            };
+
+            if !outcome.processed && state.reload(&self.path) {
+                outcome = state.process_packet(packet, operation).unwrap_or(outcome);
+            }
+
+            processed_any |= outcome.processed;
+            start += outcome.len;
+        }
+
+        processed_any.then_some(datagram)
+    }
+}
+
+/// The outcome of processing one of the (coalesced) packets of a datagram
+#[derive(Clone, Copy)]
+struct PacketOutcome {
+    len: usize,
+    /// Whether the packet's keys were found (i.e. its header protection was removed or reapplied)
+    processed: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Unprotect,
+    Protect,
+}
+
+/// The packet number space of a packet, which determines the keys protecting it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum KeySpace {
+    Initial,
+    ZeroRtt,
+    Handshake,
+    OneRtt,
+}
+
+#[derive(Default)]
+struct KeyLogState {
+    keys: Vec<PacketKeys>,
+    /// Number of bytes of the key log file that were already loaded (or skipped)
+    loaded_bytes: u64,
+    /// Destination connection IDs of the Initial packets we derived keys from
+    initial_dcids: HashSet<Vec<u8>>,
+    /// Lengths of the connection IDs seen in long headers, used to locate the packet number of
+    /// short header packets
+    cid_lengths: BTreeSet<usize>,
+}
+
+impl KeyLogState {
+    /// Loads the secrets that were appended to the key log file since the last load, returning
+    /// true if there were new secrets
+    fn reload(&mut self, path: &PathBuf) -> bool {
+        let Ok(mut file) = File::open(path) else {
+            return false;
+        };
+        let Ok(len) = file.metadata().map(|m| m.len()) else {
+            return false;
+        };
+        if len < self.loaded_bytes {
+            // The file was truncated, so start over
+            self.loaded_bytes = 0;
+        }
+
+        // Only read what was appended since the last load
+        let mut contents = Vec::new();
+        let read = file
+            .seek(SeekFrom::Start(self.loaded_bytes))
+            .and_then(|_| file.read_to_end(&mut contents));
+        if read.is_err() {
+            return false;
+        }
+
+        // Only load complete lines, the rest of the file may still be being written
+        let Some(end) = contents.iter().rposition(|&b| b == b'\n').map(|i| i + 1) else {
+            return false;
+        };
+        let new_lines = String::from_utf8_lossy(&contents[..end]);
+        self.loaded_bytes += end as u64;
+
+        let keys_before = self.keys.len();
+        for line in new_lines.lines() {
+            let mut parts = line.split_whitespace();
+            let (Some(label), Some(_client_random), Some(secret)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+
+            let space = match label {
+                "CLIENT_EARLY_TRAFFIC_SECRET" => KeySpace::ZeroRtt,
+                "CLIENT_HANDSHAKE_TRAFFIC_SECRET" | "SERVER_HANDSHAKE_TRAFFIC_SECRET" => {
+                    KeySpace::Handshake
+                }
+                "CLIENT_TRAFFIC_SECRET_0" | "SERVER_TRAFFIC_SECRET_0" => KeySpace::OneRtt,
+                _ => continue,
+            };
+
+            let Some(secret) = decode_hex(secret) else {
+                continue;
+            };
+
+            // The secret's length tells the hash of the cipher suite, but not its AEAD
+            for suite in CipherSuite::for_secret_len(secret.len()) {
+                self.keys.extend(PacketKeys::derive(space, &suite, &secret));
+            }
+        }
+
+        self.keys.len() > keys_before
+    }
+
+    /// Derives the client and server Initial keys of a connection
+    fn add_initial_keys(&mut self, dcid: &[u8]) {
+        if !self.initial_dcids.insert(dcid.to_vec()) {
+            return;
+        }
+
+        let suite = CipherSuite::aes_128_gcm();
+        let initial_secret = hkdf::Salt::new(hkdf::HKDF_SHA256, &INITIAL_SALT_V1).extract(dcid);
+        for label in [b"client in".as_slice(), b"server in"] {
+            let keys = expand_label(&initial_secret, label, 32)
+                .and_then(|secret| PacketKeys::derive(KeySpace::Initial, &suite, &secret));
+            self.keys.extend(keys);
+        }
+    }
+
+    /// Processes the packet at the start of the buffer, or returns `None` if its header can't be
+    /// parsed
+    fn process_packet(&mut self, packet: &mut [u8], operation: Operation) -> Option<PacketOutcome> {
+        let layouts = match parse_header(packet)? {
+            Header::Long {
+                layout,
+                dcid,
+                scid_len,
+            } => {
+                self.cid_lengths.insert(dcid.len());
+                self.cid_lengths.insert(scid_len);
+                if layout.space == KeySpace::Initial {
+                    self.add_initial_keys(&dcid);
+                }
+
+                vec![layout]
+            }
+            Header::Short => self
+                .cid_lengths
+                .iter()
+                .map(|&cid_len| PacketLayout {
+                    space: KeySpace::OneRtt,
+                    long_header: false,
+                    pn_offset: 1 + cid_len,
+                    end: packet.len(),
+                })
+                .collect(),
+            Header::Unprotected => {
+                return Some(PacketOutcome {
+                    len: packet.len(),
+                    processed: false,
+                });
+            }
+        };
+
+        for layout in &layouts {
+            for keys in self.keys.iter_mut().filter(|k| k.space == layout.space) {
+                if keys.process(packet, layout, operation).is_some() {
+                    return Some(PacketOutcome {
+                        len: layout.end,
+                        processed: true,
+                    });
+                }
+            }
+        }
+
+        // Short header packets extend until the end of the datagram
+        let len = layouts.first().map_or(packet.len(), |layout| layout.end);
+        Some(PacketOutcome {
+            len,
+            processed: false,
+        })
+    }
+}
+
+struct CipherSuite {
+    hkdf: hkdf::Algorithm,
+    aead: &'static aead::Algorithm,
+    header_protection: &'static aead::quic::Algorithm,
+}
+
+impl CipherSuite {
+    fn aes_128_gcm() -> Self {
+        Self {
+            hkdf: hkdf::HKDF_SHA256,
+            aead: &aead::AES_128_GCM,
+            header_protection: &aead::quic::AES_128,
+        }
+    }
+
+    /// The TLS 1.3 cipher suites whose hash produces secrets of the provided length
+    fn for_secret_len(len: usize) -> Vec<Self> {
+        match len {
+            32 => vec![
+                Self::aes_128_gcm(),
+                Self {
+                    hkdf: hkdf::HKDF_SHA256,
+                    aead: &aead::CHACHA20_POLY1305,
+                    header_protection: &aead::quic::CHACHA20,
+                },
+            ],
+            48 => vec![Self {
+                hkdf: hkdf::HKDF_SHA384,
+                aead: &aead::AES_256_GCM,
+                header_protection: &aead::quic::AES_256,
+            }],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// The keys protecting the packets of one endpoint in one packet number space
+struct PacketKeys {
+    space: KeySpace,
+    header: aead::quic::HeaderProtectionKey,
+    packet: LessSafeKey,
+    iv: [u8; IV_LEN],
+    /// The largest packet number protected by these keys, used to decode truncated packet numbers
+    largest_pn: Option<u64>,
+}
+
+impl PacketKeys {
+    /// Derives the keys from a traffic secret (RFC 9001, section 5.1)
+    fn derive(space: KeySpace, suite: &CipherSuite, secret: &[u8]) -> Option<Self> {
+        let prk = hkdf::Prk::new_less_safe(suite.hkdf, secret);
+        let key = expand_label(&prk, b"quic key", suite.aead.key_len())?;
+        let iv = expand_label(&prk, b"quic iv", IV_LEN)?;
+        let hp = expand_label(&prk, b"quic hp", suite.header_protection.key_len())?;
+        Some(Self {
+            space,
+            header: aead::quic::HeaderProtectionKey::new(suite.header_protection, &hp).ok()?,
+            packet: LessSafeKey::new(UnboundKey::new(suite.aead, &key).ok()?),
+            iv: iv.try_into().ok()?,
+            largest_pn: None,
+        })
+    }
+
+    /// Removes or reapplies header protection, if these keys protect the packet
+    fn process(
+        &mut self,
+        packet: &mut [u8],
+        layout: &PacketLayout,
+        operation: Operation,
+    ) -> Option<()> {
+        let sample_start = layout.pn_offset + MAX_PN_LEN;
+        let sample = packet.get(sample_start..sample_start + SAMPLE_LEN)?;
+        let mask = self.header.new_mask(sample).ok()?;
+        let first_byte_mask = mask[0] & if layout.long_header { 0x0f } else { 0x1f };
+
+        // The packet number length is only readable once header protection is removed
+        let unprotected_first_byte = match operation {
+            Operation::Unprotect => packet[0] ^ first_byte_mask,
+            Operation::Protect => packet[0],
+        };
+        let pn_len = (unprotected_first_byte & 0x03) as usize + 1;
+        let pn_end = layout.pn_offset + pn_len;
+        if pn_end > layout.end {
+            return None;
+        }
+
+        let mut header = packet[..pn_end].to_vec();
+        header[0] = unprotected_first_byte;
+        if operation == Operation::Unprotect {
+            for (byte, mask) in header[layout.pn_offset..].iter_mut().zip(&mask[1..]) {
+                *byte ^= mask;
+            }
+        }
+
+        // The payload only authenticates with the right keys and the unprotected header
+        let truncated_pn = header[layout.pn_offset..]
+            .iter()
+            .fold(0, |pn, &byte| (pn << 8) | byte as u64);
+        let pn = decode_packet_number(self.largest_pn, truncated_pn, pn_len);
+        self.authenticate(&header, pn, &packet[pn_end..layout.end])?;
+        self.largest_pn = Some(self.largest_pn.map_or(pn, |largest| largest.max(pn)));
+
+        match operation {
+            Operation::Unprotect => packet[..pn_end].copy_from_slice(&header),
+            Operation::Protect => {
+                packet[0] ^= first_byte_mask;
+                for (byte, mask) in packet[layout.pn_offset..pn_end].iter_mut().zip(&mask[1..]) {
+                    *byte ^= mask;
+                }
+            }
+        }
+
+        Some(())
+    }
+
+    fn authenticate(&self, header: &[u8], pn: u64, ciphertext: &[u8]) -> Option<()> {
+        let mut nonce = self.iv;
+        for (byte, pn_byte) in nonce[IV_LEN - 8..].iter_mut().zip(pn.to_be_bytes()) {
+            *byte ^= pn_byte;
+        }
+
+        let mut payload = ciphertext.to_vec();
+        self.packet
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(header),
+                &mut payload,
+            )
+            .ok()?;
+        Some(())
+    }
+}
+
+/// The location of the packet number in a packet
+struct PacketLayout {
+    space: KeySpace,
+    long_header: bool,
+    pn_offset: usize,
+    /// The end of the packet, relative to its start (long header packets may be coalesced)
+    end: usize,
+}
+
+enum Header {
+    Long {
+        layout: PacketLayout,
+        dcid: Vec<u8>,
+        scid_len: usize,
+    },
+    /// A short header, whose packet number location depends on the connection ID length
+    Short,
+    /// A packet without header protection (Retry or Version Negotiation), which extends until the
+    /// end of the datagram
+    Unprotected,
+}
+
+fn parse_header(packet: &[u8]) -> Option<Header> {
+    let first_byte = *packet.first()?;
+    if first_byte & 0x80 == 0 {
+        return Some(Header::Short);
+    }
+
+    let version = packet.get(1..5)?;
+    if version == [0; 4] {
+        return Some(Header::Unprotected);
+    }
+
+    let dcid_len = *packet.get(5)? as usize;
+    let dcid = packet.get(6..6 + dcid_len)?.to_vec();
+    let scid_len = *packet.get(6 + dcid_len)? as usize;
+    let mut offset = 7 + dcid_len + scid_len;
+
+    // The packet type is not protected
+    let space = match (first_byte >> 4) & 0x03 {
+        0x00 => KeySpace::Initial,
+        0x01 => KeySpace::ZeroRtt,
+        0x02 => KeySpace::Handshake,
+        _ => return Some(Header::Unprotected),
+    };
+
+    if space == KeySpace::Initial {
+        let (token_len, token_len_size) = decode_varint(packet.get(offset..)?)?;
+        offset += token_len_size + token_len as usize;
+    }
+
+    let (length, length_size) = decode_varint(packet.get(offset..)?)?;
+    let pn_offset = offset + length_size;
+    let end = pn_offset + length as usize;
+    if end > packet.len() {
+        return None;
+    }
+
+    Some(Header::Long {
+        layout: PacketLayout {
+            space,
+            long_header: true,
+            pn_offset,
+            end,
+        },
+        dcid,
+        scid_len,
+    })
+}
+
+/// Decodes a QUIC variable-length integer, returning its value and size
+fn decode_varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let size = 1 << (bytes.first()? >> 6);
+    let bytes = bytes.get(..size)?;
+    let value = bytes[1..]
+        .iter()
+        .fold((bytes[0] & 0x3f) as u64, |value, &byte| {
+            (value << 8) | byte as u64
+        });
+    Some((value, size))
+}
+
+/// Decodes a truncated packet number (RFC 9000, appendix A.3)
+fn decode_packet_number(largest_pn: Option<u64>, truncated_pn: u64, pn_len: usize) -> u64 {
+    let expected_pn = largest_pn.map_or(0, |largest| largest + 1);
+    let pn_win = 1 << (pn_len * 8);
+    let pn_hwin = pn_win / 2;
+    let candidate_pn = (expected_pn & !(pn_win - 1)) | truncated_pn;
+    if candidate_pn + pn_hwin <= expected_pn && candidate_pn < (1 << 62) - pn_win {
+        candidate_pn + pn_win
+    } else if candidate_pn > expected_pn + pn_hwin && candidate_pn >= pn_win {
+        candidate_pn - pn_win
+    } else {
+        candidate_pn
+    }
+}
+
+/// HKDF-Expand-Label with an empty context (RFC 8446, section 7.1)
+fn expand_label(prk: &hkdf::Prk, label: &[u8], len: usize) -> Option<Vec<u8>> {
+    struct Len(usize);
+    impl hkdf::KeyType for Len {
+        fn len(&self) -> usize {
+            self.0
+        }
+    }
+
+    let output_len = (len as u16).to_be_bytes();
+    let label_len = [(b"tls13 ".len() + label.len()) as u8];
+    let info = [&output_len[..], &label_len, b"tls13 ", label, &[0]];
+    let mut output = vec![0; len];
+    prk.expand(&info, Len(len)).ok()?.fill(&mut output).ok()?;
+    Some(output)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_server_initial_roundtrip() {
+        // RFC 9001, appendix A.3
+        let protected = decode_hex(concat!(
+            "cf000000010008f067a5502a4262b5004075c0d95a482cd0991cd25b0aac406a",
+            "5816b6394100f37a1c69797554780bb38cc5a99f5ede4cf73c3ec2493a1839b3",
+            "dbcba3f6ea46c5b7684df3548e7ddeb9c3bf9c73cc3f3bded74b562bfb19fb84",
+            "022f8ef4cdd93795d77d06edbb7aaf2f58891850abbdca3d20398c276456cbc4",
+            "2158407dd074ee",
+        ))
+        .unwrap();
+        let unprotected_header = decode_hex("c1000000010008f067a5502a4262b50040750001").unwrap();
+
+        let key_log = QuicKeyLog::new("missing-keylog.key", 0);
+        assert_eq!(key_log.unprotect(&protected), None);
+
+        // The client's original destination connection ID
+        key_log
+            .state
+            .lock()
+            .add_initial_keys(&decode_hex("8394c8f03e515708").unwrap());
+
+        let unprotected = key_log.unprotect(&protected).unwrap();
+        assert_eq!(unprotected[..20], unprotected_header);
+        assert_eq!(unprotected[20..], protected[20..]);
+        assert_eq!(key_log.protect(&unprotected).unwrap(), protected);
+    }
+
+    #[test]
+    fn test_reload_appended_secrets() {
+        use std::io::Write;
+
+        let secret_line = |label: &str| format!("{label} 00 {}\n", "ab".repeat(32));
+        let stale = secret_line("CLIENT_TRAFFIC_SECRET_0");
+
+        // Tests run in parallel, each in its own thread
+        let file_name = format!("keylog-{:?}.key", std::thread::current().id());
+        let path = std::env::temp_dir().join(file_name);
+        std::fs::write(&path, &stale).unwrap();
+        let key_log = QuicKeyLog::new(&path, stale.len() as u64);
+        let mut state = key_log.state.lock();
+        let append = |contents: &str| {
+            let mut file = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .unwrap();
+            file.write_all(contents.as_bytes()).unwrap();
+        };
+
+        // The secrets logged before the run are skipped
+        assert!(!state.reload(&key_log.path));
+        assert!(state.keys.is_empty());
+
+        // Incomplete lines are loaded once they are complete
+        let new = secret_line("SERVER_TRAFFIC_SECRET_0");
+        append(&new[..10]);
+        assert!(!state.reload(&key_log.path));
+        append(&new[10..]);
+        assert!(state.reload(&key_log.path));
+        let keys_per_secret = state.keys.len();
+        assert!(keys_per_secret > 0);
+        assert_eq!(state.loaded_bytes, (stale.len() + new.len()) as u64);
+        assert!(!state.reload(&key_log.path));
+
+        // A truncated file is loaded from the start
+        std::fs::write(&path, secret_line("CLIENT_HANDSHAKE_TRAFFIC_SECRET")).unwrap();
+        assert!(state.reload(&key_log.path));
+        assert_eq!(state.keys.len(), 2 * keys_per_secret);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
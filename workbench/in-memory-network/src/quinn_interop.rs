@@ -29,7 +29,7 @@ pub struct InMemoryUdpSocket {
     endpoint: Arc<UdpEndpoint>,
     node: Arc<Node>,
     next_packet_delivery: Mutex<Option<Pin<Box<NextPacketDelivery>>>>,
-    pcap_exporter: PcapExporter,
+    pcap_exporter: Arc<PcapExporter>,
 }
 
 impl Debug for InMemoryUdpSocket {
@@ -42,7 +42,7 @@ impl InMemoryUdpSocket {
     pub fn from_node(
         network: Arc<InMemoryNetwork>,
         node: Arc<Node>,
-        pcap_exporter: PcapExporter,
+        pcap_exporter: Arc<PcapExporter>,
     ) -> Self {
         InMemoryUdpSocket {
             endpoint: node.udp_endpoint.as_ref().unwrap().clone(),
@@ -3,12 +3,18 @@
 //! Provides actual header compression and decompression for transmitted packets.
 //! Compresses IP/UDP/QUIC headers, keeping Ethernet frame for routing.
 
-use pnet_packet::ip::IpNextHeaderProtocol;
-use pnet_packet::ipv4::MutableIpv4Packet;
-use pnet_packet::udp::MutableUdpPacket;
-use pnet_packet::{ipv4, udp};
+use crate::quic_header_protection::QuicKeyLog;
+use crate::schc_frame::{
+    ETHERNET_HEADER_SIZE, IpHeaderFields, build_synthetic_frame, datagram_differences,
+    ip_udp_header_size, synthetic_ip_udp_header_size,
+};
+use crate::schc_stats::{SchcBreakdown, SchcRuleMatch};
+use parking_lot::Mutex;
+use quinn::udp::EcnCodepoint;
 use schc::{Direction, Rule, RuleSet, TreeNode, build_tree, compress_packet, decompress_packet};
-use std::net::{IpAddr, SocketAddr};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
@@ -23,10 +29,59 @@ pub struct SchcCompressorStats {
     pub total_original_header_bits: AtomicUsize,
     /// Total compressed header bits
     pub total_compressed_header_bits: AtomicUsize,
+    /// Packets sent uncompressed using the no-compression rule
+    pub no_compression_fallbacks: AtomicUsize,
+    /// Number of no-compression fallbacks, by the reason why compression failed
+    pub no_compression_fallback_reasons: Mutex<HashMap<String, usize>>,
+    /// Packets received with the no-compression rule and restored by the decompressor
+    pub no_compression_restored: AtomicUsize,
+    /// Decompressed packets compared against their original datagram (round-trip verification)
+    pub roundtrip_checks: AtomicUsize,
+    /// Decompressed packets whose reconstructed datagram differs from the original one
+    pub roundtrip_mismatches: AtomicUsize,
+    /// Packets whose header protection was removed before compression
+    pub header_protection_removed: AtomicUsize,
+    /// Decompressed packets whose header protection was reapplied
+    pub header_protection_reapplied: AtomicUsize,
+    /// Breakdown of the compressed packets by rule, QUIC packet type, direction and node (packets
+    /// sent with the no-compression rule count as unmatched)
+    pub breakdown: SchcBreakdown,
 }
 
 impl SchcCompressorStats {
     pub fn report(&self) {
+        self.report_with_title("--- SCHC Compressor Statistics ---");
+    }
+
+    /// Like [`SchcCompressorStats::report`], for the compressor of a SCHC context
+    pub fn report_for_context(&self, context_id: &str) {
+        self.report_with_title(&format!(
+            "--- SCHC Compressor Statistics (context `{context_id}`) ---"
+        ));
+    }
+
+    /// Returns the statistics as JSON, for the machine-readable report
+    pub fn to_json(&self) -> serde_json::Value {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+        serde_json::json!({
+            "packets_compressed": load(&self.packets_compressed),
+            "packets_decompressed": load(&self.packets_decompressed),
+            "compression_failures": load(&self.compression_failures),
+            "decompression_failures": load(&self.decompression_failures),
+            "total_original_header_bits": load(&self.total_original_header_bits),
+            "total_compressed_header_bits": load(&self.total_compressed_header_bits),
+            "no_compression_fallbacks": load(&self.no_compression_fallbacks),
+            "no_compression_fallback_reasons": self.no_compression_fallback_reasons.lock().clone(),
+            "no_compression_restored": load(&self.no_compression_restored),
+            "roundtrip_checks": load(&self.roundtrip_checks),
+            "roundtrip_mismatches": load(&self.roundtrip_mismatches),
+            "header_protection_removed": load(&self.header_protection_removed),
+            "header_protection_reapplied": load(&self.header_protection_reapplied),
+            "breakdown": self.breakdown.to_json(),
+        })
+    }
+
+    fn report_with_title(&self, title: &str) {
         let compressed = self.packets_compressed.load(Ordering::Relaxed);
         let decompressed = self.packets_decompressed.load(Ordering::Relaxed);
         let comp_failures = self.compression_failures.load(Ordering::Relaxed);
@@ -35,7 +90,7 @@ impl SchcCompressorStats {
         let compressed_bits = self.total_compressed_header_bits.load(Ordering::Relaxed);
         let saved = original.saturating_sub(compressed_bits);
 
-        println!("--- SCHC Compressor Statistics ---");
+        println!("{title}");
         println!("* Packets compressed: {}", compressed);
         println!("* Packets decompressed: {}", decompressed);
         println!("* Compression failures: {}", comp_failures);
@@ -58,9 +113,164 @@ impl SchcCompressorStats {
                 original as f64 / compressed_bits.max(1) as f64
             );
         }
+
+        let fallbacks = self.no_compression_fallbacks.load(Ordering::Relaxed);
+        if fallbacks > 0 {
+            println!("* No-compression fallbacks: {}", fallbacks);
+            let reasons = self.no_compression_fallback_reasons.lock();
+            let mut reasons: Vec<_> = reasons.iter().collect();
+            reasons.sort();
+            for (reason, count) in reasons {
+                println!("  * {}: {}", reason, count);
+            }
+            println!(
+                "* No-compression packets restored: {}",
+                self.no_compression_restored.load(Ordering::Relaxed)
+            );
+        }
+
+        let roundtrip_checks = self.roundtrip_checks.load(Ordering::Relaxed);
+        if roundtrip_checks > 0 {
+            println!(
+                "* Round-trip checks: {} ({} mismatches)",
+                roundtrip_checks,
+                self.roundtrip_mismatches.load(Ordering::Relaxed)
+            );
+        }
+
+        let hp_removed = self.header_protection_removed.load(Ordering::Relaxed);
+        if hp_removed > 0 {
+            println!(
+                "* Header protection removed: {} packets ({} reapplied)",
+                hp_removed,
+                self.header_protection_reapplied.load(Ordering::Relaxed)
+            );
+        }
+
+        self.breakdown.report();
+    }
+}
+
+/// The rule used to send packets that no compression rule matches (RFC 8724, section 6)
+///
+/// Configured through the `no_compression_rule` entry of the rules file, e.g.
+/// `"no_compression_rule": { "rule_id": 0, "rule_id_length": 8 }`
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct NoCompressionRule {
+    pub rule_id: u32,
+    pub rule_id_length: u8,
+}
+
+impl NoCompressionRule {
+    /// Checks that the rule ID fits in its length, and that a receiver can tell it apart from the
+    /// IDs of the compression rules (i.e. it isn't equal to one, nor a prefix of one, nor the
+    /// other way around)
+    fn validate(&self, rule_ids: impl IntoIterator<Item = (u32, u8)>) -> anyhow::Result<()> {
+        if !(1..=32).contains(&self.rule_id_length) {
+            anyhow::bail!(
+                "invalid no-compression rule ID length: {} (expected 1 to 32 bits)",
+                self.rule_id_length
+            );
+        }
+
+        if (self.rule_id as u64) >> self.rule_id_length != 0 {
+            anyhow::bail!(
+                "invalid no-compression rule ID: {} doesn't fit in {} bits",
+                self.rule_id,
+                self.rule_id_length
+            );
+        }
+
+        for (rule_id, rule_id_length) in rule_ids {
+            let common_bits = self.rule_id_length.min(rule_id_length);
+            let prefix = |id: u32, length: u8| id as u64 >> (length - common_bits);
+            if prefix(self.rule_id, self.rule_id_length) == prefix(rule_id, rule_id_length) {
+                anyhow::bail!(
+                    "invalid no-compression rule ID: {}/{} can't be told apart from rule {rule_id}/{rule_id_length} (one ID is equal to, or a prefix of, the other)",
+                    self.rule_id,
+                    self.rule_id_length
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Prefixes the uncompressed datagram with the rule ID, padding the result to a byte boundary
+    fn encode(&self, datagram: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(datagram.len() + 5);
+        let mut pending = self.rule_id as u64;
+        let mut pending_bits = self.rule_id_length as usize;
+        for &byte in datagram {
+            pending = (pending << 8) | byte as u64;
+            pending_bits += 8;
+            while pending_bits >= 8 {
+                encoded.push((pending >> (pending_bits - 8)) as u8);
+                pending_bits -= 8;
+            }
+            pending &= (1 << pending_bits) - 1;
+        }
+
+        if pending_bits > 0 {
+            encoded.push((pending << (8 - pending_bits)) as u8);
+        }
+
+        encoded
+    }
+
+    /// Returns the uncompressed datagram if the SCHC packet starts with this rule's ID
+    fn decode(&self, schc_packet: &[u8]) -> Option<Vec<u8>> {
+        let rule_id_bits = self.rule_id_length as usize;
+        if schc_packet.len() * 8 < rule_id_bits {
+            return None;
+        }
+
+        let bit = |i: usize| (schc_packet[i / 8] >> (7 - i % 8)) & 1;
+        let rule_id = (0..rule_id_bits).fold(0u64, |id, i| (id << 1) | bit(i) as u64);
+        if rule_id != self.rule_id as u64 {
+            return None;
+        }
+
+        // Drop the rule ID (and the padding bits at the end, if any)
+        let offset = rule_id_bits / 8;
+        let shift = rule_id_bits % 8;
+        if shift == 0 {
+            return Some(schc_packet[offset..].to_vec());
+        }
+
+        let datagram = schc_packet[offset..]
+            .windows(2)
+            .map(|w| (w[0] << shift) | (w[1] >> (8 - shift)))
+            .collect();
+        Some(datagram)
     }
 }
 
+/// The SCHC compression state of an in-flight packet, present while its contents are compressed
+#[derive(Debug, Clone)]
+pub(crate) struct SchcCompressionState {
+    /// Rule ID used to compress the packet
+    pub(crate) rule_id: u32,
+    /// Length of the rule ID, in bits (if the compressor knows the rule)
+    pub(crate) rule_id_length: Option<u8>,
+    /// The node that compressed the packet
+    pub(crate) compressor_id: Arc<str>,
+    /// The SCHC context the packet was compressed with (`None` for the default rules), which the
+    /// decompressor must use too
+    pub(crate) context_id: Option<Arc<str>>,
+    /// Version of the SCHC context the packet was compressed with (see
+    /// [`schc_provisioning`](crate::schc_provisioning))
+    pub(crate) context_version: u32,
+    /// Size of the SCHC header (rule ID and residues) at the start of the packet's contents, in
+    /// bytes (0 for the no-compression rule)
+    pub(crate) header_size: usize,
+    /// Size of the packet's contents before compression, in bytes
+    pub(crate) original_size: usize,
+    /// The uncompressed IP/UDP datagram, kept when round-trip verification is enabled (it is not
+    /// part of the packet's contents, so it doesn't count towards its size)
+    pub(crate) original_datagram: Option<Arc<[u8]>>,
+}
+
 /// Result of compressing a packet
 #[derive(Debug, Clone)]
 pub struct CompressResult {
@@ -74,6 +284,8 @@ pub struct CompressResult {
     pub rule_id: u32,
     /// Whether compression was successful
     pub success: bool,
+    /// The uncompressed IP/UDP datagram, kept when round-trip verification is enabled
+    pub original_datagram: Option<Vec<u8>>,
 }
 
 /// Result of decompressing a packet
@@ -83,6 +295,9 @@ pub struct DecompressResult {
     pub decompressed_packet: Vec<u8>,
     /// Rule ID that was used
     pub rule_id: u32,
+    /// ECN codepoint of the reconstructed IP header (`Some(None)` for Not-ECT, `None` when the
+    /// header could not be parsed, in which case the packet keeps its current codepoint)
+    pub ecn: Option<Option<EcnCodepoint>>,
 }
 
 /// SCHC Compressor for actual packet compression/decompression
@@ -91,8 +306,14 @@ pub struct SchcCompressor {
     tree: TreeNode,
     /// Rules for compression/decompression
     rules: Vec<Rule>,
+    /// Rule used for packets that no other rule matches (optional)
+    no_compression_rule: Option<NoCompressionRule>,
     stats: SchcCompressorStats,
     debug: bool,
+    /// Whether to compare decompressed packets against their original datagram
+    verify_roundtrip: bool,
+    /// Key log used to remove QUIC header protection before compression (optional)
+    key_log: Option<Arc<QuicKeyLog>>,
 }
 
 impl SchcCompressor {
@@ -110,6 +331,21 @@ impl SchcCompressor {
         let ruleset = RuleSet::from_file(rules_path)?;
         let tree = build_tree(&ruleset.rules);
 
+        // The no-compression rule is a workbench-specific entry, next to the rules themselves
+        let rules_json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(rules_path)?)?;
+        let no_compression_rule: Option<NoCompressionRule> = rules_json
+            .get("no_compression_rule")
+            .map(|rule| serde_json::from_value(rule.clone()))
+            .transpose()?;
+        if let Some(rule) = &no_compression_rule {
+            let rule_ids = ruleset
+                .rules
+                .iter()
+                .map(|rule| (rule.rule_id, rule.rule_id_length));
+            rule.validate(rule_ids)?;
+        }
+
         if debug {
             println!("\n--- SCHC Compressor Rule Tree ---");
             schc::display_tree(&tree);
@@ -117,27 +353,60 @@ impl SchcCompressor {
 
         Ok(Self {
             tree,
+            stats: SchcCompressorStats {
+                breakdown: SchcBreakdown::new(&ruleset.rules),
+                ..SchcCompressorStats::default()
+            },
             rules: ruleset.rules,
-            stats: SchcCompressorStats::default(),
+            no_compression_rule,
             debug,
+            verify_roundtrip: false,
+            key_log: None,
         })
     }
 
+    /// Enables round-trip verification: compressed packets keep their original datagram, which is
+    /// compared byte for byte against the reconstructed datagram upon decompression
+    pub fn with_roundtrip_verification(mut self, enabled: bool) -> Self {
+        self.verify_roundtrip = enabled;
+        self
+    }
+
+    /// Removes QUIC header protection before compression, using the secrets of the key log, and
+    /// reapplies it after decompression
+    ///
+    /// Packets whose keys are unknown are compressed with their header protection in place.
+    pub fn with_key_log(mut self, key_log: Option<Arc<QuicKeyLog>>) -> Self {
+        self.key_log = key_log;
+        self
+    }
+
     /// Compress a QUIC packet.
     ///
     /// Takes the QUIC payload (what Quinn transmits) along with source/dest addresses.
-    /// Builds a synthetic IP/UDP frame, compresses IP+UDP+QUIC headers.
+    /// Builds a synthetic IP/UDP frame (carrying the transmit's ECN codepoint), compresses
+    /// IP+UDP+QUIC headers (without header protection, if the packet's keys are known).
     /// Returns compressed SCHC data + original payload (after QUIC headers).
     pub fn compress(
         &self,
         quic_payload: &[u8],
         source_addr: SocketAddr,
         dest_addr: SocketAddr,
+        ecn: Option<EcnCodepoint>,
         is_outgoing: bool,
         node_id: &str,
     ) -> CompressResult {
+        let unprotected = self.unprotect(quic_payload);
+        let protected_payload = quic_payload;
+        let quic_payload = unprotected.as_deref().unwrap_or(quic_payload);
+
         // Build synthetic Ethernet+IP+UDP frame for SCHC compression
-        let synthetic_packet = self.build_synthetic_packet(quic_payload, source_addr, dest_addr);
+        let synthetic_packet = build_synthetic_frame(
+            quic_payload,
+            source_addr,
+            dest_addr,
+            IpHeaderFields::with_ecn(ecn),
+        );
 
         let direction = if is_outgoing {
             Direction::Up
@@ -170,10 +439,8 @@ impl SchcCompressor {
                 // - result.data: the SCHC compressed header (rule ID + residues)
                 // - We need to append the payload (data after the headers)
 
-                // Calculate header sizes
-                let ip_header_size = 20; // IPv4 basic header
-                let udp_header_size = 8;
-                let _ethernet_header_size = 14;
+                // Calculate header sizes (IPv4 or IPv6, depending on the addresses)
+                let ip_udp_header_size = synthetic_ip_udp_header_size(source_addr, dest_addr);
 
                 // QUIC header size varies - we compressed it, residue is in result.data
                 // The original_header_bits includes IP+UDP+QUIC headers
@@ -183,8 +450,7 @@ impl SchcCompressor {
                 // Calculate QUIC payload offset (after QUIC headers)
                 // The QUIC headers we compressed are at the start of quic_payload
                 // We need to extract just the application data
-                let quic_header_bytes =
-                    original_header_bytes.saturating_sub(ip_header_size + udp_header_size);
+                let quic_header_bytes = original_header_bytes.saturating_sub(ip_udp_header_size);
                 let app_payload_start = quic_header_bytes.min(quic_payload.len());
                 let app_payload = &quic_payload[app_payload_start..];
 
@@ -193,6 +459,16 @@ impl SchcCompressor {
                 compressed_packet.extend_from_slice(app_payload);
 
                 // Track header compression stats (like observer)
+                self.stats.breakdown.record(
+                    node_id,
+                    quic_payload,
+                    is_outgoing,
+                    Some(SchcRuleMatch {
+                        rule_id,
+                        original_header_bits: result.original_header_bits,
+                        compressed_header_bits: result.compressed_header_bits,
+                    }),
+                );
                 self.stats
                     .packets_compressed
                     .fetch_add(1, Ordering::Relaxed);
@@ -219,9 +495,45 @@ impl SchcCompressor {
                     compressed_header_size: compressed_header_bytes,
                     rule_id,
                     success: true,
+                    original_datagram: self.original_datagram(&synthetic_packet),
                 }
             }
             Err(e) => {
+                self.stats
+                    .breakdown
+                    .record(node_id, quic_payload, is_outgoing, None);
+
+                // Send the full datagram behind the no-compression rule ID, if available
+                if let Some(rule) = &self.no_compression_rule {
+                    let reason = format!("{:?}", e);
+                    let compressed_packet = rule.encode(&synthetic_packet[ETHERNET_HEADER_SIZE..]);
+
+                    self.stats
+                        .no_compression_fallbacks
+                        .fetch_add(1, Ordering::Relaxed);
+                    *self
+                        .stats
+                        .no_compression_fallback_reasons
+                        .lock()
+                        .entry(reason.clone())
+                        .or_default() += 1;
+
+                    let dir_str = if is_outgoing { "UP" } else { "DOWN" };
+                    println!(
+                        "[SCHC Compress @ {}] [{}] No-compression rule {}/{} ({})",
+                        node_id, dir_str, rule.rule_id, rule.rule_id_length, reason
+                    );
+
+                    return CompressResult {
+                        compressed_packet,
+                        original_header_size: 0,
+                        compressed_header_size: 0,
+                        rule_id: rule.rule_id,
+                        success: true,
+                        original_datagram: self.original_datagram(&synthetic_packet),
+                    };
+                }
+
                 self.stats
                     .compression_failures
                     .fetch_add(1, Ordering::Relaxed);
@@ -229,11 +541,12 @@ impl SchcCompressor {
                     println!("[SCHC Compress] Failed: {:?}", e);
                 }
                 CompressResult {
-                    compressed_packet: quic_payload.to_vec(), // Return original on failure
+                    compressed_packet: protected_payload.to_vec(), // Return original on failure
                     original_header_size: 0,
                     compressed_header_size: 0,
                     rule_id: 0,
                     success: false,
+                    original_datagram: None,
                 }
             }
         }
@@ -241,10 +554,13 @@ impl SchcCompressor {
 
     /// Decompress a SCHC packet back to QUIC.
     ///
-    /// Takes compressed SCHC data + payload, reconstructs the original QUIC packet.
+    /// Takes compressed SCHC data + payload, reconstructs the original QUIC packet. If the
+    /// original datagram is provided, the reconstructed datagram is checked against it.
+    /// Header protection is reapplied afterwards, if it was removed before compression.
     pub fn decompress(
         &self,
         compressed_data: &[u8],
+        original_datagram: Option<&[u8]>,
         is_outgoing: bool,
         node_id: &str,
     ) -> Result<DecompressResult, String> {
@@ -254,6 +570,39 @@ impl SchcCompressor {
             Direction::Down
         };
 
+        // Packets sent with the no-compression rule carry the full datagram
+        let no_compression = self
+            .no_compression_rule
+            .and_then(|rule| Some((rule, rule.decode(compressed_data)?)));
+        if let Some((rule, datagram)) = no_compression {
+            let Some(quic_start) = ip_udp_header_size(&datagram) else {
+                self.stats
+                    .decompression_failures
+                    .fetch_add(1, Ordering::Relaxed);
+                return Err("No-compression packet is not a valid IP/UDP datagram".to_string());
+            };
+
+            self.stats
+                .no_compression_restored
+                .fetch_add(1, Ordering::Relaxed);
+            if self.debug {
+                println!(
+                    "[SCHC Decompress @ {}] No-compression rule {}/{}",
+                    node_id, rule.rule_id, rule.rule_id_length
+                );
+            }
+
+            if let Some(original) = original_datagram {
+                self.verify_roundtrip(original, &datagram, rule.rule_id, node_id);
+            }
+
+            return Ok(DecompressResult {
+                decompressed_packet: self.protect(datagram[quic_start..].to_vec()),
+                rule_id: rule.rule_id,
+                ecn: IpHeaderFields::parse(&datagram).map(|fields| fields.ecn_codepoint()),
+            });
+        }
+
         // Try to decompress the SCHC packet
         // Note: We need to figure out where the payload starts (after SCHC residues)
         match decompress_packet(
@@ -273,18 +622,23 @@ impl SchcCompressor {
 
                 // Reconstruct QUIC packet from decompressed headers
                 // The full_data contains the reconstructed IP+UDP+QUIC headers
-                // We skip IP (20 bytes) and UDP (8 bytes) to get QUIC packet for Quinn
-                let quic_start = 20 + 8; // IP + UDP headers
-                let quic_header = if result.full_data.len() > quic_start {
-                    &result.full_data[quic_start..]
-                } else {
-                    &[]
+                // We skip IP (20 bytes for IPv4, 40 for IPv6) and UDP (8 bytes) to get the QUIC
+                // packet for Quinn
+                let quic_header = match ip_udp_header_size(&result.full_data) {
+                    Some(quic_start) => &result.full_data[quic_start..],
+                    None => &[],
                 };
 
                 // Combine QUIC header + payload
                 let mut decompressed_packet = quic_header.to_vec();
                 decompressed_packet.extend_from_slice(original_payload);
 
+                if let Some(original) = original_datagram {
+                    let mut reconstructed = result.full_data.clone();
+                    reconstructed.extend_from_slice(original_payload);
+                    self.verify_roundtrip(original, &reconstructed, result.rule_id, node_id);
+                }
+
                 self.stats
                     .packets_decompressed
                     .fetch_add(1, Ordering::Relaxed);
@@ -299,9 +653,13 @@ impl SchcCompressor {
                     node_id, dir_str, compressed_bytes, restored_bytes, restored_saved
                 );
 
+                // Restore the ECN codepoint the rules reconstructed (the simulation does not model
+                // DSCP and TTL)
                 Ok(DecompressResult {
-                    decompressed_packet,
+                    decompressed_packet: self.protect(decompressed_packet),
                     rule_id: result.rule_id,
+                    ecn: IpHeaderFields::parse(&result.full_data)
+                        .map(|fields| fields.ecn_codepoint()),
                 })
             }
             Err(e) => {
@@ -316,68 +674,67 @@ impl SchcCompressor {
         }
     }
 
-    /// Build a synthetic Ethernet+IP+UDP packet for SCHC compression.
-    fn build_synthetic_packet(
-        &self,
-        quic_payload: &[u8],
-        source_addr: SocketAddr,
-        dest_addr: SocketAddr,
-    ) -> Vec<u8> {
-        // Extract IPv4 addresses (simulation only uses IPv4)
-        let IpAddr::V4(source_ip) = source_addr.ip() else {
-            panic!("SCHC compressor only supports IPv4");
-        };
-        let IpAddr::V4(dest_ip) = dest_addr.ip() else {
-            panic!("SCHC compressor only supports IPv4");
+    fn unprotect(&self, quic_payload: &[u8]) -> Option<Vec<u8>> {
+        let unprotected = self.key_log.as_ref()?.unprotect(quic_payload)?;
+        self.stats
+            .header_protection_removed
+            .fetch_add(1, Ordering::Relaxed);
+        Some(unprotected)
+    }
+
+    /// Reapplies header protection to a decompressed packet (packets whose keys are unknown had
+    /// their header protection in place when compressed, so they are returned as-is)
+    fn protect(&self, quic_packet: Vec<u8>) -> Vec<u8> {
+        let Some(protected) = self
+            .key_log
+            .as_ref()
+            .and_then(|key_log| key_log.protect(&quic_packet))
+        else {
+            return quic_packet;
         };
 
-        // Use a working buffer
-        let mut buffer = vec![0u8; 2000];
-
-        // Build UDP packet first
-        let udp_packet_length = 8 + quic_payload.len() as u16;
-        {
-            let mut udp_writer = MutableUdpPacket::new(&mut buffer).unwrap();
-            udp_writer.set_source(source_addr.port());
-            udp_writer.set_destination(dest_addr.port());
-            udp_writer.set_length(udp_packet_length);
-            udp_writer.set_payload(quic_payload);
-            let checksum = udp::ipv4_checksum(&udp_writer.to_immutable(), &source_ip, &dest_ip);
-            udp_writer.set_checksum(checksum);
-        }
-        let udp_packet = buffer[0..udp_packet_length as usize].to_vec();
-
-        // Build IPv4 packet with UDP as payload
-        let ip_packet_length = 20 + udp_packet_length;
-        {
-            let mut ip_writer = MutableIpv4Packet::new(&mut buffer).unwrap();
-            ip_writer.set_version(4);
-            ip_writer.set_header_length(5); // No options
-            ip_writer.set_dscp(0);
-            ip_writer.set_ecn(0);
-            ip_writer.set_total_length(ip_packet_length);
-            ip_writer.set_identification(0);
-            ip_writer.set_flags(0b010); // Don't fragment
-            ip_writer.set_fragment_offset(0);
-            ip_writer.set_ttl(64);
-            ip_writer.set_next_level_protocol(IpNextHeaderProtocol::new(17)); // UDP
-            ip_writer.set_source(source_ip);
-            ip_writer.set_destination(dest_ip);
-            ip_writer.set_payload(&udp_packet);
-            let checksum = ipv4::checksum(&ip_writer.to_immutable());
-            ip_writer.set_checksum(checksum);
+        self.stats
+            .header_protection_reapplied
+            .fetch_add(1, Ordering::Relaxed);
+        protected
+    }
+
+    fn original_datagram(&self, synthetic_packet: &[u8]) -> Option<Vec<u8>> {
+        self.verify_roundtrip
+            .then(|| synthetic_packet[ETHERNET_HEADER_SIZE..].to_vec())
+    }
+
+    /// Compares the reconstructed datagram against the original one, reporting the fields that
+    /// differ
+    fn verify_roundtrip(&self, original: &[u8], reconstructed: &[u8], rule_id: u32, node_id: &str) {
+        self.stats.roundtrip_checks.fetch_add(1, Ordering::Relaxed);
+        if original == reconstructed {
+            return;
         }
-        let ip_packet = buffer[0..ip_packet_length as usize].to_vec();
 
-        // Build final frame with Ethernet header
-        let mut frame = Vec::with_capacity(14 + ip_packet.len());
-        // Ethernet header (14 bytes)
-        frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00]); // Dst MAC
-        frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00]); // Src MAC
-        frame.extend_from_slice(&[0x08, 0x00]); // EtherType: IPv4
-        frame.extend_from_slice(&ip_packet);
+        self.stats
+            .roundtrip_mismatches
+            .fetch_add(1, Ordering::Relaxed);
+        println!(
+            "[SCHC Round-trip @ {}] Rule {}: reconstructed datagram differs from the original",
+            node_id, rule_id
+        );
+        for difference in datagram_differences(original, reconstructed) {
+            println!("  * {difference}");
+        }
+    }
 
-        frame
+    /// The length of the ID of the rule (or of the no-compression rule), in bits
+    pub(crate) fn rule_id_length(&self, rule_id: u32) -> Option<u8> {
+        self.rules
+            .iter()
+            .map(|rule| (rule.rule_id, rule.rule_id_length))
+            .chain(
+                self.no_compression_rule
+                    .map(|rule| (rule.rule_id, rule.rule_id_length)),
+            )
+            .find(|(id, _)| *id == rule_id)
+            .map(|(_, length)| length)
     }
 
     /// Get statistics
@@ -388,3 +745,165 @@ impl SchcCompressor {
 
 /// Thread-safe wrapper for SCHC compressor
 pub type SharedSchcCompressor = Arc<SchcCompressor>;
+
+/// A SCHC context, i.e. a compressor (with its own rules and statistics) shared by the nodes at
+/// both ends of a compressed segment
+pub struct SchcContext {
+    pub id: Arc<str>,
+    pub binding: SchcContextBinding,
+    pub compressor: SharedSchcCompressor,
+}
+
+/// Determines which compressed segments use a SCHC context
+pub enum SchcContextBinding {
+    /// Packets compressed by one of the nodes and decompressed by the other one
+    NodePair([Arc<str>; 2]),
+    /// Packets compressed before crossing one of the links, or decompressed after crossing one of
+    /// them
+    Links(HashSet<Arc<str>>),
+}
+
+impl SchcContext {
+    /// Returns true if the context applies to a packet crossing the provided link, between the
+    /// provided compressor and decompressor nodes (if known)
+    pub(crate) fn applies_to(
+        &self,
+        link_id: Option<&str>,
+        compressor_id: &str,
+        decompressor_id: Option<&str>,
+    ) -> bool {
+        match &self.binding {
+            SchcContextBinding::NodePair([a, b]) => {
+                let Some(decompressor_id) = decompressor_id else {
+                    return false;
+                };
+
+                (**a == *compressor_id && **b == *decompressor_id)
+                    || (**b == *compressor_id && **a == *decompressor_id)
+            }
+            SchcContextBinding::Links(links) => link_id.is_some_and(|id| links.contains(id)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_no_compression_rule_roundtrip() {
+        let datagram = [0x45, 0x00, 0x01, 0xff, 0x80, 0x7e];
+        for (rule_id, rule_id_length) in [(0, 8), (0b101, 3), (0x1ff, 12)] {
+            let rule = NoCompressionRule {
+                rule_id,
+                rule_id_length,
+            };
+
+            let encoded = rule.encode(&datagram);
+            assert_eq!(
+                encoded.len(),
+                (datagram.len() * 8 + rule_id_length as usize).div_ceil(8)
+            );
+            assert_eq!(rule.decode(&encoded).unwrap(), datagram);
+        }
+    }
+
+    #[test]
+    fn test_no_compression_rule_mismatch() {
+        let rule = NoCompressionRule {
+            rule_id: 0b101,
+            rule_id_length: 3,
+        };
+
+        assert_eq!(rule.decode(&[0b1010_0000]), Some(vec![]));
+        assert_eq!(rule.decode(&[0b0110_0000]), None);
+        assert_eq!(rule.decode(&[]), None);
+    }
+
+    #[test]
+    fn test_no_compression_rule_validation() {
+        let rule = |rule_id, rule_id_length| NoCompressionRule {
+            rule_id,
+            rule_id_length,
+        };
+        let rule_ids = [(0b0001, 4), (0b0010, 4), (0b1100_0000, 8)];
+
+        assert!(rule(0b0000, 4).validate(rule_ids).is_ok());
+        assert!(rule(0b111, 3).validate(rule_ids).is_ok());
+        assert!(rule(0b0000_0000, 8).validate(rule_ids).is_ok());
+
+        // Equal to a rule ID
+        assert!(rule(0b0010, 4).validate(rule_ids).is_err());
+        // Prefix of a rule ID
+        assert!(rule(0b11, 2).validate(rule_ids).is_err());
+        // A rule ID is a prefix of it
+        assert!(rule(0b0001_0110, 8).validate(rule_ids).is_err());
+    }
+
+    #[test]
+    fn test_no_compression_rule_validation_length() {
+        let rule = |rule_id, rule_id_length| NoCompressionRule {
+            rule_id,
+            rule_id_length,
+        };
+
+        assert!(rule(0xff, 8).validate([]).is_ok());
+        assert!(rule(u32::MAX, 32).validate([]).is_ok());
+
+        // Doesn't fit in its length
+        assert!(rule(0x100, 8).validate([]).is_err());
+        assert!(rule(0b100, 2).validate([]).is_err());
+
+        // Invalid lengths
+        assert!(rule(0, 0).validate([]).is_err());
+        assert!(rule(0, 33).validate([]).is_err());
+    }
+
+    fn context(binding: SchcContextBinding) -> SchcContext {
+        let rules =
+            r#"{ "rules": [], "no_compression_rule": { "rule_id": 0, "rule_id_length": 8 } }"#;
+
+        // Tests run in parallel, each in its own thread
+        let file_name = format!("schc-context-{:?}.json", std::thread::current().id());
+        let path = std::env::temp_dir().join(file_name);
+        std::fs::write(&path, rules).unwrap();
+        let compressor = SchcCompressor::from_files(path.to_str().unwrap(), "", false);
+        std::fs::remove_file(&path).unwrap();
+
+        SchcContext {
+            id: "context".into(),
+            binding,
+            compressor: Arc::new(compressor.unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_context_bound_to_node_pair() {
+        let context = context(SchcContextBinding::NodePair(["gw".into(), "dev".into()]));
+
+        // In both directions, over any link
+        assert!(context.applies_to(Some("gw-dev"), "gw", Some("dev")));
+        assert!(context.applies_to(Some("dev-gw"), "dev", Some("gw")));
+        assert!(context.applies_to(None, "gw", Some("dev")));
+
+        // Other nodes, or an unknown decompressor
+        assert!(!context.applies_to(Some("gw-dev"), "gw", Some("relay")));
+        assert!(!context.applies_to(Some("gw-dev"), "relay", Some("dev")));
+        assert!(!context.applies_to(Some("gw-dev"), "gw", Some("gw")));
+        assert!(!context.applies_to(Some("gw-dev"), "gw", None));
+    }
+
+    #[test]
+    fn test_context_bound_to_links() {
+        let links = HashSet::from(["gw-dev".into(), "dev-gw".into()]);
+        let context = context(SchcContextBinding::Links(links));
+
+        // Regardless of the nodes
+        assert!(context.applies_to(Some("gw-dev"), "gw", Some("dev")));
+        assert!(context.applies_to(Some("dev-gw"), "relay", None));
+
+        // Other links, or no link
+        assert!(!context.applies_to(Some("gw-relay"), "gw", Some("dev")));
+        assert!(!context.applies_to(None, "gw", Some("dev")));
+    }
+}
@@ -0,0 +1,535 @@
+//! Fault injection desynchronizing SCHC compressors and decompressors
+//!
+//! While a fault is active, the faulty node disagrees with the compressors sending it packets:
+//! it either decompresses them with another rule set (e.g. a stale one), or receives them with a
+//! mutated rule ID or residue. Faults are bound to a node and to a time window, and only affect
+//! the packets the node decompresses.
+//!
+//! Packets that fail to decompress are forwarded still compressed, so the host they reach drops
+//! them. Packets that decompress into wrong headers reach QUIC, which drops them when they fail
+//! authentication. Either way, QUIC sees them as lost.
+
+use crate::async_rt::time::Instant;
+use crate::schc_compressor::{SchcCompressionState, SharedSchcCompressor};
+use anyhow::bail;
+use fastrand::Rng;
+use parking_lot::Mutex;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// How a fault desynchronizes the node from the compressors
+pub enum SchcDesyncFaultKind {
+    /// The node decompresses packets with these rules instead of its own
+    Rules {
+        /// Where the rules come from, for the reports
+        label: String,
+        compressor: SharedSchcCompressor,
+    },
+    /// A bit of the rule ID of each packet is flipped
+    RuleId,
+    /// A bit of the residue of each packet is flipped (packets without residue, like those sent
+    /// with the no-compression rule, are left untouched)
+    Residue,
+}
+
+impl fmt::Display for SchcDesyncFaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rules { label, .. } => write!(f, "rules from {label}"),
+            Self::RuleId => write!(f, "mutated rule IDs"),
+            Self::Residue => write!(f, "mutated residues"),
+        }
+    }
+}
+
+/// A fault, active at a node during a time window
+pub struct SchcDesyncFault {
+    /// The node whose decompression is faulty
+    pub node_id: Arc<str>,
+    /// Start of the window, relative to the start of the simulation
+    pub from: Duration,
+    /// End of the window (`None` for a fault lasting until the end of the simulation)
+    pub until: Option<Duration>,
+    /// Ratio of the packets decompressed during the window that the fault affects
+    pub ratio: f64,
+    pub kind: SchcDesyncFaultKind,
+}
+
+impl SchcDesyncFault {
+    fn is_active(&self, node_id: &str, time: Duration) -> bool {
+        *self.node_id == *node_id
+            && self.from <= time
+            && self.until.is_none_or(|until| time < until)
+    }
+
+    fn window_label(&self) -> String {
+        match self.until {
+            Some(until) => format!(
+                "{:.2}s to {:.2}s",
+                self.from.as_secs_f64(),
+                until.as_secs_f64()
+            ),
+            None => format!("from {:.2}s", self.from.as_secs_f64()),
+        }
+    }
+}
+
+/// Statistics of a single fault
+#[derive(Debug, Default)]
+struct SchcDesyncFaultStats {
+    /// Packets the fault was applied to
+    affected_packets: AtomicUsize,
+    /// Packets the fault should have been applied to, but were left untouched because the node
+    /// doesn't know the length of their rule ID (rule ID and residue faults only)
+    skipped_packets: AtomicUsize,
+    /// Affected packets the node failed to decompress (and that are dropped further on)
+    decompression_failures: AtomicUsize,
+    /// Affected packets the node decompressed nonetheless, with headers that may differ from the
+    /// original ones
+    decompressed: AtomicUsize,
+    /// The first packet the node decompressed after the end of the window
+    recovered_at: Mutex<Option<Duration>>,
+}
+
+/// A fault applied to a packet about to be decompressed, to be reported along with the outcome of
+/// the decompression
+pub(crate) struct AppliedSchcDesyncFault {
+    fault: usize,
+    /// The compressor to use instead of the node's own one (for rule set faults)
+    pub(crate) compressor: Option<SharedSchcCompressor>,
+}
+
+/// The desynchronization faults of a simulation
+pub struct SchcDesync {
+    faults: Vec<(SchcDesyncFault, SchcDesyncFaultStats)>,
+    start: Instant,
+    /// Decides which packets are affected and which bits are flipped (separate from the network's
+    /// RNG, so enabling faults doesn't change the packet loss, duplication and reordering)
+    rng: Mutex<Rng>,
+    debug: bool,
+}
+
+impl SchcDesync {
+    pub fn new(
+        faults: Vec<SchcDesyncFault>,
+        start: Instant,
+        rng_seed: u64,
+        debug: bool,
+    ) -> anyhow::Result<Self> {
+        for fault in &faults {
+            if !(0.0..=1.0).contains(&fault.ratio) {
+                bail!(
+                    "invalid ratio for the SCHC desync fault at {}: {} (expected 0 to 1)",
+                    fault.node_id,
+                    fault.ratio
+                );
+            }
+
+            if fault.until.is_some_and(|until| until <= fault.from) {
+                bail!(
+                    "the SCHC desync fault at {} ends before it starts",
+                    fault.node_id
+                );
+            }
+        }
+
+        Ok(Self {
+            faults: faults
+                .into_iter()
+                .map(|fault| (fault, SchcDesyncFaultStats::default()))
+                .collect(),
+            start,
+            rng: Mutex::new(Rng::with_seed(rng_seed)),
+            debug,
+        })
+    }
+
+    /// Applies the first fault active at the node (if any) to a packet about to be decompressed
+    /// with `compressor`, mutating its contents or returning the compressor to use instead
+    pub(crate) fn apply(
+        &self,
+        node_id: &str,
+        contents: &mut [u8],
+        state: &SchcCompressionState,
+        compressor: Option<&SharedSchcCompressor>,
+    ) -> Option<AppliedSchcDesyncFault> {
+        let time = self.start.elapsed();
+        let (index, (fault, stats)) = self
+            .faults
+            .iter()
+            .enumerate()
+            .find(|(_, (fault, _))| fault.is_active(node_id, time))?;
+        let mut rng = self.rng.lock();
+        if rng.f64() >= fault.ratio {
+            return None;
+        }
+
+        let rule_id_bits = || {
+            let rule_id_length = compressor.and_then(|c| c.rule_id_length(state.rule_id));
+            if rule_id_length.is_none() {
+                stats.skipped_packets.fetch_add(1, Ordering::Relaxed);
+                if self.debug {
+                    println!(
+                        "[SCHC Desync @ {node_id}] Rule {} packet from {} left untouched (unknown rule ID length)",
+                        state.rule_id, state.compressor_id
+                    );
+                }
+            }
+
+            rule_id_length.map(usize::from)
+        };
+
+        let mut replacement = None;
+        let mutated_bit = match &fault.kind {
+            SchcDesyncFaultKind::Rules { compressor, .. } => {
+                replacement = Some(compressor.clone());
+                None
+            }
+            SchcDesyncFaultKind::RuleId => {
+                let rule_id_bits = rule_id_bits()?;
+                Some(rng.usize(0..rule_id_bits.min(contents.len() * 8)))
+            }
+            SchcDesyncFaultKind::Residue => {
+                let rule_id_bits = rule_id_bits()?;
+                let residue_end = (state.header_size * 8).min(contents.len() * 8);
+                if residue_end <= rule_id_bits {
+                    return None;
+                }
+
+                Some(rng.usize(rule_id_bits..residue_end))
+            }
+        };
+        if let Some(bit) = mutated_bit {
+            contents[bit / 8] ^= 0x80 >> (bit % 8);
+        }
+
+        stats.affected_packets.fetch_add(1, Ordering::Relaxed);
+        if self.debug {
+            println!(
+                "[SCHC Desync @ {}] Rule {} packet from {} decompressed with {}",
+                node_id, state.rule_id, state.compressor_id, fault.kind
+            );
+        }
+
+        Some(AppliedSchcDesyncFault {
+            fault: index,
+            compressor: replacement,
+        })
+    }
+
+    /// Records the outcome of the decompression of a packet, along with the fault applied to it
+    pub(crate) fn decompressed(
+        &self,
+        node_id: &str,
+        applied: Option<AppliedSchcDesyncFault>,
+        success: bool,
+    ) {
+        if let Some(applied) = applied {
+            let stats = &self.faults[applied.fault].1;
+            let counter = if success {
+                &stats.decompressed
+            } else {
+                &stats.decompression_failures
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        if !success {
+            return;
+        }
+
+        // The node recovers from the faults whose window ended
+        let time = self.start.elapsed();
+        for (fault, stats) in &self.faults {
+            let ended = fault.until.is_some_and(|until| until <= time);
+            if *fault.node_id == *node_id && ended {
+                stats.recovered_at.lock().get_or_insert(time);
+            }
+        }
+    }
+
+    /// The end of the last fault window, or `None` if a fault lasts until the end of the
+    /// simulation
+    pub fn last_fault_end(&self) -> Option<Duration> {
+        self.faults
+            .iter()
+            .map(|(fault, _)| fault.until)
+            .try_fold(Duration::ZERO, |end, until| Some(end.max(until?)))
+    }
+
+    /// The number of faults whose window ended without their node decompressing a packet
+    /// afterwards
+    pub fn unrecovered_faults(&self) -> usize {
+        self.faults
+            .iter()
+            .filter(|(fault, stats)| fault.until.is_some() && stats.recovered_at.lock().is_none())
+            .count()
+    }
+
+    pub fn report(&self) {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+
+        println!("--- SCHC Desynchronization Faults ---");
+        for (fault, stats) in &self.faults {
+            println!(
+                "* {} ({}, {:.0}% of the packets): {}",
+                fault.node_id,
+                fault.window_label(),
+                fault.ratio * 100.0,
+                fault.kind
+            );
+            println!("  * Affected packets: {}", load(&stats.affected_packets));
+            println!(
+                "  * Skipped packets (unknown rule ID length): {}",
+                load(&stats.skipped_packets)
+            );
+            println!(
+                "  * Decompression failures: {}",
+                load(&stats.decompression_failures)
+            );
+            println!(
+                "  * Decompressed despite the fault: {}",
+                load(&stats.decompressed)
+            );
+            match (fault.until, *stats.recovered_at.lock()) {
+                (None, _) => {}
+                (Some(until), Some(recovered_at)) => println!(
+                    "  * Recovered: first packet decompressed at {:.2}s ({:.2}s after the fault)",
+                    recovered_at.as_secs_f64(),
+                    (recovered_at - until).as_secs_f64()
+                ),
+                (Some(_), None) => {
+                    println!("  * Recovered: no packet decompressed after the fault")
+                }
+            }
+        }
+    }
+
+    /// Returns the statistics as JSON, for the machine-readable report
+    pub fn to_json(&self) -> serde_json::Value {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+        let faults: Vec<_> = self
+            .faults
+            .iter()
+            .map(|(fault, stats)| {
+                serde_json::json!({
+                    "node_id": fault.node_id.as_ref(),
+                    "from_sec": fault.from.as_secs_f64(),
+                    "until_sec": fault.until.map(|until| until.as_secs_f64()),
+                    "ratio": fault.ratio,
+                    "fault": fault.kind.to_string(),
+                    "affected_packets": load(&stats.affected_packets),
+                    "skipped_packets": load(&stats.skipped_packets),
+                    "decompression_failures": load(&stats.decompression_failures),
+                    "decompressed": load(&stats.decompressed),
+                    "recovered_at_sec": stats.recovered_at.lock().map(|time| time.as_secs_f64()),
+                })
+            })
+            .collect();
+
+        serde_json::json!({ "faults": faults })
+    }
+}
+
+pub type SharedSchcDesync = Arc<SchcDesync>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::async_rt;
+    use crate::schc_compressor::SchcCompressor;
+    use crate::schc_provisioning::INITIAL_CONTEXT_VERSION;
+
+    /// A compressor knowing rule 1, with an 8-bit rule ID
+    fn compressor() -> SharedSchcCompressor {
+        let rules = r#"{ "rules": [{ "RuleID": 1, "RuleIDLength": 8, "Compression": [
+            { "FID": "QUIC.DCID", "DI": "Bi", "MO": "ignore", "CDA": "value-sent" }
+        ] }] }"#;
+
+        // Tests run in parallel, each in its own thread
+        let file_name = format!("schc-desync-{:?}.json", std::thread::current().id());
+        let path = std::env::temp_dir().join(file_name);
+        std::fs::write(&path, rules).unwrap();
+        let compressor = SchcCompressor::from_files(path.to_str().unwrap(), "", false);
+        std::fs::remove_file(&path).unwrap();
+        Arc::new(compressor.unwrap())
+    }
+
+    /// A packet compressed by `SchcNode2`, with a 3-byte SCHC header
+    fn state(rule_id: u32) -> SchcCompressionState {
+        SchcCompressionState {
+            rule_id,
+            rule_id_length: None,
+            compressor_id: Arc::from("SchcNode2"),
+            context_id: None,
+            context_version: INITIAL_CONTEXT_VERSION,
+            header_size: 3,
+            original_size: 64,
+            original_datagram: None,
+        }
+    }
+
+    /// Returns the index of the single bit that differs between both packets
+    fn mutated_bit(original: &[u8], mutated: &[u8]) -> usize {
+        let diff: Vec<_> = (0..original.len() * 8)
+            .filter(|bit| (original[bit / 8] ^ mutated[bit / 8]) & (0x80 >> (bit % 8)) != 0)
+            .collect();
+        assert_eq!(diff.len(), 1, "expected a single mutated bit");
+        diff[0]
+    }
+
+    fn fault(from_ms: u64, until_ms: Option<u64>) -> SchcDesyncFault {
+        SchcDesyncFault {
+            node_id: Arc::from("SchcNode1"),
+            from: Duration::from_millis(from_ms),
+            until: until_ms.map(Duration::from_millis),
+            ratio: 1.0,
+            kind: SchcDesyncFaultKind::RuleId,
+        }
+    }
+
+    #[test]
+    fn test_fault_windows() {
+        let bounded = fault(1000, Some(2000));
+        assert!(!bounded.is_active("SchcNode1", Duration::from_millis(999)));
+        assert!(bounded.is_active("SchcNode1", Duration::from_millis(1000)));
+        assert!(!bounded.is_active("SchcNode2", Duration::from_millis(1500)));
+        assert!(!bounded.is_active("SchcNode1", Duration::from_millis(2000)));
+        assert!(fault(1000, None).is_active("SchcNode1", Duration::from_secs(3600)));
+
+        let start = Instant::now();
+        let desync = SchcDesync::new(vec![bounded, fault(0, Some(5000))], start, 0, false);
+        assert_eq!(
+            desync.unwrap().last_fault_end(),
+            Some(Duration::from_secs(5))
+        );
+        let desync = SchcDesync::new(vec![fault(0, Some(5000)), fault(0, None)], start, 0, false);
+        assert_eq!(desync.unwrap().last_fault_end(), None);
+
+        assert!(SchcDesync::new(vec![fault(2000, Some(1000))], start, 0, false).is_err());
+    }
+
+    #[test]
+    fn test_mutated_bits() {
+        let compressor = compressor();
+        let original = [0x01, 0x5a, 0xa5, 0xff, 0xff, 0xff];
+        let rule_id = fault(0, None);
+        let residue = SchcDesyncFault {
+            kind: SchcDesyncFaultKind::Residue,
+            ..fault(0, None)
+        };
+        let desync = SchcDesync::new(vec![rule_id], Instant::now(), 0, false).unwrap();
+        let residue_desync = SchcDesync::new(vec![residue], Instant::now(), 0, false).unwrap();
+
+        for _ in 0..50 {
+            // Rule ID faults flip a bit of the 8-bit rule ID
+            let mut contents = original;
+            let applied = desync.apply("SchcNode1", &mut contents, &state(1), Some(&compressor));
+            assert!(applied.is_some_and(|applied| applied.compressor.is_none()));
+            assert!(mutated_bit(&original, &contents) < 8);
+
+            // Residue faults flip a bit between the rule ID and the end of the SCHC header
+            let mut contents = original;
+            let applied =
+                residue_desync.apply("SchcNode1", &mut contents, &state(1), Some(&compressor));
+            assert!(applied.is_some());
+            assert!((8..24).contains(&mutated_bit(&original, &contents)));
+        }
+
+        // Other nodes are not affected
+        let mut contents = original;
+        assert!(
+            desync
+                .apply("SchcNode2", &mut contents, &state(1), Some(&compressor))
+                .is_none()
+        );
+        assert_eq!(contents, original);
+
+        // Packets whose rule ID length is unknown are left untouched, and counted as skipped
+        let mut contents = original;
+        assert!(
+            desync
+                .apply("SchcNode1", &mut contents, &state(7), Some(&compressor))
+                .is_none()
+        );
+        assert!(
+            desync
+                .apply("SchcNode1", &mut contents, &state(1), None)
+                .is_none()
+        );
+        assert_eq!(contents, original);
+
+        let stats = &desync.faults[0].1;
+        assert_eq!(stats.affected_packets.load(Ordering::Relaxed), 50);
+        assert_eq!(stats.skipped_packets.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_ratio() {
+        let compressor = compressor();
+        let affected_packets = |ratio: f64| {
+            let fault = SchcDesyncFault {
+                ratio,
+                ..fault(0, None)
+            };
+            let desync = SchcDesync::new(vec![fault], Instant::now(), 42, false).unwrap();
+            (0..1000)
+                .filter(|_| {
+                    let mut contents = [0x01, 0x00, 0x00];
+                    desync
+                        .apply("SchcNode1", &mut contents, &state(1), Some(&compressor))
+                        .is_some()
+                })
+                .count()
+        };
+
+        assert_eq!(affected_packets(0.0), 0);
+        assert!((200..300).contains(&affected_packets(0.25)));
+        assert_eq!(affected_packets(1.0), 1000);
+
+        // The same seed affects the same packets
+        assert_eq!(affected_packets(0.5), affected_packets(0.5));
+    }
+
+    #[test]
+    fn test_recovery() {
+        let compressor = compressor();
+        async_rt::new_rt().block_on(async {
+            let desync = SchcDesync::new(vec![fault(0, Some(1000))], Instant::now(), 0, false);
+            let desync = desync.unwrap();
+            let stats = &desync.faults[0].1;
+
+            let mut contents = [0x01, 0x00, 0x00];
+            let applied = desync.apply("SchcNode1", &mut contents, &state(1), Some(&compressor));
+            assert!(applied.is_some());
+            desync.decompressed("SchcNode1", applied, false);
+            assert_eq!(stats.decompression_failures.load(Ordering::Relaxed), 1);
+
+            // Packets that escape the fault don't count as a recovery while it lasts
+            desync.decompressed("SchcNode1", None, true);
+            assert_eq!(*stats.recovered_at.lock(), None);
+
+            async_rt::time::sleep(Duration::from_millis(1500)).await;
+            let mut contents = [0x01, 0x00, 0x00];
+            let applied = desync.apply("SchcNode1", &mut contents, &state(1), Some(&compressor));
+            assert!(applied.is_none());
+
+            // Only packets decompressed by the faulty node count as a recovery
+            desync.decompressed("SchcNode2", None, true);
+            assert_eq!(*stats.recovered_at.lock(), None);
+            assert_eq!(desync.unrecovered_faults(), 1);
+            desync.decompressed("SchcNode1", None, true);
+            assert_eq!(desync.unrecovered_faults(), 0);
+            let recovered_at = stats.recovered_at.lock().unwrap();
+            assert!(recovered_at >= Duration::from_millis(1500));
+
+            // Later packets don't move the recovery time
+            async_rt::time::sleep(Duration::from_millis(500)).await;
+            desync.decompressed("SchcNode1", None, true);
+            assert_eq!(*stats.recovered_at.lock(), Some(recovered_at));
+        });
+    }
+}
@@ -0,0 +1,2013 @@
+//! SCHC Fragmentation/Reassembly (RFC 8724)
+//!
+//! Splits SCHC packets that don't fit in a single frame into SCHC fragments at the compressing
+//! node, and reassembles them before decompression at the decompressing node. Supports the
+//! No-ACK, ACK-Always and ACK-on-Error modes. Retransmission and inactivity timers run on the
+//! simulated clock, so they interact with link delays and outages like any other packet would.
+//!
+//! Each fragment carries exactly one tile. All tiles have the same size, chosen so the last tile
+//! fits in the All-1 fragment next to the RCS. ACKs travel back towards the source of the
+//! original packet and are picked up by the node that fragmented it (routes are assumed to be
+//! symmetric).
+
+use crate::InTransitData;
+use crate::async_rt;
+use crate::async_rt::time::Instant;
+use crate::network::InMemoryNetwork;
+use crate::network::node::Node;
+use crate::transmit::OwnedTransmit;
+use anyhow::bail;
+use parking_lot::Mutex;
+use quinn::udp::EcnCodepoint;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Size of the Rule ID field, in bits
+pub(crate) const RULE_ID_BITS: usize = 8;
+/// Size of the DTag field, in bits
+const DTAG_BITS: usize = 8;
+/// Size of the RCS field, in bits (CRC32)
+const RCS_BITS: usize = 32;
+/// Size of an L2 Word, in bits
+const L2_WORD_BITS: usize = 8;
+
+/// SCHC F/R reliability mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FragmentationMode {
+    NoAck,
+    AckAlways,
+    AckOnError,
+}
+
+impl FragmentationMode {
+    /// Size of the W field, in bits (M)
+    fn window_bits(self) -> usize {
+        match self {
+            FragmentationMode::NoAck => 0,
+            FragmentationMode::AckAlways => 1,
+            FragmentationMode::AckOnError => 2,
+        }
+    }
+
+    /// Size of the FCN field, in bits (N)
+    fn fcn_bits(self) -> usize {
+        match self {
+            FragmentationMode::NoAck => 1,
+            FragmentationMode::AckAlways => 3,
+            FragmentationMode::AckOnError => 6,
+        }
+    }
+
+    /// Number of tiles per window (WINDOW_SIZE)
+    fn window_size(self) -> usize {
+        (1 << self.fcn_bits()) - 1
+    }
+
+    /// The FCN value with all bits set, used by the All-1 fragment
+    fn fcn_all_1(self) -> u64 {
+        (1 << self.fcn_bits()) - 1
+    }
+}
+
+impl Display for FragmentationMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FragmentationMode::NoAck => f.write_str("no-ack"),
+            FragmentationMode::AckAlways => f.write_str("ack-always"),
+            FragmentationMode::AckOnError => f.write_str("ack-on-error"),
+        }
+    }
+}
+
+impl FromStr for FragmentationMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "no-ack" => Ok(FragmentationMode::NoAck),
+            "ack-always" => Ok(FragmentationMode::AckAlways),
+            "ack-on-error" => Ok(FragmentationMode::AckOnError),
+            _ => bail!(
+                "invalid fragmentation mode `{s}` (expected no-ack, ack-always or ack-on-error)"
+            ),
+        }
+    }
+}
+
+/// Parameters shared by the fragmenting and reassembling nodes
+#[derive(Clone, Debug)]
+pub struct FragmentationConfig {
+    pub mode: FragmentationMode,
+    /// Rule ID used for fragments and ACKs (must not collide with the compression rules)
+    pub rule_id: u8,
+    /// Maximum size of a SCHC fragment in bytes; larger SCHC packets are fragmented
+    pub mtu_bytes: usize,
+    /// Time the sender waits for an ACK before sending an ACK REQ
+    pub retransmission_timeout: Duration,
+    /// Number of consecutive unanswered ACK REQs before the sender aborts
+    pub max_ack_requests: u32,
+    /// Time the receiver waits for the next fragment before aborting the reassembly
+    pub inactivity_timeout: Duration,
+}
+
+impl FragmentationConfig {
+    /// Size of the header shared by all fragments, in bits
+    fn header_bits(&self) -> usize {
+        RULE_ID_BITS + DTAG_BITS + self.mode.window_bits() + self.mode.fcn_bits()
+    }
+
+    /// Size of every tile (except the last one, which may be shorter), in bytes
+    fn tile_size(&self) -> usize {
+        (self.mtu_bytes * 8).saturating_sub(self.header_bits() + RCS_BITS) / 8
+    }
+}
+
+/// Simulation metadata attached to SCHC fragments and ACKs while they travel between the
+/// fragmenting and the reassembling node
+#[derive(Clone, Debug)]
+pub(crate) struct FragmentMeta {
+    /// Node that fragmented the packet (and to which ACKs are addressed)
+    fragmenter_id: Arc<str>,
+    /// Whether this is an ACK (or Receiver-Abort) travelling back to the fragmenter
+    is_ack: bool,
+    /// Id of the packet that was fragmented, reused by the reassembled packet
+    original_id: Uuid,
+    /// Number of the packet that was fragmented, reused by the reassembled packet
+    original_number: u64,
+}
+
+impl FragmentMeta {
+    pub(crate) fn is_ack_for(&self, node_id: &str) -> bool {
+        self.is_ack && self.fragmenter_id.as_ref() == node_id
+    }
+
+    pub(crate) fn is_fragment(&self) -> bool {
+        !self.is_ack
+    }
+}
+
+/// Statistics from SCHC fragmentation and reassembly
+#[derive(Debug, Default)]
+pub struct SchcFragmentationStats {
+    pub packets_fragmented: AtomicUsize,
+    /// Packets above the fragment MTU that were forwarded unfragmented, because they could not be
+    /// compressed (they are still IP datagrams, not SCHC packets)
+    pub uncompressed_packets_skipped: AtomicUsize,
+    pub fragments_sent: AtomicUsize,
+    pub fragments_retransmitted: AtomicUsize,
+    pub ack_requests_sent: AtomicUsize,
+    pub acks_sent: AtomicUsize,
+    pub packets_reassembled: AtomicUsize,
+    pub reassembly_failures: AtomicUsize,
+    pub sender_aborts: AtomicUsize,
+    pub receiver_aborts: AtomicUsize,
+}
+
+impl SchcFragmentationStats {
+    pub fn report(&self) {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+
+        println!("--- SCHC Fragmentation Statistics ---");
+        println!("* Packets fragmented: {}", load(&self.packets_fragmented));
+        println!(
+            "* Uncompressed packets left unfragmented: {}",
+            load(&self.uncompressed_packets_skipped)
+        );
+        println!("* Fragments sent: {}", load(&self.fragments_sent));
+        println!(
+            "* Fragments retransmitted: {}",
+            load(&self.fragments_retransmitted)
+        );
+        println!("* ACK REQs sent: {}", load(&self.ack_requests_sent));
+        println!("* ACKs sent: {}", load(&self.acks_sent));
+        println!("* Packets reassembled: {}", load(&self.packets_reassembled));
+        println!("* Reassembly failures: {}", load(&self.reassembly_failures));
+        println!("* Sender aborts: {}", load(&self.sender_aborts));
+        println!("* Receiver aborts: {}", load(&self.receiver_aborts));
+    }
+
+    /// Returns the statistics as JSON, for the machine-readable report
+    pub fn to_json(&self) -> serde_json::Value {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+        serde_json::json!({
+            "packets_fragmented": load(&self.packets_fragmented),
+            "uncompressed_packets_skipped": load(&self.uncompressed_packets_skipped),
+            "fragments_sent": load(&self.fragments_sent),
+            "fragments_retransmitted": load(&self.fragments_retransmitted),
+            "ack_requests_sent": load(&self.ack_requests_sent),
+            "acks_sent": load(&self.acks_sent),
+            "packets_reassembled": load(&self.packets_reassembled),
+            "reassembly_failures": load(&self.reassembly_failures),
+            "sender_aborts": load(&self.sender_aborts),
+            "receiver_aborts": load(&self.receiver_aborts),
+        })
+    }
+}
+
+/// Key of a fragmentation: fragmenting node and DTag
+type SenderKey = (Arc<str>, u8);
+
+/// Key of a reassembly: reassembling node, fragmenting node and DTag
+type ReceiverKey = (Arc<str>, Arc<str>, u8);
+
+/// SCHC fragmenter and reassembler, shared by all compression nodes
+pub struct SchcFragmenter {
+    config: FragmentationConfig,
+    /// Ongoing fragmentations, by fragmenting node and DTag
+    senders: Mutex<HashMap<SenderKey, OutgoingPacket>>,
+    /// Ongoing reassemblies, by reassembling node, fragmenting node and DTag
+    receivers: Mutex<HashMap<ReceiverKey, IncomingPacket>>,
+    /// Finished reassemblies and when they expire, kept for an inactivity timeout to recognize
+    /// late and duplicated fragments
+    completed: Mutex<HashMap<ReceiverKey, (ReceiverSession, Instant)>>,
+    /// Next DTag to use, by fragmenting node
+    next_dtag: Mutex<HashMap<Arc<str>, u8>>,
+    stats: SchcFragmentationStats,
+    debug: bool,
+}
+
+impl SchcFragmenter {
+    pub fn new(config: FragmentationConfig, debug: bool) -> anyhow::Result<Self> {
+        if config.tile_size() == 0 {
+            bail!(
+                "the SCHC fragment MTU is too small ({} bytes), the All-1 fragment header alone takes {} bits",
+                config.mtu_bytes,
+                config.header_bits() + RCS_BITS
+            );
+        }
+
+        Ok(Self {
+            config,
+            senders: Mutex::default(),
+            receivers: Mutex::default(),
+            completed: Mutex::default(),
+            next_dtag: Mutex::default(),
+            stats: SchcFragmentationStats::default(),
+            debug,
+        })
+    }
+
+    pub fn config(&self) -> &FragmentationConfig {
+        &self.config
+    }
+
+    /// Get statistics
+    pub fn stats(&self) -> &SchcFragmentationStats {
+        &self.stats
+    }
+
+    /// Fragments the SCHC packet contained in `data`, or gives it back if it fits in a single
+    /// frame (or cannot be fragmented)
+    pub(crate) fn fragment(
+        self: &Arc<Self>,
+        network: &Arc<InMemoryNetwork>,
+        node: &Arc<Node>,
+        data: InTransitData,
+    ) -> Option<InTransitData> {
+        let packet_len = data.transmit.contents.len();
+        if packet_len <= self.config.mtu_bytes {
+            return Some(data);
+        }
+
+        let mode = self.config.mode;
+        let session = SenderSession::new(&data.transmit.contents, self.config.tile_size());
+        let windows = session.last_window(mode) + 1;
+        if mode == FragmentationMode::AckOnError && windows > 1 << mode.window_bits() {
+            println!(
+                "[SCHC Fragment @ {}] WARN packet of {packet_len} bytes needs {windows} windows, but W only has {} bits; sending it unfragmented",
+                node.id,
+                mode.window_bits()
+            );
+            return Some(data);
+        }
+
+        // Reusing the DTag of an ongoing fragmentation would mix up its fragments and ACKs
+        let mut senders = self.senders.lock();
+        let Some(dtag) = self.allocate_dtag(&node.id, |dtag| {
+            senders.contains_key(&(node.id.clone(), dtag))
+        }) else {
+            println!(
+                "[SCHC Fragment @ {}] WARN all DTags are taken by ongoing fragmentations; sending the packet unfragmented",
+                node.id
+            );
+            return Some(data);
+        };
+
+        if self.debug {
+            println!(
+                "[SCHC Fragment @ {}] [{}] SCHC packet: {packet_len} bytes → {} tiles of {} bytes (DTag {dtag})",
+                node.id,
+                mode,
+                session.tiles.len(),
+                self.config.tile_size()
+            );
+        }
+
+        network.tracer.track_schc_packet_consumed(node, &data);
+        self.stats
+            .packets_fragmented
+            .fetch_add(1, Ordering::Relaxed);
+
+        let messages = match mode {
+            FragmentationMode::NoAck => session.all_messages(dtag, mode),
+            FragmentationMode::AckAlways => session.window_messages(dtag, 0, mode),
+            FragmentationMode::AckOnError => session.all_messages(dtag, mode),
+        };
+        let packet = OutgoingPacket {
+            origin: data,
+            session,
+        };
+
+        let key = (node.id.clone(), dtag);
+        if mode != FragmentationMode::NoAck {
+            senders.insert(key.clone(), packet.clone());
+        }
+        drop(senders);
+
+        self.send_fragments(network, node, &packet, &messages, false);
+
+        if mode != FragmentationMode::NoAck {
+            self.arm_timer(network, node, SessionTimer::Retransmission(key, 0));
+        }
+
+        None
+    }
+
+    /// Records a packet the compressor failed to compress, which is forwarded unfragmented (its
+    /// fragments would be priced as SCHC frames, hiding the IP/UDP headers it still carries)
+    pub(crate) fn skip_uncompressed(&self, node: &Node, data: &InTransitData) {
+        let packet_len = data.transmit.contents.len();
+        if packet_len <= self.config.mtu_bytes {
+            return;
+        }
+
+        self.stats
+            .uncompressed_packets_skipped
+            .fetch_add(1, Ordering::Relaxed);
+        if self.debug {
+            println!(
+                "[SCHC Fragment @ {}] Uncompressed packet of {packet_len} bytes exceeds the fragment MTU; sending it unfragmented",
+                node.id
+            );
+        }
+    }
+
+    /// Picks the next DTag of the node for which `in_use` is false, or `None` if there is none
+    fn allocate_dtag(&self, node_id: &Arc<str>, in_use: impl Fn(u8) -> bool) -> Option<u8> {
+        let mut next_dtag = self.next_dtag.lock();
+        let next = next_dtag.entry(node_id.clone()).or_default();
+        let dtag = (0..=u8::MAX)
+            .map(|offset| next.wrapping_add(offset))
+            .find(|&dtag| !in_use(dtag))?;
+        *next = dtag.wrapping_add(1);
+        Some(dtag)
+    }
+
+    /// Handles an ACK (or Receiver-Abort) that reached the node that fragmented the packet
+    pub(crate) fn handle_ack(
+        self: &Arc<Self>,
+        network: &Arc<InMemoryNetwork>,
+        node: &Arc<Node>,
+        data: InTransitData,
+    ) {
+        network.tracer.track_schc_packet_consumed(node, &data);
+
+        let Some(ack) = AckMessage::decode(&data.transmit.contents, &self.config) else {
+            println!("[SCHC Fragment @ {}] WARN dropping malformed ACK", node.id);
+            return;
+        };
+
+        let mut senders = self.senders.lock();
+        let key = (node.id.clone(), ack.dtag());
+        let Some(packet) = senders.get_mut(&key) else {
+            // Late ACK for a session that is already finished
+            return;
+        };
+
+        let dtag = key.1;
+        let (messages, retransmission) = match packet.session.on_ack(dtag, ack, self.config.mode) {
+            AckOutcome::Stale => return,
+            AckOutcome::Aborted => {
+                println!(
+                    "[SCHC Fragment @ {}] Receiver aborted the reassembly (DTag {dtag})",
+                    node.id
+                );
+                self.stats.receiver_aborts.fetch_add(1, Ordering::Relaxed);
+                senders.remove(&key);
+                return;
+            }
+            AckOutcome::Complete { window } => {
+                if self.debug {
+                    println!(
+                        "[SCHC Fragment @ {}] Reassembly confirmed (DTag {dtag}, W {window})",
+                        node.id
+                    );
+                }
+                senders.remove(&key);
+                return;
+            }
+            AckOutcome::NextWindow(messages) => (messages, false),
+            AckOutcome::Retransmit {
+                window,
+                missing,
+                messages,
+            } => {
+                if self.debug {
+                    println!(
+                        "[SCHC Fragment @ {}] Retransmitting {missing} tile(s) of window {window} (DTag {dtag})",
+                        node.id
+                    );
+                }
+                (messages, true)
+            }
+        };
+
+        let generation = packet.session.timer_generation;
+        let packet = packet.clone();
+        drop(senders);
+
+        self.send_fragments(network, node, &packet, &messages, retransmission);
+        self.arm_timer(network, node, SessionTimer::Retransmission(key, generation));
+    }
+
+    /// Handles a fragment that reached the reassembling node, returning the reassembled packet
+    /// once all tiles are available
+    pub(crate) fn handle_fragment(
+        self: &Arc<Self>,
+        network: &Arc<InMemoryNetwork>,
+        node: &Arc<Node>,
+        data: InTransitData,
+    ) -> Option<InTransitData> {
+        network.tracer.track_schc_packet_consumed(node, &data);
+
+        let mode = self.config.mode;
+        let meta = data.schc_fragment.clone()?;
+        let Some(message) = FragmentMessage::decode(&data.transmit.contents, &self.config) else {
+            println!(
+                "[SCHC Reassemble @ {}] WARN dropping malformed fragment",
+                node.id
+            );
+            return None;
+        };
+
+        let dtag = message.dtag();
+        let key = (node.id.clone(), meta.fragmenter_id.clone(), dtag);
+        let mut receivers = self.receivers.lock();
+        let mut completed = self.completed.lock();
+        let now = Instant::now();
+        completed.retain(|_, (_, expiry)| *expiry > now);
+
+        if let Some((session, _)) = completed.get(&key) {
+            if session.is_duplicate(&message, data.number, mode) {
+                // Late or duplicated fragment of a packet that was already reassembled, so the
+                // sender may still be waiting for the ACK confirming it
+                let ack = message
+                    .asks_for_ack(mode)
+                    .then(|| ack_data(network, node, &data, &session.ack_complete(), &self.config));
+                drop(completed);
+                drop(receivers);
+
+                if self.debug {
+                    println!(
+                        "[SCHC Reassemble @ {}] Fragment of an already reassembled packet (DTag {dtag})",
+                        node.id
+                    );
+                }
+                if let Some(ack) = ack {
+                    self.stats.acks_sent.fetch_add(1, Ordering::Relaxed);
+                    network.emit_schc_packet(node, ack);
+                }
+
+                return None;
+            }
+
+            // The DTag was reused for a new packet
+            completed.remove(&key);
+        }
+
+        let packet = receivers
+            .entry(key.clone())
+            .or_insert_with(|| IncomingPacket::new(data.clone(), dtag));
+        packet.congestion_experienced |= data
+            .transmit
+            .ecn
+            .is_some_and(|codepoint| codepoint as u8 == 0b11);
+        packet.timer_generation += 1;
+        let generation = packet.timer_generation;
+
+        let (ack, schc_packet) = match packet.session.receive(message, data.number, mode) {
+            Reception::Aborted => {
+                println!(
+                    "[SCHC Reassemble @ {}] Sender aborted the fragmentation (DTag {dtag})",
+                    node.id
+                );
+                receivers.remove(&key);
+                return None;
+            }
+            Reception::Pending(ack) => (ack, None),
+            Reception::Reassembled(schc_packet, ack) => (ack, Some(schc_packet)),
+        };
+
+        let ack = ack.map(|ack| ack_data(network, node, &packet.first, &ack, &self.config));
+        let reassembled = schc_packet.map(|schc_packet| {
+            let packet = receivers.remove(&key).unwrap();
+            let reassembled = packet.reassembled_data(schc_packet);
+            let expiry = now + self.config.inactivity_timeout;
+            completed.insert(key.clone(), (packet.session, expiry));
+            reassembled
+        });
+        drop(completed);
+        drop(receivers);
+
+        if let Some(ack) = ack {
+            self.stats.acks_sent.fetch_add(1, Ordering::Relaxed);
+            network.emit_schc_packet(node, ack);
+        }
+
+        let Some(reassembled) = reassembled else {
+            self.arm_timer(network, node, SessionTimer::Inactivity(key, generation));
+            return None;
+        };
+
+        if self.debug {
+            println!(
+                "[SCHC Reassemble @ {}] [{}] Reassembled SCHC packet: {} bytes",
+                node.id,
+                mode,
+                reassembled.transmit.contents.len()
+            );
+        }
+        self.stats
+            .packets_reassembled
+            .fetch_add(1, Ordering::Relaxed);
+        network.tracer.track_schc_packet_created(node, &reassembled);
+        network.tracer.track_packet_in_node(node, &reassembled);
+        Some(reassembled)
+    }
+
+    fn timer_expired(
+        self: &Arc<Self>,
+        network: &Arc<InMemoryNetwork>,
+        node: &Arc<Node>,
+        timer: SessionTimer,
+    ) {
+        match timer {
+            SessionTimer::Retransmission(key, generation) => {
+                let mut senders = self.senders.lock();
+                let Some(packet) = senders.get_mut(&key) else {
+                    return;
+                };
+
+                let timeout = packet.session.on_timeout(
+                    key.1,
+                    generation,
+                    self.config.max_ack_requests,
+                    self.config.mode,
+                );
+                match timeout {
+                    RetransmissionTimeout::Stale => {}
+                    RetransmissionTimeout::AckReq(ack_req) => {
+                        let attempts = packet.session.attempts;
+                        let generation = packet.session.timer_generation;
+                        let packet = packet.clone();
+                        drop(senders);
+
+                        if self.debug {
+                            println!(
+                                "[SCHC Fragment @ {}] Retransmission timer expired, sending ACK REQ (DTag {}, attempt {attempts})",
+                                node.id, key.1
+                            );
+                        }
+                        self.stats.ack_requests_sent.fetch_add(1, Ordering::Relaxed);
+                        network.emit_schc_packet(
+                            node,
+                            packet.fragment_data(network, node, &ack_req, &self.config),
+                        );
+                        self.arm_timer(
+                            network,
+                            node,
+                            SessionTimer::Retransmission(key, generation),
+                        );
+                    }
+                    RetransmissionTimeout::Abort(abort) => {
+                        let packet = senders.remove(&key).unwrap();
+                        drop(senders);
+
+                        println!(
+                            "[SCHC Fragment @ {}] No ACK after {} ACK REQs, aborting fragmentation (DTag {})",
+                            node.id, self.config.max_ack_requests, key.1
+                        );
+                        self.stats.sender_aborts.fetch_add(1, Ordering::Relaxed);
+                        network.emit_schc_packet(
+                            node,
+                            packet.fragment_data(network, node, &abort, &self.config),
+                        );
+                    }
+                }
+            }
+            SessionTimer::Inactivity(key, generation) => {
+                let mut receivers = self.receivers.lock();
+                let Some(packet) = receivers.get(&key) else {
+                    return;
+                };
+                if packet.timer_generation != generation {
+                    return;
+                }
+
+                let packet = receivers.remove(&key).unwrap();
+                drop(receivers);
+
+                if self.config.mode == FragmentationMode::NoAck && packet.session.last.is_some() {
+                    // No late tile made the RCS match, and without ACKs there is no way to recover
+                    println!(
+                        "[SCHC Reassemble @ {}] WARN RCS mismatch, dropping packet (DTag {})",
+                        node.id, key.2
+                    );
+                    self.stats
+                        .reassembly_failures
+                        .fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+
+                println!(
+                    "[SCHC Reassemble @ {}] Inactivity timer expired, aborting reassembly (DTag {})",
+                    node.id, key.2
+                );
+                self.stats.receiver_aborts.fetch_add(1, Ordering::Relaxed);
+                if self.config.mode != FragmentationMode::NoAck {
+                    let abort = AckMessage::ReceiverAbort { dtag: key.2 };
+                    let abort = ack_data(network, node, &packet.first, &abort, &self.config);
+                    network.emit_schc_packet(node, abort);
+                }
+            }
+        }
+    }
+
+    fn send_fragments(
+        &self,
+        network: &Arc<InMemoryNetwork>,
+        node: &Arc<Node>,
+        packet: &OutgoingPacket,
+        messages: &[FragmentMessage],
+        retransmission: bool,
+    ) {
+        for message in messages {
+            match message {
+                FragmentMessage::AckReq { .. } => {
+                    self.stats.ack_requests_sent.fetch_add(1, Ordering::Relaxed);
+                }
+                _ if retransmission => {
+                    self.stats
+                        .fragments_retransmitted
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+                _ => {
+                    self.stats.fragments_sent.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+
+            let data = packet.fragment_data(network, node, message, &self.config);
+            network.emit_schc_packet(node, data);
+        }
+    }
+
+    fn arm_timer(
+        self: &Arc<Self>,
+        network: &Arc<InMemoryNetwork>,
+        node: &Arc<Node>,
+        timer: SessionTimer,
+    ) {
+        let timeout = match timer {
+            SessionTimer::Retransmission(..) => self.config.retransmission_timeout,
+            SessionTimer::Inactivity(..) => self.config.inactivity_timeout,
+        };
+
+        let fragmenter = self.clone();
+        let network = Arc::downgrade(network);
+        let node = node.clone();
+        let deadline = Instant::now() + timeout;
+        async_rt::spawn(async move {
+            async_rt::time::sleep_until(deadline).await;
+            if let Some(network) = network.upgrade() {
+                fragmenter.timer_expired(&network, &node, timer);
+            }
+        });
+    }
+}
+
+/// Thread-safe wrapper for SCHC fragmenter
+pub type SharedSchcFragmenter = Arc<SchcFragmenter>;
+
+/// A session timer, tagged with the generation it was armed for (stale timers are ignored)
+enum SessionTimer {
+    Retransmission(SenderKey, u64),
+    Inactivity(ReceiverKey, u64),
+}
+
+/// A packet being fragmented
+#[derive(Clone)]
+struct OutgoingPacket {
+    /// The packet that was fragmented
+    origin: InTransitData,
+    session: SenderSession,
+}
+
+impl OutgoingPacket {
+    fn fragment_data(
+        &self,
+        network: &InMemoryNetwork,
+        node: &Node,
+        message: &FragmentMessage,
+        config: &FragmentationConfig,
+    ) -> InTransitData {
+        InTransitData {
+            id: network.new_packet_id(),
+            duplicate: false,
+            source_endpoint: self.origin.source_endpoint.clone(),
+            source_id: self.origin.source_id.clone(),
+            transmit: OwnedTransmit {
+                destination: self.origin.transmit.destination,
+                ecn: self.origin.transmit.ecn,
+                contents: message.encode(config),
+                segment_size: None,
+            },
+            number: network.next_packet_number(),
+            schc_fragment: Some(FragmentMeta {
+                fragmenter_id: node.id.clone(),
+                is_ack: false,
+                original_id: self.origin.id,
+                original_number: self.origin.number,
+            }),
+            // Restored when the fragments are reassembled
+            schc_compressed: self.origin.schc_compressed.clone(),
+            schc_rule_update: None,
+        }
+    }
+}
+
+/// What the sender does after receiving an ACK
+#[derive(Debug, PartialEq)]
+enum AckOutcome {
+    /// The ACK is about a window the sender already moved past
+    Stale,
+    /// The receiver aborted the reassembly
+    Aborted,
+    /// The receiver reassembled the packet
+    Complete { window: u8 },
+    /// The window was acknowledged, these are the fragments of the next one (ACK-Always)
+    NextWindow(Vec<FragmentMessage>),
+    /// Some tiles of the window are missing, these are the fragments resending them
+    Retransmit {
+        window: usize,
+        missing: usize,
+        messages: Vec<FragmentMessage>,
+    },
+}
+
+/// What the sender does when its retransmission timer expires
+#[derive(Debug, PartialEq)]
+enum RetransmissionTimeout {
+    /// The timer was re-armed since, so it is ignored
+    Stale,
+    AckReq(FragmentMessage),
+    /// Too many ACK REQs went unanswered
+    Abort(FragmentMessage),
+}
+
+/// Fragmentation state of a SCHC packet
+#[derive(Clone)]
+struct SenderSession {
+    tiles: Vec<Vec<u8>>,
+    rcs: u32,
+    /// Window awaiting an ACK (only used in ACK-Always mode)
+    current_window: usize,
+    /// ACK REQs sent since the last ACK
+    attempts: u32,
+    timer_generation: u64,
+}
+
+impl SenderSession {
+    fn new(schc_packet: &[u8], tile_size: usize) -> Self {
+        Self {
+            tiles: schc_packet
+                .chunks(tile_size)
+                .map(|tile| tile.to_vec())
+                .collect(),
+            rcs: crc32(schc_packet),
+            current_window: 0,
+            attempts: 0,
+            timer_generation: 0,
+        }
+    }
+
+    /// The window carrying the last tile
+    fn last_window(&self, mode: FragmentationMode) -> usize {
+        (self.tiles.len() - 1) / mode.window_size()
+    }
+
+    fn tile_message(&self, dtag: u8, tile: usize, mode: FragmentationMode) -> FragmentMessage {
+        let (window, fcn) = match mode {
+            FragmentationMode::NoAck => (0, 0),
+            _ => {
+                let window_size = mode.window_size();
+                (tile / window_size, window_size - 1 - tile % window_size)
+            }
+        };
+
+        FragmentMessage::Regular {
+            dtag,
+            window: window as u8,
+            fcn: fcn as u8,
+            tile: self.tiles[tile].clone(),
+        }
+    }
+
+    fn all_1_message(&self, dtag: u8, mode: FragmentationMode) -> FragmentMessage {
+        FragmentMessage::All1 {
+            dtag,
+            window: match mode {
+                FragmentationMode::NoAck => 0,
+                _ => self.last_window(mode) as u8,
+            },
+            rcs: self.rcs,
+            tile: self.tiles.last().unwrap().clone(),
+        }
+    }
+
+    fn all_messages(&self, dtag: u8, mode: FragmentationMode) -> Vec<FragmentMessage> {
+        let mut messages: Vec<_> = (0..self.tiles.len() - 1)
+            .map(|tile| self.tile_message(dtag, tile, mode))
+            .collect();
+        messages.push(self.all_1_message(dtag, mode));
+        messages
+    }
+
+    fn window_messages(
+        &self,
+        dtag: u8,
+        window: usize,
+        mode: FragmentationMode,
+    ) -> Vec<FragmentMessage> {
+        let window_size = mode.window_size();
+        let last_tile = self.tiles.len() - 1;
+        let tiles = window * window_size..((window + 1) * window_size).min(last_tile);
+        let mut messages: Vec<_> = tiles
+            .map(|tile| self.tile_message(dtag, tile, mode))
+            .collect();
+        if window == self.last_window(mode) {
+            messages.push(self.all_1_message(dtag, mode));
+        }
+
+        messages
+    }
+
+    /// Regular tiles of the window that the bitmap reports as missing (the last tile is always
+    /// resent through the All-1 fragment)
+    fn missing_tiles(&self, window: usize, bitmap: &[bool], mode: FragmentationMode) -> Vec<usize> {
+        let window_size = mode.window_size();
+        let last_tile = self.tiles.len() - 1;
+        bitmap
+            .iter()
+            .enumerate()
+            .filter(|&(_, &received)| !received)
+            .map(|(position, _)| window * window_size + position)
+            .filter(|&tile| tile < last_tile)
+            .collect()
+    }
+
+    /// Processes an ACK, re-arming the retransmission timer unless the session is over
+    fn on_ack(&mut self, dtag: u8, ack: AckMessage, mode: FragmentationMode) -> AckOutcome {
+        let (window, bitmap) = match ack {
+            AckMessage::ReceiverAbort { .. } => return AckOutcome::Aborted,
+            AckMessage::Ack {
+                window,
+                bitmap: None,
+                ..
+            } => return AckOutcome::Complete { window },
+            AckMessage::Ack {
+                window,
+                bitmap: Some(bitmap),
+                ..
+            } => (window, bitmap),
+        };
+
+        let window = match mode {
+            FragmentationMode::AckAlways => {
+                if window as usize != self.current_window % 2 {
+                    return AckOutcome::Stale;
+                }
+
+                self.current_window
+            }
+            _ => window as usize,
+        };
+
+        // The receiver is alive, so we start counting ACK REQs anew
+        self.attempts = 0;
+        self.timer_generation += 1;
+
+        let missing = self.missing_tiles(window, &bitmap, mode);
+        if mode == FragmentationMode::AckAlways
+            && missing.is_empty()
+            && window < self.last_window(mode)
+        {
+            // The window is complete, move on to the next one
+            self.current_window += 1;
+            return AckOutcome::NextWindow(self.window_messages(dtag, self.current_window, mode));
+        }
+
+        let mut messages: Vec<_> = missing
+            .iter()
+            .map(|&tile| self.tile_message(dtag, tile, mode))
+            .collect();
+
+        let closes_window = missing.contains(&((window + 1) * mode.window_size() - 1));
+        if window == self.last_window(mode) || mode == FragmentationMode::AckOnError {
+            // The All-1 carries the last tile and triggers a new ACK
+            messages.push(self.all_1_message(dtag, mode));
+        } else if !closes_window {
+            // The All-0 fragment wasn't retransmitted, so explicitly ask for an ACK
+            messages.push(FragmentMessage::AckReq {
+                dtag,
+                window: window as u8,
+            });
+        }
+
+        AckOutcome::Retransmit {
+            window,
+            missing: missing.len(),
+            messages,
+        }
+    }
+
+    /// Processes the expiry of the retransmission timer armed for `generation`
+    fn on_timeout(
+        &mut self,
+        dtag: u8,
+        generation: u64,
+        max_ack_requests: u32,
+        mode: FragmentationMode,
+    ) -> RetransmissionTimeout {
+        if self.timer_generation != generation {
+            return RetransmissionTimeout::Stale;
+        }
+
+        let window = match mode {
+            FragmentationMode::AckAlways => self.current_window,
+            _ => self.last_window(mode),
+        } as u8;
+
+        self.attempts += 1;
+        if self.attempts > max_ack_requests {
+            return RetransmissionTimeout::Abort(FragmentMessage::SenderAbort { dtag, window });
+        }
+
+        self.timer_generation += 1;
+        RetransmissionTimeout::AckReq(FragmentMessage::AckReq { dtag, window })
+    }
+}
+
+struct LastTile {
+    window: usize,
+    rcs: u32,
+    tile: Vec<u8>,
+}
+
+/// A packet being reassembled
+struct IncomingPacket {
+    /// The first fragment received, used as a template for ACKs and the reassembled packet
+    first: InTransitData,
+    congestion_experienced: bool,
+    timer_generation: u64,
+    session: ReceiverSession,
+}
+
+impl IncomingPacket {
+    fn new(first: InTransitData, dtag: u8) -> Self {
+        Self {
+            first,
+            congestion_experienced: false,
+            timer_generation: 0,
+            session: ReceiverSession::new(dtag),
+        }
+    }
+
+    fn reassembled_data(&self, schc_packet: Vec<u8>) -> InTransitData {
+        let meta = self.first.schc_fragment.as_ref().unwrap();
+        let ecn = if self.congestion_experienced {
+            EcnCodepoint::from_bits(0b11)
+        } else {
+            self.first.transmit.ecn
+        };
+
+        InTransitData {
+            id: meta.original_id,
+            duplicate: false,
+            source_endpoint: self.first.source_endpoint.clone(),
+            source_id: self.first.source_id.clone(),
+            transmit: OwnedTransmit {
+                destination: self.first.transmit.destination,
+                ecn,
+                contents: schc_packet,
+                segment_size: self.first.transmit.segment_size,
+            },
+            number: meta.original_number,
+            schc_fragment: None,
+            schc_compressed: self.first.schc_compressed.clone(),
+            schc_rule_update: None,
+        }
+    }
+}
+
+/// ACK (or Receiver-Abort) answering `fragment`, sent back to the node that fragmented it
+fn ack_data(
+    network: &InMemoryNetwork,
+    node: &Node,
+    fragment: &InTransitData,
+    ack: &AckMessage,
+    config: &FragmentationConfig,
+) -> InTransitData {
+    let destination_host = network.host(fragment.transmit.destination.ip());
+    InTransitData {
+        id: network.new_packet_id(),
+        duplicate: false,
+        source_endpoint: destination_host.udp_endpoint.clone().unwrap(),
+        source_id: node.id.clone(),
+        transmit: OwnedTransmit {
+            destination: fragment.source_endpoint.addr,
+            // ACKs are ECN-capable if the fragments are
+            ecn: fragment.transmit.ecn.map(|_| EcnCodepoint::Ect0),
+            contents: ack.encode(config),
+            segment_size: None,
+        },
+        number: network.next_packet_number(),
+        schc_fragment: Some(FragmentMeta {
+            is_ack: true,
+            ..fragment.schc_fragment.clone().unwrap()
+        }),
+        schc_compressed: None,
+        schc_rule_update: None,
+    }
+}
+
+/// What the receiver does after receiving a fragment
+#[derive(Debug, PartialEq)]
+enum Reception {
+    /// The packet isn't complete yet, and the ACK to send if the fragment asked for one
+    Pending(Option<AckMessage>),
+    /// The packet was reassembled, and the ACK to send if the fragment asked for one
+    Reassembled(Vec<u8>, Option<AckMessage>),
+    /// The sender aborted the fragmentation
+    Aborted,
+}
+
+/// Reassembly state of a SCHC packet
+struct ReceiverSession {
+    dtag: u8,
+    /// Regular tiles, by tile index (in No-ACK mode, where the FCN doesn't number tiles, by
+    /// arrival order)
+    tiles: Vec<Option<Vec<u8>>>,
+    /// Numbers of the fragments received, to recognize the ones the network duplicated
+    fragment_numbers: HashSet<u64>,
+    /// The tile carried by the All-1 fragment
+    last: Option<LastTile>,
+    /// Window currently being received (only used in ACK-Always mode)
+    current_window: usize,
+}
+
+impl ReceiverSession {
+    fn new(dtag: u8) -> Self {
+        Self {
+            dtag,
+            tiles: Vec::new(),
+            fragment_numbers: HashSet::new(),
+            last: None,
+            current_window: 0,
+        }
+    }
+
+    /// Processes a fragment, identified by the number of the packet that carried it
+    fn receive(
+        &mut self,
+        message: FragmentMessage,
+        number: u64,
+        mode: FragmentationMode,
+    ) -> Reception {
+        if !self.fragment_numbers.insert(number) && mode == FragmentationMode::NoAck {
+            // Duplicated by the network, so it would add the same tile twice
+            return Reception::Pending(None);
+        }
+
+        // The window to report on, if the message asks for an ACK
+        let mut ack_window = None;
+        match message {
+            FragmentMessage::Regular {
+                window, fcn, tile, ..
+            } => match mode {
+                FragmentationMode::NoAck => self.tiles.push(Some(tile)),
+                _ => {
+                    let window = self.absolute_window(window, mode);
+                    let index = window * mode.window_size() + mode.window_size() - 1 - fcn as usize;
+                    self.store_tile(index, tile);
+
+                    // In ACK-Always mode, the All-0 fragment closes a window and asks for an ACK
+                    if fcn == 0 && mode == FragmentationMode::AckAlways {
+                        ack_window = Some(window);
+                    }
+                }
+            },
+            FragmentMessage::All1 {
+                window, rcs, tile, ..
+            } => {
+                let window = self.absolute_window(window, mode);
+                self.last = Some(LastTile { window, rcs, tile });
+                if mode != FragmentationMode::NoAck {
+                    ack_window = Some(window);
+                }
+            }
+            FragmentMessage::AckReq { window, .. } => {
+                ack_window = Some(self.absolute_window(window, mode));
+            }
+            FragmentMessage::SenderAbort { .. } => return Reception::Aborted,
+        }
+
+        if let Some(schc_packet) = self.reassemble(mode) {
+            return Reception::Reassembled(schc_packet, ack_window.map(|_| self.ack_complete()));
+        }
+
+        // In No-ACK mode, an RCS mismatch may still be fixed by a tile that the All-1 overtook
+        Reception::Pending(ack_window.map(|window| {
+            if mode == FragmentationMode::AckOnError {
+                self.ack_on_error(mode)
+            } else {
+                self.ack(window, mode)
+            }
+        }))
+    }
+
+    /// Whether a fragment received after the reassembly belongs to the reassembled packet (as
+    /// opposed to a new packet that reuses the DTag)
+    fn is_duplicate(
+        &self,
+        message: &FragmentMessage,
+        number: u64,
+        mode: FragmentationMode,
+    ) -> bool {
+        if self.fragment_numbers.contains(&number) {
+            return true;
+        }
+
+        // Retransmissions are new packets, so they are recognized by their contents
+        let window_size = mode.window_size();
+        let truncated_window = |window: usize| window % (1 << mode.window_bits());
+        match message {
+            FragmentMessage::Regular {
+                window, fcn, tile, ..
+            } => {
+                mode != FragmentationMode::NoAck
+                    && self.tiles.iter().enumerate().any(|(index, stored)| {
+                        stored.as_ref() == Some(tile)
+                            && truncated_window(index / window_size) == *window as usize
+                            && window_size - 1 - index % window_size == *fcn as usize
+                    })
+            }
+            FragmentMessage::All1 { rcs, tile, .. } => self
+                .last
+                .as_ref()
+                .is_some_and(|last| last.rcs == *rcs && last.tile == *tile),
+            FragmentMessage::AckReq { window, .. } => self
+                .last
+                .as_ref()
+                .is_some_and(|last| truncated_window(last.window) == *window as usize),
+            FragmentMessage::SenderAbort { .. } => true,
+        }
+    }
+
+    /// Maps the (truncated) W field to an absolute window number
+    fn absolute_window(&mut self, window: u8, mode: FragmentationMode) -> usize {
+        match mode {
+            FragmentationMode::NoAck => 0,
+            // We never send more windows than W can represent
+            FragmentationMode::AckOnError => window as usize,
+            // The sender only moves on to the next window after this one was acknowledged as
+            // complete, so W alternates between the current and the next window
+            FragmentationMode::AckAlways => {
+                if window as usize != self.current_window % 2
+                    && !self
+                        .window_bitmap(self.current_window, mode)
+                        .contains(&false)
+                {
+                    self.current_window += 1;
+                }
+
+                self.current_window
+            }
+        }
+    }
+
+    fn store_tile(&mut self, index: usize, tile: Vec<u8>) {
+        if self.tiles.len() <= index {
+            self.tiles.resize(index + 1, None);
+        }
+
+        self.tiles[index] = Some(tile);
+    }
+
+    fn window_bitmap(&self, window: usize, mode: FragmentationMode) -> Vec<bool> {
+        let window_size = mode.window_size();
+        (window * window_size..(window + 1) * window_size)
+            .map(|index| self.tiles.get(index).is_some_and(|tile| tile.is_some()))
+            .collect()
+    }
+
+    /// ACK reporting the state of the provided window
+    fn ack(&self, window: usize, mode: FragmentationMode) -> AckMessage {
+        AckMessage::Ack {
+            dtag: self.dtag,
+            window: window as u8,
+            bitmap: Some(self.window_bitmap(window, mode)),
+        }
+    }
+
+    /// ACK confirming that the packet was reassembled
+    fn ack_complete(&self) -> AckMessage {
+        AckMessage::Ack {
+            dtag: self.dtag,
+            window: self.last.as_ref().map_or(0, |last| last.window) as u8,
+            bitmap: None,
+        }
+    }
+
+    /// ACK reporting the lowest window with missing tiles (ACK-on-Error)
+    fn ack_on_error(&self, mode: FragmentationMode) -> AckMessage {
+        let window_size = mode.window_size();
+        let last_window = match &self.last {
+            Some(last) => last.window,
+            None => self.tiles.len().saturating_sub(1) / window_size,
+        };
+
+        let window = (0..last_window)
+            .find(|&window| self.window_bitmap(window, mode).contains(&false))
+            .unwrap_or(last_window);
+        self.ack(window, mode)
+    }
+
+    /// Concatenates the tiles and checks the RCS, returning the SCHC packet on success
+    fn reassemble(&self, mode: FragmentationMode) -> Option<Vec<u8>> {
+        let last = self.last.as_ref()?;
+
+        let tile_count = match mode {
+            FragmentationMode::NoAck => self.tiles.len(),
+            _ => {
+                // Regular tiles in the last window must be contiguous
+                let window_size = mode.window_size();
+                let in_last_window = self
+                    .window_bitmap(last.window, mode)
+                    .iter()
+                    .take_while(|&&received| received)
+                    .count();
+                last.window * window_size + in_last_window
+            }
+        };
+
+        let mut schc_packet = Vec::new();
+        for tile in self.tiles.iter().take(tile_count) {
+            schc_packet.extend_from_slice(tile.as_ref()?);
+        }
+        schc_packet.extend_from_slice(&last.tile);
+
+        (crc32(&schc_packet) == last.rcs).then_some(schc_packet)
+    }
+}
+
+/// A message sent from the fragmenter to the reassembler
+#[derive(Debug, PartialEq)]
+enum FragmentMessage {
+    /// Regular fragment carrying a single tile
+    Regular {
+        dtag: u8,
+        window: u8,
+        fcn: u8,
+        tile: Vec<u8>,
+    },
+    /// All-1 fragment, carrying the RCS and the last tile
+    All1 {
+        dtag: u8,
+        window: u8,
+        rcs: u32,
+        tile: Vec<u8>,
+    },
+    /// ACK REQ (All-0 header without payload)
+    AckReq { dtag: u8, window: u8 },
+    /// Sender-Abort (All-1 header without RCS)
+    SenderAbort { dtag: u8, window: u8 },
+}
+
+impl FragmentMessage {
+    fn dtag(&self) -> u8 {
+        match self {
+            FragmentMessage::Regular { dtag, .. }
+            | FragmentMessage::All1 { dtag, .. }
+            | FragmentMessage::AckReq { dtag, .. }
+            | FragmentMessage::SenderAbort { dtag, .. } => *dtag,
+        }
+    }
+
+    /// Whether the receiver answers the message with an ACK
+    fn asks_for_ack(&self, mode: FragmentationMode) -> bool {
+        match self {
+            FragmentMessage::Regular { fcn, .. } => {
+                *fcn == 0 && mode == FragmentationMode::AckAlways
+            }
+            FragmentMessage::All1 { .. } => mode != FragmentationMode::NoAck,
+            FragmentMessage::AckReq { .. } => true,
+            FragmentMessage::SenderAbort { .. } => false,
+        }
+    }
+
+    fn encode(&self, config: &FragmentationConfig) -> Vec<u8> {
+        let mode = config.mode;
+        let mut writer = BitWriter::default();
+        writer.write(config.rule_id as u64, RULE_ID_BITS);
+        writer.write(self.dtag() as u64, DTAG_BITS);
+
+        match self {
+            FragmentMessage::Regular {
+                window, fcn, tile, ..
+            } => {
+                writer.write(*window as u64, mode.window_bits());
+                writer.write(*fcn as u64, mode.fcn_bits());
+                writer.write_bytes(tile);
+            }
+            FragmentMessage::All1 {
+                window, rcs, tile, ..
+            } => {
+                writer.write(*window as u64, mode.window_bits());
+                writer.write(mode.fcn_all_1(), mode.fcn_bits());
+                writer.write(*rcs as u64, RCS_BITS);
+                writer.write_bytes(tile);
+            }
+            FragmentMessage::AckReq { window, .. } => {
+                writer.write(*window as u64, mode.window_bits());
+                writer.write(0, mode.fcn_bits());
+            }
+            FragmentMessage::SenderAbort { window, .. } => {
+                writer.write(*window as u64, mode.window_bits());
+                writer.write(mode.fcn_all_1(), mode.fcn_bits());
+            }
+        }
+
+        writer.finish(false)
+    }
+
+    fn decode(bytes: &[u8], config: &FragmentationConfig) -> Option<Self> {
+        let mode = config.mode;
+        let mut reader = BitReader::new(bytes);
+        if reader.read(RULE_ID_BITS)? != config.rule_id as u64 {
+            return None;
+        }
+
+        let dtag = reader.read(DTAG_BITS)? as u8;
+        let window = reader.read(mode.window_bits())? as u8;
+        let fcn = reader.read(mode.fcn_bits())?;
+
+        if fcn == mode.fcn_all_1() {
+            // Only the All-1 fragment is long enough to carry an RCS
+            if reader.remaining() < RCS_BITS {
+                return Some(FragmentMessage::SenderAbort { dtag, window });
+            }
+
+            let rcs = reader.read(RCS_BITS)? as u32;
+            let tile = reader.read_bytes(reader.remaining() / 8)?;
+            return Some(FragmentMessage::All1 {
+                dtag,
+                window,
+                rcs,
+                tile,
+            });
+        }
+
+        let tile = reader.read_bytes(reader.remaining() / 8)?;
+        if tile.is_empty() {
+            Some(FragmentMessage::AckReq { dtag, window })
+        } else {
+            Some(FragmentMessage::Regular {
+                dtag,
+                window,
+                fcn: fcn as u8,
+                tile,
+            })
+        }
+    }
+}
+
+/// A message sent from the reassembler to the fragmenter
+#[derive(Debug, PartialEq)]
+enum AckMessage {
+    /// ACK, with the bitmap of received tiles if the packet could not be reassembled yet
+    Ack {
+        dtag: u8,
+        window: u8,
+        bitmap: Option<Vec<bool>>,
+    },
+    /// Receiver-Abort (W all-1, C = 1, followed by an all-1 L2 Word)
+    ReceiverAbort { dtag: u8 },
+}
+
+impl AckMessage {
+    fn dtag(&self) -> u8 {
+        match self {
+            AckMessage::Ack { dtag, .. } | AckMessage::ReceiverAbort { dtag } => *dtag,
+        }
+    }
+
+    fn encode(&self, config: &FragmentationConfig) -> Vec<u8> {
+        let mode = config.mode;
+        let mut writer = BitWriter::default();
+        writer.write(config.rule_id as u64, RULE_ID_BITS);
+        writer.write(self.dtag() as u64, DTAG_BITS);
+
+        match self {
+            AckMessage::Ack {
+                window,
+                bitmap: None,
+                ..
+            } => {
+                writer.write(*window as u64, mode.window_bits());
+                writer.write(1, 1);
+                writer.finish(false)
+            }
+            AckMessage::Ack {
+                window,
+                bitmap: Some(bitmap),
+                ..
+            } => {
+                writer.write(*window as u64, mode.window_bits());
+                writer.write(0, 1);
+
+                // Trailing received tiles may be omitted, as long as the ACK ends at an L2 Word
+                // boundary
+                let header_bits = writer.bit_len();
+                let bitmap_len = (0..bitmap.len())
+                    .find(|&len| {
+                        (header_bits + len).is_multiple_of(L2_WORD_BITS)
+                            && bitmap[len..].iter().all(|&received| received)
+                    })
+                    .unwrap_or(bitmap.len());
+                for &received in &bitmap[..bitmap_len] {
+                    writer.write(received as u64, 1);
+                }
+
+                writer.finish(false)
+            }
+            AckMessage::ReceiverAbort { .. } => {
+                writer.write((1 << mode.window_bits()) - 1, mode.window_bits());
+                writer.write(1, 1);
+                let mut bytes = writer.finish(true);
+                bytes.push(0xFF);
+                bytes
+            }
+        }
+    }
+
+    fn decode(bytes: &[u8], config: &FragmentationConfig) -> Option<Self> {
+        let mode = config.mode;
+        let mut reader = BitReader::new(bytes);
+        if reader.read(RULE_ID_BITS)? != config.rule_id as u64 {
+            return None;
+        }
+
+        let dtag = reader.read(DTAG_BITS)? as u8;
+        let window = reader.read(mode.window_bits())? as u8;
+        let integrity_check = reader.read(1)? == 1;
+
+        if integrity_check {
+            let window_all_1 = window as u64 == (1 << mode.window_bits()) - 1;
+            if window_all_1 && reader.remaining() >= L2_WORD_BITS && reader.rest_is_all_1() {
+                return Some(AckMessage::ReceiverAbort { dtag });
+            }
+
+            return Some(AckMessage::Ack {
+                dtag,
+                window,
+                bitmap: None,
+            });
+        }
+
+        // Bits omitted at the end of the bitmap correspond to received tiles
+        let window_size = mode.window_size();
+        let present = reader.remaining().min(window_size);
+        let mut bitmap = Vec::with_capacity(window_size);
+        for _ in 0..present {
+            bitmap.push(reader.read(1)? == 1);
+        }
+        bitmap.resize(window_size, true);
+
+        Some(AckMessage::Ack {
+            dtag,
+            window,
+            bitmap: Some(bitmap),
+        })
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit_len: usize,
+}
+
+impl BitWriter {
+    fn bit_len(&self) -> usize {
+        self.bit_len
+    }
+
+    /// Writes the `bits` least significant bits of `value`, most significant first
+    fn write(&mut self, value: u64, bits: usize) {
+        for i in (0..bits).rev() {
+            if self.bit_len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+
+            if (value >> i) & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bit_len % 8);
+            }
+
+            self.bit_len += 1;
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write(byte as u64, 8);
+        }
+    }
+
+    /// Pads the message up to the next L2 Word boundary
+    fn finish(mut self, padding_bit: bool) -> Vec<u8> {
+        while !self.bit_len.is_multiple_of(L2_WORD_BITS) {
+            self.write(padding_bit as u64, 1);
+        }
+
+        self.bytes
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() * 8 - self.position
+    }
+
+    fn read(&mut self, bits: usize) -> Option<u64> {
+        if bits > self.remaining() {
+            return None;
+        }
+
+        let mut value = 0;
+        for _ in 0..bits {
+            let bit = (self.bytes[self.position / 8] >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | bit as u64;
+            self.position += 1;
+        }
+
+        Some(value)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Option<Vec<u8>> {
+        (0..len).map(|_| self.read(8).map(|b| b as u8)).collect()
+    }
+
+    fn rest_is_all_1(&mut self) -> bool {
+        while self.remaining() > 0 {
+            if self.read(1) != Some(1) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// CRC32 (IEEE 802.3), the default RCS algorithm of RFC 8724
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(mode: FragmentationMode) -> FragmentationConfig {
+        FragmentationConfig {
+            mode,
+            rule_id: 20,
+            mtu_bytes: 64,
+            retransmission_timeout: Duration::from_secs(10),
+            max_ack_requests: 4,
+            inactivity_timeout: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_fragment_messages_roundtrip() {
+        for mode in [
+            FragmentationMode::NoAck,
+            FragmentationMode::AckAlways,
+            FragmentationMode::AckOnError,
+        ] {
+            let config = config(mode);
+            let messages = [
+                FragmentMessage::Regular {
+                    dtag: 7,
+                    window: 0,
+                    fcn: 0,
+                    tile: vec![1, 2, 3],
+                },
+                FragmentMessage::All1 {
+                    dtag: 7,
+                    window: 0,
+                    rcs: 0xDEAD_BEEF,
+                    tile: vec![4],
+                },
+                FragmentMessage::SenderAbort { dtag: 7, window: 0 },
+            ];
+
+            for message in messages {
+                let encoded = message.encode(&config);
+                assert!(encoded.len() <= config.mtu_bytes);
+                assert_eq!(FragmentMessage::decode(&encoded, &config), Some(message));
+            }
+        }
+    }
+
+    #[test]
+    fn test_ack_messages_roundtrip() {
+        let config = config(FragmentationMode::AckOnError);
+        let mut bitmap = vec![true; config.mode.window_size()];
+        bitmap[3] = false;
+
+        let messages = [
+            AckMessage::Ack {
+                dtag: 1,
+                window: 2,
+                bitmap: Some(bitmap),
+            },
+            AckMessage::Ack {
+                dtag: 1,
+                window: 3,
+                bitmap: None,
+            },
+            AckMessage::ReceiverAbort { dtag: 1 },
+        ];
+
+        for message in messages {
+            let encoded = message.encode(&config);
+            assert_eq!(AckMessage::decode(&encoded, &config), Some(message));
+        }
+    }
+
+    fn schc_packet(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// Sends the message over the wire
+    fn transmit(message: &FragmentMessage, config: &FragmentationConfig) -> FragmentMessage {
+        FragmentMessage::decode(&message.encode(config), config).unwrap()
+    }
+
+    /// Sends the ACK over the wire
+    fn transmit_ack(ack: &AckMessage, config: &FragmentationConfig) -> AckMessage {
+        AckMessage::decode(&ack.encode(config), config).unwrap()
+    }
+
+    #[test]
+    fn test_no_ack_duplicates_and_reordering() {
+        let config = config(FragmentationMode::NoAck);
+        let mode = config.mode;
+        let packet = schc_packet(200);
+        let sender = SenderSession::new(&packet, config.tile_size());
+        let messages = sender.all_messages(3, mode);
+        assert_eq!(messages.len(), 4);
+
+        // The network duplicates fragments, and the All-1 overtakes the last regular fragment
+        let mut receiver = ReceiverSession::new(3);
+        for number in [0, 0, 1, 3, 1, 3] {
+            let message = transmit(&messages[number], &config);
+            assert_eq!(
+                receiver.receive(message, number as u64, mode),
+                Reception::Pending(None)
+            );
+        }
+
+        let message = transmit(&messages[2], &config);
+        assert_eq!(
+            receiver.receive(message, 2, mode),
+            Reception::Reassembled(packet, None)
+        );
+
+        // Duplicates arriving after the reassembly are recognized
+        assert!(receiver.is_duplicate(&messages[1], 1, mode));
+        assert!(!receiver.is_duplicate(&messages[1], 7, mode));
+    }
+
+    #[test]
+    fn test_no_ack_loss() {
+        let config = config(FragmentationMode::NoAck);
+        let mode = config.mode;
+        let sender = SenderSession::new(&schc_packet(200), config.tile_size());
+        let messages = sender.all_messages(0, mode);
+
+        let mut receiver = ReceiverSession::new(0);
+        for number in [0, 2, 3] {
+            let message = transmit(&messages[number], &config);
+            assert_eq!(
+                receiver.receive(message, number as u64, mode),
+                Reception::Pending(None)
+            );
+        }
+
+        // The RCS doesn't match, so the packet is dropped once the inactivity timer expires
+        assert!(receiver.last.is_some());
+        assert_eq!(receiver.reassemble(mode), None);
+    }
+
+    #[test]
+    fn test_ack_on_error_retransmits_lost_tiles_window_by_window() {
+        let config = FragmentationConfig {
+            mtu_bytes: 16,
+            ..config(FragmentationMode::AckOnError)
+        };
+        let mode = config.mode;
+        let packet = schc_packet(1200);
+        let mut sender = SenderSession::new(&packet, config.tile_size());
+        assert_eq!(sender.tiles.len(), 134);
+        assert_eq!(sender.last_window(mode), 2);
+
+        // Tiles 5 (window 0) and 70 (window 1) are lost
+        let mut receiver = ReceiverSession::new(0);
+        let mut number = 0;
+        let mut deliver = |receiver: &mut ReceiverSession, message: &FragmentMessage| {
+            number += 1;
+            receiver.receive(transmit(message, &config), number, mode)
+        };
+        let messages = sender.all_messages(0, mode);
+        for (tile, message) in messages.iter().enumerate().take(133) {
+            if tile != 5 && tile != 70 {
+                assert_eq!(deliver(&mut receiver, message), Reception::Pending(None));
+            }
+        }
+
+        // The All-1 asks for an ACK, which reports the first window with missing tiles
+        let Reception::Pending(Some(ack)) = deliver(&mut receiver, &messages[133]) else {
+            panic!("expected an ACK");
+        };
+        let mut bitmap = vec![true; mode.window_size()];
+        bitmap[5] = false;
+        assert_eq!(
+            ack,
+            AckMessage::Ack {
+                dtag: 0,
+                window: 0,
+                bitmap: Some(bitmap)
+            }
+        );
+
+        let AckOutcome::Retransmit {
+            window: 0,
+            missing: 1,
+            messages,
+        } = sender.on_ack(0, transmit_ack(&ack, &config), mode)
+        else {
+            panic!("expected a retransmission of window 0");
+        };
+        assert_eq!(
+            messages,
+            [
+                sender.tile_message(0, 5, mode),
+                sender.all_1_message(0, mode)
+            ]
+        );
+        assert_eq!(
+            deliver(&mut receiver, &messages[0]),
+            Reception::Pending(None)
+        );
+
+        // Then the next window with missing tiles is reported
+        let Reception::Pending(Some(ack)) = deliver(&mut receiver, &messages[1]) else {
+            panic!("expected an ACK");
+        };
+        let mut bitmap = vec![true; mode.window_size()];
+        bitmap[70 - mode.window_size()] = false;
+        assert_eq!(
+            ack,
+            AckMessage::Ack {
+                dtag: 0,
+                window: 1,
+                bitmap: Some(bitmap)
+            }
+        );
+
+        let AckOutcome::Retransmit {
+            window: 1,
+            missing: 1,
+            messages,
+        } = sender.on_ack(0, transmit_ack(&ack, &config), mode)
+        else {
+            panic!("expected a retransmission of window 1");
+        };
+        assert_eq!(
+            deliver(&mut receiver, &messages[0]),
+            Reception::Reassembled(packet, None)
+        );
+
+        // The All-1 that follows reaches a finished reassembly, which confirms it
+        let all_1 = transmit(&messages[1], &config);
+        assert!(receiver.is_duplicate(&all_1, 1000, mode));
+        assert!(all_1.asks_for_ack(mode));
+        let ack = receiver.ack_complete();
+        assert_eq!(
+            sender.on_ack(0, transmit_ack(&ack, &config), mode),
+            AckOutcome::Complete { window: 2 }
+        );
+    }
+
+    #[test]
+    fn test_ack_always_recovers_lost_all_0() {
+        let config = config(FragmentationMode::AckAlways);
+        let mode = config.mode;
+        let packet = schc_packet(500);
+        let mut sender = SenderSession::new(&packet, config.tile_size());
+        assert_eq!(sender.tiles.len(), 9);
+
+        // The All-0 fragment closing window 0 is lost
+        let mut receiver = ReceiverSession::new(1);
+        let messages = sender.window_messages(1, 0, mode);
+        assert_eq!(messages.len(), 7);
+        for (number, message) in messages.iter().enumerate().take(6) {
+            let message = transmit(message, &config);
+            assert_eq!(
+                receiver.receive(message, number as u64, mode),
+                Reception::Pending(None)
+            );
+        }
+
+        // So the retransmission timer expires and the sender asks for an ACK
+        let RetransmissionTimeout::AckReq(ack_req) = sender.on_timeout(1, 0, 4, mode) else {
+            panic!("expected an ACK REQ");
+        };
+        assert_eq!(ack_req, FragmentMessage::AckReq { dtag: 1, window: 0 });
+        let Reception::Pending(Some(ack)) = receiver.receive(transmit(&ack_req, &config), 10, mode)
+        else {
+            panic!("expected an ACK");
+        };
+
+        let outcome = sender.on_ack(1, transmit_ack(&ack, &config), mode);
+        assert_eq!(sender.attempts, 0);
+        assert_eq!(
+            outcome,
+            AckOutcome::Retransmit {
+                window: 0,
+                missing: 1,
+                messages: vec![sender.tile_message(1, 6, mode)],
+            }
+        );
+
+        // The retransmitted All-0 completes the window, so the sender moves on to the next one
+        let Reception::Pending(Some(ack)) =
+            receiver.receive(transmit(&messages[6], &config), 11, mode)
+        else {
+            panic!("expected an ACK");
+        };
+        let AckOutcome::NextWindow(messages) = sender.on_ack(1, transmit_ack(&ack, &config), mode)
+        else {
+            panic!("expected the next window");
+        };
+        assert_eq!(messages.len(), 2);
+
+        assert_eq!(
+            receiver.receive(transmit(&messages[0], &config), 12, mode),
+            Reception::Pending(None)
+        );
+        let Reception::Reassembled(reassembled, Some(ack)) =
+            receiver.receive(transmit(&messages[1], &config), 13, mode)
+        else {
+            panic!("expected the reassembled packet");
+        };
+        assert_eq!(reassembled, packet);
+        assert_eq!(
+            sender.on_ack(1, transmit_ack(&ack, &config), mode),
+            AckOutcome::Complete { window: 1 }
+        );
+    }
+
+    #[test]
+    fn test_retransmission_timer() {
+        let config = config(FragmentationMode::AckOnError);
+        let mode = config.mode;
+        let mut sender = SenderSession::new(&schc_packet(200), config.tile_size());
+
+        for attempt in 1..=config.max_ack_requests {
+            let generation = sender.timer_generation;
+            assert_eq!(
+                sender.on_timeout(1, generation, config.max_ack_requests, mode),
+                RetransmissionTimeout::AckReq(FragmentMessage::AckReq { dtag: 1, window: 0 })
+            );
+            assert_eq!(sender.attempts, attempt);
+
+            // The timer armed before the ACK REQ was sent is stale
+            assert_eq!(
+                sender.on_timeout(1, generation, config.max_ack_requests, mode),
+                RetransmissionTimeout::Stale
+            );
+        }
+
+        // After too many unanswered ACK REQs, the sender aborts
+        assert_eq!(
+            sender.on_timeout(1, sender.timer_generation, config.max_ack_requests, mode),
+            RetransmissionTimeout::Abort(FragmentMessage::SenderAbort { dtag: 1, window: 0 })
+        );
+    }
+
+    #[test]
+    fn test_dtag_wraps_around_ongoing_fragmentations() {
+        let fragmenter = SchcFragmenter::new(config(FragmentationMode::AckOnError), false).unwrap();
+        let node: Arc<str> = Arc::from("node");
+        fragmenter.next_dtag.lock().insert(node.clone(), 254);
+
+        // DTag 255 still belongs to an ongoing fragmentation
+        let in_use = |dtag| dtag == 255;
+        assert_eq!(fragmenter.allocate_dtag(&node, in_use), Some(254));
+        assert_eq!(fragmenter.allocate_dtag(&node, in_use), Some(0));
+        assert_eq!(fragmenter.allocate_dtag(&node, in_use), Some(1));
+
+        // Each node has its own DTags
+        assert_eq!(
+            fragmenter.allocate_dtag(&Arc::from("other"), in_use),
+            Some(0)
+        );
+
+        // The packet can't be fragmented when all DTags are in use
+        assert_eq!(fragmenter.allocate_dtag(&node, |_| true), None);
+        assert_eq!(fragmenter.allocate_dtag(&node, |_| false), Some(2));
+    }
+
+    #[test]
+    fn test_reused_dtag_starts_new_reassembly() {
+        let config = config(FragmentationMode::AckOnError);
+        let mode = config.mode;
+        let first_packet = schc_packet(200);
+        let first = SenderSession::new(&first_packet, config.tile_size()).all_messages(9, mode);
+
+        let mut receiver = ReceiverSession::new(9);
+        let mut reception = Reception::Aborted;
+        for (number, message) in first.iter().enumerate() {
+            reception = receiver.receive(transmit(message, &config), number as u64, mode);
+        }
+        assert_eq!(
+            reception,
+            Reception::Reassembled(first_packet.clone(), Some(receiver.ack_complete()))
+        );
+
+        // Retransmissions of the reassembled packet are recognized, even as new packets
+        assert!(receiver.is_duplicate(&first[1], 100, mode));
+        assert!(receiver.is_duplicate(&first[3], 101, mode));
+        assert!(receiver.is_duplicate(&FragmentMessage::AckReq { dtag: 9, window: 0 }, 102, mode));
+
+        // But a new packet that wrapped around to the same DTag isn't mistaken for them
+        let second_packet: Vec<_> = first_packet.iter().rev().copied().collect();
+        let second = SenderSession::new(&second_packet, config.tile_size()).all_messages(9, mode);
+        assert!(
+            second
+                .iter()
+                .all(|message| !receiver.is_duplicate(message, 200, mode))
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//! Synthetic frames for SCHC processing
+//!
+//! The SCHC parser expects full Ethernet+IP+UDP frames, but the simulation only carries QUIC
+//! payloads around. The helpers below wrap a QUIC payload in proper headers (using the actual
+//! simulation addresses), and locate the QUIC payload again in a reconstructed IP datagram.
+
+use pnet_packet::ip::IpNextHeaderProtocol;
+use pnet_packet::ipv4::MutableIpv4Packet;
+use pnet_packet::ipv6::MutableIpv6Packet;
+use pnet_packet::udp::MutableUdpPacket;
+use pnet_packet::{ipv4, udp};
+use quinn::udp::EcnCodepoint;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+pub(crate) const ETHERNET_HEADER_SIZE: usize = 14;
+const IPV4_HEADER_SIZE: usize = 20;
+const IPV6_HEADER_SIZE: usize = 40;
+const UDP_HEADER_SIZE: usize = 8;
+const UDP_PROTOCOL: u8 = 17;
+
+/// The IP header fields of a synthetic frame that are not derived from the addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct IpHeaderFields {
+    /// The ECN codepoint (2 bits)
+    pub(crate) ecn: u8,
+    /// The DSCP (6 bits)
+    pub(crate) dscp: u8,
+    /// The IPv4 TTL or IPv6 hop limit
+    pub(crate) ttl: u8,
+}
+
+impl Default for IpHeaderFields {
+    fn default() -> Self {
+        Self {
+            ecn: 0,
+            dscp: 0,
+            ttl: 64,
+        }
+    }
+}
+
+impl IpHeaderFields {
+    /// The header fields of a transmit with the provided ECN codepoint (the simulation does not
+    /// model DSCP and TTL, so they get their default values)
+    pub(crate) fn with_ecn(ecn: Option<EcnCodepoint>) -> Self {
+        Self {
+            ecn: ecn.map(|codepoint| codepoint as u8).unwrap_or(0),
+            ..Self::default()
+        }
+    }
+
+    /// The ECN codepoint, as used by quinn (`None` stands for Not-ECT)
+    #[cfg(feature = "schc-compressor")]
+    pub(crate) fn ecn_codepoint(&self) -> Option<EcnCodepoint> {
+        EcnCodepoint::from_bits(self.ecn)
+    }
+
+    /// Reads the header fields of an IPv4 or IPv6 datagram
+    #[cfg(feature = "schc-compressor")]
+    pub(crate) fn parse(datagram: &[u8]) -> Option<Self> {
+        match datagram.first()? >> 4 {
+            4 if datagram.len() >= IPV4_HEADER_SIZE => Some(Self {
+                ecn: datagram[1] & 0b11,
+                dscp: datagram[1] >> 2,
+                ttl: datagram[8],
+            }),
+            6 if datagram.len() >= IPV6_HEADER_SIZE => {
+                let traffic_class = (datagram[0] << 4) | (datagram[1] >> 4);
+                Some(Self {
+                    ecn: traffic_class & 0b11,
+                    dscp: traffic_class >> 2,
+                    ttl: datagram[7],
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Build a synthetic Ethernet+IP+UDP frame around the QUIC payload
+///
+/// The frame uses IPv4 when both addresses are IPv4, and IPv6 otherwise (IPv4 addresses are then
+/// mapped to IPv6).
+pub(crate) fn build_synthetic_frame(
+    quic_payload: &[u8],
+    source_addr: SocketAddr,
+    dest_addr: SocketAddr,
+    fields: IpHeaderFields,
+) -> Vec<u8> {
+    let (ether_type, ip_packet) = match (source_addr.ip(), dest_addr.ip()) {
+        (IpAddr::V4(source_ip), IpAddr::V4(dest_ip)) => {
+            let udp_packet = build_udp_packet(quic_payload, source_addr, dest_addr, |udp| {
+                udp::ipv4_checksum(udp, &source_ip, &dest_ip)
+            });
+            (
+                [0x08, 0x00],
+                build_ipv4_packet(&udp_packet, source_ip, dest_ip, fields),
+            )
+        }
+        (source_ip, dest_ip) => {
+            let source_ip = to_ipv6(source_ip);
+            let dest_ip = to_ipv6(dest_ip);
+            let udp_packet = build_udp_packet(quic_payload, source_addr, dest_addr, |udp| {
+                udp::ipv6_checksum(udp, &source_ip, &dest_ip)
+            });
+            (
+                [0x86, 0xdd],
+                build_ipv6_packet(&udp_packet, source_ip, dest_ip, fields),
+            )
+        }
+    };
+
+    let mut frame = Vec::with_capacity(ETHERNET_HEADER_SIZE + ip_packet.len());
+    frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00]); // Dst MAC (placeholder)
+    frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00]); // Src MAC (placeholder)
+    frame.extend_from_slice(&ether_type);
+    frame.extend_from_slice(&ip_packet);
+    frame
+}
+
+/// The size of the IP and UDP headers at the start of the datagram, based on its IP version
+///
+/// Returns `None` if the datagram is not an IPv4 or IPv6 datagram, or is too short to hold the
+/// headers.
+#[cfg(feature = "schc-compressor")]
+pub(crate) fn ip_udp_header_size(datagram: &[u8]) -> Option<usize> {
+    let ip_header_size = match datagram.first()? >> 4 {
+        4 => (datagram[0] & 0x0f) as usize * 4,
+        6 => IPV6_HEADER_SIZE,
+        _ => return None,
+    };
+
+    let header_size = ip_header_size + UDP_HEADER_SIZE;
+    (datagram.len() >= header_size).then_some(header_size)
+}
+
+/// The size of the IP and UDP headers of the synthetic frame built for the given addresses
+#[cfg(feature = "schc-compressor")]
+pub(crate) fn synthetic_ip_udp_header_size(
+    source_addr: SocketAddr,
+    dest_addr: SocketAddr,
+) -> usize {
+    let ip_header_size = if source_addr.is_ipv4() && dest_addr.is_ipv4() {
+        IPV4_HEADER_SIZE
+    } else {
+        IPV6_HEADER_SIZE
+    };
+
+    ip_header_size + UDP_HEADER_SIZE
+}
+
+/// Describes the header fields that differ between two IP/UDP/QUIC datagrams, one per line
+///
+/// IP, UDP and QUIC long header fields are compared individually (fields sharing a byte with other
+/// fields are reported together). The rest of the QUIC packet is reported as a single field,
+/// pointing at the first byte that differs.
+#[cfg(feature = "schc-compressor")]
+pub(crate) fn datagram_differences(original: &[u8], reconstructed: &[u8]) -> Vec<String> {
+    let mut differences = Vec::new();
+    let mut start = 0;
+    for (field, len) in datagram_layout(original) {
+        let end = start + len;
+        let original_field = original.get(start..end.min(original.len())).unwrap_or(&[]);
+        let reconstructed_field = reconstructed
+            .get(start..end.min(reconstructed.len()))
+            .unwrap_or(&[]);
+        if original_field != reconstructed_field {
+            differences.push(format!(
+                "{field}: {} → {}",
+                hex(original_field),
+                hex(reconstructed_field)
+            ));
+        }
+
+        start = end;
+    }
+
+    let original_rest = original.get(start..).unwrap_or(&[]);
+    let reconstructed_rest = reconstructed.get(start..).unwrap_or(&[]);
+    if original_rest != reconstructed_rest {
+        let first_difference = original_rest
+            .iter()
+            .zip(reconstructed_rest)
+            .position(|(a, b)| a != b)
+            .unwrap_or(original_rest.len().min(reconstructed_rest.len()));
+        differences.push(format!(
+            "QUIC remainder: first difference at byte {} ({} → {} bytes)",
+            start + first_difference,
+            original.len(),
+            reconstructed.len()
+        ));
+    }
+
+    differences
+}
+
+/// The names and sizes of the header fields at the start of the datagram
+#[cfg(feature = "schc-compressor")]
+fn datagram_layout(datagram: &[u8]) -> Vec<(&'static str, usize)> {
+    let mut layout = match datagram.first().map(|b| b >> 4) {
+        Some(4) => vec![
+            ("IPv4 version/IHL", 1),
+            ("IPv4 DSCP/ECN", 1),
+            ("IPv4 total length", 2),
+            ("IPv4 identification", 2),
+            ("IPv4 flags/fragment offset", 2),
+            ("IPv4 TTL", 1),
+            ("IPv4 protocol", 1),
+            ("IPv4 checksum", 2),
+            ("IPv4 source", 4),
+            ("IPv4 destination", 4),
+        ],
+        Some(6) => vec![
+            ("IPv6 version/traffic class/flow label", 4),
+            ("IPv6 payload length", 2),
+            ("IPv6 next header", 1),
+            ("IPv6 hop limit", 1),
+            ("IPv6 source", 16),
+            ("IPv6 destination", 16),
+        ],
+        _ => return Vec::new(),
+    };
+    layout.extend([
+        ("UDP source port", 2),
+        ("UDP destination port", 2),
+        ("UDP length", 2),
+        ("UDP checksum", 2),
+        ("QUIC first byte", 1),
+    ]);
+
+    // Long header packets carry a version and both connection IDs
+    let quic_start: usize = layout.iter().map(|(_, len)| len).sum();
+    let is_long_header = datagram
+        .get(quic_start - 1)
+        .is_some_and(|first_byte| first_byte & 0x80 != 0);
+    if is_long_header {
+        let dcid_len_offset = quic_start + 4;
+        let dcid_len = datagram.get(dcid_len_offset).copied().unwrap_or(0) as usize;
+        let scid_len_offset = dcid_len_offset + 1 + dcid_len;
+        let scid_len = datagram.get(scid_len_offset).copied().unwrap_or(0) as usize;
+        layout.extend([
+            ("QUIC version", 4),
+            ("QUIC DCID length", 1),
+            ("QUIC DCID", dcid_len),
+            ("QUIC SCID length", 1),
+            ("QUIC SCID", scid_len),
+        ]);
+    }
+
+    layout
+}
+
+#[cfg(feature = "schc-compressor")]
+fn hex(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "(missing)".to_string();
+    }
+
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+fn build_udp_packet(
+    quic_payload: &[u8],
+    source_addr: SocketAddr,
+    dest_addr: SocketAddr,
+    checksum: impl FnOnce(&udp::UdpPacket) -> u16,
+) -> Vec<u8> {
+    let mut buffer = vec![0u8; UDP_HEADER_SIZE + quic_payload.len()];
+    let mut udp_writer = MutableUdpPacket::new(&mut buffer).unwrap();
+    udp_writer.set_source(source_addr.port());
+    udp_writer.set_destination(dest_addr.port());
+    udp_writer.set_length((UDP_HEADER_SIZE + quic_payload.len()) as u16);
+    udp_writer.set_payload(quic_payload);
+    let checksum = checksum(&udp_writer.to_immutable());
+    udp_writer.set_checksum(checksum);
+    buffer
+}
+
+fn build_ipv4_packet(
+    udp_packet: &[u8],
+    source_ip: Ipv4Addr,
+    dest_ip: Ipv4Addr,
+    fields: IpHeaderFields,
+) -> Vec<u8> {
+    let mut buffer = vec![0u8; IPV4_HEADER_SIZE + udp_packet.len()];
+    let mut ip_writer = MutableIpv4Packet::new(&mut buffer).unwrap();
+    ip_writer.set_version(4);
+    ip_writer.set_header_length(5); // No options
+    ip_writer.set_dscp(fields.dscp);
+    ip_writer.set_ecn(fields.ecn);
+    ip_writer.set_total_length((IPV4_HEADER_SIZE + udp_packet.len()) as u16);
+    ip_writer.set_identification(0);
+    ip_writer.set_flags(0b010); // Don't fragment
+    ip_writer.set_fragment_offset(0);
+    ip_writer.set_ttl(fields.ttl);
+    ip_writer.set_next_level_protocol(IpNextHeaderProtocol::new(UDP_PROTOCOL));
+    ip_writer.set_source(source_ip);
+    ip_writer.set_destination(dest_ip);
+    ip_writer.set_payload(udp_packet);
+    let checksum = ipv4::checksum(&ip_writer.to_immutable());
+    ip_writer.set_checksum(checksum);
+    buffer
+}
+
+fn build_ipv6_packet(
+    udp_packet: &[u8],
+    source_ip: Ipv6Addr,
+    dest_ip: Ipv6Addr,
+    fields: IpHeaderFields,
+) -> Vec<u8> {
+    let mut buffer = vec![0u8; IPV6_HEADER_SIZE + udp_packet.len()];
+    let mut ip_writer = MutableIpv6Packet::new(&mut buffer).unwrap();
+    ip_writer.set_version(6);
+    ip_writer.set_traffic_class((fields.dscp << 2) | fields.ecn);
+    ip_writer.set_flow_label(0);
+    ip_writer.set_payload_length(udp_packet.len() as u16);
+    ip_writer.set_next_header(IpNextHeaderProtocol::new(UDP_PROTOCOL));
+    ip_writer.set_hop_limit(fields.ttl);
+    ip_writer.set_source(source_ip);
+    ip_writer.set_destination(dest_ip);
+    ip_writer.set_payload(udp_packet);
+    buffer
+}
+
+#[cfg(all(test, feature = "schc-compressor"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_synthetic_frame_ip_versions() {
+        let payload = [0x40, 0x01, 0x02, 0x03];
+        let v4_source: SocketAddr = "10.0.0.1:8080".parse().unwrap();
+        let v4_dest: SocketAddr = "10.0.0.2:8080".parse().unwrap();
+        let v6_source: SocketAddr = "[2001:db8::1]:8080".parse().unwrap();
+        let v6_dest: SocketAddr = "[2001:db8::2]:8080".parse().unwrap();
+
+        for (source, dest, ether_type) in [
+            (v4_source, v4_dest, [0x08, 0x00]),
+            (v6_source, v6_dest, [0x86, 0xdd]),
+            (v4_source, v6_dest, [0x86, 0xdd]),
+        ] {
+            let fields = IpHeaderFields {
+                ecn: 0b10,
+                dscp: 46,
+                ttl: 17,
+            };
+            let frame = build_synthetic_frame(&payload, source, dest, fields);
+            assert_eq!(frame[12..14], ether_type);
+
+            let datagram = &frame[ETHERNET_HEADER_SIZE..];
+            assert_eq!(IpHeaderFields::parse(datagram), Some(fields));
+            let header_size = ip_udp_header_size(datagram).unwrap();
+            assert_eq!(header_size, synthetic_ip_udp_header_size(source, dest));
+            assert_eq!(&datagram[header_size..], payload);
+        }
+    }
+
+    #[test]
+    fn test_datagram_differences() {
+        // Long header with an 8-byte DCID and an empty SCID
+        let mut payload = vec![0xc0, 0x00, 0x00, 0x00, 0x01, 0x08];
+        payload.extend([0xaa; 8]);
+        payload.extend([0x00, 0x01, 0x02, 0x03]);
+        let source: SocketAddr = "10.0.0.1:8080".parse().unwrap();
+        let dest: SocketAddr = "10.0.0.2:8080".parse().unwrap();
+        let frame = build_synthetic_frame(&payload, source, dest, IpHeaderFields::default());
+        let original = &frame[ETHERNET_HEADER_SIZE..];
+        assert!(datagram_differences(original, original).is_empty());
+
+        let mut reconstructed = original.to_vec();
+        reconstructed[8] = 63; // TTL
+        reconstructed[28 + 6] = 0xbb; // First DCID byte
+        reconstructed.pop();
+        assert_eq!(
+            datagram_differences(original, &reconstructed),
+            [
+                "IPv4 TTL: 40 → 3f",
+                "QUIC DCID: aaaaaaaaaaaaaaaa → bbaaaaaaaaaaaaaa",
+                "QUIC remainder: first difference at byte 45 (46 → 45 bytes)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ip_udp_header_size_rejects_invalid_datagrams() {
+        assert_eq!(ip_udp_header_size(&[]), None);
+        assert_eq!(ip_udp_header_size(&[0x45; 27]), None);
+        assert_eq!(ip_udp_header_size(&[0x60; 47]), None);
+        assert_eq!(ip_udp_header_size(&[0x10; 64]), None);
+    }
+}
@@ -0,0 +1,166 @@
+//! Per-node roles of the SCHC machinery: which nodes observe the packets they forward, and which
+//! nodes compress and decompress them
+//!
+//! These are not to be confused with the [`SchcRole`](crate::network::spec::SchcRole) of a node,
+//! which tells on which side of a SCHC deployment the node is (and thereby the direction of the
+//! packets it sends and receives).
+//!
+//! A node may have both roles, in which case `InMemoryNetwork::forward` runs them in a fixed
+//! order: the observer sees the packet as it arrived at the node, and only then the compressor
+//! compresses or decompresses it. An observer on a compressing gateway thus sees uncompressed
+//! packets, and an observer on a decompressing gateway (or between gateways) sees compressed ones.
+
+use anyhow::{Context, bail};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SchcNodeRole {
+    /// Analyzes the packets it forwards, without modifying them
+    Observer,
+    /// Compresses and decompresses the packets it forwards over SCHC-constrained links
+    Compressor,
+}
+
+impl FromStr for SchcNodeRole {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "observer" => Ok(Self::Observer),
+            "compressor" => Ok(Self::Compressor),
+            _ => bail!("invalid SCHC node role `{s}` (expected `observer` or `compressor`)"),
+        }
+    }
+}
+
+impl fmt::Display for SchcNodeRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Observer => write!(f, "observer"),
+            Self::Compressor => write!(f, "compressor"),
+        }
+    }
+}
+
+/// The SCHC roles of every node of the network
+#[derive(Clone, Debug, Default)]
+pub struct SchcNodeRoles {
+    by_node: HashMap<Arc<str>, BTreeSet<SchcNodeRole>>,
+    /// Whether routers observe packets when no node has the observer role
+    routers_observe_by_default: bool,
+}
+
+impl SchcNodeRoles {
+    /// Parses role assignments of the form `NODE=ROLE[+ROLE...]`, e.g. `SchcNode1=compressor` or
+    /// `MoonOrbiter1=observer+compressor` (a node may also be listed more than once)
+    pub fn parse(assignments: &[String]) -> anyhow::Result<Self> {
+        let mut roles = Self::default();
+        for assignment in assignments {
+            let (node_id, node_roles) = assignment.split_once('=').with_context(|| {
+                format!("invalid SCHC node role assignment `{assignment}` (expected `NODE=ROLE`)")
+            })?;
+            for role in node_roles.split('+') {
+                roles.add(node_id, role.trim().parse()?);
+            }
+        }
+
+        Ok(roles)
+    }
+
+    pub fn add(&mut self, node_id: &str, role: SchcNodeRole) {
+        self.by_node
+            .entry(Arc::from(node_id.trim()))
+            .or_default()
+            .insert(role);
+    }
+
+    /// Lets every router observe the packets it forwards, unless some node has the observer role
+    pub fn with_routers_observing_by_default(mut self) -> Self {
+        self.routers_observe_by_default = true;
+        self
+    }
+
+    /// Returns true if the node has the role
+    pub fn has_role(&self, node_id: &str, is_router: bool, role: SchcNodeRole) -> bool {
+        if self
+            .by_node
+            .get(node_id)
+            .is_some_and(|roles| roles.contains(&role))
+        {
+            return true;
+        }
+
+        role == SchcNodeRole::Observer
+            && is_router
+            && self.routers_observe_by_default
+            && !self
+                .by_node
+                .values()
+                .any(|roles| roles.contains(&SchcNodeRole::Observer))
+    }
+
+    /// Returns the nodes explicitly assigned the role, in alphabetical order
+    pub fn nodes_with_role(&self, role: SchcNodeRole) -> Vec<Arc<str>> {
+        let mut nodes: Vec<_> = self
+            .by_node
+            .iter()
+            .filter(|(_, roles)| roles.contains(&role))
+            .map(|(node_id, _)| node_id.clone())
+            .collect();
+        nodes.sort();
+        nodes
+    }
+
+    /// Returns the ids of the nodes that have at least one role
+    pub fn node_ids(&self) -> impl Iterator<Item = &Arc<str>> {
+        self.by_node.keys()
+    }
+
+    /// Returns the roles of each node, in alphabetical order of the node ids
+    pub fn by_node(&self) -> Vec<(Arc<str>, Vec<SchcNodeRole>)> {
+        let mut by_node: Vec<_> = self
+            .by_node
+            .iter()
+            .map(|(node_id, roles)| (node_id.clone(), roles.iter().copied().collect()))
+            .collect();
+        by_node.sort();
+        by_node
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_node_roles() {
+        let roles = SchcNodeRoles::parse(&[
+            "SchcNode1=compressor".to_string(),
+            "SchcNode2=compressor+observer".to_string(),
+            "SchcNode1=observer".to_string(),
+        ])
+        .unwrap()
+        .with_routers_observing_by_default();
+
+        assert!(roles.has_role("SchcNode1", true, SchcNodeRole::Compressor));
+        assert!(roles.has_role("SchcNode1", true, SchcNodeRole::Observer));
+        assert!(roles.has_role("SchcNode2", true, SchcNodeRole::Observer));
+        assert_eq!(
+            roles.nodes_with_role(SchcNodeRole::Compressor),
+            vec![Arc::from("SchcNode1"), Arc::from("SchcNode2")]
+        );
+
+        // Routers only observe by default when no node has the observer role
+        assert!(!roles.has_role("Router", true, SchcNodeRole::Observer));
+        let roles = SchcNodeRoles::default().with_routers_observing_by_default();
+        assert!(roles.has_role("Router", true, SchcNodeRole::Observer));
+        assert!(!roles.has_role("Host", false, SchcNodeRole::Observer));
+        assert!(!roles.has_role("Router", true, SchcNodeRole::Compressor));
+
+        assert!(SchcNodeRoles::parse(&["SchcNode1".to_string()]).is_err());
+        assert!(SchcNodeRoles::parse(&["SchcNode1=router".to_string()]).is_err());
+    }
+}
@@ -3,12 +3,16 @@
 //! Provides header compression observation without modifying transmitted packets.
 //! Useful for measuring potential SCHC compression gains in simulated networks.
 
-use pnet_packet::ip::IpNextHeaderProtocol;
-use pnet_packet::ipv4::MutableIpv4Packet;
-use pnet_packet::udp::MutableUdpPacket;
-use pnet_packet::{ipv4, udp};
+use crate::quic_header_protection::QuicKeyLog;
+use crate::schc_frame::{ETHERNET_HEADER_SIZE, IpHeaderFields, build_synthetic_frame};
+use crate::schc_residue::SchcResidueStats;
+use crate::schc_rule_synthesis::SchcRuleSynthesizer;
+use crate::schc_stats::{SchcBreakdown, SchcRuleMatch};
+use quinn::udp::EcnCodepoint;
+use parking_lot::Mutex;
 use schc::{build_tree, compress_packet, Direction, Rule, RuleSet, TreeNode};
-use std::net::{IpAddr, SocketAddr};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
@@ -19,6 +23,31 @@ pub struct SchcStats {
     pub packets_matched: AtomicUsize,
     pub total_original_bits: AtomicUsize,
     pub total_compressed_bits: AtomicUsize,
+    /// Packets whose header protection was removed before rule matching
+    pub header_protection_removed: AtomicUsize,
+    /// Packets matched with their header protection in place (i.e. their keys were unknown)
+    pub header_protection_kept: AtomicUsize,
+    /// Packets that weren't observed because neither of their endpoints has a SCHC role, so their
+    /// direction is unknown
+    pub packets_without_direction: AtomicUsize,
+    /// Breakdown by rule, QUIC packet type, direction and node
+    pub breakdown: SchcBreakdown,
+    /// Residue bits per field of the matching rules
+    pub residue: SchcResidueStats,
+    /// Packets that were already SCHC packets when they reached an observer (i.e. compressed or
+    /// fragmented by a compressor upstream), by node
+    pub schc_packets_by_node: Mutex<BTreeMap<String, SchcPacketCounts>>,
+}
+
+/// SCHC packets seen by an observer node, which are counted instead of being matched against the
+/// rules
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SchcPacketCounts {
+    pub compressed_packets: usize,
+    pub compressed_bytes: usize,
+    /// SCHC fragments and ACKs
+    pub fragments: usize,
+    pub fragment_bytes: usize,
 }
 
 impl SchcStats {
@@ -28,6 +57,9 @@ impl SchcStats {
         let original = self.total_original_bits.load(Ordering::Relaxed);
         let compressed = self.total_compressed_bits.load(Ordering::Relaxed);
         let saved = original.saturating_sub(compressed);
+        let hp_removed = self.header_protection_removed.load(Ordering::Relaxed);
+        let hp_kept = self.header_protection_kept.load(Ordering::Relaxed);
+        let without_direction = self.packets_without_direction.load(Ordering::Relaxed);
         
         println!("--- SCHC Observer Statistics ---");
         println!("* Packets processed: {}", processed);
@@ -41,6 +73,60 @@ impl SchcStats {
                      100.0 * saved as f64 / original as f64,
                      original as f64 / compressed.max(1) as f64);
         }
+        if hp_removed + hp_kept > 0 {
+            println!("* Header protection removed: {} packets ({} kept, keys unknown)", hp_removed, hp_kept);
+        }
+        if without_direction > 0 {
+            println!("* WARNING: {} packets not observed, neither of their endpoints has a SCHC role", without_direction);
+        }
+        self.breakdown.report();
+        self.residue.report();
+
+        let schc_packets_by_node = self.schc_packets_by_node.lock();
+        if !schc_packets_by_node.is_empty() {
+            println!("* SCHC packets seen (compressed upstream):");
+            for (node_id, counts) in schc_packets_by_node.iter() {
+                println!(
+                    "  * {}: {} compressed packets ({} bytes), {} fragments and ACKs ({} bytes)",
+                    node_id,
+                    counts.compressed_packets,
+                    counts.compressed_bytes,
+                    counts.fragments,
+                    counts.fragment_bytes
+                );
+            }
+        }
+    }
+
+    /// Returns the statistics as JSON, for the machine-readable report
+    pub fn to_json(&self) -> serde_json::Value {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+        let schc_packets_by_node: serde_json::Map<_, _> = self
+            .schc_packets_by_node
+            .lock()
+            .iter()
+            .map(|(node_id, counts)| {
+                let counts = serde_json::json!({
+                    "compressed_packets": counts.compressed_packets,
+                    "compressed_bytes": counts.compressed_bytes,
+                    "fragments": counts.fragments,
+                    "fragment_bytes": counts.fragment_bytes,
+                });
+                (node_id.clone(), counts)
+            })
+            .collect();
+        serde_json::json!({
+            "packets_processed": load(&self.packets_processed),
+            "packets_matched": load(&self.packets_matched),
+            "total_original_bits": load(&self.total_original_bits),
+            "total_compressed_bits": load(&self.total_compressed_bits),
+            "header_protection_removed": load(&self.header_protection_removed),
+            "header_protection_kept": load(&self.header_protection_kept),
+            "packets_without_direction": load(&self.packets_without_direction),
+            "breakdown": self.breakdown.to_json(),
+            "residue": self.residue.to_json(),
+            "schc_packets_by_node": schc_packets_by_node,
+        })
     }
 }
 
@@ -50,6 +136,8 @@ pub struct SchcObserver {
     rules: Vec<Rule>,
     stats: SchcStats,
     debug: bool,
+    key_log: Option<Arc<QuicKeyLog>>,
+    synthesizer: Option<SchcRuleSynthesizer>,
 }
 
 impl SchcObserver {
@@ -61,6 +149,7 @@ impl SchcObserver {
     ) -> anyhow::Result<Self> {
         let ruleset = RuleSet::from_file(rules_path)?;
         let tree = build_tree(&ruleset.rules);
+        let residue = SchcResidueStats::from_rules(&ruleset.rules)?;
         
         if debug {
             println!("\n--- SCHC Rule Tree ---");
@@ -69,12 +158,46 @@ impl SchcObserver {
         
         Ok(Self {
             tree,
+            stats: SchcStats {
+                breakdown: SchcBreakdown::new(&ruleset.rules),
+                residue,
+                ..SchcStats::default()
+            },
             rules: ruleset.rules,
-            stats: SchcStats::default(),
             debug,
+            key_log: None,
+            synthesizer: None,
         })
     }
 
+    /// Create a new SCHC observer without rules (no packet matches), e.g. to synthesize a rule set
+    /// from scratch
+    pub fn without_rules(debug: bool) -> Self {
+        Self {
+            tree: build_tree(&[]),
+            rules: Vec::new(),
+            stats: SchcStats::default(),
+            debug,
+            key_log: None,
+            synthesizer: None,
+        }
+    }
+
+    /// Remove QUIC header protection before rule matching, using the secrets of the key log
+    ///
+    /// Packets whose keys are unknown are matched with their header protection in place.
+    pub fn with_key_log(mut self, key_log: Option<Arc<QuicKeyLog>>) -> Self {
+        self.key_log = key_log;
+        self
+    }
+
+    /// Record the header field values of the observed packets, to synthesize a candidate rule set
+    /// at the end of the run
+    pub fn with_rule_synthesis(mut self, enabled: bool) -> Self {
+        self.synthesizer = enabled.then(SchcRuleSynthesizer::default);
+        self
+    }
+
     /// Observe compression for a UDP payload (QUIC packet)
     ///
     /// This does NOT modify the packet - it only measures potential compression.
@@ -85,13 +208,42 @@ impl SchcObserver {
         quic_payload: &[u8],
         source_addr: SocketAddr,
         dest_addr: SocketAddr,
+        ecn: Option<EcnCodepoint>,
         is_outgoing: bool,
+        node_id: &str,
     ) {
         self.stats.packets_processed.fetch_add(1, Ordering::Relaxed);
 
-        // Build a proper Ethernet+IPv4+UDP frame around the QUIC payload
-        // using the actual simulation addresses (like pcap_exporter does)
-        let synthetic_packet = self.build_synthetic_packet(quic_payload, source_addr, dest_addr);
+        // Match rules against the actual header values, not the masked ones
+        let unprotected = self.key_log.as_ref().map(|key_log| key_log.unprotect(quic_payload));
+        if let Some(unprotected) = &unprotected {
+            let counter = if unprotected.is_some() {
+                &self.stats.header_protection_removed
+            } else {
+                &self.stats.header_protection_kept
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        let quic_payload = unprotected.as_ref().and_then(|p| p.as_deref()).unwrap_or(quic_payload);
+
+        // Build a proper Ethernet+IP+UDP frame around the QUIC payload
+        // using the actual simulation addresses (IPv4 or IPv6) and ECN codepoint
+        let synthetic_packet = build_synthetic_frame(
+            quic_payload,
+            source_addr,
+            dest_addr,
+            IpHeaderFields::with_ecn(ecn),
+        );
+
+        if let Some(synthesizer) = &self.synthesizer {
+            synthesizer.record(
+                &synthetic_packet[ETHERNET_HEADER_SIZE..],
+                source_addr,
+                dest_addr,
+                is_outgoing,
+                matches!(unprotected, Some(Some(_))),
+            );
+        }
         
         let direction = if is_outgoing {
             Direction::Up
@@ -109,6 +261,11 @@ impl SchcObserver {
                 packet_num, dir_str, quic_payload.len()
             );
             println!("║ {} → {}", source_addr, dest_addr);
+            match &unprotected {
+                Some(Some(_)) => println!("║ Header protection: removed"),
+                Some(None) => println!("║ Header protection: kept (keys unknown)"),
+                None => {}
+            }
             println!(
                 "║ QUIC first byte: 0x{:02x} ({})",
                 quic_payload.get(0).copied().unwrap_or(0),
@@ -143,6 +300,23 @@ impl SchcObserver {
             self.debug, // Pass debug flag to see tree traversal output
         ) {
             Ok(result) => {
+                self.stats.breakdown.record(
+                    node_id,
+                    quic_payload,
+                    is_outgoing,
+                    Some(SchcRuleMatch {
+                        rule_id: result.rule_id,
+                        original_header_bits: result.original_header_bits,
+                        compressed_header_bits: result.compressed_header_bits,
+                    }),
+                );
+                self.stats.residue.record(
+                    result.rule_id,
+                    &synthetic_packet[ETHERNET_HEADER_SIZE..],
+                    is_outgoing,
+                    matches!(unprotected, Some(Some(_))),
+                    result.compressed_header_bits,
+                );
                 self.stats.packets_matched.fetch_add(1, Ordering::Relaxed);
                 self.stats
                     .total_original_bits
@@ -174,6 +348,9 @@ impl SchcObserver {
             }
             Err(e) => {
                 // No matching rule - packet not compressible
+                self.stats
+                    .breakdown
+                    .record(node_id, quic_payload, is_outgoing, None);
                 if self.debug {
                     println!("║ NO MATCH: {:?}", e);
                     println!("╚══════════════════════════════════════════════════════════════════════════════");
@@ -182,77 +359,48 @@ impl SchcObserver {
         }
     }
 
-    /// Build a packet for SCHC parsing using actual simulation addresses.
+    /// Observe a packet that is already a SCHC packet (compressed, or a fragment or ACK), e.g. at
+    /// a node between two compressing gateways
     ///
-    /// The SCHC parser expects full Ethernet+IP+UDP frames.
-    /// We construct proper headers using pnet_packet (same approach as pcap_exporter).
-    fn build_synthetic_packet(
-        &self,
-        quic_payload: &[u8],
-        source_addr: SocketAddr,
-        dest_addr: SocketAddr,
-    ) -> Vec<u8> {
-        // Extract IPv4 addresses (simulation only uses IPv4)
-        let IpAddr::V4(source_ip) = source_addr.ip() else {
-            panic!("SCHC observer only supports IPv4");
-        };
-        let IpAddr::V4(dest_ip) = dest_addr.ip() else {
-            panic!("SCHC observer only supports IPv4");
-        };
-
-        // Use a working buffer (similar to pcap_exporter)
-        let mut buffer = vec![0u8; 2000];
-
-        // Build UDP packet first
-        let udp_packet_length = 8 + quic_payload.len() as u16;
-        {
-            let mut udp_writer = MutableUdpPacket::new(&mut buffer).unwrap();
-            udp_writer.set_source(source_addr.port());
-            udp_writer.set_destination(dest_addr.port());
-            udp_writer.set_length(udp_packet_length);
-            udp_writer.set_payload(quic_payload);
-            let checksum = udp::ipv4_checksum(&udp_writer.to_immutable(), &source_ip, &dest_ip);
-            udp_writer.set_checksum(checksum);
+    /// Its header is no longer an IP/UDP/QUIC header, so it is only counted.
+    pub fn observe_schc_packet(&self, size: usize, is_fragment: bool, node_id: &str) {
+        let mut schc_packets_by_node = self.stats.schc_packets_by_node.lock();
+        let counts = schc_packets_by_node.entry(node_id.to_string()).or_default();
+        if is_fragment {
+            counts.fragments += 1;
+            counts.fragment_bytes += size;
+        } else {
+            counts.compressed_packets += 1;
+            counts.compressed_bytes += size;
         }
-        let udp_packet = buffer[0..udp_packet_length as usize].to_vec();
 
-        // Build IPv4 packet with UDP as payload
-        let ip_packet_length = 20 + udp_packet_length;
-        {
-            let mut ip_writer = MutableIpv4Packet::new(&mut buffer).unwrap();
-            ip_writer.set_version(4);
-            ip_writer.set_header_length(5); // No options
-            ip_writer.set_dscp(0);
-            ip_writer.set_ecn(0);
-            ip_writer.set_total_length(ip_packet_length);
-            ip_writer.set_identification(0);
-            ip_writer.set_flags(0b010); // Don't fragment
-            ip_writer.set_fragment_offset(0);
-            ip_writer.set_ttl(64);
-            ip_writer.set_next_level_protocol(IpNextHeaderProtocol::new(17)); // UDP
-            ip_writer.set_source(source_ip);
-            ip_writer.set_destination(dest_ip);
-            ip_writer.set_payload(&udp_packet);
-            let checksum = ipv4::checksum(&ip_writer.to_immutable());
-            ip_writer.set_checksum(checksum);
+        if self.debug {
+            let kind = if is_fragment { "fragment/ACK" } else { "compressed packet" };
+            println!("[SCHC Observer @ {node_id}] SCHC {kind} ({size} bytes), not matched");
         }
-        let ip_packet = buffer[0..ip_packet_length as usize].to_vec();
+    }
 
-        // Build final frame with Ethernet header
-        let mut frame = Vec::with_capacity(14 + ip_packet.len());
-        // Ethernet header (14 bytes)
-        frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00]); // Dst MAC (placeholder)
-        frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00]); // Src MAC (placeholder)
-        frame.extend_from_slice(&[0x08, 0x00]); // EtherType: IPv4
-        frame.extend_from_slice(&ip_packet);
+    /// Count a packet that can't be observed because neither of its endpoints has a SCHC role,
+    /// so rules can't be matched in the right direction
+    pub fn skip_without_direction(&self, node_id: &str) {
+        self.stats
+            .packets_without_direction
+            .fetch_add(1, Ordering::Relaxed);
 
-        frame
+        if self.debug {
+            println!("[SCHC Observer @ {node_id}] Packet without SCHC role at either end, not observed");
+        }
     }
 
     /// Get statistics
     pub fn stats(&self) -> &SchcStats {
         &self.stats
     }
+
+    /// Get the rule synthesizer, if rule synthesis is enabled
+    pub fn rule_synthesizer(&self) -> Option<&SchcRuleSynthesizer> {
+        self.synthesizer.as_ref()
+    }
 }
 
 /// Format bytes as hex string with optional truncation
@@ -0,0 +1,170 @@
+//! Encoding of SCHC packets in the network captures
+//!
+//! Host captures (see [`pcap_exporter`](crate::pcap_exporter)) contain the datagrams as the
+//! endpoints send and receive them, i.e. before compression and after decompression. Node and link
+//! captures contain the bytes actually travelling through the network instead, including SCHC
+//! packets (compressed packets, fragments, ACKs and rule update chunks).
+//!
+//! SCHC packets are written on the capture's `DLT_USER0` interface (link type 147). Every SCHC
+//! frame starts with an 8-byte pseudo-header carrying the frame's metadata, followed by the SCHC
+//! packet:
+//!
+//! | Byte | Field                                                                  |
+//! | ---- | ---------------------------------------------------------------------- |
+//! | 0    | Pseudo-header version (1)                                              |
+//! | 1    | Frame kind (0: compressed packet, 1: fragment, 2: ACK, 3: rule update) |
+//! | 2    | Direction (0: unknown, 1: UP, 2: DOWN)                                 |
+//! | 3    | Length of the rule ID, in bits (0 when unknown)                        |
+//! | 4-7  | Rule ID (big endian)                                                   |
+//!
+//! The same metadata is written as a comment of each packet, in a human-readable form.
+
+use crate::InTransitData;
+use crate::network::InMemoryNetwork;
+use crate::schc_fragmentation::RULE_ID_BITS;
+
+const PSEUDO_HEADER_VERSION: u8 = 1;
+
+/// The kind of a SCHC frame, as stored in its pseudo-header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SchcFrameKind {
+    Compressed = 0,
+    Fragment = 1,
+    Ack = 2,
+    RuleUpdate = 3,
+}
+
+/// The metadata of a SCHC frame
+struct SchcFrameMeta {
+    kind: SchcFrameKind,
+    /// `Some(true)` for UP, `Some(false)` for DOWN and `None` when unknown
+    is_uplink: Option<bool>,
+    /// The rule ID and its length in bits, when known
+    rule: Option<(u32, u8)>,
+    /// Human-readable description of the frame, stored as a packet comment
+    comment: String,
+}
+
+impl SchcFrameMeta {
+    fn pseudo_header(&self) -> [u8; 8] {
+        let direction = match self.is_uplink {
+            None => 0,
+            Some(true) => 1,
+            Some(false) => 2,
+        };
+        let (rule_id, rule_id_length) = self.rule.unwrap_or_default();
+        let rule_id = rule_id.to_be_bytes();
+        [
+            PSEUDO_HEADER_VERSION,
+            self.kind as u8,
+            direction,
+            rule_id_length,
+            rule_id[0],
+            rule_id[1],
+            rule_id[2],
+            rule_id[3],
+        ]
+    }
+}
+
+/// The packet as a SCHC frame (pseudo-header and SCHC packet) along with its description, or
+/// `None` if it travels as an IP datagram
+pub(crate) fn schc_frame(
+    network: &InMemoryNetwork,
+    data: &InTransitData,
+) -> Option<(Vec<u8>, String)> {
+    let meta = frame_meta(network, data)?;
+    let mut frame = meta.pseudo_header().to_vec();
+    frame.extend_from_slice(&data.transmit.contents);
+    Some((frame, meta.comment))
+}
+
+/// The metadata of the packet as a SCHC frame, or `None` if it travels as an IP datagram
+fn frame_meta(network: &InMemoryNetwork, data: &InTransitData) -> Option<SchcFrameMeta> {
+    let is_uplink = network.schc_is_uplink(data);
+    let direction = match is_uplink {
+        None => "unknown direction",
+        Some(true) => "UP",
+        Some(false) => "DOWN",
+    };
+
+    if let Some(update) = &data.schc_rule_update {
+        return Some(SchcFrameMeta {
+            kind: SchcFrameKind::RuleUpdate,
+            is_uplink: None,
+            rule: None,
+            comment: format!("SCHC rule update {}", update.label()),
+        });
+    }
+
+    if let Some(fragment) = &data.schc_fragment {
+        let rule = network
+            .schc_fragmenter
+            .read()
+            .as_ref()
+            .map(|fragmenter| (fragmenter.config().rule_id as u32, RULE_ID_BITS as u8));
+        let (kind, description) = if fragment.is_fragment() {
+            (SchcFrameKind::Fragment, "SCHC fragment")
+        } else {
+            (SchcFrameKind::Ack, "SCHC ACK")
+        };
+        return Some(SchcFrameMeta {
+            kind,
+            is_uplink,
+            rule,
+            comment: format!(
+                "{description} (packet #{}, {}, {direction})",
+                data.number,
+                rule_label(rule)
+            ),
+        });
+    }
+
+    let state = data.schc_compressed.as_ref()?;
+    let rule = Some((state.rule_id, state.rule_id_length.unwrap_or(0)));
+    Some(SchcFrameMeta {
+        kind: SchcFrameKind::Compressed,
+        is_uplink,
+        rule,
+        comment: format!(
+            "SCHC packet #{} ({}, {direction}, compressed by {} with context v{}, {} bytes before compression)",
+            data.number,
+            rule_label(rule),
+            state.compressor_id,
+            state.context_version,
+            state.original_size
+        ),
+    })
+}
+
+fn rule_label(rule: Option<(u32, u8)>) -> String {
+    match rule {
+        Some((rule_id, 0)) => format!("rule {rule_id}"),
+        Some((rule_id, rule_id_length)) => format!("rule {rule_id}/{rule_id_length}"),
+        None => "unknown rule".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pseudo_header() {
+        let meta = SchcFrameMeta {
+            kind: SchcFrameKind::Fragment,
+            is_uplink: Some(false),
+            rule: Some((0x1ff, 12)),
+            comment: String::new(),
+        };
+        assert_eq!(meta.pseudo_header(), [1, 1, 2, 12, 0, 0, 0x01, 0xff]);
+
+        let meta = SchcFrameMeta {
+            kind: SchcFrameKind::RuleUpdate,
+            is_uplink: None,
+            rule: None,
+            comment: String::new(),
+        };
+        assert_eq!(meta.pseudo_header(), [1, 3, 0, 0, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,717 @@
+//! In-band SCHC rule provisioning
+//!
+//! Rule updates are sent over the simulated network, from the node issuing them to the nodes that
+//! install them. Each update is split into chunks, carried by packets that go through the same
+//! links as any other packet (with their delays, outages and losses). A target node only switches
+//! to the new context version once it received every chunk of the update, while the issuing node
+//! switches as soon as it sends it. Until then, packets compressed with one version may be
+//! decompressed with another one, which is counted as a version mismatch.
+//!
+//! Updates are not acknowledged. Sending an update again (with the same version) lets the nodes
+//! that missed some of its chunks complete it, and nodes ignore updates older than the version
+//! they already use.
+
+use crate::async_rt;
+use crate::async_rt::time::Instant;
+use crate::network::InMemoryNetwork;
+use crate::network::inbound_queue::InboundQueue;
+use crate::network::node::{Node, UdpEndpoint};
+use crate::schc_compressor::SharedSchcCompressor;
+use crate::transmit::OwnedTransmit;
+use crate::{HOST_PORT, InTransitData};
+use anyhow::{Context, bail};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// The version of the rules loaded at startup, used by every node until it installs an update
+pub const INITIAL_CONTEXT_VERSION: u32 = 1;
+
+/// Size of the header of every chunk: the context version (4 bytes), the chunk index and the
+/// chunk count (2 bytes each)
+const CHUNK_HEADER_BYTES: usize = 8;
+
+/// A rule update, sent at a fixed time
+pub struct RuleUpdate {
+    /// Time at which the update is sent, relative to the start of the simulation
+    pub time: Duration,
+    /// The node issuing the update (which switches to the new version when sending it)
+    pub source_id: Arc<str>,
+    /// The nodes the update is sent to
+    pub target_ids: Vec<Arc<str>>,
+    /// The SCHC context whose rules are replaced (`None` for the rules of `--schc-rules`)
+    pub context_id: Option<Arc<str>>,
+    pub version: u32,
+    /// The rules, as sent over the network
+    pub payload: Vec<u8>,
+    /// The compressor built from the new rules
+    pub compressor: SharedSchcCompressor,
+}
+
+impl RuleUpdate {
+    fn chunk_count(&self, chunk_size: usize) -> usize {
+        self.payload.len().div_ceil(chunk_size).max(1)
+    }
+
+    fn label(&self) -> String {
+        version_label(self.context_id.as_deref(), self.version)
+    }
+}
+
+/// Simulation metadata attached to the chunks of a rule update while they travel to their target
+/// node
+#[derive(Clone, Debug)]
+pub(crate) struct RuleUpdateChunkMeta {
+    /// Index of the update in the schedule
+    update: usize,
+    target_id: Arc<str>,
+    chunk: usize,
+    chunk_count: usize,
+}
+
+impl RuleUpdateChunkMeta {
+    pub(crate) fn is_for(&self, node_id: &str) -> bool {
+        self.target_id.as_ref() == node_id
+    }
+
+    pub(crate) fn label(&self) -> String {
+        format!(
+            "chunk {}/{} for {}",
+            self.chunk + 1,
+            self.chunk_count,
+            self.target_id
+        )
+    }
+}
+
+/// A context version change at a node
+#[derive(Clone, Debug)]
+pub struct ContextVersionChange {
+    /// Time of the change, relative to the start of the simulation
+    pub time: Duration,
+    pub node_id: Arc<str>,
+    /// The SCHC context whose rules changed (`None` for the rules of `--schc-rules`)
+    pub context_id: Option<Arc<str>>,
+    pub version: u32,
+}
+
+/// Statistics from in-band rule provisioning
+#[derive(Debug, Default)]
+pub struct SchcProvisioningStats {
+    /// Updates sent, counting each target node separately
+    pub updates_sent: AtomicUsize,
+    pub chunks_sent: AtomicUsize,
+    /// Chunks that reached their target node (including duplicates)
+    pub chunks_received: AtomicUsize,
+    pub updates_installed: AtomicUsize,
+    /// Complete updates ignored because the node already used a newer version
+    pub stale_updates: AtomicUsize,
+    /// Packets decompressed with another context version than the one they were compressed with
+    pub version_mismatches: AtomicUsize,
+    /// Context version changes, in chronological order
+    pub version_changes: Mutex<Vec<ContextVersionChange>>,
+}
+
+/// The outcome of installing an update at a node
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VersionSwitch {
+    Switched,
+    /// The node already uses the version of the update
+    Current,
+    /// The node already uses a newer version
+    Stale {
+        current_version: u32,
+    },
+}
+
+#[derive(Clone)]
+struct InstalledContext {
+    version: u32,
+    compressor: SharedSchcCompressor,
+}
+
+/// Sends rule updates over the network and keeps track of the context versions of each node
+pub struct SchcProvisioning {
+    /// The updates, in the order in which they are sent
+    updates: Vec<RuleUpdate>,
+    /// Size of the rules carried by each chunk, in bytes
+    chunk_size: usize,
+    start: Instant,
+    /// The contexts installed through updates, by node and context (the other contexts use the
+    /// rules loaded at startup)
+    installed: Mutex<HashMap<(Arc<str>, Option<Arc<str>>), InstalledContext>>,
+    /// The chunks received so far, by target node, context and version (so the chunks of an update
+    /// sent again add up to those of the first attempt)
+    received: Mutex<HashMap<(Arc<str>, Option<Arc<str>>, u32), Vec<bool>>>,
+    stats: SchcProvisioningStats,
+    debug: bool,
+}
+
+impl SchcProvisioning {
+    /// Creates the provisioning flow for the provided updates, to be sent relative to `start`
+    /// (the start of the simulation)
+    pub fn new(
+        mut updates: Vec<RuleUpdate>,
+        chunk_size: usize,
+        start: Instant,
+        debug: bool,
+    ) -> anyhow::Result<Self> {
+        if chunk_size == 0 {
+            bail!("the SCHC rule update chunk size must be at least 1 byte");
+        }
+
+        for update in &updates {
+            if update.chunk_count(chunk_size) > u16::MAX as usize {
+                bail!(
+                    "{} needs {} chunks of {chunk_size} bytes, but chunks are numbered with 16 bits",
+                    update.label(),
+                    update.chunk_count(chunk_size)
+                );
+            }
+        }
+
+        updates.sort_by_key(|update| update.time);
+        Ok(Self {
+            updates,
+            chunk_size,
+            start,
+            installed: Mutex::default(),
+            received: Mutex::default(),
+            stats: SchcProvisioningStats::default(),
+            debug,
+        })
+    }
+
+    /// Get statistics
+    pub fn stats(&self) -> &SchcProvisioningStats {
+        &self.stats
+    }
+
+    /// Sends the updates in the background, each at its time
+    ///
+    /// Fails if a node of an update doesn't exist, or if a target can't be reached from the
+    /// source of the update.
+    pub fn schedule(self: &Arc<Self>, network: &Arc<InMemoryNetwork>) -> anyhow::Result<()> {
+        for update in &self.updates {
+            let source = network.node_by_id(&update.source_id).with_context(|| {
+                format!(
+                    "{} is sent by unknown node {}",
+                    update.label(),
+                    update.source_id
+                )
+            })?;
+            for target_id in &update.target_ids {
+                let target = network.node_by_id(target_id).with_context(|| {
+                    format!("{} is sent to unknown node {target_id}", update.label())
+                })?;
+                if !network.has_route(source, target.addresses[0]) {
+                    bail!(
+                        "{} can't be sent from {} to {target_id}: there is no route between them",
+                        update.label(),
+                        update.source_id
+                    );
+                }
+            }
+        }
+
+        let provisioning = self.clone();
+        let network = Arc::downgrade(network);
+        async_rt::spawn(async move {
+            for (index, update) in provisioning.updates.iter().enumerate() {
+                async_rt::time::sleep_until(provisioning.start + update.time).await;
+
+                let Some(network) = network.upgrade() else {
+                    break;
+                };
+                provisioning.send_update(&network, index);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// The context installed at the node through an update, if any, along with its version
+    pub(crate) fn installed_context(
+        &self,
+        node_id: &str,
+        context_id: Option<&Arc<str>>,
+    ) -> Option<(SharedSchcCompressor, u32)> {
+        let key = (Arc::from(node_id), context_id.cloned());
+        let installed = self.installed.lock().get(&key).cloned()?;
+        Some((installed.compressor, installed.version))
+    }
+
+    /// Records a packet decompressed with another context version than the one it was compressed
+    /// with
+    pub(crate) fn version_mismatch(
+        &self,
+        node: &Node,
+        compressed_with: u32,
+        decompressed_with: u32,
+    ) {
+        self.stats
+            .version_mismatches
+            .fetch_add(1, Ordering::Relaxed);
+
+        if self.debug {
+            println!(
+                "[SCHC Provisioning @ {}] Decompressing a packet compressed with v{compressed_with} using v{decompressed_with}",
+                node.id
+            );
+        }
+    }
+
+    /// Handles a chunk that reached its target node, installing the update once all its chunks
+    /// arrived
+    pub(crate) fn handle_chunk(&self, network: &InMemoryNetwork, node: &Node, data: InTransitData) {
+        network.tracer.track_schc_packet_consumed(node, &data);
+        self.stats.chunks_received.fetch_add(1, Ordering::Relaxed);
+
+        let Some(meta) = data.schc_rule_update else {
+            return;
+        };
+        if self.receive_chunk(&node.id, &meta) {
+            self.install(network, node, &self.updates[meta.update]);
+        }
+    }
+
+    /// Records a chunk received by the node, returning whether it completed its update (chunks
+    /// received again after that don't complete it a second time)
+    fn receive_chunk(&self, node_id: &Arc<str>, meta: &RuleUpdateChunkMeta) -> bool {
+        let update = &self.updates[meta.update];
+        let mut received = self.received.lock();
+        let key = (node_id.clone(), update.context_id.clone(), update.version);
+        let chunks = received
+            .entry(key)
+            .or_insert_with(|| vec![false; meta.chunk_count]);
+        if chunks.len() != meta.chunk_count {
+            // Another update with the same version, which doesn't add up to this one
+            *chunks = vec![false; meta.chunk_count];
+        }
+
+        let already_complete = !chunks.contains(&false);
+        chunks[meta.chunk] = true;
+        !already_complete && !chunks.contains(&false)
+    }
+
+    fn send_update(&self, network: &Arc<InMemoryNetwork>, index: usize) {
+        let update = &self.updates[index];
+        let Some(source) = network.node_by_id(&update.source_id).cloned() else {
+            return;
+        };
+
+        // The source switches right away, the targets once they receive the update
+        self.install(network, &source, update);
+
+        let chunks: Vec<_> = update.payload.chunks(self.chunk_size).collect();
+        let chunk_count = update.chunk_count(self.chunk_size);
+        for target_id in &update.target_ids {
+            let Some(target) = network.node_by_id(target_id) else {
+                continue;
+            };
+
+            if self.debug {
+                println!(
+                    "[SCHC Provisioning @ {}] Sending {} to {target_id} ({} bytes in {chunk_count} chunks)",
+                    source.id,
+                    update.label(),
+                    update.payload.len(),
+                );
+            }
+            self.stats.updates_sent.fetch_add(1, Ordering::Relaxed);
+
+            for chunk in 0..chunk_count {
+                let meta = RuleUpdateChunkMeta {
+                    update: index,
+                    target_id: target_id.clone(),
+                    chunk,
+                    chunk_count,
+                };
+                let contents = encode_chunk(
+                    update.version,
+                    &meta,
+                    chunks.get(chunk).copied().unwrap_or_default(),
+                );
+                let data = chunk_data(network, &source, target, meta, contents);
+                self.stats.chunks_sent.fetch_add(1, Ordering::Relaxed);
+                network.emit_schc_packet(&source, data);
+            }
+        }
+    }
+
+    fn install(&self, network: &InMemoryNetwork, node: &Node, update: &RuleUpdate) {
+        let switch = self.switch_version(&node.id, update);
+        if self.debug {
+            match switch {
+                VersionSwitch::Switched => println!(
+                    "[SCHC Provisioning @ {}] Switched to {}",
+                    node.id,
+                    update.label()
+                ),
+                VersionSwitch::Stale { current_version } => println!(
+                    "[SCHC Provisioning @ {}] Ignoring {}, already using v{current_version}",
+                    node.id,
+                    update.label()
+                ),
+                VersionSwitch::Current => {}
+            }
+        }
+        if switch != VersionSwitch::Switched {
+            return;
+        }
+
+        self.stats
+            .version_changes
+            .lock()
+            .push(ContextVersionChange {
+                time: self.start.elapsed(),
+                node_id: node.id.clone(),
+                context_id: update.context_id.clone(),
+                version: update.version,
+            });
+        network.tracer.track_schc_context_version_changed(
+            node,
+            update.context_id.as_deref(),
+            update.version,
+        );
+    }
+
+    /// Switches the node's context to the version of the update, unless it already uses that version
+    /// or a newer one
+    fn switch_version(&self, node_id: &Arc<str>, update: &RuleUpdate) -> VersionSwitch {
+        let mut installed = self.installed.lock();
+        let key = (node_id.clone(), update.context_id.clone());
+        let current_version = installed
+            .get(&key)
+            .map_or(INITIAL_CONTEXT_VERSION, |context| context.version);
+        if update.version == current_version {
+            // E.g. the source sending an update again
+            return VersionSwitch::Current;
+        }
+        if update.version < current_version {
+            self.stats.stale_updates.fetch_add(1, Ordering::Relaxed);
+            return VersionSwitch::Stale { current_version };
+        }
+
+        installed.insert(
+            key,
+            InstalledContext {
+                version: update.version,
+                compressor: update.compressor.clone(),
+            },
+        );
+        self.stats.updates_installed.fetch_add(1, Ordering::Relaxed);
+        VersionSwitch::Switched
+    }
+
+    /// The updates some target node didn't receive completely, along with the number of chunks it
+    /// received (updates sent more than once are only listed once)
+    fn incomplete_updates(&self) -> Vec<(Arc<str>, &RuleUpdate, usize)> {
+        let received = self.received.lock();
+        let mut listed = HashSet::new();
+        let mut incomplete = Vec::new();
+        for update in &self.updates {
+            let chunk_count = update.chunk_count(self.chunk_size);
+            for target_id in &update.target_ids {
+                let key = (target_id.clone(), update.context_id.clone(), update.version);
+                let chunks_received = received
+                    .get(&key)
+                    .filter(|chunks| chunks.len() == chunk_count)
+                    .map_or(0, |chunks| chunks.iter().filter(|&&chunk| chunk).count());
+                if chunks_received < chunk_count && listed.insert(key) {
+                    incomplete.push((target_id.clone(), update, chunks_received));
+                }
+            }
+        }
+
+        incomplete
+    }
+
+    pub fn report(&self) {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+
+        println!("--- SCHC Rule Provisioning Statistics ---");
+        println!("* Updates sent: {}", load(&self.stats.updates_sent));
+        println!("* Chunks sent: {}", load(&self.stats.chunks_sent));
+        println!("* Chunks received: {}", load(&self.stats.chunks_received));
+        println!(
+            "* Updates installed: {}",
+            load(&self.stats.updates_installed)
+        );
+        println!("* Stale updates: {}", load(&self.stats.stale_updates));
+
+        let incomplete = self.incomplete_updates();
+        println!("* Incomplete updates: {}", incomplete.len());
+        for (target_id, update, chunks_received) in incomplete {
+            println!(
+                "  * {target_id}: {} ({chunks_received}/{} chunks received)",
+                update.label(),
+                update.chunk_count(self.chunk_size)
+            );
+        }
+
+        println!(
+            "* Context version mismatches: {}",
+            load(&self.stats.version_mismatches)
+        );
+        println!("* Context version changes:");
+        for change in self.stats.version_changes.lock().iter() {
+            println!(
+                "  * {:.2}s {}: {}",
+                change.time.as_secs_f64(),
+                change.node_id,
+                version_label(change.context_id.as_deref(), change.version)
+            );
+        }
+    }
+
+    /// Returns the statistics as JSON, for the machine-readable report
+    pub fn to_json(&self) -> serde_json::Value {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+        let incomplete_updates: Vec<_> = self
+            .incomplete_updates()
+            .into_iter()
+            .map(|(target_id, update, chunks_received)| {
+                serde_json::json!({
+                    "node_id": target_id.as_ref(),
+                    "context_id": update.context_id.as_deref(),
+                    "version": update.version,
+                    "chunks_received": chunks_received,
+                    "chunk_count": update.chunk_count(self.chunk_size),
+                })
+            })
+            .collect();
+        let version_changes: Vec<_> = self
+            .stats
+            .version_changes
+            .lock()
+            .iter()
+            .map(|change| {
+                serde_json::json!({
+                    "time_sec": change.time.as_secs_f64(),
+                    "node_id": change.node_id.as_ref(),
+                    "context_id": change.context_id.as_deref(),
+                    "version": change.version,
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "updates_sent": load(&self.stats.updates_sent),
+            "chunks_sent": load(&self.stats.chunks_sent),
+            "chunks_received": load(&self.stats.chunks_received),
+            "updates_installed": load(&self.stats.updates_installed),
+            "stale_updates": load(&self.stats.stale_updates),
+            "incomplete_updates": incomplete_updates,
+            "version_mismatches": load(&self.stats.version_mismatches),
+            "version_changes": version_changes,
+        })
+    }
+}
+
+/// Thread-safe wrapper for SCHC rule provisioning
+pub type SharedSchcProvisioning = Arc<SchcProvisioning>;
+
+fn version_label(context_id: Option<&str>, version: u32) -> String {
+    match context_id {
+        Some(context_id) => format!("v{version} of context `{context_id}`"),
+        None => format!("v{version} of the default rules"),
+    }
+}
+
+fn encode_chunk(version: u32, meta: &RuleUpdateChunkMeta, rules: &[u8]) -> Vec<u8> {
+    let mut contents = Vec::with_capacity(CHUNK_HEADER_BYTES + rules.len());
+    contents.extend_from_slice(&version.to_be_bytes());
+    contents.extend_from_slice(&(meta.chunk as u16).to_be_bytes());
+    contents.extend_from_slice(&(meta.chunk_count as u16).to_be_bytes());
+    contents.extend_from_slice(rules);
+    contents
+}
+
+/// A packet carrying a chunk from the source to the target of an update
+fn chunk_data(
+    network: &InMemoryNetwork,
+    source: &Node,
+    target: &Node,
+    meta: RuleUpdateChunkMeta,
+    contents: Vec<u8>,
+) -> InTransitData {
+    // Routers have no UDP endpoint, so they send from their first address
+    let source_endpoint = source.udp_endpoint.clone().unwrap_or_else(|| {
+        Arc::new(UdpEndpoint {
+            addr: SocketAddr::new(source.addresses[0], HOST_PORT),
+            inbound: Arc::new(Mutex::new(InboundQueue::new())),
+        })
+    });
+
+    InTransitData {
+        id: network.new_packet_id(),
+        duplicate: false,
+        source_endpoint,
+        source_id: source.id.clone(),
+        transmit: OwnedTransmit {
+            destination: SocketAddr::new(target.addresses[0], HOST_PORT),
+            ecn: None,
+            contents,
+            segment_size: None,
+        },
+        number: network.next_packet_number(),
+        schc_fragment: None,
+        schc_compressed: None,
+        schc_rule_update: Some(meta),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::schc_compressor::SchcCompressor;
+
+    const CHUNK_SIZE: usize = 10;
+
+    /// Provisioning for updates of the default rules to `SchcNode1`, with the provided versions
+    /// and payload lengths
+    fn new_provisioning(updates: &[(u32, usize)]) -> SchcProvisioning {
+        // Tests run in parallel, each in its own thread
+        let file_name = format!("schc-provisioning-{:?}.json", std::thread::current().id());
+        let path = std::env::temp_dir().join(file_name);
+        std::fs::write(&path, r#"{ "rules": [] }"#).unwrap();
+        let compressor = SchcCompressor::from_files(path.to_str().unwrap(), "", false);
+        std::fs::remove_file(&path).unwrap();
+        let compressor = Arc::new(compressor.unwrap());
+
+        let updates = updates
+            .iter()
+            .enumerate()
+            .map(|(index, &(version, payload_len))| RuleUpdate {
+                time: Duration::from_secs(index as u64),
+                source_id: Arc::from("SchcNode2"),
+                target_ids: vec![Arc::from("SchcNode1")],
+                context_id: None,
+                version,
+                payload: vec![0; payload_len],
+                compressor: compressor.clone(),
+            })
+            .collect();
+        let start = async_rt::new_rt().block_on(async { Instant::now() });
+        SchcProvisioning::new(updates, CHUNK_SIZE, start, false).unwrap()
+    }
+
+    fn chunk(provisioning: &SchcProvisioning, update: usize, chunk: usize) -> RuleUpdateChunkMeta {
+        RuleUpdateChunkMeta {
+            update,
+            target_id: Arc::from("SchcNode1"),
+            chunk,
+            chunk_count: provisioning.updates[update].chunk_count(CHUNK_SIZE),
+        }
+    }
+
+    fn installed_version(provisioning: &SchcProvisioning) -> Option<u32> {
+        provisioning
+            .installed_context("SchcNode1", None)
+            .map(|(_, version)| version)
+    }
+
+    #[test]
+    fn test_chunks_out_of_order() {
+        let provisioning = new_provisioning(&[(2, 25)]);
+        let node_id = Arc::from("SchcNode1");
+
+        assert!(!provisioning.receive_chunk(&node_id, &chunk(&provisioning, 0, 2)));
+        assert!(!provisioning.receive_chunk(&node_id, &chunk(&provisioning, 0, 0)));
+        assert!(provisioning.receive_chunk(&node_id, &chunk(&provisioning, 0, 1)));
+        assert!(provisioning.incomplete_updates().is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_chunks() {
+        let provisioning = new_provisioning(&[(2, 20)]);
+        let node_id = Arc::from("SchcNode1");
+
+        assert!(!provisioning.receive_chunk(&node_id, &chunk(&provisioning, 0, 0)));
+        assert!(!provisioning.receive_chunk(&node_id, &chunk(&provisioning, 0, 0)));
+        assert_eq!(provisioning.incomplete_updates()[0].2, 1);
+        assert!(provisioning.receive_chunk(&node_id, &chunk(&provisioning, 0, 1)));
+
+        // Duplicates arriving after the update completed don't complete it again
+        assert!(!provisioning.receive_chunk(&node_id, &chunk(&provisioning, 0, 1)));
+    }
+
+    #[test]
+    fn test_resent_update_completes() {
+        // The same update sent twice, and a third time once installed
+        let provisioning = new_provisioning(&[(2, 20), (2, 20), (2, 20)]);
+        let node_id = Arc::from("SchcNode1");
+
+        // The second chunk of the first attempt is lost, the first one of the second attempt too
+        assert!(!provisioning.receive_chunk(&node_id, &chunk(&provisioning, 0, 0)));
+        assert_eq!(installed_version(&provisioning), None);
+        assert!(provisioning.receive_chunk(&node_id, &chunk(&provisioning, 1, 1)));
+        assert_eq!(
+            provisioning.switch_version(&node_id, &provisioning.updates[1]),
+            VersionSwitch::Switched
+        );
+        assert_eq!(installed_version(&provisioning), Some(2));
+
+        // The third attempt doesn't install the version again
+        assert!(!provisioning.receive_chunk(&node_id, &chunk(&provisioning, 2, 0)));
+        assert!(!provisioning.receive_chunk(&node_id, &chunk(&provisioning, 2, 1)));
+        assert_eq!(
+            provisioning.switch_version(&node_id, &provisioning.updates[2]),
+            VersionSwitch::Current
+        );
+        assert_eq!(
+            provisioning.stats.updates_installed.load(Ordering::Relaxed),
+            1
+        );
+    }
+
+    #[test]
+    fn test_stale_update() {
+        let provisioning = new_provisioning(&[(3, 5), (2, 5)]);
+        let node_id = Arc::from("SchcNode1");
+
+        assert!(provisioning.receive_chunk(&node_id, &chunk(&provisioning, 0, 0)));
+        assert_eq!(
+            provisioning.switch_version(&node_id, &provisioning.updates[0]),
+            VersionSwitch::Switched
+        );
+
+        // An older version completing later is ignored
+        assert!(provisioning.receive_chunk(&node_id, &chunk(&provisioning, 1, 0)));
+        assert_eq!(
+            provisioning.switch_version(&node_id, &provisioning.updates[1]),
+            VersionSwitch::Stale { current_version: 3 }
+        );
+        assert_eq!(installed_version(&provisioning), Some(3));
+        assert_eq!(provisioning.stats.stale_updates.load(Ordering::Relaxed), 1);
+
+        // So is an update of the initial version
+        let initial = new_provisioning(&[(INITIAL_CONTEXT_VERSION, 5)]);
+        assert_eq!(
+            initial.switch_version(&node_id, &initial.updates[0]),
+            VersionSwitch::Current
+        );
+        assert_eq!(installed_version(&initial), None);
+    }
+
+    #[test]
+    fn test_encode_chunk() {
+        let meta = RuleUpdateChunkMeta {
+            update: 0,
+            target_id: Arc::from("SchcNode1"),
+            chunk: 2,
+            chunk_count: 3,
+        };
+        assert!(meta.is_for("SchcNode1"));
+        assert!(!meta.is_for("SchcNode2"));
+
+        let contents = encode_chunk(7, &meta, b"rules");
+        assert_eq!(contents.len(), CHUNK_HEADER_BYTES + 5);
+        assert_eq!(&contents[..CHUNK_HEADER_BYTES], &[0, 0, 0, 7, 0, 2, 0, 3]);
+        assert_eq!(&contents[CHUNK_HEADER_BYTES..], b"rules");
+    }
+}
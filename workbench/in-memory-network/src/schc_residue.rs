@@ -0,0 +1,417 @@
+//! Per-field residue cost analysis for the SCHC observer
+//!
+//! The SCHC library reports the compressed size of each packet as a whole, so the cost of each
+//! field is derived from the field descriptors of the matching rule (RFC 8724, section 7.4):
+//! `not-sent` and `compute-*` fields cost nothing, `mapping-sent` fields cost enough bits to index
+//! their target values, `LSB` fields cost the bits the MSB matching operator doesn't cover, and
+//! fixed-length `value-sent` fields cost their length. Variable-length residues cost the length of
+//! the field in the observed packet (minus the MSB bits, for `LSB` fields), preceded by their
+//! length in bytes (RFC 8724, section 7.4.2). Compressed bits that can't be attributed to a field
+//! (e.g. a variable-length field the observer can't find in the packet) are reported separately.
+
+use crate::schc_rule_synthesis::{ConnectionIds, read_fields};
+use anyhow::bail;
+use parking_lot::Mutex;
+use schc::{CompressionAction, DirectionIndicator, Field, MatchingOperator, Rule};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+
+/// The cost of a field, as given by its descriptor
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ResidueBits {
+    Fixed(usize),
+    /// Variable-length residue, which costs the length of the field in the packet, minus the
+    /// provided MSB bits
+    Variable {
+        msb_bits: usize,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct FieldDescriptor {
+    /// Field ID, matching operator and compression/decompression action
+    key: FieldKey,
+    /// Whether the field applies to uplink (`Some(true)`) or downlink packets, or both (`None`)
+    uplink: Option<bool>,
+    residue: ResidueBits,
+}
+
+impl FieldDescriptor {
+    fn new(field: &Field) -> Self {
+        let msb_bits = match field.mo {
+            MatchingOperator::Msb(bits) => bits as usize,
+            _ => 0,
+        };
+        let field_length = field.fl.map(usize::from);
+        let residue = match field.cda {
+            CompressionAction::NotSent
+            | CompressionAction::ComputeLength
+            | CompressionAction::ComputeChecksum
+            | CompressionAction::DevIid
+            | CompressionAction::AppIid => ResidueBits::Fixed(0),
+            CompressionAction::MappingSent => {
+                let mappings = field
+                    .tv
+                    .as_ref()
+                    .and_then(Value::as_array)
+                    .map_or(0, Vec::len);
+                ResidueBits::Fixed(index_bits(mappings))
+            }
+            CompressionAction::Lsb => match field_length {
+                Some(length) => ResidueBits::Fixed(length.saturating_sub(msb_bits)),
+                None => ResidueBits::Variable { msb_bits },
+            },
+            CompressionAction::ValueSent => match field_length {
+                Some(length) => ResidueBits::Fixed(length),
+                None => ResidueBits::Variable { msb_bits: 0 },
+            },
+        };
+
+        Self {
+            key: FieldKey {
+                field_id: field.fid.to_string(),
+                matching_operator: matching_operator_name(field.mo).to_string(),
+                action: action_name(field.cda).to_string(),
+            },
+            uplink: match field.di {
+                DirectionIndicator::Up => Some(true),
+                DirectionIndicator::Down => Some(false),
+                DirectionIndicator::Bi => None,
+            },
+            residue,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct FieldKey {
+    field_id: String,
+    matching_operator: String,
+    action: String,
+}
+
+#[derive(Clone, Debug)]
+struct RuleFields {
+    rule_id_length: usize,
+    fields: Vec<FieldDescriptor>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct FieldTotals {
+    packets: usize,
+    residue_bits: usize,
+}
+
+#[derive(Debug, Default)]
+struct ResidueTotals {
+    matched_packets: usize,
+    compressed_bits: usize,
+    rule_id_bits: usize,
+    /// Compressed bits that could not be attributed to a field
+    unattributed_bits: usize,
+    by_field: BTreeMap<FieldKey, FieldTotals>,
+    /// The connection IDs seen so far, to find the connection ID of short header packets
+    connection_ids: ConnectionIds,
+}
+
+/// Residue bits per field, aggregated over the packets the observer matched
+#[derive(Debug, Default)]
+pub struct SchcResidueStats {
+    rules: HashMap<u32, RuleFields>,
+    totals: Mutex<ResidueTotals>,
+}
+
+impl SchcResidueStats {
+    /// Reads the field descriptors of the provided rules, which must all have some
+    pub(crate) fn from_rules(rules: &[Rule]) -> anyhow::Result<Self> {
+        let mut rule_fields = HashMap::new();
+        for rule in rules {
+            if rule.compression.is_empty() {
+                bail!(
+                    "SCHC rule {}/{} has no field descriptors, so its residue cannot be analyzed",
+                    rule.rule_id,
+                    rule.rule_id_length
+                );
+            }
+
+            rule_fields.insert(
+                rule.rule_id,
+                RuleFields {
+                    rule_id_length: rule.rule_id_length as usize,
+                    fields: rule.compression.iter().map(FieldDescriptor::new).collect(),
+                },
+            );
+        }
+
+        Ok(Self {
+            rules: rule_fields,
+            totals: Mutex::default(),
+        })
+    }
+
+    /// Records the residue cost of an IP/UDP/QUIC datagram that matched the provided rule
+    pub(crate) fn record(
+        &self,
+        rule_id: u32,
+        datagram: &[u8],
+        is_outgoing: bool,
+        header_protection_removed: bool,
+        compressed_header_bits: usize,
+    ) {
+        let Some(rule) = self.rules.get(&rule_id) else {
+            return;
+        };
+
+        let mut totals = self.totals.lock();
+
+        // The length of the fields in the packet, for variable-length residues
+        let field_bits: HashMap<_, _> = read_fields(
+            datagram,
+            is_outgoing,
+            header_protection_removed,
+            &mut totals.connection_ids,
+        )
+        .into_iter()
+        .flat_map(|(_, fields)| fields)
+        .map(|field| (field.field_id, field.bits))
+        .collect();
+
+        let fields: Vec<_> = rule
+            .fields
+            .iter()
+            .filter(|field| field.uplink.is_none_or(|uplink| uplink == is_outgoing))
+            .filter_map(|field| {
+                let residue_bits = match field.residue {
+                    ResidueBits::Fixed(bits) => bits,
+                    ResidueBits::Variable { msb_bits } => {
+                        let bits = *field_bits.get(field.key.field_id.as_str())?;
+                        length_prefix_bits(bits / 8) + bits.saturating_sub(msb_bits)
+                    }
+                };
+                Some((field, residue_bits))
+            })
+            .collect();
+        let attributed_bits: usize = fields.iter().map(|&(_, bits)| bits).sum();
+
+        totals.matched_packets += 1;
+        totals.compressed_bits += compressed_header_bits;
+        totals.rule_id_bits += rule.rule_id_length;
+        totals.unattributed_bits += compressed_header_bits
+            .saturating_sub(rule.rule_id_length)
+            .saturating_sub(attributed_bits);
+        for (field, residue_bits) in fields {
+            let field_totals = totals.by_field.entry(field.key.clone()).or_default();
+            field_totals.packets += 1;
+            field_totals.residue_bits += residue_bits;
+        }
+    }
+
+    /// Returns the residue cost of each field as JSON, for the machine-readable report
+    pub fn to_json(&self) -> Value {
+        let totals = self.totals.lock();
+        let fields: Vec<_> = sorted_fields(&totals)
+            .into_iter()
+            .map(|(key, field)| {
+                json!({
+                    "field_id": key.field_id,
+                    "matching_operator": key.matching_operator,
+                    "action": key.action,
+                    "packets": field.packets,
+                    "residue_bits": field.residue_bits,
+                })
+            })
+            .collect();
+
+        json!({
+            "matched_packets": totals.matched_packets,
+            "compressed_bits": totals.compressed_bits,
+            "rule_id_bits": totals.rule_id_bits,
+            "unattributed_bits": totals.unattributed_bits,
+            "fields": fields,
+        })
+    }
+
+    /// Prints the residue cost of each field, most expensive first
+    pub fn report(&self) {
+        let totals = self.totals.lock();
+        if totals.matched_packets == 0 {
+            return;
+        }
+
+        let share = |bits: f64| 100.0 * bits / totals.compressed_bits.max(1) as f64;
+        println!(
+            "* Residue cost by field ({} matched packets, {} compressed bits):",
+            totals.matched_packets, totals.compressed_bits
+        );
+        println!(
+            "  * Rule ID: {:.1} bits on average, {:.1}% of the compressed size",
+            totals.rule_id_bits as f64 / totals.matched_packets as f64,
+            share(totals.rule_id_bits as f64)
+        );
+
+        for (key, field) in sorted_fields(&totals) {
+            println!(
+                "  * {} ({} / {}): {:.1} residue bits on average over {} packets, {:.1}% of the compressed size",
+                key.field_id,
+                key.matching_operator,
+                key.action,
+                field.residue_bits as f64 / field.packets as f64,
+                field.packets,
+                share(field.residue_bits as f64)
+            );
+        }
+
+        if totals.unattributed_bits > 0 {
+            println!(
+                "  * Not attributed to a field: {:.1} bits on average, {:.1}% of the compressed size",
+                totals.unattributed_bits as f64 / totals.matched_packets as f64,
+                share(totals.unattributed_bits as f64)
+            );
+        }
+    }
+}
+
+/// Returns the fields of the totals, most expensive first
+fn sorted_fields(totals: &ResidueTotals) -> Vec<(&FieldKey, &FieldTotals)> {
+    let mut fields: Vec<_> = totals.by_field.iter().collect();
+    fields.sort_by(|(a_key, a), (b_key, b)| {
+        b.residue_bits
+            .cmp(&a.residue_bits)
+            .then_with(|| a_key.cmp(b_key))
+    });
+    fields
+}
+
+fn matching_operator_name(matching_operator: MatchingOperator) -> &'static str {
+    match matching_operator {
+        MatchingOperator::Equal => "equal",
+        MatchingOperator::Ignore => "ignore",
+        MatchingOperator::MatchMapping => "match-mapping",
+        MatchingOperator::Msb(_) => "msb",
+    }
+}
+
+fn action_name(action: CompressionAction) -> &'static str {
+    match action {
+        CompressionAction::NotSent => "not-sent",
+        CompressionAction::ValueSent => "value-sent",
+        CompressionAction::MappingSent => "mapping-sent",
+        CompressionAction::Lsb => "lsb",
+        CompressionAction::ComputeLength => "compute-length",
+        CompressionAction::ComputeChecksum => "compute-checksum",
+        CompressionAction::DevIid => "deviid",
+        CompressionAction::AppIid => "appiid",
+    }
+}
+
+/// The length of the size that precedes a variable-length residue of the provided length in bytes
+/// (RFC 8724, section 7.4.2)
+fn length_prefix_bits(len: usize) -> usize {
+    match len {
+        0..15 => 4,
+        15..255 => 12,
+        _ => 28,
+    }
+}
+
+/// The number of bits needed to index a list of the provided length
+pub(crate) fn index_bits(len: usize) -> usize {
+    match len {
+        0 | 1 => 0,
+        len => (usize::BITS - (len - 1).leading_zeros()) as usize,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::schc_frame::{ETHERNET_HEADER_SIZE, IpHeaderFields, build_synthetic_frame};
+    use schc::RuleSet;
+    use std::net::SocketAddr;
+
+    fn load_rules(name: &str, rules_json: Value) -> anyhow::Result<Vec<Rule>> {
+        let path = std::env::temp_dir().join(format!("schc-residue-{name}.json"));
+        std::fs::write(&path, rules_json.to_string())?;
+        let rule_set = RuleSet::from_file(path.to_str().unwrap());
+        std::fs::remove_file(&path)?;
+        Ok(rule_set?.rules)
+    }
+
+    fn datagram(quic_payload: &[u8]) -> Vec<u8> {
+        let source: SocketAddr = "192.168.40.1:8000".parse().unwrap();
+        let destination: SocketAddr = "192.168.41.2:8080".parse().unwrap();
+        let frame =
+            build_synthetic_frame(quic_payload, source, destination, IpHeaderFields::default());
+        frame[ETHERNET_HEADER_SIZE..].to_vec()
+    }
+
+    #[test]
+    fn test_field_residue_costs() {
+        let rules = load_rules(
+            "costs",
+            json!({
+                "rules": [{
+                    "RuleID": 1,
+                    "RuleIDLength": 8,
+                    "Compression": [
+                        { "FID": "QUIC.FIRST_BYTE", "FL": 8, "DI": "Bi", "TV": 64, "MO": "equal", "CDA": "not-sent" },
+                        { "FID": "QUIC.PN", "FL": 16, "DI": "Bi", "TV": 0, "MO": "MSB(8)", "CDA": "LSB" },
+                        { "FID": "QUIC.VERSION", "FL": 32, "DI": "Up", "TV": [1, 2, 3], "MO": "match-mapping", "CDA": "mapping-sent" },
+                        { "FID": "QUIC.DCID", "DI": "Bi", "MO": "ignore", "CDA": "value-sent" },
+                        { "FID": "UDP.CKSUM", "FL": 16, "DI": "Bi", "MO": "ignore", "CDA": "compute-checksum" }
+                    ]
+                }]
+            }),
+        )
+        .unwrap();
+        let stats = SchcResidueStats::from_rules(&rules).unwrap();
+
+        let cid = [0xaa; 8];
+        let short_header = [&[0x41][..], &cid, &[0x01, 0x00], &[0xff; 16]].concat();
+        let handshake = [
+            &[0xe1, 0, 0, 0, 1, 8][..],
+            &cid,
+            &[0, 0x40, 0x10, 0x01, 0x00],
+        ]
+        .concat();
+
+        // The connection ID of short header packets is unknown until a long header packet carries
+        // it, so its residue is not attributed: 90 - rule ID (8) - PN LSB (8)
+        stats.record(1, &datagram(&short_header), false, true, 90);
+        // Rule ID (8) + PN LSB (8) + version mapping (2) + DCID (4-bit length and 64 bits)
+        stats.record(1, &datagram(&handshake), true, true, 86);
+        // The version field only applies to uplink packets
+        stats.record(1, &datagram(&short_header), false, true, 84);
+        // Unknown rules are ignored
+        stats.record(2, &datagram(&short_header), true, true, 100);
+
+        let totals = stats.totals.lock();
+        assert_eq!(totals.matched_packets, 3);
+        assert_eq!(totals.rule_id_bits, 24);
+        assert_eq!(totals.unattributed_bits, 74);
+
+        let field = |field_id: &str| {
+            let (_, totals) = totals
+                .by_field
+                .iter()
+                .find(|(key, _)| key.field_id == field_id)
+                .unwrap();
+            (totals.packets, totals.residue_bits)
+        };
+        assert_eq!(field("QUIC.FIRST_BYTE"), (3, 0));
+        assert_eq!(field("QUIC.PN"), (3, 24));
+        assert_eq!(field("QUIC.VERSION"), (1, 2));
+        assert_eq!(field("QUIC.DCID"), (2, 136));
+        assert_eq!(field("UDP.CKSUM"), (3, 0));
+    }
+
+    #[test]
+    fn test_rule_without_field_descriptors() {
+        let rules = load_rules(
+            "no-descriptors",
+            json!({ "rules": [{ "RuleID": 1, "RuleIDLength": 8, "Compression": [] }] }),
+        )
+        .unwrap();
+        assert!(SchcResidueStats::from_rules(&rules).is_err());
+    }
+}
@@ -0,0 +1,692 @@
+//! Synthesis of candidate SCHC rules from the traffic seen by the observer
+//!
+//! The synthesizer records the header field values of the observed packets, grouped by flow
+//! (source and destination address), direction and header layout (i.e. the QUIC packet type and the
+//! fields it carries). At the end of the run, each group becomes a candidate rule, choosing for each
+//! field the cheapest matching operator and compression/decompression action that covers all the
+//! values seen:
+//!
+//! * `equal` / `not-sent` for constant fields;
+//! * `match-mapping` / `mapping-sent` for small value sets;
+//! * `MSB` / `LSB` for slowly varying fields (sharing their most significant bits);
+//! * `ignore` / `value-sent` otherwise.
+//!
+//! Length and checksum fields are always computed upon decompression. The packet number is only
+//! recorded when header protection was removed (otherwise its length is unknown).
+//!
+//! The rules are written with RFC 9363-style keys (`RuleID`, `RuleIDLength`, `Compression`, `FID`,
+//! `FL`, `FP`, `DI`, `TV`, `MO`, `MO.VAL` and `CDA`), along with a `no_compression_rule` and the
+//! compression ratio each rule is expected to achieve on the observed traffic.
+
+use crate::schc_residue::index_bits;
+use crate::schc_stats::QuicPacketType;
+use anyhow::Context;
+use parking_lot::Mutex;
+use serde_json::{Map, Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+
+/// The maximum number of values of a `match-mapping` field
+const MAX_MAPPING_VALUES: usize = 8;
+
+/// Fields whose value is computed upon decompression, whatever the observations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ComputedField {
+    Length,
+    Checksum,
+}
+
+/// The value of a header field in an observed packet
+#[derive(Debug)]
+pub(crate) struct FieldValue {
+    pub(crate) field_id: &'static str,
+    pub(crate) bits: usize,
+    /// The value in network byte order (fields that aren't a multiple of 8 bits are right-aligned)
+    value: Vec<u8>,
+    computed: Option<ComputedField>,
+}
+
+/// Reads the fields of a datagram, in the order they appear
+struct FieldReader<'a> {
+    data: &'a [u8],
+    bit_offset: usize,
+    fields: Vec<FieldValue>,
+}
+
+impl<'a> FieldReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            bit_offset: 0,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Reads an integer field of up to 64 bits
+    fn uint(&mut self, field_id: &'static str, bits: usize) -> Option<u64> {
+        self.read_uint(field_id, bits, None)
+    }
+
+    /// Reads an integer field that is computed upon decompression
+    fn computed(&mut self, field_id: &'static str, bits: usize, computed: ComputedField) {
+        self.read_uint(field_id, bits, Some(computed));
+    }
+
+    fn read_uint(
+        &mut self,
+        field_id: &'static str,
+        bits: usize,
+        computed: Option<ComputedField>,
+    ) -> Option<u64> {
+        if self.bit_offset + bits > self.data.len() * 8 {
+            return None;
+        }
+
+        let mut value = 0u64;
+        for _ in 0..bits {
+            let byte = self.data[self.bit_offset / 8];
+            let bit = (byte >> (7 - self.bit_offset % 8)) & 1;
+            value = (value << 1) | bit as u64;
+            self.bit_offset += 1;
+        }
+
+        self.fields.push(FieldValue {
+            field_id,
+            bits,
+            value: value.to_be_bytes()[8 - bits.div_ceil(8)..].to_vec(),
+            computed,
+        });
+        Some(value)
+    }
+
+    /// Reads a byte-aligned field of the provided length (empty fields are left out)
+    fn bytes(&mut self, field_id: &'static str, len: usize) -> Option<&'a [u8]> {
+        debug_assert_eq!(self.bit_offset % 8, 0);
+        let start = self.bit_offset / 8;
+        let value = self.data.get(start..start + len)?;
+        self.bit_offset += len * 8;
+
+        if len > 0 {
+            self.fields.push(FieldValue {
+                field_id,
+                bits: len * 8,
+                value: value.to_vec(),
+                computed: None,
+            });
+        }
+        Some(value)
+    }
+
+    /// Reads a QUIC variable-length integer (the length prefix is part of the field)
+    fn varint(&mut self, field_id: &'static str) -> Option<u64> {
+        let first_byte = *self.data.get(self.bit_offset / 8)?;
+        let len = 1 << (first_byte >> 6);
+        let value = self.uint(field_id, len * 8)?;
+        Some(value & (u64::MAX >> (64 - (len * 8 - 2))))
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.data[(self.bit_offset / 8).min(self.data.len())..]
+    }
+}
+
+/// The connection IDs seen in long header packets
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionIds {
+    known: BTreeSet<Vec<u8>>,
+    /// The number of long header packets with a source connection ID of each length
+    scid_lengths: BTreeMap<usize, usize>,
+}
+
+impl ConnectionIds {
+    /// The length of the destination connection ID at the start of the provided short header
+    /// packet (after the first byte): the longest known connection ID it starts with or, for
+    /// connection IDs issued in NEW_CONNECTION_ID frames, the most common source connection ID
+    /// length
+    fn short_header_dcid_len(&self, packet: &[u8]) -> Option<usize> {
+        let known = self
+            .known
+            .iter()
+            .filter(|cid| packet.starts_with(cid))
+            .map(Vec::len)
+            .max();
+        known.or_else(|| {
+            self.scid_lengths
+                .iter()
+                .max_by_key(|&(_, count)| count)
+                .map(|(&len, _)| len)
+        })
+    }
+}
+
+/// Reads the header fields of an IP/UDP/QUIC datagram, naming the addresses and ports after the
+/// device (the source of uplink packets) and the application (the destination of uplink packets)
+pub(crate) fn read_fields(
+    datagram: &[u8],
+    is_outgoing: bool,
+    header_protection_removed: bool,
+    connection_ids: &mut ConnectionIds,
+) -> Option<(QuicPacketType, Vec<FieldValue>)> {
+    let mut reader = FieldReader::new(datagram);
+    let addresses = |device: &'static str, application: &'static str| {
+        if is_outgoing {
+            (device, application)
+        } else {
+            (application, device)
+        }
+    };
+
+    match datagram.first()? >> 4 {
+        4 => {
+            reader.uint("IPV4.VER", 4)?;
+            if reader.uint("IPV4.IHL", 4)? != 5 {
+                // IPv4 options are not supported
+                return None;
+            }
+            reader.uint("IPV4.DSCP", 6)?;
+            reader.uint("IPV4.ECN", 2)?;
+            reader.computed("IPV4.LEN", 16, ComputedField::Length);
+            reader.uint("IPV4.ID", 16)?;
+            reader.uint("IPV4.FLAGS", 3)?;
+            reader.uint("IPV4.FRAG_OFFSET", 13)?;
+            reader.uint("IPV4.TTL", 8)?;
+            reader.uint("IPV4.PROTO", 8)?;
+            reader.computed("IPV4.CKSUM", 16, ComputedField::Checksum);
+            let (source, destination) = addresses("IPV4.DEV_ADDR", "IPV4.APP_ADDR");
+            reader.uint(source, 32)?;
+            reader.uint(destination, 32)?;
+        }
+        6 => {
+            reader.uint("IPV6.VER", 4)?;
+            reader.uint("IPV6.TC", 8)?;
+            reader.uint("IPV6.FL", 20)?;
+            reader.computed("IPV6.LEN", 16, ComputedField::Length);
+            reader.uint("IPV6.NXT", 8)?;
+            reader.uint("IPV6.HOP_LMT", 8)?;
+            let (source_prefix, destination_prefix) =
+                addresses("IPV6.DEV_PREFIX", "IPV6.APP_PREFIX");
+            let (source_iid, destination_iid) = addresses("IPV6.DEV_IID", "IPV6.APP_IID");
+            reader.uint(source_prefix, 64)?;
+            reader.uint(source_iid, 64)?;
+            reader.uint(destination_prefix, 64)?;
+            reader.uint(destination_iid, 64)?;
+        }
+        _ => return None,
+    }
+
+    let (source_port, destination_port) = addresses("UDP.DEV_PORT", "UDP.APP_PORT");
+    reader.uint(source_port, 16)?;
+    reader.uint(destination_port, 16)?;
+    reader.computed("UDP.LEN", 16, ComputedField::Length);
+    reader.computed("UDP.CKSUM", 16, ComputedField::Checksum);
+
+    let packet_type = QuicPacketType::classify(reader.remaining());
+    let first_byte = reader.uint("QUIC.FIRST_BYTE", 8)? as u8;
+    let packet_number_bits = ((first_byte & 0x03) as usize + 1) * 8;
+
+    if first_byte & 0x80 == 0 {
+        // Short header packets don't carry the length of their connection ID
+        let Some(dcid_len) = connection_ids.short_header_dcid_len(reader.remaining()) else {
+            return Some((packet_type, reader.fields));
+        };
+        reader.bytes("QUIC.DCID", dcid_len)?;
+        if header_protection_removed {
+            reader.uint("QUIC.PN", packet_number_bits)?;
+        }
+
+        return Some((packet_type, reader.fields));
+    }
+
+    reader.uint("QUIC.VERSION", 32)?;
+    let dcid_len = reader.uint("QUIC.DCID_LEN", 8)? as usize;
+    let dcid = reader.bytes("QUIC.DCID", dcid_len)?;
+    let scid_len = reader.uint("QUIC.SCID_LEN", 8)? as usize;
+    let scid = reader.bytes("QUIC.SCID", scid_len)?;
+    connection_ids.known.insert(dcid.to_vec());
+    connection_ids.known.insert(scid.to_vec());
+    *connection_ids.scid_lengths.entry(scid_len).or_default() += 1;
+
+    match packet_type {
+        QuicPacketType::Initial | QuicPacketType::ZeroRtt | QuicPacketType::Handshake => {
+            if packet_type == QuicPacketType::Initial {
+                let token_len = reader.varint("QUIC.TOKEN_LEN")? as usize;
+                reader.bytes("QUIC.TOKEN", token_len)?;
+            }
+            reader.varint("QUIC.LENGTH")?;
+            if header_protection_removed {
+                reader.uint("QUIC.PN", packet_number_bits)?;
+            }
+        }
+        // Retry and Version Negotiation packets have no packet number
+        _ => {}
+    }
+
+    Some((packet_type, reader.fields))
+}
+
+/// The values a field took over the packets of a group
+#[derive(Debug)]
+struct FieldObservations {
+    computed: Option<ComputedField>,
+    /// The length of the values in bits, if they all had the same length
+    bits: Option<usize>,
+    first: Vec<u8>,
+    /// The distinct values (up to `MAX_MAPPING_VALUES + 1`, after which we stop counting)
+    distinct: BTreeSet<Vec<u8>>,
+    /// The bits that differed from the first value (for fixed-length fields up to 64 bits)
+    varying_bits: u64,
+    total_bits: usize,
+}
+
+impl FieldObservations {
+    fn new(field: &FieldValue) -> Self {
+        Self {
+            computed: field.computed,
+            bits: Some(field.bits),
+            first: field.value.clone(),
+            distinct: BTreeSet::new(),
+            varying_bits: 0,
+            total_bits: 0,
+        }
+    }
+
+    fn record(&mut self, field: &FieldValue) {
+        if self.distinct.len() <= MAX_MAPPING_VALUES {
+            self.distinct.insert(field.value.clone());
+        }
+        if self.bits != Some(field.bits) {
+            self.bits = None;
+        }
+        if self.bits.is_some_and(|bits| bits <= 64) {
+            self.varying_bits |= to_u64(&self.first) ^ to_u64(&field.value);
+        }
+        self.total_bits += field.bits;
+    }
+
+    /// Chooses the cheapest field descriptor covering all observed values, returning it along with
+    /// the total residue bits it would take over the group
+    fn descriptor(&self, packets: usize) -> (Map<String, Value>, usize) {
+        let mut descriptor = Map::new();
+        let mut set = |key: &str, value: Value| descriptor.insert(key.to_string(), value);
+
+        if let Some(computed) = self.computed {
+            let action = match computed {
+                ComputedField::Length => "compute-length",
+                ComputedField::Checksum => "compute-checksum",
+            };
+            set("MO", "ignore".into());
+            set("CDA", action.into());
+            return (descriptor, 0);
+        }
+
+        if self.distinct.len() == 1 {
+            set("TV", target_value(self.bits, &self.first));
+            set("MO", "equal".into());
+            set("CDA", "not-sent".into());
+            return (descriptor, 0);
+        }
+
+        // The candidates, from the most to the least general (ties go to the most general one)
+        let mut candidates = vec![(self.total_bits, Candidate::ValueSent)];
+        if let Some(bits) = self.bits.filter(|&bits| bits <= 64) {
+            let msb = bits - (64 - self.varying_bits.leading_zeros() as usize);
+            if msb > 0 {
+                candidates.push(((bits - msb) * packets, Candidate::Msb(msb)));
+            }
+        }
+        if self.distinct.len() <= MAX_MAPPING_VALUES {
+            candidates.push((
+                index_bits(self.distinct.len()) * packets,
+                Candidate::Mapping,
+            ));
+        }
+        let (residue_bits, candidate) = candidates
+            .into_iter()
+            .min_by_key(|&(residue_bits, _)| residue_bits)
+            .unwrap();
+
+        match candidate {
+            Candidate::Msb(msb) => {
+                let bits = self.bits.unwrap();
+                let lsb_mask = u64::MAX >> (64 - (bits - msb));
+                set("TV", (to_u64(&self.first) & !lsb_mask).into());
+                set("MO", "MSB".into());
+                set("MO.VAL", msb.into());
+                set("CDA", "LSB".into());
+            }
+            Candidate::Mapping => {
+                let values = self
+                    .distinct
+                    .iter()
+                    .map(|value| target_value(self.bits, value))
+                    .collect();
+                set("TV", Value::Array(values));
+                set("MO", "match-mapping".into());
+                set("CDA", "mapping-sent".into());
+            }
+            Candidate::ValueSent => {
+                set("MO", "ignore".into());
+                set("CDA", "value-sent".into());
+            }
+        }
+
+        (descriptor, residue_bits)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Candidate {
+    Msb(usize),
+    Mapping,
+    ValueSent,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct GroupKey {
+    source: SocketAddr,
+    destination: SocketAddr,
+    is_outgoing: bool,
+    packet_type: QuicPacketType,
+    field_ids: Vec<&'static str>,
+}
+
+#[derive(Debug)]
+struct GroupObservations {
+    packets: usize,
+    fields: Vec<FieldObservations>,
+}
+
+#[derive(Debug, Default)]
+struct Observations {
+    groups: BTreeMap<GroupKey, GroupObservations>,
+    connection_ids: ConnectionIds,
+}
+
+/// Records the header field values seen by the observer, to synthesize a candidate rule set
+#[derive(Debug, Default)]
+pub struct SchcRuleSynthesizer {
+    observations: Mutex<Observations>,
+}
+
+impl SchcRuleSynthesizer {
+    /// Records the fields of an IP/UDP/QUIC datagram
+    pub(crate) fn record(
+        &self,
+        datagram: &[u8],
+        source: SocketAddr,
+        destination: SocketAddr,
+        is_outgoing: bool,
+        header_protection_removed: bool,
+    ) {
+        let mut observations = self.observations.lock();
+        let Some((packet_type, fields)) = read_fields(
+            datagram,
+            is_outgoing,
+            header_protection_removed,
+            &mut observations.connection_ids,
+        ) else {
+            return;
+        };
+
+        let key = GroupKey {
+            source,
+            destination,
+            is_outgoing,
+            packet_type,
+            field_ids: fields.iter().map(|field| field.field_id).collect(),
+        };
+        let group = observations
+            .groups
+            .entry(key)
+            .or_insert_with(|| GroupObservations {
+                packets: 0,
+                fields: fields.iter().map(FieldObservations::new).collect(),
+            });
+        group.packets += 1;
+        for (observations, field) in group.fields.iter_mut().zip(&fields) {
+            observations.record(field);
+        }
+    }
+
+    /// Returns the candidate rule set, one rule per group of observed packets
+    pub fn synthesize(&self) -> Value {
+        let observations = self.observations.lock();
+
+        // Rule 0 is kept for the no-compression rule
+        let rule_id_length = index_bits(observations.groups.len() + 1).max(1);
+        let mut rules = Vec::new();
+        let mut expected = Vec::new();
+        let mut total_original_bits = 0;
+        let mut total_compressed_bits = 0;
+        for (rule_id, (key, group)) in (1..).zip(&observations.groups) {
+            let direction = if key.is_outgoing { "Up" } else { "Dw" };
+            let mut original_bits = 0;
+            let mut compressed_bits = rule_id_length * group.packets;
+            let mut compression = Vec::new();
+            for (field_id, field) in key.field_ids.iter().zip(&group.fields) {
+                let (descriptor, residue_bits) = field.descriptor(group.packets);
+                original_bits += field.total_bits;
+                compressed_bits += residue_bits;
+
+                let mut entry = Map::new();
+                entry.insert("FID".to_string(), (*field_id).into());
+                if let Some(bits) = field.bits {
+                    entry.insert("FL".to_string(), bits.into());
+                }
+                entry.insert("FP".to_string(), 1.into());
+                entry.insert("DI".to_string(), direction.into());
+                entry.extend(descriptor);
+                compression.push(Value::Object(entry));
+            }
+
+            total_original_bits += original_bits;
+            total_compressed_bits += compressed_bits;
+            rules.push(json!({
+                "RuleID": rule_id,
+                "RuleIDLength": rule_id_length,
+                "Compression": compression,
+            }));
+            expected.push(json!({
+                "rule_id": rule_id,
+                "source": key.source.to_string(),
+                "destination": key.destination.to_string(),
+                "direction": direction,
+                "packet_type": key.packet_type.to_string(),
+                "packets": group.packets,
+                "original_header_bits": original_bits,
+                "compressed_header_bits": compressed_bits,
+                "ratio": ratio(original_bits, compressed_bits),
+            }));
+        }
+
+        json!({
+            "rules": rules,
+            "no_compression_rule": { "rule_id": 0, "rule_id_length": rule_id_length },
+            "expected_compression": {
+                "original_header_bits": total_original_bits,
+                "compressed_header_bits": total_compressed_bits,
+                "ratio": ratio(total_original_bits, total_compressed_bits),
+                "rules": expected,
+            },
+        })
+    }
+
+    /// Writes the candidate rule set to the provided path, and prints its expected compression
+    pub fn write_rules(&self, path: &Path) -> anyhow::Result<()> {
+        let rule_set = self.synthesize();
+        let json = serde_json::to_vec_pretty(&rule_set).unwrap();
+        fs::write(path, json).with_context(|| {
+            format!("failed to store synthesized rules at `{}`", path.display())
+        })?;
+
+        println!("--- SCHC Rule Synthesis ---");
+        println!("* Candidate rules available at {}", path.display());
+        let expected = &rule_set["expected_compression"];
+        for rule in expected["rules"].as_array().into_iter().flatten() {
+            println!(
+                "  * Rule {}: {} → {} ({}, {}): {} packets, {} → {} header bits (ratio {:.2}:1)",
+                rule["rule_id"],
+                rule["source"].as_str().unwrap_or_default(),
+                rule["destination"].as_str().unwrap_or_default(),
+                rule["direction"].as_str().unwrap_or_default(),
+                rule["packet_type"].as_str().unwrap_or_default(),
+                rule["packets"],
+                rule["original_header_bits"],
+                rule["compressed_header_bits"],
+                rule["ratio"].as_f64().unwrap_or_default()
+            );
+        }
+        println!(
+            "* Expected compression: {} → {} header bits (ratio {:.2}:1)",
+            expected["original_header_bits"],
+            expected["compressed_header_bits"],
+            expected["ratio"].as_f64().unwrap_or_default()
+        );
+
+        Ok(())
+    }
+}
+
+/// The target value of a field: an integer for fields up to 64 bits, a hex string otherwise
+fn target_value(bits: Option<usize>, value: &[u8]) -> Value {
+    match bits {
+        Some(bits) if bits <= 64 => to_u64(value).into(),
+        _ => {
+            let hex: String = value.iter().map(|b| format!("{b:02x}")).collect();
+            format!("0x{hex}").into()
+        }
+    }
+}
+
+fn to_u64(value: &[u8]) -> u64 {
+    value.iter().fold(0, |acc, &byte| (acc << 8) | byte as u64)
+}
+
+fn ratio(original_bits: usize, compressed_bits: usize) -> f64 {
+    original_bits as f64 / compressed_bits.max(1) as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::schc_frame::{ETHERNET_HEADER_SIZE, IpHeaderFields, build_synthetic_frame};
+    use crate::schc_residue::SchcResidueStats;
+    use quinn::udp::EcnCodepoint;
+    use schc::{Direction, RuleSet, build_tree, compress_packet};
+
+    #[test]
+    fn test_synthesize_rules() {
+        let source: SocketAddr = "192.168.40.1:8000".parse().unwrap();
+        let destination: SocketAddr = "192.168.41.2:8080".parse().unwrap();
+        let cid = [0xaa, 0xbb, 0xcc, 0xdd];
+        let synthesizer = SchcRuleSynthesizer::default();
+        let record = |quic_payload: &[u8], ecn| {
+            let frame = build_synthetic_frame(
+                quic_payload,
+                source,
+                destination,
+                IpHeaderFields::with_ecn(ecn),
+            );
+            synthesizer.record(
+                &frame[ETHERNET_HEADER_SIZE..],
+                source,
+                destination,
+                true,
+                true,
+            );
+            frame
+        };
+        let mut frames = Vec::new();
+
+        // A Handshake packet makes the connection ID known
+        let mut handshake = vec![0xe1, 0, 0, 0, 1, 4];
+        handshake.extend_from_slice(&cid);
+        handshake.extend_from_slice(&[4]);
+        handshake.extend_from_slice(&cid);
+        handshake.extend_from_slice(&[0x40, 0x10, 0x00, 0x00, 0xff]);
+        frames.push(record(&handshake, None));
+
+        // Short header packets with a 2-byte packet number and two ECN codepoints
+        for (pn, ecn) in [
+            (0x0100u16, None),
+            (0x0101, Some(EcnCodepoint::Ect0)),
+            (0x0102, None),
+            (0x0103, Some(EcnCodepoint::Ect0)),
+        ] {
+            let mut packet = vec![0x41];
+            packet.extend_from_slice(&cid);
+            packet.extend_from_slice(&pn.to_be_bytes());
+            packet.extend_from_slice(&[0xff; 16]);
+            frames.push(record(&packet, ecn));
+        }
+
+        let rule_set = synthesizer.synthesize();
+        let rules = rule_set["rules"].as_array().unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rule_set["no_compression_rule"]["rule_id_length"], 2);
+
+        // Rules are sorted by packet type, so the 1-RTT rule comes last
+        let short_header = &rules[1];
+        let field = |field_id: &str| {
+            short_header["Compression"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|field| field["FID"] == field_id)
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(field("IPV4.ECN")["MO"], "match-mapping");
+        assert_eq!(field("IPV4.ECN")["TV"], json!([0, 2]));
+        assert_eq!(field("IPV4.DEV_ADDR")["MO"], "equal");
+        assert_eq!(field("UDP.APP_PORT")["TV"], 8080);
+        assert_eq!(field("UDP.LEN")["CDA"], "compute-length");
+        assert_eq!(field("QUIC.DCID")["TV"], 0xaabbccdd_u64);
+        assert_eq!(field("QUIC.PN")["MO"], "MSB");
+        assert_eq!(field("QUIC.PN")["MO.VAL"], 14);
+        assert_eq!(field("QUIC.PN")["TV"], 0x0100);
+
+        // Rule ID (2 bits) + ECN mapping (1 bit) + packet number LSB (2 bits), per packet
+        let expected = &rule_set["expected_compression"]["rules"][1];
+        assert_eq!(expected["packets"], 4);
+        assert_eq!(expected["compressed_header_bits"], 4 * 5);
+
+        // The synthesized file can be loaded as a rule set
+        let path = std::env::temp_dir().join("schc-synthesized-rules.json");
+        synthesizer.write_rules(&path).unwrap();
+        let rules = RuleSet::from_file(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        let rules = rules.unwrap().rules;
+        assert_eq!(rules.len(), 2);
+
+        // The expected compression is what the rules achieve on the observed packets
+        let tree = build_tree(&rules);
+        let mut compressed_bits = BTreeMap::new();
+        for frame in &frames {
+            let result = compress_packet(&tree, frame, Direction::Up, &rules, false).unwrap();
+            *compressed_bits.entry(result.rule_id).or_default() += result.compressed_header_bits;
+        }
+        let expected_rules = rule_set["expected_compression"]["rules"]
+            .as_array()
+            .unwrap();
+        for expected in expected_rules {
+            let rule_id = expected["rule_id"].as_u64().unwrap() as u32;
+            assert_eq!(
+                expected["compressed_header_bits"],
+                compressed_bits[&rule_id]
+            );
+        }
+        assert_eq!(
+            rule_set["expected_compression"]["compressed_header_bits"],
+            compressed_bits.values().sum::<usize>()
+        );
+
+        // The rules can be used by the residue analysis
+        let residue = SchcResidueStats::from_rules(&rules).unwrap();
+        let short_header = &frames.last().unwrap()[ETHERNET_HEADER_SIZE..];
+        residue.record(2, short_header, true, true, 5);
+        assert!(residue.to_json()["fields"].as_array().unwrap().len() > 10);
+    }
+}
@@ -0,0 +1,351 @@
+//! Breakdown of SCHC statistics by rule, QUIC packet type, direction and node
+//!
+//! The global counters tell how much the rule set compresses overall, while the breakdown tells
+//! which traffic it fails to cover (e.g. rules that never match, or packet types that always fall
+//! through).
+
+use crate::tracing::simulation_step::SchcDirection;
+use parking_lot::Mutex;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// The type of a QUIC packet, as told by its first byte and version (which are not protected)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum QuicPacketType {
+    Initial,
+    ZeroRtt,
+    Handshake,
+    Retry,
+    VersionNegotiation,
+    /// 1-RTT packets (the only ones using the short header)
+    OneRtt,
+    /// Not a QUIC packet (e.g. an empty payload)
+    Unknown,
+}
+
+impl QuicPacketType {
+    /// Classifies the first packet of a QUIC datagram (coalesced packets are not inspected)
+    pub fn classify(quic_payload: &[u8]) -> Self {
+        let Some(&first_byte) = quic_payload.first() else {
+            return Self::Unknown;
+        };
+
+        if first_byte & 0x80 == 0 {
+            return Self::OneRtt;
+        }
+
+        match quic_payload.get(1..5) {
+            None => Self::Unknown,
+            Some([0, 0, 0, 0]) => Self::VersionNegotiation,
+            Some(_) => match (first_byte >> 4) & 0x03 {
+                0x00 => Self::Initial,
+                0x01 => Self::ZeroRtt,
+                0x02 => Self::Handshake,
+                _ => Self::Retry,
+            },
+        }
+    }
+}
+
+impl fmt::Display for QuicPacketType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Initial => "Initial",
+            Self::ZeroRtt => "0-RTT",
+            Self::Handshake => "Handshake",
+            Self::Retry => "Retry",
+            Self::VersionNegotiation => "Version Negotiation",
+            Self::OneRtt => "1-RTT (short header)",
+            Self::Unknown => "Unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The header sizes of a packet that matched a SCHC rule
+#[derive(Clone, Copy, Debug)]
+pub struct SchcRuleMatch {
+    pub rule_id: u32,
+    pub original_header_bits: usize,
+    pub compressed_header_bits: usize,
+}
+
+/// Counters of one of the entries of the breakdown
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SchcCounts {
+    pub packets: usize,
+    pub matched: usize,
+    pub original_header_bits: usize,
+    pub compressed_header_bits: usize,
+}
+
+impl SchcCounts {
+    fn record(&mut self, rule_match: Option<SchcRuleMatch>) {
+        self.packets += 1;
+        if let Some(rule_match) = rule_match {
+            self.matched += 1;
+            self.original_header_bits += rule_match.original_header_bits;
+            self.compressed_header_bits += rule_match.compressed_header_bits;
+        }
+    }
+
+    pub fn saved_bits(&self) -> usize {
+        self.original_header_bits
+            .saturating_sub(self.compressed_header_bits)
+    }
+
+    fn to_json(self) -> Value {
+        json!({
+            "packets": self.packets,
+            "matched": self.matched,
+            "original_header_bits": self.original_header_bits,
+            "compressed_header_bits": self.compressed_header_bits,
+            "saved_bits": self.saved_bits(),
+        })
+    }
+
+    fn summary(&self) -> String {
+        let matched_pct = 100.0 * self.matched as f64 / self.packets.max(1) as f64;
+        let saved_pct = 100.0 * self.saved_bits() as f64 / self.original_header_bits.max(1) as f64;
+        format!(
+            "{}/{} packets matched ({:.1}%), {} bits saved ({:.1}%)",
+            self.matched,
+            self.packets,
+            matched_pct,
+            self.saved_bits(),
+            saved_pct
+        )
+    }
+}
+
+/// The tables of the breakdown
+#[derive(Clone, Debug, Default)]
+pub struct SchcBreakdownTables {
+    /// Counters of the packets that matched each rule (`packets` and `matched` are equal)
+    pub by_rule: BTreeMap<u32, SchcCounts>,
+    pub by_packet_type: BTreeMap<QuicPacketType, SchcCounts>,
+    pub by_direction: BTreeMap<SchcDirection, SchcCounts>,
+    pub by_node: BTreeMap<Arc<str>, SchcCounts>,
+}
+
+/// Breakdown of the packets processed by a SCHC observer or compressor
+#[derive(Debug, Default)]
+pub struct SchcBreakdown {
+    /// The rules of the rule set (ID and ID length), so rules that never matched can be reported
+    rules: Vec<(u32, u8)>,
+    tables: Mutex<SchcBreakdownTables>,
+}
+
+impl SchcBreakdown {
+    pub(crate) fn new(rules: &[schc::Rule]) -> Self {
+        Self {
+            rules: rules
+                .iter()
+                .map(|rule| (rule.rule_id, rule.rule_id_length))
+                .collect(),
+            tables: Mutex::default(),
+        }
+    }
+
+    /// Records a processed packet, along with the rule it matched (if any)
+    pub(crate) fn record(
+        &self,
+        node_id: &str,
+        quic_payload: &[u8],
+        is_outgoing: bool,
+        rule_match: Option<SchcRuleMatch>,
+    ) {
+        let mut tables = self.tables.lock();
+        if let Some(rule_match) = rule_match {
+            tables
+                .by_rule
+                .entry(rule_match.rule_id)
+                .or_default()
+                .record(Some(rule_match));
+        }
+
+        tables
+            .by_packet_type
+            .entry(QuicPacketType::classify(quic_payload))
+            .or_default()
+            .record(rule_match);
+        tables
+            .by_direction
+            .entry(SchcDirection::from_is_outgoing(is_outgoing))
+            .or_default()
+            .record(rule_match);
+
+        if let Some(counts) = tables.by_node.get_mut(node_id) {
+            counts.record(rule_match);
+        } else {
+            let mut counts = SchcCounts::default();
+            counts.record(rule_match);
+            tables.by_node.insert(node_id.into(), counts);
+        }
+    }
+
+    /// Returns a snapshot of the tables
+    pub fn tables(&self) -> SchcBreakdownTables {
+        self.tables.lock().clone()
+    }
+
+    /// The rules of the rule set, along with any other rule that matched (with an unknown rule ID
+    /// length of 0)
+    fn all_rules(&self, tables: &SchcBreakdownTables) -> Vec<(u32, u8)> {
+        let mut rules = self.rules.clone();
+        for &rule_id in tables.by_rule.keys() {
+            if !rules.iter().any(|&(id, _)| id == rule_id) {
+                // Not part of the rule set (the rule ID length is unknown)
+                rules.push((rule_id, 0));
+            }
+        }
+        rules.sort();
+        rules
+    }
+
+    /// Returns the breakdown as JSON, for the machine-readable report
+    pub fn to_json(&self) -> Value {
+        let tables = self.tables.lock();
+        let by_rule: Vec<_> = self
+            .all_rules(&tables)
+            .into_iter()
+            .map(|(rule_id, rule_id_length)| {
+                let counts = tables.by_rule.get(&rule_id).copied().unwrap_or_default();
+                json!({
+                    "rule_id": rule_id,
+                    "rule_id_length": rule_id_length,
+                    "packets": counts.matched,
+                    "saved_bits": counts.saved_bits(),
+                })
+            })
+            .collect();
+        let by_packet_type: serde_json::Map<_, _> = tables
+            .by_packet_type
+            .iter()
+            .map(|(packet_type, counts)| (packet_type.to_string(), counts.to_json()))
+            .collect();
+        let by_direction: serde_json::Map<_, _> = tables
+            .by_direction
+            .iter()
+            .map(|(direction, counts)| {
+                let direction = match direction {
+                    SchcDirection::Up => "up",
+                    SchcDirection::Down => "down",
+                };
+                (direction.to_string(), counts.to_json())
+            })
+            .collect();
+        let by_node: serde_json::Map<_, _> = tables
+            .by_node
+            .iter()
+            .map(|(node_id, counts)| (node_id.to_string(), counts.to_json()))
+            .collect();
+
+        json!({
+            "by_rule": by_rule,
+            "by_packet_type": by_packet_type,
+            "by_direction": by_direction,
+            "by_node": by_node,
+        })
+    }
+
+    /// Prints the breakdown, as part of the end-of-run report
+    pub fn report(&self) {
+        let tables = self.tables.lock();
+        if tables.by_packet_type.is_empty() {
+            return;
+        }
+
+        println!("* By rule:");
+        for (rule_id, rule_id_length) in self.all_rules(&tables) {
+            let rule = match rule_id_length {
+                0 => format!("Rule {rule_id}"),
+                _ => format!("Rule {rule_id}/{rule_id_length}"),
+            };
+            match tables.by_rule.get(&rule_id) {
+                Some(counts) => println!(
+                    "  * {rule}: {} packets, {} bits saved ({:.1} bits per packet)",
+                    counts.matched,
+                    counts.saved_bits(),
+                    counts.saved_bits() as f64 / counts.matched.max(1) as f64
+                ),
+                None => println!("  * {rule}: never matched"),
+            }
+        }
+
+        println!("* By QUIC packet type:");
+        for (packet_type, counts) in &tables.by_packet_type {
+            println!("  * {packet_type}: {}", counts.summary());
+        }
+
+        println!("* By direction:");
+        for (direction, counts) in &tables.by_direction {
+            let direction = match direction {
+                SchcDirection::Up => "Up",
+                SchcDirection::Down => "Down",
+            };
+            println!("  * {direction}: {}", counts.summary());
+        }
+
+        println!("* By node:");
+        for (node_id, counts) in &tables.by_node {
+            println!("  * {node_id}: {}", counts.summary());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_classify_packet_types() {
+        let long_header = |first_byte: u8, version: u32| {
+            let mut packet = vec![first_byte];
+            packet.extend_from_slice(&version.to_be_bytes());
+            packet
+        };
+
+        let cases = [
+            (long_header(0xc3, 1), QuicPacketType::Initial),
+            (long_header(0xd0, 1), QuicPacketType::ZeroRtt),
+            (long_header(0xe1, 1), QuicPacketType::Handshake),
+            (long_header(0xf0, 1), QuicPacketType::Retry),
+            (long_header(0x80, 0), QuicPacketType::VersionNegotiation),
+            (vec![0x41, 0x00], QuicPacketType::OneRtt),
+            (vec![0xc0, 0x00], QuicPacketType::Unknown),
+            (vec![], QuicPacketType::Unknown),
+        ];
+        for (packet, expected) in cases {
+            assert_eq!(QuicPacketType::classify(&packet), expected);
+        }
+    }
+
+    #[test]
+    fn test_breakdown_counts() {
+        let breakdown = SchcBreakdown::default();
+        let rule_match = SchcRuleMatch {
+            rule_id: 5,
+            original_header_bits: 400,
+            compressed_header_bits: 100,
+        };
+        breakdown.record("SchcNode1", &[0x41, 0x00], true, Some(rule_match));
+        breakdown.record("SchcNode1", &[0x41, 0x00], true, None);
+        breakdown.record("SchcNode2", &[0x41, 0x00], false, Some(rule_match));
+
+        let tables = breakdown.tables();
+        assert_eq!(tables.by_rule[&5].matched, 2);
+        assert_eq!(tables.by_rule[&5].saved_bits(), 600);
+
+        let one_rtt = tables.by_packet_type[&QuicPacketType::OneRtt];
+        assert_eq!((one_rtt.packets, one_rtt.matched), (3, 2));
+
+        let up = tables.by_direction[&SchcDirection::Up];
+        assert_eq!((up.packets, up.matched), (2, 1));
+
+        let node1 = tables.by_node["SchcNode1"];
+        assert_eq!((node1.packets, node1.saved_bits()), (2, 300));
+    }
+}
@@ -34,6 +34,24 @@ pub enum SimulationStepKind {
     PacketInTransit(PacketInTransit),
     /// The packet has been delivered to an application
     PacketDeliveredToApplication(GenericPacketEvent),
+    /// The packet was consumed by a SCHC F/R endpoint (i.e. it was fragmented, or it is a
+    /// fragment or ACK that reached its destination endpoint), or it is a rule update chunk that
+    /// reached its target node
+    SchcPacketConsumed(GenericPacketEvent),
+    /// The packet was created by a SCHC F/R endpoint (i.e. it is a fragment, an ACK or a
+    /// reassembled packet), or it is a rule update chunk
+    SchcPacketCreated(GenericPacketEvent),
+    /// The packet reached its destination host while still SCHC-compressed, so it was dropped
+    SchcCompressedPacketDropped(SchcCompressedPacketDropped),
+    /// The packet was compressed by a SCHC compressor (its size changes)
+    SchcPacketCompressed(SchcPacketResized),
+    /// The packet was decompressed by a SCHC decompressor (its size changes)
+    SchcPacketDecompressed(SchcPacketResized),
+    /// A SCHC compressor or decompressor failed to process the packet, so it was forwarded as-is
+    SchcProcessingFailed(SchcProcessingFailed),
+    /// A node switched to another version of a SCHC context (i.e. it sent or received a rule
+    /// update)
+    SchcContextVersionChanged(SchcContextVersionChanged),
     /// A network event happened
     NetworkEvent(NetworkEventPayload),
 }
@@ -80,6 +98,82 @@ pub struct PacketInTransit {
     pub node_id: Arc<str>,
     #[serde(with = "crate::util::serde_arc_str")]
     pub link_id: Arc<str>,
+    /// The number of bytes the packet occupies on the link (i.e. the packet's size plus the link's
+    /// framing overhead), if the link adds any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame_size_bytes: Option<usize>,
+}
+
+#[serde_as]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SchcCompressedPacketDropped {
+    #[serde_as(as = "DisplayFromStr")]
+    pub packet_id: Uuid,
+    #[serde(with = "crate::util::serde_arc_str")]
+    pub node_id: Arc<str>,
+    #[serde(with = "crate::util::serde_arc_str")]
+    pub compressor_node_id: Arc<str>,
+    pub rule_id: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SchcDirection {
+    Up,
+    Down,
+}
+
+impl SchcDirection {
+    pub fn from_is_outgoing(is_outgoing: bool) -> Self {
+        if is_outgoing { Self::Up } else { Self::Down }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SchcOperation {
+    Compress,
+    Decompress,
+}
+
+#[serde_as]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SchcPacketResized {
+    #[serde_as(as = "DisplayFromStr")]
+    pub packet_id: Uuid,
+    #[serde(with = "crate::util::serde_arc_str")]
+    pub node_id: Arc<str>,
+    pub rule_id: u32,
+    pub direction: SchcDirection,
+    pub size_before_bytes: usize,
+    pub size_after_bytes: usize,
+    /// Digest of the uncompressed contents (before compression, or after decompression)
+    pub uncompressed_contents_digest: u64,
+    /// Version of the SCHC context used by the node (1 for the rules loaded at startup)
+    pub context_version: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SchcContextVersionChanged {
+    #[serde(with = "crate::util::serde_arc_str")]
+    pub node_id: Arc<str>,
+    /// The SCHC context whose rules changed (`None` for the default rules)
+    pub context_id: Option<String>,
+    pub version: u32,
+}
+
+#[serde_as]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SchcProcessingFailed {
+    #[serde_as(as = "DisplayFromStr")]
+    pub packet_id: Uuid,
+    #[serde(with = "crate::util::serde_arc_str")]
+    pub node_id: Arc<str>,
+    pub operation: SchcOperation,
+    /// The rule the packet was compressed with (only known when decompressing)
+    pub rule_id: Option<u32>,
+    pub direction: SchcDirection,
+    pub packet_size_bytes: usize,
 }
 
 #[serde_as]
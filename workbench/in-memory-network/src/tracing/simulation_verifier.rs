@@ -116,6 +116,43 @@ pub enum NonFatalError {
         max_bps: usize,
         observed_bps: usize,
     },
+    #[error(
+        "packet `{packet_id}` reached host `{node_id}` while still SCHC-compressed (compressed by `{compressor_node_id}`), so it was dropped"
+    )]
+    SchcCompressedPacketReachedHost {
+        node_id: Arc<str>,
+        packet_id: Uuid,
+        compressor_node_id: Arc<str>,
+    },
+    #[error(
+        "packet `{packet_id}` was delivered to the application at host `{node_id}` while still SCHC-compressed (compressed by `{compressor_node_id}`)"
+    )]
+    SchcCompressedPacketDelivered {
+        node_id: Arc<str>,
+        packet_id: Uuid,
+        compressor_node_id: Arc<str>,
+    },
+    #[error(
+        "network node `{node_id}` compressed packet `{packet_id}`, which was already compressed by `{first_compressor_node_id}`"
+    )]
+    SchcPacketCompressedTwice {
+        node_id: Arc<str>,
+        packet_id: Uuid,
+        first_compressor_node_id: Arc<str>,
+    },
+    #[error(
+        "network node `{node_id}` compressed packet `{packet_id}`, but the node is not configured as a SCHC compression node"
+    )]
+    SchcCompressionAtUnconfiguredNode { node_id: Arc<str>, packet_id: Uuid },
+    #[error(
+        "network node `{node_id}` decompressed packet `{packet_id}` (compressed by `{compressor_node_id}` with rule {rule_id}), but its contents differ from the contents before compression"
+    )]
+    SchcDecompressedContentsMismatch {
+        node_id: Arc<str>,
+        packet_id: Uuid,
+        compressor_node_id: Arc<str>,
+        rule_id: u32,
+    },
 }
 
 #[derive(Error, Debug)]
@@ -157,6 +194,12 @@ pub struct SimulationVerifier {
     in_flight_packets: HashMap<Uuid, InFlightPacket>,
     /// Ids of nodes considered to be hosts
     host_nodes: HashSet<Arc<str>>,
+    /// Ids of packets created by SCHC F/R endpoints, which haven't entered their node yet
+    schc_created_packets: HashSet<Uuid>,
+    /// Map from the ids of SCHC-compressed packets to their compression details
+    schc_compressed_packets: HashMap<Uuid, CompressedPacket>,
+    /// Ids of nodes configured to compress and decompress packets
+    schc_compress_nodes: HashSet<Arc<str>>,
     /// Map from nodes to metadata useful for verification
     node_metadata: HashMap<Arc<str>, NodeMetadata>,
     /// Map from links to metadata useful for verification
@@ -236,6 +279,12 @@ impl SimulationVerifier {
         })
     }
 
+    /// Sets the nodes that are allowed to compress packets (by default, no node is allowed to)
+    pub fn with_schc_compress_nodes(mut self, nodes: HashSet<Arc<str>>) -> Self {
+        self.schc_compress_nodes = nodes;
+        self
+    }
+
     pub fn verify(mut self) -> Result<VerifiedSimulation, InvalidSimulation> {
         let steps = mem::take(&mut self.steps);
 
@@ -296,8 +345,9 @@ impl SimulationVerifier {
                         try_fatal!(node.packet_received(s), self.non_fatal_errors);
                     } else {
                         // The packet was not in flight, so it must have just been created at
-                        // one of the hosts
-                        if !self.host_nodes.contains(&s.node_id) {
+                        // one of the hosts (or by a SCHC F/R endpoint)
+                        let created_by_schc = self.schc_created_packets.remove(&s.packet_id);
+                        if !self.host_nodes.contains(&s.node_id) && !created_by_schc {
                             self.non_fatal_errors
                                 .push(NonFatalError::PacketCreatedByRouterNode {
                                     node_id: s.node_id.clone(),
@@ -333,7 +383,11 @@ impl SimulationVerifier {
                 }
                 SimulationStepKind::PacketInTransit(s) => {
                     let node = try_fatal!(self.node(&s.node_id), self.non_fatal_errors);
-                    let packet = try_fatal!(node.packet_sent(s.packet_id), self.non_fatal_errors);
+                    let packet = try_fatal!(
+                        node.packet_sent(s.packet_id, s.frame_size_bytes),
+                        self.non_fatal_errors
+                    );
+                    let frame_size_bytes = s.frame_size_bytes.unwrap_or(packet.size_bytes);
 
                     // Check that the link is actually connected to the source node
                     let link_metadata = try_fatal!(
@@ -367,7 +421,7 @@ impl SimulationVerifier {
                     let link = try_fatal!(self.link(&s.link_id), self.non_fatal_errors);
                     let used_bandwidth_bps = link.packet_sent(
                         step.relative_time,
-                        packet.size_bytes,
+                        frame_size_bytes,
                         link_metadata.bandwidth_bps,
                     );
                     if link_metadata.bandwidth_bps < used_bandwidth_bps {
@@ -381,10 +435,15 @@ impl SimulationVerifier {
                             });
                     }
 
+                    stats_by_link
+                        .entry(s.link_id.clone())
+                        .or_default()
+                        .sent
+                        .track_one(frame_size_bytes);
                     self.in_flight_packets.insert(
                         s.packet_id,
                         InFlightPacket {
-                            size_bytes: packet.size_bytes,
+                            size_bytes: frame_size_bytes,
                             sent_at_relative: step.relative_time,
                             link_id: s.link_id.clone(),
                             extra_delay: packet.extra_delay,
@@ -398,10 +457,109 @@ impl SimulationVerifier {
                 }
 
                 SimulationStepKind::PacketDeliveredToApplication(s) => {
+                    // Check that compressed packets are decompressed before reaching the
+                    // application
+                    if let Some(compressed) = self.schc_compressed_packets.remove(&s.packet_id) {
+                        self.non_fatal_errors
+                            .push(NonFatalError::SchcCompressedPacketDelivered {
+                                node_id: s.node_id.clone(),
+                                packet_id: s.packet_id,
+                                compressor_node_id: compressed.compressor_node_id,
+                            });
+                    }
+
+                    let node = try_fatal!(self.node(&s.node_id), self.non_fatal_errors);
+                    try_fatal!(node.packet_delivered(s.packet_id), self.non_fatal_errors);
+                }
+
+                SimulationStepKind::SchcPacketConsumed(s) => {
                     let node = try_fatal!(self.node(&s.node_id), self.non_fatal_errors);
                     try_fatal!(node.packet_delivered(s.packet_id), self.non_fatal_errors);
                 }
 
+                SimulationStepKind::SchcPacketCreated(s) => {
+                    self.schc_created_packets.insert(s.packet_id);
+                }
+
+                SimulationStepKind::SchcCompressedPacketDropped(s) => {
+                    self.schc_compressed_packets.remove(&s.packet_id);
+                    self.non_fatal_errors
+                        .push(NonFatalError::SchcCompressedPacketReachedHost {
+                            node_id: s.node_id.clone(),
+                            packet_id: s.packet_id,
+                            compressor_node_id: s.compressor_node_id.clone(),
+                        });
+
+                    let node = try_fatal!(self.node(&s.node_id), self.non_fatal_errors);
+                    try_fatal!(node.packet_delivered(s.packet_id), self.non_fatal_errors);
+                }
+
+                SimulationStepKind::SchcPacketCompressed(s) => {
+                    // Check that compression only happens at the configured nodes
+                    if !self.schc_compress_nodes.contains(&s.node_id) {
+                        self.non_fatal_errors.push(
+                            NonFatalError::SchcCompressionAtUnconfiguredNode {
+                                node_id: s.node_id.clone(),
+                                packet_id: s.packet_id,
+                            },
+                        );
+                    }
+
+                    // Check that packets are not compressed twice
+                    let previous = self.schc_compressed_packets.insert(
+                        s.packet_id,
+                        CompressedPacket {
+                            compressor_node_id: s.node_id.clone(),
+                            rule_id: s.rule_id,
+                            uncompressed_contents_digest: s.uncompressed_contents_digest,
+                        },
+                    );
+                    if let Some(previous) = previous {
+                        self.non_fatal_errors
+                            .push(NonFatalError::SchcPacketCompressedTwice {
+                                node_id: s.node_id.clone(),
+                                packet_id: s.packet_id,
+                                first_compressor_node_id: previous.compressor_node_id,
+                            });
+                    }
+
+                    // The packet stays in the node, but its size changes
+                    let node = try_fatal!(self.node(&s.node_id), self.non_fatal_errors);
+                    try_fatal!(
+                        node.packet_resized(s.packet_id, s.size_after_bytes),
+                        self.non_fatal_errors
+                    );
+                }
+
+                SimulationStepKind::SchcPacketDecompressed(s) => {
+                    // Check that decompression restored the contents from before compression
+                    // (duplicates of compressed packets are not tracked, so they are skipped)
+                    let compressed = self.schc_compressed_packets.remove(&s.packet_id);
+                    if let Some(compressed) = compressed.filter(|c| {
+                        c.uncompressed_contents_digest != s.uncompressed_contents_digest
+                    }) {
+                        self.non_fatal_errors.push(
+                            NonFatalError::SchcDecompressedContentsMismatch {
+                                node_id: s.node_id.clone(),
+                                packet_id: s.packet_id,
+                                compressor_node_id: compressed.compressor_node_id,
+                                rule_id: compressed.rule_id,
+                            },
+                        );
+                    }
+
+                    // The packet stays in the node, but its size changes
+                    let node = try_fatal!(self.node(&s.node_id), self.non_fatal_errors);
+                    try_fatal!(
+                        node.packet_resized(s.packet_id, s.size_after_bytes),
+                        self.non_fatal_errors
+                    );
+                }
+
+                SimulationStepKind::SchcProcessingFailed(_) => {}
+
+                SimulationStepKind::SchcContextVersionChanged(_) => {}
+
                 SimulationStepKind::PacketExtraDelay(s) => {
                     let node = try_fatal!(self.node(&s.node_id), self.non_fatal_errors);
                     try_fatal!(
@@ -540,9 +698,14 @@ impl ReplayedNode {
         self.add_packet_to_buffer(s.packet_id, s.packet_size_bytes)
     }
 
-    fn packet_sent(&mut self, packet_id: Uuid) -> Result<ReplayedPacket, FatalError> {
+    fn packet_sent(
+        &mut self,
+        packet_id: Uuid,
+        frame_size_bytes: Option<usize>,
+    ) -> Result<ReplayedPacket, FatalError> {
         let packet = self.remove_packet_from_buffer(packet_id)?;
-        self.sent_packets.track_one(packet.size_bytes);
+        self.sent_packets
+            .track_one(frame_size_bytes.unwrap_or(packet.size_bytes));
         Ok(packet)
     }
 
@@ -566,6 +729,16 @@ impl ReplayedNode {
         self.ecn_packets.track_one(s.packet_size_bytes);
     }
 
+    fn packet_resized(&mut self, packet_id: Uuid, size_bytes: usize) -> Result<(), FatalError> {
+        let packet = self
+            .packets
+            .get_mut(&packet_id)
+            .ok_or(FatalError::MissingPacket { packet_id })?;
+        self.buffer_usage = self.buffer_usage + size_bytes - packet.size_bytes;
+        packet.size_bytes = size_bytes;
+        Ok(())
+    }
+
     fn packet_has_extra_delay(
         &mut self,
         packet_id: Uuid,
@@ -706,6 +879,12 @@ struct ReplayedPacket {
     extra_delay: Duration,
 }
 
+struct CompressedPacket {
+    compressor_node_id: Arc<str>,
+    rule_id: u32,
+    uncompressed_contents_digest: u64,
+}
+
 struct InFlightPacket {
     size_bytes: usize,
     sent_at_relative: Duration,
@@ -725,3 +904,293 @@ struct LinkMetadata {
 struct NodeMetadata {
     buffer_size_bytes: usize,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::network::ip::IpCidr;
+    use crate::network::spec::{NetworkInterface, NetworkLinkSpec, NetworkNodeSpec};
+    use crate::tracing::simulation_step::{PacketInTransit, SchcDirection, SchcPacketResized};
+    use std::net::Ipv4Addr;
+
+    const PACKET_ID: Uuid = Uuid::from_u128(1);
+
+    fn created(node: &mut ReplayedNode, packet_id: u128, packet_size_bytes: usize) -> Uuid {
+        let packet_id = Uuid::from_u128(packet_id);
+        node.packet_created(&GenericPacketEvent {
+            packet_id,
+            packet_number: 0,
+            packet_size_bytes,
+            node_id: Arc::from("node"),
+        })
+        .unwrap();
+        packet_id
+    }
+
+    #[test]
+    fn test_packet_grows() {
+        let mut node = ReplayedNode::default();
+        let other = created(&mut node, 1, 100);
+        let packet = created(&mut node, 2, 50);
+
+        // E.g. a SCHC packet restored to its full size by the decompressor
+        node.packet_resized(packet, 1200).unwrap();
+        assert_eq!(node.buffer_usage, 1300);
+        assert_eq!(node.update_max_buffer_usage(), 1300);
+
+        assert_eq!(node.packet_sent(packet, None).unwrap().size_bytes, 1200);
+        node.packet_sent(other, None).unwrap();
+        assert_eq!(node.buffer_usage, 0);
+        assert_eq!(node.sent_packets.bytes, 1300);
+    }
+
+    #[test]
+    fn test_packet_shrinks() {
+        let mut node = ReplayedNode::default();
+        let packet = created(&mut node, 1, 1200);
+        let other = created(&mut node, 2, 100);
+        assert_eq!(node.update_max_buffer_usage(), 1300);
+
+        // E.g. a packet compressed by SCHC
+        node.packet_resized(packet, 50).unwrap();
+        assert_eq!(node.buffer_usage, 150);
+        assert_eq!(node.update_max_buffer_usage(), 1300);
+
+        node.packet_sent(other, None).unwrap();
+        assert_eq!(node.packet_sent(packet, None).unwrap().size_bytes, 50);
+        assert_eq!(node.buffer_usage, 0);
+        assert_eq!(node.sent_packets.bytes, 150);
+    }
+
+    #[test]
+    fn test_missing_packet_resized() {
+        let mut node = ReplayedNode::default();
+        assert!(matches!(
+            node.packet_resized(Uuid::from_u128(1), 50),
+            Err(FatalError::MissingPacket { .. })
+        ));
+    }
+
+    /// A client sending packets to a server through a router
+    fn spec() -> NetworkSpec {
+        let node = |id: &str, kind, last_octet| NetworkNodeSpec {
+            id: id.to_string(),
+            buffer_size_bytes: 10_000,
+            kind,
+            interfaces: vec![NetworkInterface {
+                addresses: vec![IpCidr::from_ipv4(Ipv4Addr::new(10, 0, 0, last_octet), 24)],
+                routes: Vec::new(),
+            }],
+            packet_loss_ratio: 0.0,
+            packet_duplication_ratio: 0.0,
+            schc_role: None,
+        };
+        let link = |id: &str, source_octet, target_octet| NetworkLinkSpec {
+            id: id.into(),
+            source: Ipv4Addr::new(10, 0, 0, source_octet).into(),
+            target: Ipv4Addr::new(10, 0, 0, target_octet).into(),
+            delay: Duration::from_millis(10),
+            bandwidth_bps: 1_000_000,
+            congestion_event_ratio: 0.0,
+            extra_delay: Duration::ZERO,
+            extra_delay_ratio: 0.0,
+            schc_constrained: false,
+            framing_overhead_bytes: 0,
+        };
+
+        NetworkSpec {
+            nodes: vec![
+                node("client", NodeKind::Host, 1),
+                node("router", NodeKind::Router, 2),
+                node("server", NodeKind::Host, 3),
+            ],
+            links: vec![link("client-router", 1, 2), link("router-server", 2, 3)],
+        }
+    }
+
+    fn step(time_ms: u64, kind: SimulationStepKind) -> SimulationStep {
+        SimulationStep {
+            relative_time: Duration::from_millis(time_ms),
+            kind,
+        }
+    }
+
+    fn packet_event(node_id: &str, packet_size_bytes: usize) -> GenericPacketEvent {
+        GenericPacketEvent {
+            packet_id: PACKET_ID,
+            packet_number: 0,
+            packet_size_bytes,
+            node_id: node_id.into(),
+        }
+    }
+
+    fn in_transit(node_id: &str, link_id: &str) -> SimulationStepKind {
+        SimulationStepKind::PacketInTransit(PacketInTransit {
+            packet_id: PACKET_ID,
+            node_id: node_id.into(),
+            link_id: link_id.into(),
+            frame_size_bytes: None,
+        })
+    }
+
+    fn resized(
+        node_id: &str,
+        size_before_bytes: usize,
+        size_after_bytes: usize,
+    ) -> SchcPacketResized {
+        SchcPacketResized {
+            packet_id: PACKET_ID,
+            node_id: node_id.into(),
+            rule_id: 1,
+            direction: SchcDirection::Up,
+            size_before_bytes,
+            size_after_bytes,
+            uncompressed_contents_digest: 42,
+            context_version: 1,
+        }
+    }
+
+    /// A packet compressed by the client and decompressed by the router
+    fn compressed_packet_steps() -> Vec<SimulationStep> {
+        vec![
+            step(
+                0,
+                SimulationStepKind::PacketInNode(packet_event("client", 100)),
+            ),
+            step(
+                0,
+                SimulationStepKind::SchcPacketCompressed(resized("client", 100, 40)),
+            ),
+            step(0, in_transit("client", "client-router")),
+            step(
+                10,
+                SimulationStepKind::PacketInNode(packet_event("router", 40)),
+            ),
+            step(
+                10,
+                SimulationStepKind::SchcPacketDecompressed(resized("router", 40, 100)),
+            ),
+            step(10, in_transit("router", "router-server")),
+            step(
+                20,
+                SimulationStepKind::PacketInNode(packet_event("server", 100)),
+            ),
+            step(
+                20,
+                SimulationStepKind::PacketDeliveredToApplication(packet_event("server", 100)),
+            ),
+        ]
+    }
+
+    fn verify(steps: Vec<SimulationStep>, compress_nodes: &[&str]) -> Vec<NonFatalError> {
+        let compress_nodes = compress_nodes
+            .iter()
+            .map(|&node_id| node_id.into())
+            .collect();
+        let verified = SimulationVerifier::new(steps, &spec())
+            .unwrap()
+            .with_schc_compress_nodes(compress_nodes)
+            .verify()
+            .unwrap();
+        verified.non_fatal_errors
+    }
+
+    #[test]
+    fn test_schc_compressed_packet_verified() {
+        let errors = verify(compressed_packet_steps(), &["client", "router"]);
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn test_schc_compressed_packet_delivered() {
+        // Nobody decompresses the packet
+        let mut steps = compressed_packet_steps();
+        steps.retain(|s| !matches!(s.kind, SimulationStepKind::SchcPacketDecompressed(_)));
+        for s in &mut steps {
+            if let SimulationStepKind::PacketInNode(e)
+            | SimulationStepKind::PacketDeliveredToApplication(e) = &mut s.kind
+            {
+                e.packet_size_bytes = 40;
+            }
+        }
+
+        let errors = verify(steps, &["client", "router"]);
+        let [
+            NonFatalError::SchcCompressedPacketDelivered {
+                node_id,
+                packet_id,
+                compressor_node_id,
+            },
+        ] = &errors[..]
+        else {
+            panic!("unexpected errors: {errors:?}");
+        };
+        assert_eq!(&**node_id, "server");
+        assert_eq!(*packet_id, PACKET_ID);
+        assert_eq!(&**compressor_node_id, "client");
+    }
+
+    #[test]
+    fn test_schc_packet_compressed_twice() {
+        // The router compresses the packet again before decompressing it
+        let mut steps = compressed_packet_steps();
+        let compressed_again = step(
+            10,
+            SimulationStepKind::SchcPacketCompressed(resized("router", 40, 40)),
+        );
+        steps.insert(4, compressed_again);
+
+        let errors = verify(steps, &["client", "router"]);
+        let [
+            NonFatalError::SchcPacketCompressedTwice {
+                node_id,
+                packet_id,
+                first_compressor_node_id,
+            },
+        ] = &errors[..]
+        else {
+            panic!("unexpected errors: {errors:?}");
+        };
+        assert_eq!(&**node_id, "router");
+        assert_eq!(*packet_id, PACKET_ID);
+        assert_eq!(&**first_compressor_node_id, "client");
+    }
+
+    #[test]
+    fn test_schc_compression_at_unconfigured_node() {
+        let errors = verify(compressed_packet_steps(), &["router"]);
+        let [NonFatalError::SchcCompressionAtUnconfiguredNode { node_id, packet_id }] = &errors[..]
+        else {
+            panic!("unexpected errors: {errors:?}");
+        };
+        assert_eq!(&**node_id, "client");
+        assert_eq!(*packet_id, PACKET_ID);
+    }
+
+    #[test]
+    fn test_schc_decompressed_contents_mismatch() {
+        let mut steps = compressed_packet_steps();
+        for s in &mut steps {
+            if let SimulationStepKind::SchcPacketDecompressed(resized) = &mut s.kind {
+                resized.uncompressed_contents_digest = 43;
+            }
+        }
+
+        let errors = verify(steps, &["client", "router"]);
+        let [
+            NonFatalError::SchcDecompressedContentsMismatch {
+                node_id,
+                packet_id,
+                compressor_node_id,
+                rule_id,
+            },
+        ] = &errors[..]
+        else {
+            panic!("unexpected errors: {errors:?}");
+        };
+        assert_eq!(&**node_id, "router");
+        assert_eq!(*packet_id, PACKET_ID);
+        assert_eq!(&**compressor_node_id, "client");
+        assert_eq!(*rule_id, 1);
+    }
+}
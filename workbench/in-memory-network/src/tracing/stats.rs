@@ -1,10 +1,14 @@
-#[derive(Default)]
+use serde::Serialize;
+
+#[derive(Default, Serialize)]
 pub struct LinkStats {
+    /// Packets sent through the link (counting the bytes they occupy on the link)
+    pub sent: PacketStats,
     pub dropped_in_transit: PacketStats,
     pub max_used_bandwidth_bps: usize,
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize)]
 pub struct NodeStats {
     pub sent: PacketStats,
     pub received: PacketStats,
@@ -16,7 +20,7 @@ pub struct NodeStats {
     pub max_buffer_usage: usize,
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize)]
 pub struct PacketStats {
     pub packets: u64,
     pub bytes: usize,
@@ -60,16 +60,20 @@ impl SimulationStepTracer {
         self.record(SimulationStepKind::PacketInNode(GenericPacketEvent {
             packet_id: packet.id,
             packet_number: packet.number,
-            packet_size_bytes: packet.transmit.packet_size(),
+            packet_size_bytes: packet.packet_size(),
             node_id: node.id().clone(),
         }));
     }
 
     pub fn track_packet_in_transit(&self, node: &Node, link: &NetworkLink, packet: &InTransitData) {
+        // Only recorded for links with framing overhead, so other replay logs are unchanged
+        let frame_size_bytes =
+            Some(link.frame_size(packet)).filter(|&size| size != packet.packet_size());
         self.record(SimulationStepKind::PacketInTransit(PacketInTransit {
             packet_id: packet.id,
             node_id: node.id().clone(),
             link_id: link.id.clone(),
+            frame_size_bytes,
         }));
     }
 
@@ -138,7 +142,7 @@ impl SimulationStepTracer {
             self.record(SimulationStepKind::PacketDuplicated(GenericPacketEvent {
                 packet_id: data.id,
                 packet_number: data.number,
-                packet_size_bytes: data.transmit.packet_size(),
+                packet_size_bytes: data.packet_size(),
                 node_id: current_node.id().clone(),
             }));
 
@@ -155,7 +159,7 @@ impl SimulationStepTracer {
                 GenericPacketEvent {
                     packet_id: data.id,
                     packet_number: data.number,
-                    packet_size_bytes: data.transmit.packet_size(),
+                    packet_size_bytes: data.packet_size(),
                     node_id: current_node.id().clone(),
                 },
             ));
@@ -169,14 +173,165 @@ impl SimulationStepTracer {
         }
     }
 
+    pub fn track_schc_packet_consumed(&self, node: &Node, data: &InTransitData) {
+        self.record(SimulationStepKind::SchcPacketConsumed(GenericPacketEvent {
+            packet_id: data.id,
+            packet_number: data.number,
+            packet_size_bytes: data.packet_size(),
+            node_id: node.id().clone(),
+        }));
+    }
+
+    pub fn track_schc_packet_created(&self, node: &Node, data: &InTransitData) {
+        self.record(SimulationStepKind::SchcPacketCreated(GenericPacketEvent {
+            packet_id: data.id,
+            packet_number: data.number,
+            packet_size_bytes: data.packet_size(),
+            node_id: node.id().clone(),
+        }));
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    pub(crate) fn track_schc_packet_compressed(
+        &self,
+        node: &Node,
+        data: &InTransitData,
+        size_before_bytes: usize,
+        uncompressed_contents: &[u8],
+        is_outgoing: bool,
+    ) {
+        use crate::tracing::simulation_step::{SchcDirection, SchcPacketResized};
+
+        let Some(state) = &data.schc_compressed else {
+            return;
+        };
+
+        self.record(SimulationStepKind::SchcPacketCompressed(
+            SchcPacketResized {
+                packet_id: data.id,
+                node_id: node.id().clone(),
+                rule_id: state.rule_id,
+                direction: SchcDirection::from_is_outgoing(is_outgoing),
+                size_before_bytes,
+                size_after_bytes: data.packet_size(),
+                uncompressed_contents_digest: contents_digest(uncompressed_contents),
+                context_version: state.context_version,
+            },
+        ));
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    pub(crate) fn track_schc_packet_decompressed(
+        &self,
+        node: &Node,
+        data: &InTransitData,
+        rule_id: u32,
+        context_version: u32,
+        size_before_bytes: usize,
+        is_outgoing: bool,
+    ) {
+        use crate::tracing::simulation_step::{SchcDirection, SchcPacketResized};
+
+        self.record(SimulationStepKind::SchcPacketDecompressed(
+            SchcPacketResized {
+                packet_id: data.id,
+                node_id: node.id().clone(),
+                rule_id,
+                direction: SchcDirection::from_is_outgoing(is_outgoing),
+                size_before_bytes,
+                size_after_bytes: data.packet_size(),
+                uncompressed_contents_digest: contents_digest(&data.transmit.contents),
+                context_version,
+            },
+        ));
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    pub(crate) fn track_schc_context_version_changed(
+        &self,
+        node: &Node,
+        context_id: Option<&str>,
+        version: u32,
+    ) {
+        use crate::tracing::simulation_step::SchcContextVersionChanged;
+
+        self.record(SimulationStepKind::SchcContextVersionChanged(
+            SchcContextVersionChanged {
+                node_id: node.id().clone(),
+                context_id: context_id.map(str::to_string),
+                version,
+            },
+        ));
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    pub(crate) fn track_schc_processing_failed(
+        &self,
+        node: &Node,
+        data: &InTransitData,
+        operation: crate::tracing::simulation_step::SchcOperation,
+        is_outgoing: bool,
+    ) {
+        use crate::tracing::simulation_step::{SchcDirection, SchcProcessingFailed};
+
+        self.record(SimulationStepKind::SchcProcessingFailed(
+            SchcProcessingFailed {
+                packet_id: data.id,
+                node_id: node.id().clone(),
+                operation,
+                rule_id: data.schc_compressed.as_ref().map(|state| state.rule_id),
+                direction: SchcDirection::from_is_outgoing(is_outgoing),
+                packet_size_bytes: data.packet_size(),
+            },
+        ));
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    pub(crate) fn track_schc_compressed_packet_dropped(
+        &self,
+        host: &Node,
+        data: &InTransitData,
+        state: &crate::schc_compressor::SchcCompressionState,
+    ) {
+        use crate::tracing::simulation_step::SchcCompressedPacketDropped;
+
+        self.record(SimulationStepKind::SchcCompressedPacketDropped(
+            SchcCompressedPacketDropped {
+                packet_id: data.id,
+                node_id: host.id().clone(),
+                compressor_node_id: state.compressor_id.clone(),
+                rule_id: state.rule_id,
+            },
+        ));
+
+        println!(
+            "{:.2}s ERROR packet #{} reached host `{}` still SCHC-compressed (compressed by `{}` with rule {}, {} bytes before compression) and was dropped",
+            self.simulation_start.elapsed().as_secs_f64(),
+            data.number,
+            host.id(),
+            state.compressor_id,
+            state.rule_id,
+            state.original_size,
+        );
+    }
+
     pub fn track_read_by_host(&self, host_id: Arc<str>, data: &InTransitData) {
         self.record(SimulationStepKind::PacketDeliveredToApplication(
             GenericPacketEvent {
                 packet_id: data.id,
                 packet_number: data.number,
-                packet_size_bytes: data.transmit.packet_size(),
+                packet_size_bytes: data.packet_size(),
                 node_id: host_id.clone(),
             },
         ));
     }
 }
+
+/// A stable digest of the packet's contents (64-bit FNV-1a), so the replay log can tell whether
+/// decompression restored the original contents without storing them
+#[cfg(feature = "schc-compressor")]
+fn contents_digest(contents: &[u8]) -> u64 {
+    contents.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
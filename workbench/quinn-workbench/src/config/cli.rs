@@ -1,4 +1,5 @@
 use clap::{Parser, Subcommand};
+use serde::Serialize;
 use std::net::IpAddr;
 use std::path::PathBuf;
 
@@ -11,7 +12,7 @@ pub struct CliOpt {
 #[derive(Subcommand, Debug, Clone)]
 pub enum Command {
     /// Run the QUIC simulation
-    Quic(QuicOpt),
+    Quic(Box<QuicOpt>),
     /// Run a ping simulation at the UDP level
     Ping(PingOpt),
     /// Run a throughput simulation at the UDP level
@@ -20,7 +21,7 @@ pub enum Command {
     Rt,
 }
 
-#[derive(Parser, Debug, Clone)]
+#[derive(Parser, Debug, Clone, Serialize)]
 pub struct NetworkOpt {
     /// The IP address of the node used as a client
     #[arg(long)]
@@ -52,9 +53,28 @@ pub struct NetworkOpt {
     /// Path to the JSON file containing the network events
     #[arg(long)]
     pub network_events: PathBuf,
+
+    /// Path where a machine-readable JSON report of the run should be stored (parameters, seeds,
+    /// node and link stats, non-fatal errors and SCHC statistics)
+    #[arg(long)]
+    pub report_json: Option<PathBuf>,
+
+    /// Directory where the pcapng captures are written (created if missing)
+    #[arg(long, default_value = ".")]
+    pub pcap_dir: PathBuf,
+
+    /// Nodes to capture inside the network, besides the sockets of the hosts (comma-separated
+    /// ids, or `all`), each in a `<NODE>.node.pcap` file including the packets the node drops
+    #[arg(long, value_delimiter = ',')]
+    pub pcap_nodes: Vec<String>,
+
+    /// Links to capture (comma-separated ids, or `all`), each in a `<LINK>.link.pcap` file
+    /// including the packets lost while the link was down
+    #[arg(long, value_delimiter = ',')]
+    pub pcap_links: Vec<String>,
 }
 
-#[derive(Parser, Debug, Clone)]
+#[derive(Parser, Debug, Clone, Serialize)]
 pub struct QuicOpt {
     /// The number of requests that should be made
     #[arg(long, default_value_t = 10)]
@@ -80,11 +100,29 @@ pub struct QuicOpt {
     #[arg(long)]
     pub schc_rules: Option<PathBuf>,
 
-    /// Node IDs where SCHC observer should be active (comma-separated, e.g., "MoonOrbiter1")
-    /// If not specified, SCHC is applied at all router nodes
+    /// SCHC roles of the nodes (comma-separated `NODE=ROLE` assignments, where ROLE is `observer`,
+    /// `compressor` or both joined with `+`, e.g., "SchcNode1=compressor,MoonOrbiter1=observer").
+    /// A node with both roles observes packets before compressing or decompressing them
+    #[arg(long, value_delimiter = ',')]
+    pub schc_node_roles: Option<Vec<String>>,
+
+    /// Node IDs where SCHC observer should be active (comma-separated, e.g., "MoonOrbiter1"),
+    /// shorthand for the observer role of --schc-node-roles
+    /// If no node has the observer role, SCHC is applied at all router nodes
     #[arg(long, value_delimiter = ',')]
     pub schc_nodes: Option<Vec<String>>,
 
+    /// Remove QUIC header protection before SCHC rule matching in observer mode, using the
+    /// secrets of the TLS key log (`keylog.key`)
+    #[arg(long, default_value_t = false)]
+    pub schc_unprotect_headers: bool,
+
+    /// Record the header field values seen by the SCHC observer, and write a candidate rule set
+    /// synthesized from them to the provided path at the end of the run (--schc-rules is optional
+    /// in that case)
+    #[arg(long)]
+    pub schc_synthesize_rules: Option<PathBuf>,
+
     /// Enable verbose SCHC debug output showing per-packet matching and compression details
     #[arg(long, default_value_t = false)]
     pub schc_debug: bool,
@@ -93,16 +131,86 @@ pub struct QuicOpt {
     #[arg(long, default_value_t = false)]
     pub schc_compress: bool,
 
-    /// Node IDs where SCHC compression is active (comma-separated, e.g., "SchcNode1,SchcNode2")
+    /// Node IDs where SCHC compression is active (comma-separated, e.g., "SchcNode1,SchcNode2"),
+    /// shorthand for the compressor role of --schc-node-roles
     /// These nodes will compress packets going UP and decompress packets going DOWN
     #[arg(long, value_delimiter = ',')]
     pub schc_compress_nodes: Option<Vec<String>>,
 
+    /// Path to a JSON file declaring SCHC contexts, each bound to a pair of nodes or to a set of
+    /// links and with its own rules file. Compressed segments that no context applies to use the
+    /// rules from --schc-rules (if provided)
+    #[arg(long)]
+    pub schc_contexts: Option<PathBuf>,
+
+    /// Path to a JSON file scheduling SCHC rule updates. Each update is sent as packets over the
+    /// network, and the nodes only switch to the new rules once they receive all of them
+    #[arg(long)]
+    pub schc_rule_updates: Option<PathBuf>,
+
+    /// Path to a JSON file injecting SCHC desynchronization faults: during each fault, a node
+    /// decompresses packets with other rules, or receives them with mutated rule IDs or residues
+    #[arg(long)]
+    pub schc_desync: Option<PathBuf>,
+
+    /// Capture the SCHC-constrained links, as if they were passed to `--pcap-links` (SCHC
+    /// packets are captured with their rule ID and direction)
+    #[arg(long)]
+    pub schc_pcap: bool,
+
+    /// Keep the original datagram of each SCHC-compressed packet (without sending it) and compare
+    /// it byte for byte against the reconstructed datagram upon decompression, reporting any
+    /// mismatching fields
+    #[arg(long, default_value_t = false)]
+    pub schc_verify_roundtrip: bool,
+
+    /// Run the scenario twice, without SCHC compression and with the compressor as configured, and
+    /// compare both runs side by side (completion time, link usage, buffer peaks and lost packets).
+    /// The captures of each run are written to the `run-a` and `run-b` subdirectories of
+    /// --pcap-dir
+    #[arg(long, default_value_t = false)]
+    pub schc_compare: bool,
+
+    /// Like --schc-compare, but comparing the rules from --schc-rules against the rules in the
+    /// provided file (both runs use the compressor, and --schc-contexts is not supported)
+    #[arg(long)]
+    pub schc_compare_rules: Option<PathBuf>,
+
+    /// Remove QUIC header protection before SCHC compression, using the secrets of the TLS key log
+    /// (`keylog.key`), and reapply it after decompression
+    #[arg(long, default_value_t = false)]
+    pub schc_compress_unprotect_headers: bool,
+
+    /// Enable SCHC fragmentation/reassembly at the compression nodes, using the provided mode
+    /// (no-ack, ack-always or ack-on-error)
+    #[arg(long)]
+    pub schc_fragment_mode: Option<String>,
+
+    /// Maximum size of a SCHC fragment, in bytes. SCHC packets above this size are fragmented
+    #[arg(long, default_value_t = 128)]
+    pub schc_fragment_mtu: usize,
+
+    /// The rule ID used for SCHC fragments and ACKs (must not be used by the compression rules)
+    #[arg(long, default_value_t = 255)]
+    pub schc_fragment_rule_id: u8,
+
+    /// Time the fragmenting node waits for an ACK before sending an ACK REQ, in milliseconds
+    #[arg(long, default_value_t = 10_000)]
+    pub schc_retransmission_timeout_ms: u64,
+
+    /// Number of consecutive unanswered ACK REQs before the fragmenting node aborts
+    #[arg(long, default_value_t = 4)]
+    pub schc_max_ack_requests: u32,
+
+    /// Time the reassembling node waits for the next fragment before aborting, in milliseconds
+    #[arg(long, default_value_t = 60_000)]
+    pub schc_inactivity_timeout_ms: u64,
+
     #[command(flatten)]
     pub network: NetworkOpt,
 }
 
-#[derive(Parser, Debug, Clone)]
+#[derive(Parser, Debug, Clone, Serialize)]
 pub struct PingOpt {
     /// The duration of the run, after which we will stop sending pings and the program will
     /// terminate
@@ -122,7 +230,7 @@ pub struct PingOpt {
     pub network: NetworkOpt,
 }
 
-#[derive(Parser, Debug, Clone)]
+#[derive(Parser, Debug, Clone, Serialize)]
 pub struct ThroughputOpt {
     /// The duration of the run
     #[arg(long)]
@@ -3,6 +3,8 @@ use crate::config::network::{NetworkEventJson, NetworkSpecJson};
 pub mod cli;
 pub mod network;
 pub mod quinn;
+#[cfg(feature = "schc-compressor")]
+pub mod schc;
 
 pub struct NetworkConfig {
     pub network_graph: NetworkSpecJson,
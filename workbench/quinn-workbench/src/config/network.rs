@@ -1,6 +1,6 @@
 use crate::config::quinn::QuinnJsonConfig;
 use in_memory_network::network::event::{NetworkEvent, NetworkEventPayload, UpdateLinkStatus};
-use in_memory_network::network::ip::Ipv4Cidr;
+use in_memory_network::network::ip::IpCidr;
 use in_memory_network::network::route::IpRange;
 use serde::Deserialize;
 use serde_with::{DisplayFromStr, serde_as};
@@ -43,6 +43,17 @@ struct NetworkNodeJson {
     /// 0 and 1)
     #[serde(default)]
     packet_loss_ratio: f64,
+    /// The SCHC role of the node (`device` or `application`), used to derive the direction of the
+    /// packets it sends and receives
+    #[serde(default)]
+    schc_role: Option<SchcRoleJson>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+enum SchcRoleJson {
+    Device,
+    Application,
 }
 
 #[derive(Deserialize, Clone)]
@@ -63,7 +74,7 @@ struct NetworkInterfaceJson {
 #[derive(Deserialize, Clone)]
 struct NetworkAddressJson {
     #[serde_as(as = "DisplayFromStr")]
-    address: Ipv4Cidr,
+    address: IpCidr,
 }
 
 #[serde_as]
@@ -98,6 +109,13 @@ struct NetworkLinkJson {
     /// The ratio of packets that will be marked with a CE ECN codepoint (the value must be between 0 and 1)
     #[serde(default)]
     congestion_event_ratio: f64,
+    /// Whether the link is SCHC-constrained (packets are compressed before crossing it and
+    /// decompressed after crossing it)
+    #[serde(default)]
+    schc_constrained: bool,
+    /// The link-layer framing overhead added to every packet sent over the link, in bytes
+    #[serde(default)]
+    framing_overhead_bytes: usize,
 }
 
 #[derive(Deserialize, Clone)]
@@ -141,6 +159,12 @@ impl From<NetworkSpecJson> for in_memory_network::network::spec::NetworkSpec {
                     .collect(),
                 packet_loss_ratio: n.packet_loss_ratio,
                 packet_duplication_ratio: n.packet_duplication_ratio,
+                schc_role: n.schc_role.map(|r| match r {
+                    SchcRoleJson::Device => in_memory_network::network::spec::SchcRole::Device,
+                    SchcRoleJson::Application => {
+                        in_memory_network::network::spec::SchcRole::Application
+                    }
+                }),
             })
             .collect();
 
@@ -161,6 +185,8 @@ impl From<NetworkLinkJson> for in_memory_network::network::spec::NetworkLinkSpec
             congestion_event_ratio: l.congestion_event_ratio,
             extra_delay: Duration::from_millis(l.extra_delay_ms),
             extra_delay_ratio: l.extra_delay_ratio,
+            schc_constrained: l.schc_constrained,
+            framing_overhead_bytes: l.framing_overhead_bytes,
         }
     }
 }
@@ -55,4 +55,10 @@ pub struct QuinnJsonConfig {
     /// default is used.
     /// For 'NoCc', this value is used as the fixed, constant window. If missing it defaults to u64::MAX.
     pub initial_congestion_window_packets: Option<u64>,
+    /// The length of the connection IDs issued by the endpoint, in bytes (between 0 and 20). If
+    /// missing, Quinn's default 8-byte connection IDs are used.
+    ///
+    /// A zero-length connection ID removes the destination connection ID from short header
+    /// packets, which is the largest field SCHC can't compress.
+    pub connection_id_length: Option<usize>,
 }
@@ -0,0 +1,95 @@
+use serde::Deserialize;
+use std::path::PathBuf;
+
+#[derive(Deserialize)]
+pub struct SchcContextsJson {
+    pub contexts: Vec<SchcContextJson>,
+}
+
+/// A SCHC context, bound either to a pair of nodes or to a set of links
+#[derive(Deserialize)]
+pub struct SchcContextJson {
+    pub id: String,
+    /// Path to the SCHC rules JSON file of the context (relative paths are resolved against the
+    /// directory of the contexts file)
+    pub rules: PathBuf,
+    /// The compressor and decompressor nodes sharing the context (in any order)
+    #[serde(default)]
+    pub nodes: Option<[String; 2]>,
+    /// The ids of the links the context is bound to
+    #[serde(default)]
+    pub links: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SchcRuleUpdatesJson {
+    /// Size of the rules carried by each rule update packet, in bytes
+    #[serde(default = "default_rule_update_chunk_size")]
+    pub chunk_size_bytes: usize,
+    pub updates: Vec<SchcRuleUpdateJson>,
+}
+
+fn default_rule_update_chunk_size() -> usize {
+    120
+}
+
+/// A rule update, sent over the network by one node to the nodes that install it
+#[derive(Deserialize)]
+pub struct SchcRuleUpdateJson {
+    /// Time at which the update is sent, relative to the start of the simulation
+    pub time_ms: u64,
+    /// The node sending the update
+    pub source: String,
+    /// The nodes the update is sent to (by default, the other compressor nodes)
+    #[serde(default)]
+    pub targets: Option<Vec<String>>,
+    /// Path to the new SCHC rules JSON file (relative paths are resolved against the directory of
+    /// the updates file)
+    pub rules: PathBuf,
+    /// The SCHC context whose rules are replaced (by default, the rules from --schc-rules)
+    #[serde(default)]
+    pub context: Option<String>,
+    /// The new version of the context (by default, the last version of the context plus one)
+    #[serde(default)]
+    pub version: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct SchcDesyncJson {
+    pub faults: Vec<SchcDesyncFaultJson>,
+}
+
+/// A fault desynchronizing the decompressor of a node from the compressors, during a time window
+#[derive(Deserialize)]
+pub struct SchcDesyncFaultJson {
+    /// The node whose decompression is faulty
+    pub node: String,
+    /// Start of the fault, relative to the start of the simulation
+    #[serde(default)]
+    pub from_ms: u64,
+    /// End of the fault (by default, the end of the simulation)
+    #[serde(default)]
+    pub until_ms: Option<u64>,
+    /// Ratio of the packets decompressed during the fault that it affects
+    #[serde(default = "default_desync_ratio")]
+    pub ratio: f64,
+    #[serde(flatten)]
+    pub kind: SchcDesyncFaultKindJson,
+}
+
+fn default_desync_ratio() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum SchcDesyncFaultKindJson {
+    /// The node decompresses packets with other rules (relative paths are resolved against the
+    /// directory of the faults file)
+    Rules { rules: PathBuf },
+    /// A bit of the rule ID of each packet is flipped
+    RuleId,
+    /// A bit of the residue of each packet is flipped
+    Residue,
+}
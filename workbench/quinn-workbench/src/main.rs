@@ -1,6 +1,7 @@
 mod config;
 mod quic;
 mod quinn_extensions;
+mod report;
 mod udp;
 mod util;
 
@@ -13,12 +14,39 @@ use cfg_if::cfg_if;
 use clap::Parser;
 use config::cli::CliOpt;
 use in_memory_network::async_rt;
+use in_memory_network::network::spec::NetworkSpec;
+use in_memory_network::pcap_exporter::{FileBasedPcapExporterFactory, PcapSelection};
 use serde::de::DeserializeOwned;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io;
 use std::path::Path;
 use tracing_subscriber::EnvFilter;
 use tracing_subscriber::fmt::Subscriber;
 
+/// The TLS key log file, where the client logs its secrets
+pub const KEYLOG_PATH: &str = "keylog.key";
+
+/// Returns the length of the TLS key log, to tell the secrets logged afterwards apart from those of
+/// previous runs (the key log is appended to)
+fn tls_key_log_len() -> u64 {
+    fs::metadata(KEYLOG_PATH).map_or(0, |m| m.len())
+}
+
+/// Reads the secrets logged to the TLS key log since it had the provided length
+fn read_tls_key_log_since(len: u64) -> anyhow::Result<Vec<u8>> {
+    let key_log = match fs::read(KEYLOG_PATH) {
+        Ok(key_log) => key_log,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("unable to read TLS key log at `{KEYLOG_PATH}`"));
+        }
+    };
+
+    // The whole file is new if it was recreated during the run
+    Ok(key_log.get(len as usize..).unwrap_or(&key_log).to_vec())
+}
+
 fn main() -> anyhow::Result<()> {
     Subscriber::builder()
         .with_env_filter(EnvFilter::from_default_env())
@@ -27,11 +55,16 @@ fn main() -> anyhow::Result<()> {
         .init();
 
     // Safety: we are fully single-threaded
-    unsafe { std::env::set_var("SSLKEYLOGFILE", "keylog.key") };
+    unsafe { std::env::set_var("SSLKEYLOGFILE", KEYLOG_PATH) };
     let opt = CliOpt::parse();
 
     let rt = async_rt::new_rt();
     match &opt.command {
+        Command::Quic(quic_opt)
+            if quic_opt.schc_compare || quic_opt.schc_compare_rules.is_some() =>
+        {
+            quic::compare::run_comparison(quic_opt)
+        }
         Command::Quic(quic_opt) => rt.block_on(quic::run_and_report_stats(quic_opt)),
         Command::Ping(ping_opt) => {
             let network_config = load_network_config(&ping_opt.network)?;
@@ -67,6 +100,30 @@ fn load_network_config(cli: &NetworkOpt) -> anyhow::Result<NetworkConfig> {
     })
 }
 
+/// Returns the factory writing the captures selected on the command line
+fn pcap_exporter_factory(
+    cli: &NetworkOpt,
+    network_spec: &NetworkSpec,
+) -> anyhow::Result<FileBasedPcapExporterFactory> {
+    let nodes = PcapSelection::parse(&cli.pcap_nodes);
+    nodes.validate("node", network_spec.nodes.iter().map(|n| n.id.as_ref()))?;
+    let links = PcapSelection::parse(&cli.pcap_links);
+    links.validate("link", network_spec.links.iter().map(|l| l.id.as_ref()))?;
+
+    fs::create_dir_all(&cli.pcap_dir).with_context(|| {
+        format!(
+            "unable to create capture directory `{}`",
+            cli.pcap_dir.display()
+        )
+    })?;
+
+    Ok(FileBasedPcapExporterFactory {
+        dir: cli.pcap_dir.clone(),
+        nodes,
+        links,
+    })
+}
+
 fn load_json<T: DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
     let file =
         File::open(path).with_context(|| format!("unable to open file at `{}`", path.display()))?;
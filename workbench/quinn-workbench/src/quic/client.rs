@@ -6,7 +6,7 @@ use in_memory_network::async_rt;
 use in_memory_network::async_rt::time::Instant;
 use in_memory_network::quinn_interop::InMemoryUdpSocket;
 use parking_lot::Mutex;
-use quinn::Endpoint;
+use quinn::{ConnectionStats, Endpoint};
 use quinn_proto::crypto::rustls::QuicClientConfig;
 use quinn_proto::{ClientConfig, VarInt};
 use rustls::RootCertStore;
@@ -22,7 +22,7 @@ pub async fn run_connection(
     requests_left: Arc<Mutex<u32>>,
     concurrent_streams: u32,
     start: Instant,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<ConnectionStats> {
     println!(
         "{:.2}s CONNECT (conn = {connection_name})",
         start.elapsed().as_secs_f64()
@@ -94,8 +94,9 @@ pub async fn run_connection(
         start.elapsed().as_secs_f64()
     );
 
+    let stats = connection.stats();
     connection.close(VarInt::from_u32(0), &[]);
-    Ok(())
+    Ok(stats)
 }
 
 pub fn client_endpoint(
@@ -108,7 +109,7 @@ pub fn client_endpoint(
     quinn_rng.fill(&mut seed);
 
     let mut endpoint = Endpoint::new_with_abstract_socket(
-        crate::quic::endpoint_config(seed),
+        crate::quic::endpoint_config(seed, quinn_config)?,
         None,
         Arc::new(client_socket),
         async_rt::active_rt(),
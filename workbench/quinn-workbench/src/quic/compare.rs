@@ -0,0 +1,384 @@
+//! Comparison of two runs of the same deterministic scenario, to measure the gain of SCHC
+//! compression (or of a rule set over another one)
+
+use crate::config::cli::QuicOpt;
+use crate::quic::simulation::QuicSimulation;
+use crate::quic::verify_simulation;
+use crate::util::{print_link_stats, print_network_captures};
+use crate::{load_network_config, read_tls_key_log_since, tls_key_log_len};
+use anyhow::{Context, bail};
+use in_memory_network::async_rt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The results of a run that are compared
+struct RunSummary {
+    label: String,
+    total_time_sec: f64,
+    /// Packets declared lost by the client and server connections (Quinn doesn't count
+    /// retransmissions, but it retransmits the frames of lost packets, so losses stand in for them)
+    lost_packets: u64,
+    lost_bytes: u64,
+    non_fatal_errors: usize,
+    links: BTreeMap<Arc<str>, LinkSummary>,
+    /// The max buffer usage of each node, in bytes
+    buffer_peaks: BTreeMap<Arc<str>, usize>,
+}
+
+impl RunSummary {
+    /// The summary of the link (all zeros if the link wasn't used during the run)
+    fn link(&self, link_id: &Arc<str>) -> LinkSummary {
+        self.links.get(link_id).copied().unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct LinkSummary {
+    sent_bytes: usize,
+    max_used_bandwidth_bps: usize,
+    bandwidth_bps: usize,
+}
+
+/// Runs the scenario without SCHC compression and with the compressor (or with the rules from
+/// `--schc-rules` and those from `--schc-compare-rules`), and prints the results side by side
+pub fn run_comparison(quic_options: &QuicOpt) -> anyhow::Result<()> {
+    if quic_options.network.non_deterministic {
+        bail!("comparing runs requires a deterministic simulation (without --non-deterministic)");
+    }
+    if !quic_options.schc_compress {
+        bail!("--schc-compare and --schc-compare-rules require --schc-compress");
+    }
+    if quic_options.schc_compare_rules.is_some() && quic_options.schc_contexts.is_some() {
+        bail!(
+            "--schc-compare-rules cannot be combined with --schc-contexts (the contexts bring their own rules)"
+        );
+    }
+
+    let mut baseline = quic_options.clone();
+    let mut variant = quic_options.clone();
+    let (baseline_label, variant_label) = match &quic_options.schc_compare_rules {
+        Some(rules_path) => {
+            variant.schc_rules = Some(rules_path.clone());
+            (
+                rules_label(quic_options.schc_rules.as_deref()),
+                rules_label(Some(rules_path)),
+            )
+        }
+        None => {
+            // Without compression, there are no SCHC frames to capture
+            baseline.schc_compress = false;
+            baseline.schc_pcap = false;
+            (
+                "without SCHC compression".to_string(),
+                "with SCHC compression".to_string(),
+            )
+        }
+    };
+
+    // Each run gets its own captures
+    baseline.network.pcap_dir = quic_options.network.pcap_dir.join("run-a");
+    variant.network.pcap_dir = quic_options.network.pcap_dir.join("run-b");
+
+    let baseline = run("A", baseline_label, &baseline)?;
+    let variant = run("B", variant_label, &variant)?;
+    print_comparison(&baseline, &variant);
+
+    Ok(())
+}
+
+fn rules_label(rules_path: Option<&Path>) -> String {
+    match rules_path {
+        Some(rules_path) => format!("rules from {}", rules_path.display()),
+        None => "no default rules".to_string(),
+    }
+}
+
+/// Runs the scenario in its own runtime, so both runs start from the same simulated time
+fn run(name: &str, label: String, quic_options: &QuicOpt) -> anyhow::Result<RunSummary> {
+    println!("=== Run {name} ({label}) ===");
+    let network_config = load_network_config(&quic_options.network)?;
+    let mut simulation = QuicSimulation::new();
+    let key_log_len = tls_key_log_len();
+    async_rt::new_rt()
+        .block_on(simulation.run(quic_options, network_config, key_log_len))
+        .with_context(|| format!("run {name} ({label}) failed"))?;
+
+    let (tracer, network) = simulation
+        .tracer_and_network
+        .as_ref()
+        .context("the simulation did not start")?;
+    network.embed_tls_key_log(&read_tls_key_log_since(key_log_len)?)?;
+    print_network_captures(network)?;
+    let verified_simulation = verify_simulation(tracer, network)?;
+    print_link_stats(&verified_simulation, network, true);
+
+    let links = verified_simulation
+        .stats
+        .stats_by_link
+        .iter()
+        .map(|(link_id, stats)| {
+            let link = LinkSummary {
+                sent_bytes: stats.sent.bytes,
+                max_used_bandwidth_bps: stats.max_used_bandwidth_bps,
+                bandwidth_bps: network.get_link_bandwidth_bps(link_id),
+            };
+            (link_id.clone(), link)
+        })
+        .collect();
+    let buffer_peaks = verified_simulation
+        .stats
+        .stats_by_node
+        .iter()
+        .map(|(node_id, stats)| (node_id.clone(), stats.max_buffer_usage))
+        .collect();
+
+    Ok(RunSummary {
+        label,
+        total_time_sec: simulation.total_time.unwrap_or_default().as_secs_f64(),
+        lost_packets: simulation
+            .connection_stats
+            .iter()
+            .map(|stats| stats.path.lost_packets)
+            .sum(),
+        lost_bytes: simulation
+            .connection_stats
+            .iter()
+            .map(|stats| stats.path.lost_bytes)
+            .sum(),
+        non_fatal_errors: verified_simulation.non_fatal_errors.len(),
+        links,
+        buffer_peaks,
+    })
+}
+
+fn print_comparison(a: &RunSummary, b: &RunSummary) {
+    println!("--- Comparison ---");
+    println!("* A: {}", a.label);
+    println!("* B: {}", b.label);
+
+    println!("* Run (lost packets and bytes approximate the retransmissions):");
+    print_table(
+        ["", "A", "B", "B - A"],
+        vec![
+            [
+                "Completion time (s)".to_string(),
+                format!("{:.2}", a.total_time_sec),
+                format!("{:.2}", b.total_time_sec),
+                format!(
+                    "{:+.2} ({})",
+                    b.total_time_sec - a.total_time_sec,
+                    change_pct(a.total_time_sec, b.total_time_sec)
+                ),
+            ],
+            count_row("Lost packets", a.lost_packets, b.lost_packets),
+            count_row("Lost bytes", a.lost_bytes, b.lost_bytes),
+            count_row(
+                "Non-fatal errors",
+                a.non_fatal_errors as u64,
+                b.non_fatal_errors as u64,
+            ),
+        ],
+    );
+
+    println!("* Bytes sent per link:");
+    print_table(["", "A", "B", "B - A"], sent_bytes_rows(a, b));
+
+    println!("* Max used bandwidth per link (bps):");
+    let rows = link_ids(a, b)
+        .into_iter()
+        .map(|link_id| {
+            let (a, b) = (a.link(link_id), b.link(link_id));
+            let usage = |link: LinkSummary| {
+                let usage_pct =
+                    link.max_used_bandwidth_bps as f64 / link.bandwidth_bps.max(1) as f64 * 100.0;
+                format!("{} ({usage_pct:.2}%)", link.max_used_bandwidth_bps)
+            };
+            [
+                link_id.to_string(),
+                usage(a),
+                usage(b),
+                difference(
+                    a.max_used_bandwidth_bps as u64,
+                    b.max_used_bandwidth_bps as u64,
+                ),
+            ]
+        })
+        .collect();
+    print_table(["", "A", "B", "B - A"], rows);
+
+    println!("* Max buffer usage per node (bytes):");
+    let node_ids: BTreeSet<_> = a.buffer_peaks.keys().chain(b.buffer_peaks.keys()).collect();
+    let rows = node_ids
+        .into_iter()
+        .map(|node_id| {
+            let peak = |run: &RunSummary| run.buffer_peaks.get(node_id).copied().unwrap_or(0);
+            count_row(node_id, peak(a) as u64, peak(b) as u64)
+        })
+        .collect();
+    print_table(["", "A", "B", "B - A"], rows);
+
+    println!("* Bandwidth saved per link (B over A):");
+    print_table(["", "Saved", "Saved (%)", ""], saved_bandwidth_rows(a, b));
+}
+
+/// The links used by any of the runs (a link used by one run only is compared against an unused
+/// link)
+fn link_ids<'a>(a: &'a RunSummary, b: &'a RunSummary) -> BTreeSet<&'a Arc<str>> {
+    a.links.keys().chain(b.links.keys()).collect()
+}
+
+fn sent_bytes_rows(a: &RunSummary, b: &RunSummary) -> Vec<[String; 4]> {
+    link_ids(a, b)
+        .into_iter()
+        .map(|link_id| {
+            count_row(
+                link_id,
+                a.link(link_id).sent_bytes as u64,
+                b.link(link_id).sent_bytes as u64,
+            )
+        })
+        .collect()
+}
+
+fn saved_bandwidth_rows(a: &RunSummary, b: &RunSummary) -> Vec<[String; 4]> {
+    link_ids(a, b)
+        .into_iter()
+        .map(|link_id| {
+            let (a, b) = (a.link(link_id), b.link(link_id));
+            let saved_bytes = a.sent_bytes as i64 - b.sent_bytes as i64;
+            let saved_pct = if a.sent_bytes == 0 {
+                "n/a".to_string()
+            } else {
+                format!("{:.1}%", 100.0 * saved_bytes as f64 / a.sent_bytes as f64)
+            };
+            [
+                link_id.to_string(),
+                format!("{saved_bytes} bytes"),
+                saved_pct,
+                String::new(),
+            ]
+        })
+        .collect()
+}
+
+fn count_row(name: &str, a: u64, b: u64) -> [String; 4] {
+    [
+        name.to_string(),
+        a.to_string(),
+        b.to_string(),
+        difference(a, b),
+    ]
+}
+
+fn difference(a: u64, b: u64) -> String {
+    format!(
+        "{:+} ({})",
+        b as i64 - a as i64,
+        change_pct(a as f64, b as f64)
+    )
+}
+
+fn change_pct(a: f64, b: f64) -> String {
+    if a == 0.0 {
+        return "n/a".to_string();
+    }
+
+    format!("{:+.1}%", 100.0 * (b - a) / a)
+}
+
+/// Prints a table with its first column aligned to the left and the others to the right
+fn print_table(header: [&str; 4], rows: Vec<[String; 4]>) {
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: [&str; 4]| {
+        let line = format!(
+            "  {:<w0$}  {:>w1$}  {:>w2$}  {:>w3$}",
+            cells[0],
+            cells[1],
+            cells[2],
+            cells[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        );
+        println!("{}", line.trim_end());
+    };
+    print_row(header);
+    for row in &rows {
+        print_row(row.each_ref().map(String::as_str));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn summary(sent_bytes_by_link: &[(&str, usize)]) -> RunSummary {
+        let links = sent_bytes_by_link
+            .iter()
+            .map(|&(link_id, sent_bytes)| {
+                let link = LinkSummary {
+                    sent_bytes,
+                    ..LinkSummary::default()
+                };
+                (link_id.into(), link)
+            })
+            .collect();
+
+        RunSummary {
+            label: String::new(),
+            total_time_sec: 0.0,
+            lost_packets: 0,
+            lost_bytes: 0,
+            non_fatal_errors: 0,
+            links,
+            buffer_peaks: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_change_pct() {
+        assert_eq!(change_pct(100.0, 150.0), "+50.0%");
+        assert_eq!(change_pct(150.0, 100.0), "-33.3%");
+        assert_eq!(change_pct(2.5, 2.5), "+0.0%");
+        assert_eq!(change_pct(0.0, 3.0), "n/a");
+    }
+
+    #[test]
+    fn test_difference() {
+        assert_eq!(difference(100, 150), "+50 (+50.0%)");
+        assert_eq!(difference(150, 100), "-50 (-33.3%)");
+        assert_eq!(difference(7, 7), "+0 (+0.0%)");
+        assert_eq!(difference(0, 7), "+7 (n/a)");
+    }
+
+    #[test]
+    fn test_link_used_by_one_run() {
+        let a = summary(&[("both", 200), ("only-a", 100)]);
+        let b = summary(&[("both", 150), ("only-b", 50)]);
+
+        assert_eq!(
+            sent_bytes_rows(&a, &b),
+            [
+                ["both", "200", "150", "-50 (-25.0%)"],
+                ["only-a", "100", "0", "-100 (-100.0%)"],
+                ["only-b", "0", "50", "+50 (n/a)"],
+            ]
+        );
+        assert_eq!(
+            saved_bandwidth_rows(&a, &b),
+            [
+                ["both", "50 bytes", "25.0%", ""],
+                ["only-a", "100 bytes", "100.0%", ""],
+                ["only-b", "-50 bytes", "n/a", ""],
+            ]
+        );
+    }
+}
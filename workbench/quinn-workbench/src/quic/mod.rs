@@ -1,31 +1,54 @@
 use crate::config::cli::QuicOpt;
 use crate::config::quinn::{CongestionControlAlgorithm, QuinnJsonConfig};
-use crate::load_network_config;
 use crate::quic::simulation::QuicSimulation;
 use crate::quinn_extensions::ecn_cc::EcnCcFactory;
 use crate::quinn_extensions::no_cc::NoCCConfig;
-use crate::util::{print_link_stats, print_max_buffer_usage_per_node, print_node_stats};
-use anyhow::Context;
+use crate::quinn_extensions::no_cid::NoConnectionIdGenerator;
+use crate::report::JsonReport;
+use crate::util::{
+    print_link_stats, print_max_buffer_usage_per_node, print_network_captures, print_node_stats,
+};
+use crate::{load_network_config, read_tls_key_log_since, tls_key_log_len};
+use anyhow::{Context, bail};
+use in_memory_network::network::InMemoryNetwork;
+#[cfg(feature = "schc-compressor")]
+use in_memory_network::schc_desync::SchcDesync;
+#[cfg(feature = "schc-compressor")]
+use in_memory_network::schc_node_roles::SchcNodeRole;
+use in_memory_network::tracing::simulation_verifier::VerifiedSimulation;
+use in_memory_network::tracing::tracer::SimulationStepTracer;
 use quinn_proto::congestion::{CubicConfig, NewRenoConfig};
-use quinn_proto::{AckFrequencyConfig, EndpointConfig, TransportConfig, VarInt};
+use quinn_proto::{
+    AckFrequencyConfig, ConnectionIdGenerator, EndpointConfig, RandomConnectionIdGenerator,
+    TransportConfig, VarInt,
+};
+#[cfg(feature = "schc-compressor")]
+use std::collections::BTreeMap;
 use std::fs;
 use std::sync::Arc;
 use std::time::Duration;
 
 mod client;
+pub mod compare;
 mod server;
 pub mod simulation;
 
 pub async fn run_and_report_stats(quic_options: &QuicOpt) -> anyhow::Result<()> {
     let mut simulation = QuicSimulation::new();
     let network_config = load_network_config(&quic_options.network)?;
-    let result = simulation.run(quic_options, network_config).await;
+    let key_log_len = tls_key_log_len();
+    let result = simulation
+        .run(quic_options, network_config, key_log_len)
+        .await;
 
     let Some((tracer, network)) = simulation.tracer_and_network else {
         eprintln!("Error...");
         return result;
     };
 
+    network.embed_tls_key_log(&read_tls_key_log_since(key_log_len)?)?;
+    print_network_captures(&network)?;
+
     println!("--- Replay log ---");
     let replay_log_path = "replay-log.json";
     let json_steps = serde_json::to_vec_pretty(&tracer.stepper().steps()).unwrap();
@@ -33,16 +56,20 @@ pub async fn run_and_report_stats(quic_options: &QuicOpt) -> anyhow::Result<()>
     println!("* Replay log available at {replay_log_path}");
 
     println!("--- Node stats ---");
-    let verified_simulation = tracer
-        .verifier()
-        .context("failed to create simulation verifier")?
-        .verify()
-        .context("failed to verify simulation")?;
+    let verified_simulation = verify_simulation(&tracer, &network)?;
     let server_node = network.host(quic_options.network.server_ip_address);
     let client_node = network.host(quic_options.network.client_ip_address);
     print_node_stats(&verified_simulation, server_node, client_node);
     print_max_buffer_usage_per_node(&verified_simulation);
-    print_link_stats(&verified_simulation, &network);
+    print_link_stats(&verified_simulation, &network, false);
+
+    let report = match (&quic_options.network.report_json, simulation.seeds) {
+        (Some(_), Some(seeds)) => Some(
+            JsonReport::new("quic", quic_options, seeds, simulation.total_time)
+                .with_simulation(&verified_simulation, &network),
+        ),
+        _ => None,
+    };
 
     const DISPLAY_MAX_ERRORS: usize = 10;
     if !verified_simulation.non_fatal_errors.is_empty() {
@@ -69,6 +96,12 @@ pub async fn run_and_report_stats(quic_options: &QuicOpt) -> anyhow::Result<()>
     if quic_options.schc_observer {
         if let Some(ref observer) = *network.schc_observer.read() {
             observer.stats().report();
+
+            if let Some(path) = &quic_options.schc_synthesize_rules {
+                if let Some(synthesizer) = observer.rule_synthesizer() {
+                    synthesizer.write_rules(path)?;
+                }
+            }
         }
     }
 
@@ -78,6 +111,42 @@ pub async fn run_and_report_stats(quic_options: &QuicOpt) -> anyhow::Result<()>
         if let Some(ref compressor) = *network.schc_compressor.read() {
             compressor.stats().report();
         }
+
+        for context in network.schc_contexts.read().iter() {
+            context.compressor.stats().report_for_context(&context.id);
+        }
+
+        if let Some(ref fragmenter) = *network.schc_fragmenter.read() {
+            fragmenter.stats().report();
+        }
+
+        if let Some(ref provisioning) = *network.schc_provisioning.read() {
+            provisioning.report();
+        }
+
+        if let Some(ref desync) = *network.schc_desync.read() {
+            desync.report();
+            report_schc_desync_effects(
+                desync,
+                &simulation.connection_stats,
+                &simulation.close_reasons,
+                simulation.total_time,
+                &result,
+            );
+        }
+    }
+
+    if let (Some(path), Some(mut report)) = (&quic_options.network.report_json, report) {
+        if let Err(e) = &result {
+            report = report.with_error(e);
+        }
+
+        #[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+        {
+            report = report.with_schc(schc_report_json(quic_options, &network));
+        }
+
+        report.write(path)?;
     }
 
     if result.is_err() {
@@ -87,11 +156,161 @@ pub async fn run_and_report_stats(quic_options: &QuicOpt) -> anyhow::Result<()>
     result
 }
 
-fn endpoint_config(rng_seed: [u8; 32]) -> EndpointConfig {
+/// Collects the statistics of the enabled SCHC modes, for the JSON report
+#[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+fn schc_report_json(quic_options: &QuicOpt, network: &InMemoryNetwork) -> serde_json::Value {
+    let mut schc = serde_json::Map::new();
+
+    #[cfg(feature = "schc-observer")]
+    if quic_options.schc_observer {
+        if let Some(ref observer) = *network.schc_observer.read() {
+            schc.insert("observer".into(), observer.stats().to_json());
+        }
+    }
+
+    #[cfg(feature = "schc-compressor")]
+    if quic_options.schc_compress {
+        if let Some(ref compressor) = *network.schc_compressor.read() {
+            schc.insert("compressor".into(), compressor.stats().to_json());
+        }
+
+        let contexts: serde_json::Map<_, _> = network
+            .schc_contexts
+            .read()
+            .iter()
+            .map(|context| (context.id.to_string(), context.compressor.stats().to_json()))
+            .collect();
+        if !contexts.is_empty() {
+            schc.insert("contexts".into(), contexts.into());
+        }
+
+        if let Some(ref fragmenter) = *network.schc_fragmenter.read() {
+            schc.insert("fragmentation".into(), fragmenter.stats().to_json());
+        }
+
+        if let Some(ref provisioning) = *network.schc_provisioning.read() {
+            schc.insert("provisioning".into(), provisioning.to_json());
+        }
+
+        if let Some(ref desync) = *network.schc_desync.read() {
+            schc.insert("desync".into(), desync.to_json());
+        }
+    }
+
+    schc.into()
+}
+
+/// Reports how the SCHC desynchronization faults affected the QUIC connections, how the connections
+/// were closed, and whether the faulty nodes recovered from every fault
+#[cfg(feature = "schc-compressor")]
+fn report_schc_desync_effects(
+    desync: &SchcDesync,
+    connection_stats: &[quinn_proto::ConnectionStats],
+    close_reasons: &[quinn::ConnectionError],
+    total_time: Option<Duration>,
+    result: &anyhow::Result<()>,
+) {
+    let lost_packets: u64 = connection_stats
+        .iter()
+        .map(|stats| stats.path.lost_packets)
+        .sum();
+    println!("* QUIC effects:");
+    println!("  * Packets declared lost by the closed connections (all causes): {lost_packets}");
+
+    match result {
+        Err(e) => println!("  * Connections closed: errored ({e:#})"),
+        Ok(()) => {
+            let mut reason_counts = BTreeMap::new();
+            for reason in close_reasons {
+                *reason_counts.entry(reason.to_string()).or_insert(0) += 1;
+            }
+            let reason_counts: Vec<_> = reason_counts
+                .into_iter()
+                .map(|(reason, connections)| format!("{reason} ({connections} connections)"))
+                .collect();
+            println!("  * Connections closed: {}", reason_counts.join(", "));
+        }
+    }
+
+    match (
+        desync.unrecovered_faults(),
+        desync.last_fault_end(),
+        total_time,
+    ) {
+        (0, Some(last_fault_end), Some(total_time)) if total_time > last_fault_end => println!(
+            "  * Recovered: yes, after every fault (connections closed {:.2}s after the last fault ended)",
+            (total_time - last_fault_end).as_secs_f64()
+        ),
+        (0, Some(_), _) => println!("  * Recovered: yes, after every fault"),
+        (0, None, _) => {
+            println!("  * Recovered: n/a, a fault lasts until the end of the simulation")
+        }
+        (unrecovered, _, _) => println!(
+            "  * Recovered: no, {unrecovered} fault(s) ended without their node decompressing a packet afterwards"
+        ),
+    }
+}
+
+/// Replays the simulation, checking its invariants and collecting the node and link stats
+#[cfg_attr(
+    not(feature = "schc-compressor"),
+    expect(
+        unused_variables,
+        reason = "the network is only needed for SCHC compression"
+    )
+)]
+fn verify_simulation(
+    tracer: &SimulationStepTracer,
+    network: &InMemoryNetwork,
+) -> anyhow::Result<VerifiedSimulation> {
+    let verifier = tracer
+        .verifier()
+        .context("failed to create simulation verifier")?;
+    #[cfg(feature = "schc-compressor")]
+    let verifier = verifier.with_schc_compress_nodes(
+        network
+            .schc_node_roles
+            .read()
+            .nodes_with_role(SchcNodeRole::Compressor)
+            .into_iter()
+            .collect(),
+    );
+    verifier.verify().context("failed to verify simulation")
+}
+
+fn endpoint_config(
+    rng_seed: [u8; 32],
+    quinn_config: &QuinnJsonConfig,
+) -> anyhow::Result<EndpointConfig> {
     let mut config = EndpointConfig::default();
     config.rng_seed(Some(rng_seed));
 
-    config
+    if let Some(cid_generator) = cid_generator(quinn_config.connection_id_length)? {
+        config.cid_generator(cid_generator);
+    }
+
+    Ok(config)
+}
+
+/// Returns the factory of the connection ID generators for the configured connection ID length,
+/// or `None` to keep Quinn's default
+fn cid_generator(
+    connection_id_length: Option<usize>,
+) -> anyhow::Result<Option<impl Fn() -> Box<dyn ConnectionIdGenerator> + Send + Sync + 'static>> {
+    let Some(len) = connection_id_length else {
+        return Ok(None);
+    };
+    if len > MAX_CID_SIZE {
+        bail!("invalid connection ID length: {len} (expected at most {MAX_CID_SIZE} bytes)")
+    }
+
+    Ok(Some(move || -> Box<dyn ConnectionIdGenerator> {
+        if len == 0 {
+            Box::new(NoConnectionIdGenerator)
+        } else {
+            Box::new(RandomConnectionIdGenerator::new(len))
+        }
+    }))
 }
 
 fn transport_config(quinn_config: &QuinnJsonConfig) -> TransportConfig {
@@ -168,3 +387,25 @@ fn transport_config(quinn_config: &QuinnJsonConfig) -> TransportConfig {
 }
 
 const BASE_DATAGRAM_SIZE: u64 = 1200;
+/// The maximum length of a QUIC v1 connection ID (RFC 9000, section 17.2)
+const MAX_CID_SIZE: usize = 20;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cid_generator() {
+        assert!(cid_generator(None).unwrap().is_none());
+        assert!(cid_generator(Some(MAX_CID_SIZE + 1)).is_err());
+
+        // Zero-length connection IDs
+        let cid_generator_0 = cid_generator(Some(0)).unwrap().unwrap();
+        assert_eq!(cid_generator_0().cid_len(), 0);
+        assert!(cid_generator_0().generate_cid().is_empty());
+
+        let cid_generator_8 = cid_generator(Some(8)).unwrap().unwrap();
+        assert_eq!(cid_generator_8().cid_len(), 8);
+        assert_eq!(cid_generator_8().generate_cid().len(), 8);
+    }
+}
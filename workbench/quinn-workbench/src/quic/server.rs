@@ -4,7 +4,7 @@ use fastrand::Rng;
 use futures::channel::mpsc::UnboundedReceiver;
 use in_memory_network::async_rt;
 use in_memory_network::quinn_interop::InMemoryUdpSocket;
-use quinn::Endpoint;
+use quinn::{ConnectionError, ConnectionStats, Endpoint};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use std::sync::Arc;
 
@@ -174,12 +174,15 @@ pub static _KEY_PAIR_DER_ECDSA: &[u8] = &[
     0x9d, 0xa7, 0x78, 0x1d, 0xf9, 0x6, 0x3b, 0xb,
 ];
 
+// The stats of a connection handled by the server, along with the reason it was closed
+type HandledConnection = anyhow::Result<(ConnectionStats, Option<ConnectionError>)>;
+
 // A server that concurrently handles connections and their streams. Each stream is expected to make
 // a "request" in the form `GET /index.html`.
 pub fn server_listen(
     endpoint: Endpoint,
     response_payload_size: usize,
-) -> UnboundedReceiver<async_rt::JoinHandle<anyhow::Result<()>>> {
+) -> UnboundedReceiver<async_rt::JoinHandle<HandledConnection>> {
     let (connection_result_tx, connection_result_rx) = futures::channel::mpsc::unbounded();
 
     async_rt::spawn(async move {
@@ -220,7 +223,7 @@ pub fn server_listen(
                         .context("server stream task errored")?;
                 }
 
-                Result::<_, anyhow::Error>::Ok(())
+                Result::<_, anyhow::Error>::Ok((conn.stats(), conn.close_reason()))
             });
 
             // Notify observers that we are done handling the connection
@@ -244,7 +247,7 @@ pub fn server_endpoint(
     let mut server_config = quinn::ServerConfig::with_single_cert(vec![cert], key).unwrap();
     server_config.transport = Arc::new(crate::quic::transport_config(quinn_config));
     Endpoint::new_with_abstract_socket(
-        crate::quic::endpoint_config(seed),
+        crate::quic::endpoint_config(seed, quinn_config)?,
         Some(server_config),
         Arc::new(server_socket),
         async_rt::active_rt(),
@@ -1,6 +1,8 @@
 use crate::config::NetworkConfig;
 use crate::config::cli::QuicOpt;
+use crate::pcap_exporter_factory;
 use crate::quic::{client, server};
+use crate::report::Seeds;
 use anyhow::{Context, bail};
 use async_lock::Semaphore;
 use fastrand::Rng;
@@ -10,9 +12,10 @@ use in_memory_network::async_rt::time::Instant;
 use in_memory_network::network::InMemoryNetwork;
 use in_memory_network::network::event::NetworkEvents;
 use in_memory_network::network::spec::NetworkSpec;
-use in_memory_network::pcap_exporter::{FileBasedPcapExporterFactory, NoOpPcapExporterFactory};
+use in_memory_network::pcap_exporter::NoOpPcapExporterFactory;
 use in_memory_network::tracing::tracer::SimulationStepTracer;
 use parking_lot::Mutex;
+use quinn::{ConnectionError, ConnectionStats};
 use quinn_proto::VarInt;
 use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
 use std::sync::Arc;
@@ -21,6 +24,14 @@ use std::time::Duration;
 #[derive(Default)]
 pub struct QuicSimulation {
     pub tracer_and_network: Option<(Arc<SimulationStepTracer>, Arc<InMemoryNetwork>)>,
+    /// The seeds used by the run
+    pub seeds: Option<Seeds>,
+    /// The simulated time it took to close all connections
+    pub total_time: Option<Duration>,
+    /// The stats of the client and server connections, once closed
+    pub connection_stats: Vec<ConnectionStats>,
+    /// The reasons the server connections were closed for, as seen by the server
+    pub close_reasons: Vec<ConnectionError>,
 }
 
 impl QuicSimulation {
@@ -28,10 +39,17 @@ impl QuicSimulation {
         Self::default()
     }
 
+    /// Runs the simulation, with the TLS key log having `key_log_len` bytes before the run (the
+    /// secrets it already holds belong to previous runs)
     pub async fn run(
         &mut self,
         quic_options: &QuicOpt,
         network_config: NetworkConfig,
+        #[cfg_attr(
+            not(any(feature = "schc-observer", feature = "schc-compressor")),
+            allow(unused_variables)
+        )]
+        key_log_len: u64,
     ) -> anyhow::Result<()> {
         println!("--- Params ---");
         let (quinn_rng_seed, simulated_network_rng_seed) = if quic_options.network.non_deterministic
@@ -44,6 +62,10 @@ impl QuicSimulation {
                 quic_options.network.network_rng_seed,
             )
         };
+        self.seeds = Some(Seeds {
+            quinn: Some(quinn_rng_seed),
+            network: simulated_network_rng_seed,
+        });
         println!("* Quinn seed: {}", quinn_rng_seed);
         println!("* Network seed: {}", simulated_network_rng_seed);
         println!(
@@ -62,28 +84,32 @@ impl QuicSimulation {
         // Network check
         let network_spec: NetworkSpec = network_config.network_graph.into();
 
-        // Validate SCHC nodes early before any network setup
-        #[cfg(feature = "schc-observer")]
-        if quic_options.schc_observer {
-            if let Some(ref node_ids) = quic_options.schc_nodes {
-                let available_node_ids: Vec<&str> =
-                    network_spec.nodes.iter().map(|n| n.id.as_str()).collect();
-
-                let mut invalid_nodes = Vec::new();
-                for node_id in node_ids {
-                    if !available_node_ids.contains(&node_id.as_str()) {
-                        invalid_nodes.push(node_id.as_str());
-                    }
-                }
+        if quic_options.schc_synthesize_rules.is_some() && !quic_options.schc_observer {
+            bail!("--schc-synthesize-rules requires --schc-observer");
+        }
 
-                if !invalid_nodes.is_empty() {
-                    bail!(
-                        "Invalid SCHC node(s) specified: [{}]\n\
-                         Available nodes in the network graph: [{}]",
-                        invalid_nodes.join(", "),
-                        available_node_ids.join(", ")
-                    );
-                }
+        if quic_options.schc_rule_updates.is_some() && !quic_options.schc_compress {
+            bail!("--schc-rule-updates requires --schc-compress");
+        }
+
+        if quic_options.schc_desync.is_some() && !quic_options.schc_compress {
+            bail!("--schc-desync requires --schc-compress");
+        }
+
+        if quic_options.schc_pcap && !quic_options.schc_compress {
+            bail!("--schc-pcap requires --schc-compress");
+        }
+
+        // Validate SCHC nodes early before any network setup
+        #[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+        let schc_node_roles = schc_node_roles(quic_options, &network_spec)?;
+
+        // Capture points, also validated before any network setup
+        let mut pcap_exporter_factory =
+            pcap_exporter_factory(&quic_options.network, &network_spec)?;
+        if quic_options.schc_pcap {
+            for link in network_spec.links.iter().filter(|l| l.schc_constrained) {
+                pcap_exporter_factory.links.add(&link.id);
             }
         }
 
@@ -129,114 +155,240 @@ impl QuicSimulation {
         // Network
         let tracer = Arc::new(SimulationStepTracer::new(network_spec.clone()));
         // Clone for later SCHC node validation
-        #[cfg(feature = "schc-compressor")]
+        #[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
         let network_spec_for_validation = network_spec.clone();
         let network = InMemoryNetwork::initialize(
             network_spec,
             network_events,
             tracer.clone(),
-            Arc::new(FileBasedPcapExporterFactory),
+            Arc::new(pcap_exporter_factory),
             Rng::with_seed(simulated_network_rng_seed),
             start,
         )?;
         self.tracer_and_network = Some((tracer.clone(), network.clone()));
 
+        #[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+        {
+            *network.schc_node_roles.write() = schc_node_roles;
+        }
+
         // Initialize SCHC observer if enabled
         #[cfg(feature = "schc-observer")]
         if quic_options.schc_observer {
+            use in_memory_network::quic_header_protection::QuicKeyLog;
+            use in_memory_network::schc_node_roles::SchcNodeRole;
             use in_memory_network::schc_observer::SchcObserver;
-            use std::collections::HashSet;
 
-            let rules_path = quic_options
-                .schc_rules
-                .as_ref()
-                .context("--schc-rules required when --schc-observer is enabled")?;
+            let synthesize_rules = quic_options.schc_synthesize_rules.is_some();
+            let rules_path = match &quic_options.schc_rules {
+                Some(rules_path) => Some(rules_path),
+                None if synthesize_rules => None,
+                None => bail!("--schc-rules required when --schc-observer is enabled"),
+            };
 
             println!("--- SCHC Observer ---");
-            println!("* Rules: {}", rules_path.display());
+            match rules_path {
+                Some(rules_path) => println!("* Rules: {}", rules_path.display()),
+                None => println!("* Rules: none"),
+            }
             println!("* Debug mode: {}", quic_options.schc_debug);
-
-            let observer = Arc::new(SchcObserver::from_files(
-                rules_path.to_str().unwrap(),
-                "", // Field context no longer needed
-                quic_options.schc_debug,
-            )?);
+            println!(
+                "* Header protection removal: {}",
+                quic_options.schc_unprotect_headers
+            );
+            println!("* Rule synthesis: {synthesize_rules}");
+
+            let key_log = quic_options
+                .schc_unprotect_headers
+                .then(|| Arc::new(QuicKeyLog::new(crate::KEYLOG_PATH, key_log_len)));
+            let observer = match rules_path {
+                Some(rules_path) => SchcObserver::from_files(
+                    rules_path.to_str().unwrap(),
+                    "", // Field context no longer needed
+                    quic_options.schc_debug,
+                )?,
+                None => SchcObserver::without_rules(quic_options.schc_debug),
+            };
+            let observer = Arc::new(
+                observer
+                    .with_key_log(key_log)
+                    .with_rule_synthesis(synthesize_rules),
+            );
 
             // Set the observer on the network
             *network.schc_observer.write() = Some(observer);
 
-            // Configure enabled nodes if specified
-            if let Some(ref node_ids) = quic_options.schc_nodes {
-                // Node validation already done earlier
-
-                println!("* Enabled nodes: {}", node_ids.join(", "));
-                let nodes: HashSet<Arc<str>> = node_ids
-                    .iter()
-                    .map(|s| Arc::<str>::from(s.as_str()))
-                    .collect();
-                *network.schc_enabled_nodes.write() = Some(nodes);
-            } else {
+            // Node validation already done earlier
+            let observer_nodes = network
+                .schc_node_roles
+                .read()
+                .nodes_with_role(SchcNodeRole::Observer);
+            if observer_nodes.is_empty() {
                 println!("* Enabled nodes: all routers");
+            } else {
+                println!("* Enabled nodes: {}", observer_nodes.join(", "));
+            }
+
+            // The direction of a packet, needed to match rules, comes from the SCHC roles
+            if network_spec_for_validation
+                .nodes
+                .iter()
+                .all(|n| n.schc_role.is_none())
+            {
+                println!(
+                    "* WARNING: No SCHC roles in the network graph, no packets will be observed"
+                );
             }
         }
 
         // Initialize SCHC compressor if enabled
         #[cfg(feature = "schc-compressor")]
         if quic_options.schc_compress {
+            use in_memory_network::network::spec::SchcRole;
+            use in_memory_network::quic_header_protection::QuicKeyLog;
             use in_memory_network::schc_compressor::SchcCompressor;
-            use std::collections::HashSet;
+            use in_memory_network::schc_node_roles::SchcNodeRole;
+
+            if quic_options.schc_rules.is_none() && quic_options.schc_contexts.is_none() {
+                bail!("--schc-rules or --schc-contexts required when --schc-compress is enabled");
+            }
 
-            let rules_path = quic_options
-                .schc_rules
-                .as_ref()
-                .context("--schc-rules required when --schc-compress is enabled")?;
+            let key_log = quic_options
+                .schc_compress_unprotect_headers
+                .then(|| Arc::new(QuicKeyLog::new(crate::KEYLOG_PATH, key_log_len)));
 
             println!("--- SCHC Compressor ---");
-            println!("* Rules: {}", rules_path.display());
+            if let Some(rules_path) = &quic_options.schc_rules {
+                println!("* Rules: {}", rules_path.display());
+                let compressor = Arc::new(
+                    SchcCompressor::from_files(
+                        rules_path.to_str().unwrap(),
+                        "", // Field context no longer needed
+                        quic_options.schc_debug,
+                    )?
+                    .with_roundtrip_verification(quic_options.schc_verify_roundtrip)
+                    .with_key_log(key_log.clone()),
+                );
+
+                // Set the compressor on the network
+                *network.schc_compressor.write() = Some(compressor);
+            }
             println!("* Debug mode: {}", quic_options.schc_debug);
+            println!(
+                "* Round-trip verification: {}",
+                quic_options.schc_verify_roundtrip
+            );
+            println!(
+                "* Header protection removal: {}",
+                quic_options.schc_compress_unprotect_headers
+            );
 
-            let compressor = Arc::new(SchcCompressor::from_files(
-                rules_path.to_str().unwrap(),
-                "", // Field context no longer needed
-                quic_options.schc_debug,
-            )?);
-
-            // Set the compressor on the network
-            *network.schc_compressor.write() = Some(compressor);
+            // Contexts take precedence over the default compressor, for the segments they apply to
+            if let Some(contexts_path) = &quic_options.schc_contexts {
+                let contexts = load_schc_contexts(
+                    contexts_path,
+                    &network_spec_for_validation,
+                    quic_options.schc_debug,
+                    quic_options.schc_verify_roundtrip,
+                    key_log.clone(),
+                )?;
+                *network.schc_contexts.write() = contexts;
+            }
 
-            // Configure compression nodes if specified
-            if let Some(ref node_ids) = quic_options.schc_compress_nodes {
-                // Validate nodes exist
-                let available_node_ids: Vec<&str> = network_spec_for_validation
-                    .nodes
+            // Compression nodes (already validated)
+            let compressor_nodes = network
+                .schc_node_roles
+                .read()
+                .nodes_with_role(SchcNodeRole::Compressor);
+            if !compressor_nodes.is_empty() {
+                println!("* Compression nodes: {}", compressor_nodes.join(", "));
+
+                // Compression decisions are derived from the topology
+                let constrained_links: Vec<&str> = network_spec_for_validation
+                    .links
                     .iter()
-                    .map(|n| n.id.as_str())
+                    .filter(|l| l.schc_constrained)
+                    .map(|l| l.id.as_ref())
                     .collect();
-
-                let mut invalid_nodes = Vec::new();
-                for node_id in node_ids {
-                    if !available_node_ids.contains(&node_id.as_str()) {
-                        invalid_nodes.push(node_id.as_str());
-                    }
-                }
-
-                if !invalid_nodes.is_empty() {
-                    bail!(
-                        "Invalid SCHC compress node(s) specified: [{}]\n\
-                         Available nodes in the network graph: [{}]",
-                        invalid_nodes.join(", "),
-                        available_node_ids.join(", ")
+                if constrained_links.is_empty() {
+                    println!(
+                        "* WARNING: No SCHC-constrained links in the network graph, compression disabled"
                     );
+                } else {
+                    println!("* Constrained links: {}", constrained_links.join(", "));
                 }
 
-                println!("* Compression nodes: {}", node_ids.join(", "));
-                let nodes: HashSet<Arc<str>> = node_ids
+                let roles: Vec<String> = network_spec_for_validation
+                    .nodes
                     .iter()
-                    .map(|s| Arc::<str>::from(s.as_str()))
+                    .filter_map(|n| {
+                        let role = match n.schc_role? {
+                            SchcRole::Device => "device",
+                            SchcRole::Application => "application",
+                        };
+                        Some(format!("{} ({role})", n.id))
+                    })
                     .collect();
-                *network.schc_compress_nodes.write() = Some(nodes);
+                if roles.is_empty() {
+                    println!("* WARNING: No SCHC roles in the network graph, compression disabled");
+                } else {
+                    println!("* Roles: {}", roles.join(", "));
+                }
             } else {
-                println!("* WARNING: No --schc-compress-nodes specified, compression disabled");
+                println!("* WARNING: No compressor nodes specified, compression disabled");
+            }
+
+            // Rule updates are sent over the network while the simulation runs
+            if let Some(updates_path) = &quic_options.schc_rule_updates {
+                let provisioning = Arc::new(load_schc_rule_updates(
+                    updates_path,
+                    &network,
+                    quic_options.schc_debug,
+                    quic_options.schc_verify_roundtrip,
+                    key_log.clone(),
+                    start,
+                )?);
+                provisioning.schedule(&network)?;
+                *network.schc_provisioning.write() = Some(provisioning);
+            }
+
+            // Desynchronization faults are applied by the decompressors while the simulation runs
+            if let Some(desync_path) = &quic_options.schc_desync {
+                let desync = load_schc_desync(
+                    desync_path,
+                    &network,
+                    quic_options.schc_debug,
+                    quic_options.schc_verify_roundtrip,
+                    key_log,
+                    start,
+                    simulated_network_rng_seed,
+                )?;
+                *network.schc_desync.write() = Some(Arc::new(desync));
+            }
+
+            // Initialize SCHC fragmentation if enabled
+            if let Some(ref mode) = quic_options.schc_fragment_mode {
+                use in_memory_network::schc_fragmentation::{FragmentationConfig, SchcFragmenter};
+
+                let config = FragmentationConfig {
+                    mode: mode.parse()?,
+                    rule_id: quic_options.schc_fragment_rule_id,
+                    mtu_bytes: quic_options.schc_fragment_mtu,
+                    retransmission_timeout: Duration::from_millis(
+                        quic_options.schc_retransmission_timeout_ms,
+                    ),
+                    max_ack_requests: quic_options.schc_max_ack_requests,
+                    inactivity_timeout: Duration::from_millis(
+                        quic_options.schc_inactivity_timeout_ms,
+                    ),
+                };
+
+                println!(
+                    "* Fragmentation: {} (MTU: {} bytes, rule ID: {})",
+                    config.mode, config.mtu_bytes, config.rule_id
+                );
+                let fragmenter = SchcFragmenter::new(config, quic_options.schc_debug)?;
+                *network.schc_fragmenter.write() = Some(Arc::new(fragmenter));
             }
         }
 
@@ -312,21 +464,26 @@ impl QuicSimulation {
         // Wait for all connections to finish
         let total_connections = connection_tasks.len();
         for task in connection_tasks {
-            task.await
+            let stats = task
+                .await
                 .context("client connection task crashed")?
                 .context("client connection errored")?;
+            self.connection_stats.push(stats);
         }
 
-        let total_time_sec = start.elapsed().as_secs_f64();
-        println!("{:.2}s All connections closed", total_time_sec);
+        let total_time = start.elapsed();
+        self.total_time = Some(total_time);
+        println!("{:.2}s All connections closed", total_time.as_secs_f64());
 
         // Cleanly shut down the server
         let mut handled_connections = 0;
         while let Some(conn_task_handle) = server_handled_connections.next().await {
-            conn_task_handle
+            let (stats, close_reason) = conn_task_handle
                 .await
                 .context("server connection task crashed")?
                 .context("server connection task errored")?;
+            self.connection_stats.push(stats);
+            self.close_reasons.extend(close_reason);
 
             handled_connections += 1;
             if handled_connections >= total_connections {
@@ -338,3 +495,324 @@ impl QuicSimulation {
         Ok(())
     }
 }
+
+/// Collects the SCHC roles of the nodes from --schc-node-roles, --schc-nodes (observers) and
+/// --schc-compress-nodes (compressors), validating them against the network
+///
+/// The roles of disabled SCHC modes are left out, so e.g. a run without --schc-compress ignores
+/// the compressor roles.
+#[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+fn schc_node_roles(
+    quic_options: &QuicOpt,
+    network_spec: &NetworkSpec,
+) -> anyhow::Result<in_memory_network::schc_node_roles::SchcNodeRoles> {
+    use in_memory_network::schc_node_roles::{SchcNodeRole, SchcNodeRoles};
+
+    let mut assigned =
+        SchcNodeRoles::parse(quic_options.schc_node_roles.as_deref().unwrap_or_default())?;
+    for node_id in quic_options.schc_nodes.iter().flatten() {
+        assigned.add(node_id, SchcNodeRole::Observer);
+    }
+    for node_id in quic_options.schc_compress_nodes.iter().flatten() {
+        assigned.add(node_id, SchcNodeRole::Compressor);
+    }
+
+    let available_node_ids: Vec<&str> = network_spec.nodes.iter().map(|n| n.id.as_str()).collect();
+    let mut invalid_nodes: Vec<&str> = assigned
+        .node_ids()
+        .map(|node_id| node_id.as_ref())
+        .filter(|node_id| !available_node_ids.contains(node_id))
+        .collect();
+    invalid_nodes.sort();
+    if !invalid_nodes.is_empty() {
+        bail!(
+            "Invalid SCHC node(s) specified: [{}]\n\
+             Available nodes in the network graph: [{}]",
+            invalid_nodes.join(", "),
+            available_node_ids.join(", ")
+        );
+    }
+
+    let is_enabled = |role| match role {
+        SchcNodeRole::Observer => cfg!(feature = "schc-observer") && quic_options.schc_observer,
+        SchcNodeRole::Compressor => cfg!(feature = "schc-compressor") && quic_options.schc_compress,
+    };
+    let mut roles = SchcNodeRoles::default();
+    for (node_id, node_roles) in assigned.by_node() {
+        for role in node_roles.into_iter().filter(|&role| is_enabled(role)) {
+            roles.add(&node_id, role);
+        }
+    }
+
+    if is_enabled(SchcNodeRole::Observer) {
+        roles = roles.with_routers_observing_by_default();
+    }
+
+    Ok(roles)
+}
+
+/// Loads the SCHC contexts declared in the provided file, validating them against the network
+#[cfg(feature = "schc-compressor")]
+fn load_schc_contexts(
+    path: &std::path::Path,
+    network_spec: &NetworkSpec,
+    debug: bool,
+    verify_roundtrip: bool,
+    key_log: Option<Arc<in_memory_network::quic_header_protection::QuicKeyLog>>,
+) -> anyhow::Result<Vec<in_memory_network::schc_compressor::SchcContext>> {
+    use crate::config::schc::SchcContextsJson;
+    use in_memory_network::schc_compressor::{SchcCompressor, SchcContext, SchcContextBinding};
+    use std::collections::HashSet;
+
+    let contexts_json: SchcContextsJson = crate::load_json(path)?;
+    let base_dir = path.parent().unwrap_or(std::path::Path::new(""));
+
+    let mut ids = HashSet::new();
+    let mut contexts = Vec::new();
+    for context in contexts_json.contexts {
+        if !ids.insert(context.id.clone()) {
+            bail!("there is more than one SCHC context with id {}", context.id);
+        }
+
+        let binding = match (context.nodes, context.links.is_empty()) {
+            (Some(nodes), true) => {
+                for node_id in &nodes {
+                    if !network_spec.nodes.iter().any(|n| n.id == *node_id) {
+                        bail!("SCHC context {} uses unknown node {node_id}", context.id);
+                    }
+                }
+
+                println!(
+                    "* Context `{}`: nodes {} and {}",
+                    context.id, nodes[0], nodes[1]
+                );
+                SchcContextBinding::NodePair(nodes.map(Arc::from))
+            }
+            (None, false) => {
+                for link_id in &context.links {
+                    if !network_spec.links.iter().any(|l| *l.id == **link_id) {
+                        bail!("SCHC context {} uses unknown link {link_id}", context.id);
+                    }
+                }
+
+                println!(
+                    "* Context `{}`: links {}",
+                    context.id,
+                    context.links.join(", ")
+                );
+                SchcContextBinding::Links(context.links.iter().map(|l| l.as_str().into()).collect())
+            }
+            _ => bail!(
+                "SCHC context {} must be bound to either a pair of nodes or a set of links",
+                context.id
+            ),
+        };
+
+        let rules_path = base_dir.join(&context.rules);
+        println!("  * Rules: {}", rules_path.display());
+        let compressor = SchcCompressor::from_files(rules_path.to_str().unwrap(), "", debug)
+            .with_context(|| format!("failed to load the rules of SCHC context {}", context.id))?
+            .with_roundtrip_verification(verify_roundtrip)
+            .with_key_log(key_log.clone());
+
+        contexts.push(SchcContext {
+            id: context.id.into(),
+            binding,
+            compressor: Arc::new(compressor),
+        });
+    }
+
+    Ok(contexts)
+}
+
+/// Loads the SCHC rule updates scheduled in the provided file, to be sent relative to `start`
+#[cfg(feature = "schc-compressor")]
+fn load_schc_rule_updates(
+    path: &std::path::Path,
+    network: &InMemoryNetwork,
+    debug: bool,
+    verify_roundtrip: bool,
+    key_log: Option<Arc<in_memory_network::quic_header_protection::QuicKeyLog>>,
+    start: Instant,
+) -> anyhow::Result<in_memory_network::schc_provisioning::SchcProvisioning> {
+    use crate::config::schc::SchcRuleUpdatesJson;
+    use in_memory_network::schc_compressor::SchcCompressor;
+    use in_memory_network::schc_node_roles::SchcNodeRole;
+    use in_memory_network::schc_provisioning::{
+        INITIAL_CONTEXT_VERSION, RuleUpdate, SchcProvisioning,
+    };
+    use std::collections::HashMap;
+
+    let mut updates_json: SchcRuleUpdatesJson = crate::load_json(path)?;
+    let base_dir = path.parent().unwrap_or(std::path::Path::new(""));
+    println!(
+        "* Rule updates: {} (chunks of {} bytes)",
+        path.display(),
+        updates_json.chunk_size_bytes
+    );
+
+    let compressor_nodes = network
+        .schc_node_roles
+        .read()
+        .nodes_with_role(SchcNodeRole::Compressor);
+    let context_ids: Vec<Arc<str>> = network
+        .schc_contexts
+        .read()
+        .iter()
+        .map(|context| context.id.clone())
+        .collect();
+
+    // Versions are assigned in the order in which the updates are sent
+    updates_json.updates.sort_by_key(|update| update.time_ms);
+    let mut last_versions: HashMap<Option<Arc<str>>, u32> = HashMap::new();
+    let mut updates = Vec::new();
+    for update in updates_json.updates {
+        let context_id = update.context.map(Arc::<str>::from);
+        let unknown_context = context_id.as_ref().filter(|id| !context_ids.contains(id));
+        if let Some(context_id) = unknown_context {
+            bail!(
+                "SCHC rule update at {} ms uses unknown context {context_id}",
+                update.time_ms
+            );
+        }
+
+        let last_version = last_versions
+            .entry(context_id.clone())
+            .or_insert(INITIAL_CONTEXT_VERSION);
+        let version = update.version.unwrap_or(*last_version + 1);
+        *last_version = (*last_version).max(version);
+
+        let source_id = Arc::<str>::from(update.source);
+        let target_ids: Vec<Arc<str>> = match update.targets {
+            Some(targets) => targets.into_iter().map(Arc::from).collect(),
+            None => compressor_nodes
+                .iter()
+                .filter(|&node_id| *node_id != source_id)
+                .cloned()
+                .collect(),
+        };
+        if target_ids.is_empty() {
+            bail!(
+                "SCHC rule update at {} ms has no target nodes (no other compressor node than {source_id})",
+                update.time_ms
+            );
+        }
+
+        let rules_path = base_dir.join(&update.rules);
+        let payload = std::fs::read(&rules_path)
+            .with_context(|| format!("unable to read SCHC rules at `{}`", rules_path.display()))?;
+        let compressor = SchcCompressor::from_files(rules_path.to_str().unwrap(), "", debug)
+            .with_context(|| {
+                format!(
+                    "failed to load the rules of the SCHC rule update at {} ms",
+                    update.time_ms
+                )
+            })?
+            .with_roundtrip_verification(verify_roundtrip)
+            .with_key_log(key_log.clone());
+
+        let target_list: Vec<&str> = target_ids.iter().map(|id| id.as_ref()).collect();
+        match &context_id {
+            Some(context_id) => println!(
+                "  * {} ms: v{version} of context `{context_id}` from {source_id} to {}",
+                update.time_ms,
+                target_list.join(", ")
+            ),
+            None => println!(
+                "  * {} ms: v{version} of the default rules from {source_id} to {}",
+                update.time_ms,
+                target_list.join(", ")
+            ),
+        }
+        println!("    * Rules: {}", rules_path.display());
+
+        updates.push(RuleUpdate {
+            time: Duration::from_millis(update.time_ms),
+            source_id,
+            target_ids,
+            context_id,
+            version,
+            payload,
+            compressor: Arc::new(compressor),
+        });
+    }
+
+    SchcProvisioning::new(updates, updates_json.chunk_size_bytes, start, debug)
+}
+
+/// Loads the SCHC desynchronization faults from the provided file, with time windows relative to
+/// `start` and the packets they affect drawn from an RNG seeded with `rng_seed`
+#[cfg(feature = "schc-compressor")]
+fn load_schc_desync(
+    path: &std::path::Path,
+    network: &InMemoryNetwork,
+    debug: bool,
+    verify_roundtrip: bool,
+    key_log: Option<Arc<in_memory_network::quic_header_protection::QuicKeyLog>>,
+    start: Instant,
+    rng_seed: u64,
+) -> anyhow::Result<in_memory_network::schc_desync::SchcDesync> {
+    use crate::config::schc::{SchcDesyncFaultKindJson, SchcDesyncJson};
+    use in_memory_network::schc_compressor::SchcCompressor;
+    use in_memory_network::schc_desync::{SchcDesync, SchcDesyncFault, SchcDesyncFaultKind};
+    use in_memory_network::schc_node_roles::SchcNodeRole;
+
+    let desync_json: SchcDesyncJson = crate::load_json(path)?;
+    let base_dir = path.parent().unwrap_or(std::path::Path::new(""));
+    println!("* Desynchronization faults: {}", path.display());
+
+    let compressor_nodes = network
+        .schc_node_roles
+        .read()
+        .nodes_with_role(SchcNodeRole::Compressor);
+    let mut faults = Vec::new();
+    for fault in desync_json.faults {
+        let node_id = Arc::<str>::from(fault.node);
+        if !compressor_nodes.contains(&node_id) {
+            bail!("SCHC desync fault at {node_id}, which is not a compressor node");
+        }
+
+        let kind = match fault.kind {
+            SchcDesyncFaultKindJson::Rules { rules } => {
+                let rules_path = base_dir.join(rules);
+                let compressor =
+                    SchcCompressor::from_files(rules_path.to_str().unwrap(), "", debug)
+                        .with_context(|| {
+                            format!(
+                                "failed to load the rules of the SCHC desync fault at {node_id}"
+                            )
+                        })?
+                        .with_roundtrip_verification(verify_roundtrip)
+                        .with_key_log(key_log.clone());
+                SchcDesyncFaultKind::Rules {
+                    label: rules_path.display().to_string(),
+                    compressor: Arc::new(compressor),
+                }
+            }
+            SchcDesyncFaultKindJson::RuleId => SchcDesyncFaultKind::RuleId,
+            SchcDesyncFaultKindJson::Residue => SchcDesyncFaultKind::Residue,
+        };
+
+        let fault = SchcDesyncFault {
+            node_id,
+            from: Duration::from_millis(fault.from_ms),
+            until: fault.until_ms.map(Duration::from_millis),
+            ratio: fault.ratio,
+            kind,
+        };
+        let until = match fault.until {
+            Some(until) => format!("{} ms", until.as_millis()),
+            None => "the end".to_string(),
+        };
+        println!(
+            "  * {}: {} from {} ms to {until} ({:.0}% of the packets)",
+            fault.node_id,
+            fault.kind,
+            fault.from.as_millis(),
+            fault.ratio * 100.0
+        );
+        faults.push(fault);
+    }
+
+    SchcDesync::new(faults, start, rng_seed, debug)
+}
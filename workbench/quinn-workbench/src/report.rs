@@ -0,0 +1,173 @@
+//! Machine-readable report of a run, stored when `--report-json` is provided
+
+use anyhow::Context;
+use in_memory_network::network::InMemoryNetwork;
+use in_memory_network::tracing::simulation_verifier::VerifiedSimulation;
+use in_memory_network::tracing::stats::LinkStats;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// The seeds actually used by the random number generators of a run (which are random for
+/// non-deterministic runs)
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Seeds {
+    /// Quinn's seed (only used by the `quic` command)
+    pub quinn: Option<u64>,
+    pub network: u64,
+}
+
+#[derive(Serialize)]
+pub struct JsonReport {
+    command: &'static str,
+    parameters: Value,
+    seeds: Seeds,
+    simulated_time_sec: Option<f64>,
+    nodes: BTreeMap<String, Value>,
+    links: BTreeMap<String, Value>,
+    non_fatal_errors: Vec<String>,
+    /// The error the run ended with, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schc: Option<Value>,
+}
+
+impl JsonReport {
+    pub fn new(
+        command: &'static str,
+        parameters: &impl Serialize,
+        seeds: Seeds,
+        simulated_time: Option<Duration>,
+    ) -> Self {
+        Self {
+            command,
+            parameters: json!(parameters),
+            seeds,
+            simulated_time_sec: simulated_time.map(|time| time.as_secs_f64()),
+            nodes: BTreeMap::new(),
+            links: BTreeMap::new(),
+            non_fatal_errors: Vec::new(),
+            error: None,
+            schc: None,
+        }
+    }
+
+    /// Adds the node and link stats, along with the non-fatal errors, of the verified simulation
+    pub fn with_simulation(
+        mut self,
+        verified_simulation: &VerifiedSimulation,
+        network: &InMemoryNetwork,
+    ) -> Self {
+        let stats = &verified_simulation.stats;
+        self.nodes = stats
+            .stats_by_node
+            .iter()
+            .map(|(node_id, stats)| (node_id.to_string(), json!(stats)))
+            .collect();
+        self.links = stats
+            .stats_by_link
+            .iter()
+            .map(|(link_id, stats)| {
+                let bandwidth_bps = network.get_link_bandwidth_bps(link_id);
+                (link_id.to_string(), link_json(stats, bandwidth_bps))
+            })
+            .collect();
+        self.non_fatal_errors = verified_simulation
+            .non_fatal_errors
+            .iter()
+            .map(|error| error.to_string())
+            .collect();
+        self
+    }
+
+    pub fn with_error(mut self, error: &anyhow::Error) -> Self {
+        self.error = Some(format!("{error:#}"));
+        self
+    }
+
+    #[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+    pub fn with_schc(mut self, schc: Value) -> Self {
+        self.schc = Some(schc);
+        self
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        println!("--- JSON report ---");
+        let json = serde_json::to_vec_pretty(self).unwrap();
+        fs::write(path, json)
+            .with_context(|| format!("failed to store JSON report at `{}`", path.display()))?;
+        println!("* JSON report available at {}", path.display());
+        Ok(())
+    }
+}
+
+fn link_json(stats: &LinkStats, bandwidth_bps: usize) -> Value {
+    // Links may be configured without bandwidth
+    let usage_pct = stats.max_used_bandwidth_bps as f64 / bandwidth_bps.max(1) as f64 * 100.0;
+    json!({
+        "bandwidth_bps": bandwidth_bps,
+        "max_used_bandwidth_bps": stats.max_used_bandwidth_bps,
+        "max_used_bandwidth_pct": usage_pct,
+        "sent": stats.sent,
+        "dropped_in_transit": stats.dropped_in_transit,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_report_keys() {
+        let seeds = Seeds {
+            quinn: Some(1),
+            network: 2,
+        };
+        let parameters = json!({ "delay_ms": 10 });
+        let report = JsonReport::new("quic", &parameters, seeds, Some(Duration::from_secs(3)));
+        #[cfg(any(feature = "schc-observer", feature = "schc-compressor"))]
+        let report = report.with_schc(json!({}));
+
+        let json = serde_json::to_value(&report).unwrap();
+        let mut keys: Vec<_> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        keys.sort();
+        let mut expected_keys = vec![
+            "command",
+            "links",
+            "nodes",
+            "non_fatal_errors",
+            "parameters",
+            "seeds",
+            "simulated_time_sec",
+        ];
+        if cfg!(any(feature = "schc-observer", feature = "schc-compressor")) {
+            expected_keys.push("schc");
+            expected_keys.sort();
+        }
+        assert_eq!(keys, expected_keys);
+
+        assert_eq!(json["command"], "quic");
+        assert_eq!(json["parameters"], parameters);
+        assert_eq!(json["seeds"], json!({ "quinn": 1, "network": 2 }));
+        assert_eq!(json["simulated_time_sec"], 3.0);
+    }
+
+    #[test]
+    fn test_link_without_bandwidth() {
+        let stats = LinkStats {
+            max_used_bandwidth_bps: 8,
+            ..LinkStats::default()
+        };
+        assert_eq!(link_json(&stats, 0)["max_used_bandwidth_pct"], 800.0);
+        assert_eq!(link_json(&stats, 16)["max_used_bandwidth_pct"], 50.0);
+    }
+}
@@ -1,5 +1,8 @@
 use crate::config::NetworkConfig;
 use crate::config::cli::PingOpt;
+use crate::pcap_exporter_factory;
+use crate::report::{JsonReport, Seeds};
+use crate::util::print_network_captures;
 use anyhow::Context as _;
 use fastrand::Rng;
 use in_memory_network::async_rt;
@@ -7,7 +10,6 @@ use in_memory_network::async_rt::time::Instant;
 use in_memory_network::network::InMemoryNetwork;
 use in_memory_network::network::event::NetworkEvents;
 use in_memory_network::network::spec::NetworkSpec;
-use in_memory_network::pcap_exporter::FileBasedPcapExporterFactory;
 use in_memory_network::quinn_interop::BufsAndMeta;
 use in_memory_network::tracing::tracer::SimulationStepTracer;
 use parking_lot::Mutex;
@@ -38,7 +40,7 @@ pub async fn run(ping_opt: &PingOpt, network_config: NetworkConfig) -> anyhow::R
         network_spec.clone(),
         network_events,
         tracer.clone(),
-        Arc::new(FileBasedPcapExporterFactory),
+        Arc::new(pcap_exporter_factory(&ping_opt.network, &network_spec)?),
         Rng::with_seed(ping_opt.network.network_rng_seed),
         simulation_start,
     )?;
@@ -162,7 +164,10 @@ pub async fn run(ping_opt: &PingOpt, network_config: NetworkConfig) -> anyhow::R
 
     // Wait till done
     async_rt::time::sleep(duration).await;
-    println!("{:.2}s Done", simulation_start.elapsed().as_secs_f64());
+    let simulated_time = simulation_start.elapsed();
+    println!("{:.2}s Done", simulated_time.as_secs_f64());
+
+    print_network_captures(&network)?;
 
     println!("--- Replay log ---");
     let replay_log_path = "replay-log.json";
@@ -170,5 +175,20 @@ pub async fn run(ping_opt: &PingOpt, network_config: NetworkConfig) -> anyhow::R
     fs::write(replay_log_path, json_steps).context("failed to store replay log")?;
     println!("* Replay log available at {replay_log_path}");
 
+    if let Some(path) = &ping_opt.network.report_json {
+        let verified_simulation = tracer
+            .verifier()
+            .context("failed to create simulation verifier")?
+            .verify()
+            .context("failed to verify simulation")?;
+        let seeds = Seeds {
+            quinn: None,
+            network: ping_opt.network.network_rng_seed,
+        };
+        JsonReport::new("ping", ping_opt, seeds, Some(simulated_time))
+            .with_simulation(&verified_simulation, &network)
+            .write(path)?;
+    }
+
     Ok(())
 }
@@ -1,7 +1,10 @@
 use crate::config::NetworkConfig;
 use crate::config::cli::ThroughputOpt;
+use crate::pcap_exporter_factory;
+use crate::report::{JsonReport, Seeds};
 use crate::util::{
-    CancellationToken, print_link_stats, print_max_buffer_usage_per_node, print_node_stats,
+    CancellationToken, print_link_stats, print_max_buffer_usage_per_node, print_network_captures,
+    print_node_stats,
 };
 use anyhow::Context as _;
 use fastrand::Rng;
@@ -11,7 +14,6 @@ use in_memory_network::async_rt::time::Instant;
 use in_memory_network::network::InMemoryNetwork;
 use in_memory_network::network::event::NetworkEvents;
 use in_memory_network::network::spec::NetworkSpec;
-use in_memory_network::pcap_exporter::FileBasedPcapExporterFactory;
 use in_memory_network::quinn_interop::BufsAndMeta;
 use in_memory_network::tracing::tracer::SimulationStepTracer;
 use quinn::AsyncUdpSocket;
@@ -44,7 +46,10 @@ pub async fn run(
         network_spec.clone(),
         network_events,
         tracer.clone(),
-        Arc::new(FileBasedPcapExporterFactory),
+        Arc::new(pcap_exporter_factory(
+            &throughput_opt.network,
+            &network_spec,
+        )?),
         Rng::with_seed(throughput_opt.network.network_rng_seed),
         simulation_start,
     )?;
@@ -140,7 +145,10 @@ pub async fn run(
 
     // Wait till done
     async_rt::time::sleep(duration).await;
-    println!("{:.2}s Done", simulation_start.elapsed().as_secs_f64());
+    let simulated_time = simulation_start.elapsed();
+    println!("{:.2}s Done", simulated_time.as_secs_f64());
+
+    print_network_captures(&network)?;
 
     println!("--- Replay log ---");
     let replay_log_path = "replay-log.json";
@@ -189,7 +197,17 @@ pub async fn run(
 
     print_node_stats(&verified_simulation, server_node, client_node);
     print_max_buffer_usage_per_node(&verified_simulation);
-    print_link_stats(&verified_simulation, &network);
+    print_link_stats(&verified_simulation, &network, false);
+
+    if let Some(path) = &throughput_opt.network.report_json {
+        let seeds = Seeds {
+            quinn: None,
+            network: throughput_opt.network.network_rng_seed,
+        };
+        JsonReport::new("throughput", throughput_opt, seeds, Some(simulated_time))
+            .with_simulation(&verified_simulation, &network)
+            .write(path)?;
+    }
 
     const DISPLAY_MAX_ERRORS: usize = 10;
     if !verified_simulation.non_fatal_errors.is_empty() {
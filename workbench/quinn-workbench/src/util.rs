@@ -4,6 +4,19 @@ use in_memory_network::network::node::Node;
 use in_memory_network::tracing::simulation_verifier::VerifiedSimulation;
 use std::sync::atomic::{AtomicBool, Ordering};
 
+/// Flushes the captures of the nodes and links, and prints where they are
+pub fn print_network_captures(network: &InMemoryNetwork) -> anyhow::Result<()> {
+    let paths = network.flush_pcap_exporters()?;
+    if !paths.is_empty() {
+        println!("--- Network captures ---");
+        for path in paths {
+            println!("* Capture available at {}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
 pub fn print_max_buffer_usage_per_node(verified_simulation: &VerifiedSimulation) {
     println!("--- Max buffer usage per node ---");
     let mut buffer_usage: Vec<_> = verified_simulation.stats.stats_by_node.iter().collect();
@@ -20,7 +33,13 @@ pub fn print_max_buffer_usage_per_node(verified_simulation: &VerifiedSimulation)
     }
 }
 
-pub fn print_link_stats(verified_simulation: &VerifiedSimulation, network: &InMemoryNetwork) {
+/// Prints the stats of each link, including the packets sent over it if `show_sent_packets` is set
+/// (e.g. to compare runs)
+pub fn print_link_stats(
+    verified_simulation: &VerifiedSimulation,
+    network: &InMemoryNetwork,
+    show_sent_packets: bool,
+) {
     if !verified_simulation.stats.stats_by_link.is_empty() {
         println!("--- Link stats ---");
     }
@@ -28,6 +47,12 @@ pub fn print_link_stats(verified_simulation: &VerifiedSimulation, network: &InMe
     link_stats.sort_unstable_by_key(|(id, _)| *id);
     for (link_id, stats) in link_stats {
         println!("* {link_id}:");
+        if show_sent_packets {
+            println!(
+                "|-> Sent {} packets ({} bytes)",
+                stats.sent.packets, stats.sent.bytes
+            );
+        }
         println!(
             "|-> Lost in transit {} packets ({} bytes)",
             stats.dropped_in_transit.packets, stats.dropped_in_transit.bytes